@@ -0,0 +1,18 @@
+MEMORY {
+    BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
+    FLASH : ORIGIN = 0x10000100, LENGTH = 24K - 0x100
+    /* The same partitions as the firmware's memory.x, keep them in step */
+    BOOTLOADER_STATE : ORIGIN = 0x10006000, LENGTH = 4K
+    ACTIVE : ORIGIN = 0x10007000, LENGTH = 1000K
+    DFU : ORIGIN = 0x10101000, LENGTH = 1004K
+    RAM : ORIGIN = 0x20000000, LENGTH = 264K
+}
+
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE) - ORIGIN(BOOT2);
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE) - ORIGIN(BOOT2);
+
+__bootloader_active_start = ORIGIN(ACTIVE) - ORIGIN(BOOT2);
+__bootloader_active_end = ORIGIN(ACTIVE) + LENGTH(ACTIVE) - ORIGIN(BOOT2);
+
+__bootloader_dfu_start = ORIGIN(DFU) - ORIGIN(BOOT2);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - ORIGIN(BOOT2);
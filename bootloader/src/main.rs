@@ -0,0 +1,46 @@
+//! Swaps in an update the firmware has downloaded to the DFU partition,
+//! or swaps the old firmware back if the update never confirmed itself,
+//! then jumps to the active partition. See the firmware's `ota` module.
+
+#![no_std]
+#![no_main]
+
+use core::cell::RefCell;
+
+use cortex_m_rt::{entry, exception};
+use embassy_boot_rp::{BootLoader, BootLoaderConfig, WatchdogFlash};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Duration;
+
+const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+#[entry]
+fn main() -> ! {
+    let p = embassy_rp::init(Default::default());
+
+    // Fed as it goes, so a swap that hangs starts over rather than bricking
+    let flash = WatchdogFlash::<FLASH_SIZE>::start(p.FLASH, p.WATCHDOG, Duration::from_secs(8));
+    let flash = Mutex::new(RefCell::new(flash));
+
+    let config = BootLoaderConfig::from_linkerfile_blocking(&flash, &flash, &flash);
+    let active_offset = config.active.offset();
+    let bootloader = BootLoader::prepare(config);
+
+    unsafe { bootloader.load(embassy_rp::flash::FLASH_BASE as u32 + active_offset) }
+}
+
+#[unsafe(no_mangle)]
+#[cfg_attr(target_os = "none", unsafe(link_section = ".HardFault.user"))]
+unsafe extern "C" fn HardFault() {
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+#[exception]
+unsafe fn DefaultHandler(_: i16) -> ! {
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    cortex_m::peripheral::SCB::sys_reset();
+}
@@ -30,6 +30,18 @@ fn setup_env() {
     }
 }
 
+/// `.widgets` is optional, so an empty one stands in when it's missing
+fn setup_widgets() {
+    println!("cargo:rerun-if-changed=.widgets");
+
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    let widgets = std::fs::read(".widgets").unwrap_or_default();
+    File::create(out.join("widgets"))
+        .unwrap()
+        .write_all(&widgets)
+        .unwrap();
+}
+
 fn setup_build() {
     // Put `memory.x` in our output directory and ensure it's
     // on the linker search path.
@@ -54,5 +66,6 @@ fn setup_build() {
 
 fn main() {
     setup_env();
+    setup_widgets();
     setup_build();
 }
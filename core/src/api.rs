@@ -0,0 +1,64 @@
+//! The fields we need from each API, picked out as the body streams in
+
+use crate::{json::Extract, weather::CurrentWeather};
+
+pub const TIME_API: [&str; 1] = ["$.datetime"];
+
+pub const OPEN_METEO: [&str; 2] = ["$.current.temperature", "$.current.weathercode"];
+
+/// Local time with its offset, see [`crate::rfc3339::parse`]
+pub fn datetime<'a>(fields: &'a Extract<'_, 1>) -> Option<&'a str> {
+    fields.finish().ok()?;
+    fields.get(0)
+}
+
+pub fn current_weather(fields: &Extract<'_, 2>) -> Option<CurrentWeather> {
+    fields.finish().ok()?;
+
+    Some(CurrentWeather {
+        temperature: fields.get(0)?.parse().ok()?,
+        weathercode: fields.get(1)?.parse().ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::BodySink;
+
+    fn extract<const N: usize>(paths: [&'static str; N], body: &[u8]) -> Extract<'static, N> {
+        let mut fields = Extract::new(paths);
+        fields.write(body);
+        fields
+    }
+
+    #[test]
+    fn time_api_response() {
+        let body = br#"{"abbreviation":"CDT","client_ip":"1.2.3.4","datetime":"2025-10-17T14:05:09.123456-05:00","day_of_week":5,"utc_offset":"-05:00"}"#;
+
+        let fields = extract(TIME_API, body);
+
+        assert_eq!(datetime(&fields), Some("2025-10-17T14:05:09.123456-05:00"));
+    }
+
+    #[test]
+    fn open_meteo_response() {
+        let body = br#"{"latitude":20.66,"longitude":-87.04,"current_units":{"temperature":"C"},"current":{"time":"2025-10-17T14:00","interval":900,"temperature":29.4,"weathercode":2,"is_day":1}}"#;
+
+        let current = current_weather(&extract(OPEN_METEO, body)).unwrap();
+
+        assert_eq!(current.temperature, 29.4);
+        assert_eq!(current.weathercode, 2);
+    }
+
+    #[test]
+    fn rejects_html() {
+        assert!(current_weather(&extract(OPEN_METEO, b"<html>Login</html>")).is_none());
+    }
+
+    #[test]
+    fn rejects_truncated_bodies() {
+        let body = br#"{"current":{"temperature":29.4,"weathercode":2"#;
+        assert!(current_weather(&extract(OPEN_METEO, body)).is_none());
+    }
+}
@@ -0,0 +1,162 @@
+//! Counts the unique access points the badge has seen, in a bloom filter
+//! that fills one flash sector
+
+use crate::hal::Store;
+
+/// One RP2040 flash erase sector
+pub const SET_SIZE: usize = 4096;
+
+const HEADER: usize = 8;
+const MAGIC: [u8; 4] = *b"BSS1";
+const BITS: usize = (SET_SIZE - HEADER) * 8;
+/// Misses about one in 200 new access points once 3000 are in
+const HASHES: u32 = 6;
+
+pub type Bssid = [u8; 6];
+
+/// Stored as is: magic, count, then the filter bits
+pub struct BssidSet {
+    bytes: [u8; SET_SIZE],
+}
+
+impl BssidSet {
+    pub const fn new() -> Self {
+        let mut bytes = [0; SET_SIZE];
+        let mut i = 0;
+        while i < MAGIC.len() {
+            bytes[i] = MAGIC[i];
+            i += 1;
+        }
+
+        Self { bytes }
+    }
+
+    pub fn count(&self) -> u32 {
+        u32::from_le_bytes([self.bytes[4], self.bytes[5], self.bytes[6], self.bytes[7]])
+    }
+
+    /// Returns true if `bssid` hadn't been seen, a false positive now and
+    /// then means a new one is missed, but nothing is ever counted twice
+    pub fn insert(&mut self, bssid: &Bssid) -> bool {
+        let hash = fnv1a(bssid);
+        let (h1, h2) = (hash as u32, (hash >> 32) as u32 | 1);
+
+        let mut new = false;
+        for i in 0..HASHES {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) as usize % BITS;
+            let (byte, mask) = (HEADER + bit / 8, 1 << (bit % 8));
+
+            if self.bytes[byte] & mask == 0 {
+                self.bytes[byte] |= mask;
+                new = true;
+            }
+        }
+
+        if new {
+            let count = self.count().saturating_add(1);
+            self.bytes[4..HEADER].copy_from_slice(&count.to_le_bytes());
+        }
+
+        new
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Anything that isn't a set, like erased flash, starts a new one
+    pub async fn load<S: Store>(&mut self, store: &mut S) {
+        let ok = store.read(&mut self.bytes).await.is_ok();
+
+        if !ok || self.bytes[..MAGIC.len()] != MAGIC {
+            *self = Self::new();
+        }
+    }
+
+    pub async fn save<S: Store>(&self, store: &mut S) -> bool {
+        store.write(&self.bytes).await.is_ok()
+    }
+}
+
+impl Default for BssidSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_futures::block_on;
+
+    struct Sector(Vec<u8>);
+
+    impl Store for Sector {
+        type Error = ();
+
+        async fn read(&mut self, buf: &mut [u8]) -> Result<(), ()> {
+            buf.copy_from_slice(&self.0[..buf.len()]);
+            Ok(())
+        }
+
+        async fn write(&mut self, data: &[u8]) -> Result<(), ()> {
+            self.0 = data.to_vec();
+            Ok(())
+        }
+    }
+
+    /// Sequential, like the access points of one vendor
+    fn bssid(n: u32) -> Bssid {
+        let [a, b, c, d] = n.to_be_bytes();
+        [0x00, 0x1a, a, b, c, d]
+    }
+
+    #[test]
+    fn counts_each_once() {
+        let mut set = BssidSet::new();
+
+        assert!(set.insert(&bssid(1)));
+        assert!(set.insert(&bssid(2)));
+        assert!(!set.insert(&bssid(1)));
+        assert!(!set.insert(&bssid(2)));
+        assert_eq!(set.count(), 2);
+    }
+
+    #[test]
+    fn thousands_fit() {
+        let mut set = BssidSet::new();
+
+        for n in 0..3000 {
+            set.insert(&bssid(n));
+        }
+        for n in 0..3000 {
+            assert!(!set.insert(&bssid(n)));
+        }
+
+        // Everything missed was a false positive
+        assert!(set.count() > 2970, "{}", set.count());
+    }
+
+    #[test]
+    fn survives_a_reboot() {
+        let mut sector = Sector(vec![0xFF; SET_SIZE]);
+
+        let mut set = BssidSet::new();
+        block_on(set.load(&mut sector));
+        assert_eq!(set.count(), 0);
+        assert!(set.insert(&bssid(7)));
+        assert!(block_on(set.save(&mut sector)));
+
+        let mut set = BssidSet::new();
+        block_on(set.load(&mut sector));
+        assert_eq!(set.count(), 1);
+        assert!(!set.insert(&bssid(7)));
+        assert!(set.insert(&bssid(8)));
+    }
+}
@@ -0,0 +1,133 @@
+//! Whether the network joined reaches the internet, or a captive portal
+//! answers everything with its login page. The probe URL answers an empty
+//! 204 when online, and a portal answers it with a page, a redirect to one
+//! or a 511.
+
+use serde::{Deserialize, Serialize};
+
+use crate::hal::Probe;
+use crate::http::{BodySink, HttpError, Response, host};
+
+/// Android's, plain HTTP so a portal can intercept it
+pub const DEFAULT_PROBE: &str = "http://connectivitycheck.gstatic.com/generate_204";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Connectivity {
+    Online,
+    /// Something answered for the probe, the API calls would get the same
+    Captive,
+    /// Nothing answered
+    Offline,
+    /// The probe's host answered with an error of its own, so carry on
+    Unknown,
+}
+
+/// Only the length of the body matters
+struct Length(usize);
+
+impl BodySink for Length {
+    fn reset(&mut self) {
+        self.0 = 0;
+    }
+
+    fn write(&mut self, chunk: &[u8]) {
+        self.0 += chunk.len();
+    }
+}
+
+fn classify(url: &str, answer: Result<Response, HttpError>, body: usize) -> Connectivity {
+    let Ok(response) = answer else {
+        return Connectivity::Offline;
+    };
+
+    match response.status {
+        200..=299 if body == 0 => Connectivity::Online,
+        200..=299 | 511 => Connectivity::Captive,
+        // Relative locations stay on the probe's host
+        300..=399 => match response.location.as_deref().and_then(host) {
+            Some(to) if host(url).is_some_and(|from| !from.eq_ignore_ascii_case(to)) => {
+                Connectivity::Captive
+            }
+            _ => Connectivity::Unknown,
+        },
+        _ => Connectivity::Unknown,
+    }
+}
+
+pub async fn probe<P: Probe>(prober: &P, buf: &mut [u8], url: &str) -> Connectivity {
+    let mut body = Length(0);
+    let answer = prober.probe(url, buf, &mut body).await;
+
+    classify(url, answer, body.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_futures::block_on;
+    use heapless::String;
+
+    /// Answers every request the same way
+    struct Canned(Result<(u16, &'static str), HttpError>);
+
+    impl Probe for Canned {
+        async fn probe(
+            &self,
+            _url: &str,
+            _buf: &mut [u8],
+            sink: &mut impl BodySink,
+        ) -> Result<Response, HttpError> {
+            let (status, text) = self.0?;
+
+            match status {
+                300..=399 => Ok(Response {
+                    status,
+                    location: String::try_from(text).ok(),
+                }),
+                _ => {
+                    sink.write(text.as_bytes());
+                    Ok(Response {
+                        status,
+                        location: None,
+                    })
+                }
+            }
+        }
+    }
+
+    fn probed(answer: Result<(u16, &'static str), HttpError>) -> Connectivity {
+        block_on(probe(&Canned(answer), &mut [0; 64], DEFAULT_PROBE))
+    }
+
+    #[test]
+    fn classifies_answers() {
+        assert_eq!(probed(Ok((204, ""))), Connectivity::Online);
+        assert_eq!(
+            probed(Ok((200, "<html><form action=\"/login\">"))),
+            Connectivity::Captive
+        );
+        assert_eq!(probed(Ok((511, ""))), Connectivity::Captive);
+        assert_eq!(
+            probed(Ok((302, "http://portal.hotel.example/login"))),
+            Connectivity::Captive
+        );
+        assert_eq!(probed(Err(HttpError::Dns)), Connectivity::Offline);
+        assert_eq!(probed(Err(HttpError::Timeout)), Connectivity::Offline);
+    }
+
+    #[test]
+    fn other_answers_carry_on() {
+        assert_eq!(probed(Ok((503, "busy"))), Connectivity::Unknown);
+        assert_eq!(probed(Ok((404, ""))), Connectivity::Unknown);
+        assert_eq!(
+            probed(Ok((
+                301,
+                "https://connectivitycheck.gstatic.com/generate_204"
+            ))),
+            Connectivity::Unknown
+        );
+        assert_eq!(probed(Ok((302, "/generate_204"))), Connectivity::Unknown);
+        assert_eq!(probed(Ok((302, ""))), Connectivity::Unknown);
+    }
+}
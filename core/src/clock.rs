@@ -0,0 +1,200 @@
+//! Formats the top bar clock, e.g. `| 02:05 PM` or `| Fri 17 Oct 14:05`
+
+use heapless::String;
+use time::PrimitiveDateTime;
+
+use crate::helpers::easy_format;
+
+/// Names must be ASCII, the top bar font has nothing else
+pub struct Names {
+    /// Monday first
+    pub weekdays: [&'static str; 7],
+    pub months: [&'static str; 12],
+    pub am: &'static str,
+    pub pm: &'static str,
+}
+
+pub const ENGLISH: Names = Names {
+    weekdays: ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"],
+    months: [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ],
+    am: "AM",
+    pm: "PM",
+};
+
+pub const SPANISH: Names = Names {
+    weekdays: ["Lun", "Mar", "Mie", "Jue", "Vie", "Sab", "Dom"],
+    months: [
+        "Ene", "Feb", "Mar", "Abr", "May", "Jun", "Jul", "Ago", "Sep", "Oct", "Nov", "Dic",
+    ],
+    am: "AM",
+    pm: "PM",
+};
+
+pub const GERMAN: Names = Names {
+    weekdays: ["Mo", "Di", "Mi", "Do", "Fr", "Sa", "So"],
+    months: [
+        "Jan", "Feb", "Mrz", "Apr", "Mai", "Jun", "Jul", "Aug", "Sep", "Okt", "Nov", "Dez",
+    ],
+    am: "AM",
+    pm: "PM",
+};
+
+/// Looks up names by language code, `en`, `es` or `de`
+pub fn names(code: &str) -> Option<&'static Names> {
+    match code {
+        "en" => Some(&ENGLISH),
+        "es" => Some(&SPANISH),
+        "de" => Some(&GERMAN),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HourFormat {
+    Twelve,
+    TwentyFour,
+}
+
+impl HourFormat {
+    /// `12`, `12h`, `24` or `24h`
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim_end_matches('h') {
+            "12" => Some(Self::Twelve),
+            "24" => Some(Self::TwentyFour),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct ClockFormat {
+    pub hours: HourFormat,
+    /// Adds the weekday, day and month before the time
+    pub date: bool,
+    pub names: &'static Names,
+}
+
+impl ClockFormat {
+    pub const DEFAULT: Self = Self {
+        hours: HourFormat::Twelve,
+        date: false,
+        names: &ENGLISH,
+    };
+
+    pub fn format(&self, time: PrimitiveDateTime) -> String<32> {
+        let mut out = easy_format::<32>(format_args!("| "));
+
+        if self.date {
+            let weekday = self.names.weekdays[time.weekday().number_days_from_monday() as usize];
+            let month = self.names.months[time.month() as usize - 1];
+
+            out.push_str(&easy_format::<24>(format_args!(
+                "{} {} {} ",
+                weekday,
+                time.day(),
+                month
+            )))
+            .ok();
+        }
+
+        let clock: String<16> = match self.hours {
+            HourFormat::Twelve => {
+                let (hour, suffix) = match time.hour() {
+                    0 => (12, self.names.am),
+                    12 => (12, self.names.pm),
+                    x if x > 12 => (x - 12, self.names.pm),
+                    x => (x, self.names.am),
+                };
+
+                easy_format::<16>(format_args!("{:02}:{:02} {}", hour, time.minute(), suffix))
+            }
+            HourFormat::TwentyFour => {
+                easy_format::<16>(format_args!("{:02}:{:02}", time.hour(), time.minute()))
+            }
+        };
+
+        out.push_str(&clock).ok();
+        out
+    }
+}
+
+impl Default for ClockFormat {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::{Date, Month, Time};
+
+    fn at(hour: u8, minute: u8) -> PrimitiveDateTime {
+        PrimitiveDateTime::new(
+            Date::from_calendar_date(2025, Month::October, 17).unwrap(),
+            Time::from_hms(hour, minute, 0).unwrap(),
+        )
+    }
+
+    #[test]
+    fn twelve_hour() {
+        let clock = ClockFormat::DEFAULT;
+
+        assert_eq!(clock.format(at(0, 0)), "| 12:00 AM");
+        assert_eq!(clock.format(at(0, 59)), "| 12:59 AM");
+        assert_eq!(clock.format(at(9, 5)), "| 09:05 AM");
+        assert_eq!(clock.format(at(11, 59)), "| 11:59 AM");
+        assert_eq!(clock.format(at(12, 0)), "| 12:00 PM");
+        assert_eq!(clock.format(at(13, 0)), "| 01:00 PM");
+        assert_eq!(clock.format(at(23, 59)), "| 11:59 PM");
+    }
+
+    #[test]
+    fn twenty_four_hour() {
+        let clock = ClockFormat {
+            hours: HourFormat::TwentyFour,
+            ..ClockFormat::DEFAULT
+        };
+
+        assert_eq!(clock.format(at(0, 0)), "| 00:00");
+        assert_eq!(clock.format(at(14, 5)), "| 14:05");
+        assert_eq!(clock.format(at(23, 59)), "| 23:59");
+    }
+
+    #[test]
+    fn with_date() {
+        let clock = ClockFormat {
+            date: true,
+            ..ClockFormat::DEFAULT
+        };
+        assert_eq!(clock.format(at(14, 5)), "| Fri 17 Oct 02:05 PM");
+
+        let clock = ClockFormat {
+            hours: HourFormat::TwentyFour,
+            date: true,
+            names: names("es").unwrap(),
+        };
+        assert_eq!(clock.format(at(14, 5)), "| Vie 17 Oct 14:05");
+
+        let clock = ClockFormat {
+            names: &GERMAN,
+            ..clock
+        };
+        let march = PrimitiveDateTime::new(
+            Date::from_calendar_date(2026, Month::March, 1).unwrap(),
+            Time::MIDNIGHT,
+        );
+        assert_eq!(clock.format(march), "| So 1 Mrz 00:00");
+    }
+
+    #[test]
+    fn settings() {
+        assert_eq!(HourFormat::parse("24h"), Some(HourFormat::TwentyFour));
+        assert_eq!(HourFormat::parse("12"), Some(HourFormat::Twelve));
+        assert_eq!(HourFormat::parse("13"), None);
+        assert!(names("fr").is_none());
+    }
+}
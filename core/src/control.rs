@@ -0,0 +1,294 @@
+//! The JSON API the badge serves on external power, so dashboards and
+//! scripts can read what it's showing and drive it.
+//!
+//! * `GET /api/state`
+//! * `POST /api/redraw`
+//! * `POST /api/image`, `{"index":1}` or `{"shift":"next"}` / `"prev"`
+//! * `POST /api/status`, `{"message":"In a meeting"}`, empty to clear
+//!
+//! Each answers with the state as it will be once the command is applied.
+
+use embedded_io_async::{Read, Write};
+use heapless::String;
+use serde::{Deserialize, Serialize};
+use time::PrimitiveDateTime;
+
+use crate::{
+    helpers::{easy_format, utc_timestamp},
+    httpd::{self, HttpdError, JSON, Method},
+    image::{self, IMAGES, Shift},
+    weather::CurrentWeather,
+};
+
+pub const PORT: u16 = 80;
+
+/// Longest status message, in bytes
+pub const STATUS_SIZE: usize = 64;
+
+/// Big enough for the state with the longest status, every character escaped
+pub const BODY_SIZE: usize = 512;
+
+#[derive(Clone, Copy)]
+pub struct State<'a> {
+    pub weather: Option<CurrentWeather>,
+    /// UTC, `None` until the RTC can be trusted
+    pub time: Option<PrimitiveDateTime>,
+    pub image: usize,
+    /// Empty when there isn't one
+    pub status: &'a str,
+}
+
+#[derive(Serialize)]
+struct Body<'a> {
+    weather: Option<CurrentWeather>,
+    time: Option<String<20>>,
+    image: usize,
+    images: usize,
+    status: &'a str,
+}
+
+impl State<'_> {
+    fn to_json(self) -> String<BODY_SIZE> {
+        let body = Body {
+            weather: self.weather,
+            time: self.time.map(utc_timestamp),
+            image: self.image,
+            images: IMAGES.len(),
+            status: self.status,
+        };
+
+        // Every field is bounded, so this always fits
+        serde_json_core::to_string(&body).unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Redraw,
+    /// Show the image at this index, already checked against `IMAGES`
+    Image(usize),
+    /// Shown across the bottom of the image, empty to clear
+    Status(String<STATUS_SIZE>),
+}
+
+#[derive(Deserialize)]
+struct ImageRequest<'a> {
+    index: Option<usize>,
+    shift: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct StatusRequest {
+    message: String<STATUS_SIZE>,
+}
+
+fn image_command(body: &[u8], current: usize) -> Option<Command> {
+    let (request, _) = serde_json_core::from_slice::<ImageRequest>(body).ok()?;
+
+    let index = match (request.index, request.shift) {
+        (Some(index), None) if index < IMAGES.len() => index,
+        (None, Some("next")) => image::shift(current, Shift::Next),
+        (None, Some("prev")) => image::shift(current, Shift::Prev),
+        _ => return None,
+    };
+
+    Some(Command::Image(index))
+}
+
+fn status_command(body: &[u8]) -> Option<Command> {
+    let mut unescaped = [0; STATUS_SIZE];
+    let (request, _) =
+        serde_json_core::from_slice_escaped::<StatusRequest>(body, &mut unescaped).ok()?;
+
+    Some(Command::Status(request.message))
+}
+
+async fn error<C: Write>(conn: &mut C, status: u16, message: &str) -> Result<(), HttpdError> {
+    let body: String<96> = easy_format::<96>(format_args!("{{\"error\":\"{}\"}}", message));
+    httpd::respond(conn, status, JSON, body.as_bytes()).await
+}
+
+/// A request worked out from what was read, before the state is needed
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    /// With the state, after the command if there is one
+    State(Option<Command>),
+    Error(u16, &'static str),
+    /// Too large or malformed, answered and then passed on
+    Unreadable(HttpdError),
+}
+
+/// Reads one request. `image` is the one showing, for a shift.
+pub async fn read<C: Read>(
+    conn: &mut C,
+    buf: &mut [u8],
+    image: usize,
+) -> Result<Reply, HttpdError> {
+    let request = match httpd::read_request(conn, buf).await {
+        Ok(request) => request,
+        Err(e @ (HttpdError::TooLarge | HttpdError::Malformed)) => {
+            return Ok(Reply::Unreadable(e));
+        }
+        Err(e) => return Err(e),
+    };
+
+    Ok(match (request.method, request.path) {
+        (Method::Get, "/api/state") => Reply::State(None),
+        (Method::Post, "/api/redraw") => Reply::State(Some(Command::Redraw)),
+        (Method::Post, "/api/image") => match image_command(request.body, image) {
+            Some(command) => Reply::State(Some(command)),
+            None => Reply::Error(400, "expected an index, or a shift of next or prev"),
+        },
+        (Method::Post, "/api/status") => match status_command(request.body) {
+            Some(command) => Reply::State(Some(command)),
+            None => Reply::Error(400, "expected a message of at most 64 bytes"),
+        },
+        (_, "/api/state" | "/api/redraw" | "/api/image" | "/api/status") => {
+            Reply::Error(405, "method not allowed")
+        }
+        _ => Reply::Error(404, "not found"),
+    })
+}
+
+/// Answers `reply` against `state`. Returns the command to carry out, if
+/// it was one.
+pub async fn respond<C: Write>(
+    conn: &mut C,
+    reply: Reply,
+    state: &State<'_>,
+) -> Result<Option<Command>, HttpdError> {
+    let command = match reply {
+        Reply::State(command) => command,
+        Reply::Error(status, message) => {
+            error(conn, status, message).await?;
+            return Ok(None);
+        }
+        Reply::Unreadable(e) => {
+            let status = if e == HttpdError::TooLarge { 413 } else { 400 };
+            error(conn, status, "bad request").await?;
+            return Err(e);
+        }
+    };
+
+    let after = match &command {
+        Some(Command::Image(index)) => State {
+            image: *index,
+            ..*state
+        },
+        Some(Command::Status(message)) => State {
+            status: message,
+            ..*state
+        },
+        _ => *state,
+    };
+
+    httpd::respond(conn, 200, JSON, after.to_json().as_bytes()).await?;
+
+    Ok(command)
+}
+
+/// Serves one request against `state`, [`read`] then [`respond`]
+pub async fn handle<C: Read + Write>(
+    conn: &mut C,
+    buf: &mut [u8],
+    state: &State<'_>,
+) -> Result<Option<Command>, HttpdError> {
+    let reply = read(conn, buf, state.image).await?;
+    respond(conn, reply, state).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::{Date, Month, Time};
+
+    fn state() -> State<'static> {
+        State {
+            weather: Some(CurrentWeather {
+                temperature: 21.5,
+                weathercode: 3,
+            }),
+            time: Some(PrimitiveDateTime::new(
+                Date::from_calendar_date(2025, Month::October, 17).unwrap(),
+                Time::from_hms(19, 5, 9).unwrap(),
+            )),
+            image: 0,
+            status: "",
+        }
+    }
+
+    #[test]
+    fn state_as_json() {
+        assert_eq!(
+            state().to_json(),
+            r#"{"weather":{"temperature":21.5,"weathercode":3},"time":"2025-10-17T19:05:09Z","image":0,"images":3,"status":""}"#
+        );
+
+        let unknown = State {
+            weather: None,
+            time: None,
+            status: "say \"hi\"",
+            ..state()
+        };
+        assert_eq!(
+            unknown.to_json(),
+            r#"{"weather":null,"time":null,"image":0,"images":3,"status":"say \"hi\""}"#
+        );
+    }
+
+    #[test]
+    fn longest_status_fits() {
+        // Control characters take six bytes each, escaped
+        let status = "\u{1}".repeat(STATUS_SIZE);
+        let json = State {
+            status: &status,
+            ..state()
+        }
+        .to_json();
+
+        assert!(json.ends_with("\"}"));
+    }
+
+    #[test]
+    fn image_commands() {
+        let last = IMAGES.len() - 1;
+
+        assert_eq!(image_command(br#"{"index":1}"#, 0), Some(Command::Image(1)));
+        assert_eq!(
+            image_command(br#"{"shift":"next"}"#, last),
+            Some(Command::Image(0))
+        );
+        assert_eq!(
+            image_command(br#"{"shift":"prev"}"#, 0),
+            Some(Command::Image(last))
+        );
+
+        for bad in [
+            &br#"{"index":99}"#[..],
+            br#"{"shift":"sideways"}"#,
+            br#"{"index":1,"shift":"next"}"#,
+            br#"{}"#,
+            b"1",
+        ] {
+            assert_eq!(image_command(bad, 0), None);
+        }
+    }
+
+    #[test]
+    fn status_commands() {
+        assert_eq!(
+            status_command(br#"{"message":"Back at 3 \u00b7 \"lunch\""}"#),
+            Some(Command::Status(
+                String::try_from("Back at 3 \u{b7} \"lunch\"").unwrap()
+            ))
+        );
+        assert_eq!(
+            status_command(br#"{"message":""}"#),
+            Some(Command::Status(String::new()))
+        );
+
+        let long = format!(r#"{{"message":"{}"}}"#, "x".repeat(STATUS_SIZE + 1));
+        assert_eq!(status_command(long.as_bytes()), None);
+        assert_eq!(status_command(br#"{"text":"hi"}"#), None);
+    }
+}
@@ -0,0 +1,258 @@
+//! A small DHCP server for the setup access point, enough for a phone or
+//! laptop to get an address, with the badge as its router and DNS server
+
+pub const SERVER_PORT: u16 = 67;
+pub const CLIENT_PORT: u16 = 68;
+
+/// Replies are padded to the smallest BOOTP message some clients accept
+pub const PACKET_SIZE: usize = 300;
+
+const MAGIC: [u8; 4] = [99, 130, 83, 99];
+const OPTIONS: usize = 240;
+const LEASE_SECS: u32 = 60 * 60;
+
+const DISCOVER: u8 = 1;
+const OFFER: u8 = 2;
+const REQUEST: u8 = 3;
+const ACK: u8 = 5;
+const NAK: u8 = 6;
+
+pub type Mac = [u8; 6];
+
+/// Hands out the addresses after the server's own, one per client,
+/// reusing the oldest once they run out
+pub struct Leases<const N: usize> {
+    server: [u8; 4],
+    macs: [Option<Mac>; N],
+    next: usize,
+}
+
+impl<const N: usize> Leases<N> {
+    pub fn new(server: [u8; 4]) -> Self {
+        Self {
+            server,
+            macs: [None; N],
+            next: 0,
+        }
+    }
+
+    fn address(&self, slot: usize) -> [u8; 4] {
+        let [a, b, c, d] = self.server;
+        [a, b, c, d.wrapping_add(1 + slot as u8)]
+    }
+
+    fn lease(&mut self, mac: Mac) -> usize {
+        if let Some(slot) = self.macs.iter().position(|m| *m == Some(mac)) {
+            return slot;
+        }
+
+        let slot = match self.macs.iter().position(Option::is_none) {
+            Some(slot) => slot,
+            None => {
+                let slot = self.next;
+                self.next = (self.next + 1) % N;
+                slot
+            }
+        };
+
+        self.macs[slot] = Some(mac);
+        slot
+    }
+
+    /// Builds the reply to a client's message in `out`, to be broadcast to
+    /// the client port. `None` for anything that doesn't need one.
+    pub fn reply(&mut self, request: &[u8], out: &mut [u8; PACKET_SIZE]) -> Option<usize> {
+        if request.len() < OPTIONS || request[0] != 1 || request[236..OPTIONS] != MAGIC {
+            return None;
+        }
+
+        let mut mac = [0; 6];
+        mac.copy_from_slice(&request[28..34]);
+
+        let options = &request[OPTIONS..];
+        let kind = option(options, 53).and_then(|v| v.first().copied())?;
+
+        // Meant for a different server
+        if let Some(id) = option(options, 54)
+            && id != self.server
+        {
+            return None;
+        }
+
+        let (kind, yiaddr) = match kind {
+            DISCOVER => {
+                let slot = self.lease(mac);
+                (OFFER, self.address(slot))
+            }
+            REQUEST => {
+                let slot = self.lease(mac);
+                let yiaddr = self.address(slot);
+
+                let requested = option(options, 50).unwrap_or(&request[12..16]);
+                match requested == yiaddr {
+                    true => (ACK, yiaddr),
+                    false => (NAK, [0; 4]),
+                }
+            }
+            _ => return None,
+        };
+
+        out.fill(0);
+        out[0] = 2;
+        out[1..3].copy_from_slice(&request[1..3]);
+        // Transaction id and flags
+        out[4..8].copy_from_slice(&request[4..8]);
+        out[10..12].copy_from_slice(&request[10..12]);
+        out[16..20].copy_from_slice(&yiaddr);
+        out[20..24].copy_from_slice(&self.server);
+        out[28..44].copy_from_slice(&request[28..44]);
+        out[236..OPTIONS].copy_from_slice(&MAGIC);
+
+        let mut at = OPTIONS;
+        let mut put = |code: u8, value: &[u8]| {
+            out[at] = code;
+            out[at + 1] = value.len() as u8;
+            out[at + 2..at + 2 + value.len()].copy_from_slice(value);
+            at += 2 + value.len();
+        };
+
+        put(53, &[kind]);
+        put(54, &self.server);
+        if kind != NAK {
+            put(51, &LEASE_SECS.to_be_bytes());
+            put(1, &[255, 255, 255, 0]);
+            put(3, &self.server);
+            put(6, &self.server);
+        }
+        out[at] = 255;
+
+        Some(PACKET_SIZE)
+    }
+}
+
+fn option(mut options: &[u8], code: u8) -> Option<&[u8]> {
+    loop {
+        match options {
+            [255, ..] | [] => return None,
+            [0, rest @ ..] => options = rest,
+            [c, len, rest @ ..] => {
+                let value = rest.get(..*len as usize)?;
+                if *c == code {
+                    return Some(value);
+                }
+                options = &rest[*len as usize..];
+            }
+            [_] => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER: [u8; 4] = [192, 168, 4, 1];
+    const PHONE: Mac = [0x02, 0x11, 0x22, 0x33, 0x44, 0x55];
+
+    fn message(mac: Mac, options: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0; OPTIONS];
+        packet[0] = 1;
+        packet[1] = 1;
+        packet[2] = 6;
+        packet[4..8].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        packet[10] = 0x80;
+        packet[28..34].copy_from_slice(&mac);
+        packet[236..OPTIONS].copy_from_slice(&MAGIC);
+        packet.extend_from_slice(options);
+        packet.push(255);
+        packet
+    }
+
+    fn reply(leases: &mut Leases<4>, request: &[u8]) -> Option<[u8; PACKET_SIZE]> {
+        let mut out = [0; PACKET_SIZE];
+        leases.reply(request, &mut out).map(|_| out)
+    }
+
+    #[test]
+    fn discover_then_request() {
+        let mut leases = Leases::<4>::new(SERVER);
+
+        let offer = reply(&mut leases, &message(PHONE, &[53, 1, DISCOVER])).unwrap();
+        assert_eq!(offer[0], 2);
+        assert_eq!(offer[4..8], [0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(offer[10], 0x80);
+        assert_eq!(offer[16..20], [192, 168, 4, 2]);
+        assert_eq!(offer[28..34], PHONE);
+        assert_eq!(option(&offer[OPTIONS..], 53), Some(&[OFFER][..]));
+        assert_eq!(option(&offer[OPTIONS..], 3), Some(&SERVER[..]));
+        assert_eq!(option(&offer[OPTIONS..], 6), Some(&SERVER[..]));
+        assert_eq!(option(&offer[OPTIONS..], 1), Some(&[255, 255, 255, 0][..]));
+
+        let ack = reply(
+            &mut leases,
+            &message(
+                PHONE,
+                &[53, 1, REQUEST, 50, 4, 192, 168, 4, 2, 54, 4, 192, 168, 4, 1],
+            ),
+        )
+        .unwrap();
+        assert_eq!(option(&ack[OPTIONS..], 53), Some(&[ACK][..]));
+        assert_eq!(ack[16..20], [192, 168, 4, 2]);
+    }
+
+    #[test]
+    fn clients_get_their_own_address() {
+        let mut leases = Leases::<4>::new(SERVER);
+        let laptop = [0x02, 0, 0, 0, 0, 1];
+
+        let phone = reply(&mut leases, &message(PHONE, &[53, 1, DISCOVER])).unwrap();
+        let other = reply(&mut leases, &message(laptop, &[53, 1, DISCOVER])).unwrap();
+        let again = reply(&mut leases, &message(PHONE, &[53, 1, DISCOVER])).unwrap();
+
+        assert_eq!(phone[16..20], [192, 168, 4, 2]);
+        assert_eq!(other[16..20], [192, 168, 4, 3]);
+        assert_eq!(again[16..20], [192, 168, 4, 2]);
+    }
+
+    #[test]
+    fn stale_address_is_refused() {
+        let mut leases = Leases::<4>::new(SERVER);
+
+        let nak = reply(
+            &mut leases,
+            &message(PHONE, &[53, 1, REQUEST, 50, 4, 10, 0, 0, 7]),
+        )
+        .unwrap();
+        assert_eq!(option(&nak[OPTIONS..], 53), Some(&[NAK][..]));
+        assert_eq!(nak[16..20], [0; 4]);
+        assert_eq!(option(&nak[OPTIONS..], 51), None);
+    }
+
+    #[test]
+    fn ignores_what_isnt_for_us() {
+        let mut leases = Leases::<4>::new(SERVER);
+
+        // Another server's offer was picked
+        assert!(
+            reply(
+                &mut leases,
+                &message(PHONE, &[53, 1, REQUEST, 54, 4, 10, 0, 0, 1])
+            )
+            .is_none()
+        );
+        // Release, and garbage
+        assert!(reply(&mut leases, &message(PHONE, &[53, 1, 7])).is_none());
+        assert!(reply(&mut leases, &[1, 2, 3]).is_none());
+    }
+
+    #[test]
+    fn oldest_lease_is_reused() {
+        let mut leases = Leases::<2>::new(SERVER);
+
+        for n in 0..3 {
+            let mut out = [0; PACKET_SIZE];
+            leases.reply(&message([2, 0, 0, 0, 0, n], &[53, 1, DISCOVER]), &mut out);
+            assert_eq!(out[19], [2, 3, 2][n as usize]);
+        }
+    }
+}
@@ -0,0 +1,499 @@
+//! What the last syncs ran into, kept in its own flash sector so a badge
+//! on battery can show it on the diagnostics screen, see
+//! [`crate::layout::draw_diagnostics`]. Times are Unix seconds, `None`
+//! when the RTC couldn't be trusted.
+
+use core::fmt::Write;
+
+use heapless::{String, Vec};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    captive::Connectivity,
+    hal::Store,
+    http::HttpError,
+    sntp::SntpError,
+    sync::{FetchError, Synced},
+    widgets::{MAX_WIDGETS, Region},
+};
+
+/// Big enough for any `Diagnostics`
+pub const DIAGNOSTICS_SIZE: usize = 512;
+
+/// As many characters as fit across the screen
+pub const LINE_SIZE: usize = 48;
+pub const MAX_LINES: usize = 9;
+
+pub type Line = String<LINE_SIZE>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum JoinStatus {
+    Joined,
+    /// Joined, but no address came before the timeout
+    NoAddress,
+    /// The status the radio gave up with
+    Failed(u32),
+    TimedOut,
+    /// There was nothing to try, or no time left to try it
+    NotTried,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Join {
+    /// The network tried last, the one joined if any was
+    pub ssid: String<32>,
+    pub status: JoinStatus,
+    /// The strongest access point the scan found for it
+    pub rssi: Option<i16>,
+    pub at: Option<i64>,
+}
+
+/// Why a request failed, kept small for flash
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Failure {
+    Dns,
+    Connection,
+    Timeout,
+    Status(u16),
+    Redirect,
+    TooLarge,
+    Config,
+    /// An answer without what was asked for
+    Response,
+    /// An NTP server said to go away
+    Refused,
+    /// Not tried, behind a captive portal
+    Captive,
+}
+
+impl Failure {
+    fn describe(&self, out: &mut Line) {
+        let _ = match self {
+            Failure::Dns => out.push_str("DNS"),
+            Failure::Connection => out.push_str("no connection"),
+            Failure::Timeout => out.push_str("timed out"),
+            Failure::Status(status) => write!(out, "HTTP {}", status).map_err(|_| ()),
+            Failure::Redirect => out.push_str("bad redirect"),
+            Failure::TooLarge => out.push_str("too large"),
+            Failure::Config => out.push_str("bad URL"),
+            Failure::Response => out.push_str("bad response"),
+            Failure::Refused => out.push_str("refused"),
+            Failure::Captive => out.push_str("skipped"),
+        };
+    }
+}
+
+impl From<HttpError> for Failure {
+    fn from(e: HttpError) -> Self {
+        match e {
+            HttpError::Dns => Failure::Dns,
+            HttpError::Connection => Failure::Connection,
+            HttpError::Timeout => Failure::Timeout,
+            HttpError::Status(status) => Failure::Status(status),
+            HttpError::BadRedirect | HttpError::TooManyRedirects => Failure::Redirect,
+            HttpError::TooLarge => Failure::TooLarge,
+            HttpError::Config => Failure::Config,
+        }
+    }
+}
+
+impl From<&FetchError<HttpError>> for Failure {
+    fn from(e: &FetchError<HttpError>) -> Self {
+        match e {
+            FetchError::Http(e) => (*e).into(),
+            FetchError::Parse | FetchError::Time(_) => Failure::Response,
+            FetchError::Captive => Failure::Captive,
+        }
+    }
+}
+
+/// The UDP side reports a [`Failure`] of its own
+impl From<&SntpError<Failure>> for Failure {
+    fn from(e: &SntpError<Failure>) -> Self {
+        match e {
+            SntpError::Udp(e) => *e,
+            SntpError::NoServers => Failure::Config,
+            SntpError::Malformed | SntpError::Unexpected => Failure::Response,
+            SntpError::KissOfDeath => Failure::Refused,
+        }
+    }
+}
+
+/// One thing a sync fetches
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Endpoint {
+    /// The last try, and when it was
+    pub result: Option<Result<(), Failure>>,
+    pub at: Option<i64>,
+    /// The last success, for when the last try wasn't one
+    pub ok_at: Option<i64>,
+}
+
+impl Endpoint {
+    pub fn record<T, E>(&mut self, result: &Result<T, E>, at: Option<i64>)
+    where
+        for<'e> &'e E: Into<Failure>,
+    {
+        self.result = Some(result.as_ref().map(|_| ()).map_err(Into::into));
+        self.at = at;
+
+        if result.is_ok() {
+            self.ok_at = at;
+        }
+    }
+
+    /// `None` if it's never been tried
+    fn describe(&self, name: &str, now: Option<i64>) -> Option<Line> {
+        let result = self.result?;
+        let mut line = Line::new();
+        let _ = write!(line, "{}: ", name);
+
+        match result {
+            Ok(()) => {
+                line.push_str("ok").ok();
+                push_age(&mut line, self.at, now);
+            }
+            Err(failure) => {
+                failure.describe(&mut line);
+                push_age(&mut line, self.at, now);
+
+                if let Some(ok_at) = self.ok_at {
+                    line.push_str(", ok").ok();
+                    push_age(&mut line, Some(ok_at), now);
+                }
+            }
+        }
+
+        Some(line)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Diagnostics {
+    pub join: Option<Join>,
+    /// From joining to having an address, DHCP or otherwise
+    pub address_ms: Option<u32>,
+    /// What the last sync's probe found
+    pub connectivity: Option<Connectivity>,
+    pub ntp: Endpoint,
+    /// Only tried when no NTP server answers
+    pub time_api: Endpoint,
+    pub weather: Endpoint,
+    /// By region
+    pub widgets: [Endpoint; MAX_WIDGETS],
+}
+
+impl Diagnostics {
+    pub const fn new() -> Self {
+        const NEVER: Endpoint = Endpoint {
+            result: None,
+            at: None,
+            ok_at: None,
+        };
+
+        Self {
+            join: None,
+            address_ms: None,
+            connectivity: None,
+            ntp: NEVER,
+            time_api: NEVER,
+            weather: NEVER,
+            widgets: [NEVER; MAX_WIDGETS],
+        }
+    }
+
+    /// Everything a sync tried, `at` is when it started
+    pub fn record_sync(&mut self, synced: &Synced<HttpError, Failure>, at: Option<i64>) {
+        self.connectivity = synced.connectivity;
+        self.ntp.record(&synced.ntp, at);
+        // Only asked when NTP failed
+        if synced.ntp.is_err() {
+            self.time_api.record(&synced.time, at);
+        }
+        self.weather.record(&synced.weather, at);
+
+        for (region, text) in &synced.widgets {
+            self.widgets[*region as usize].record(text, at);
+        }
+    }
+
+    /// The screen's text, ages counted back from `now`
+    pub fn lines(&self, now: Option<i64>) -> Vec<Line, MAX_LINES> {
+        let mut lines = Vec::new();
+
+        let mut line = Line::new();
+        match &self.join {
+            Some(join) => {
+                let _ = write!(line, "WiFi: {}", join.ssid);
+                if let Some(rssi) = join.rssi {
+                    let _ = write!(line, " {}dBm", rssi);
+                }
+                let _ = match join.status {
+                    JoinStatus::Joined => line.push_str(" joined"),
+                    JoinStatus::NoAddress => line.push_str(" no address"),
+                    JoinStatus::Failed(status) => {
+                        write!(line, " failed ({})", status).map_err(|_| ())
+                    }
+                    JoinStatus::TimedOut => line.push_str(" timed out"),
+                    JoinStatus::NotTried => line.push_str(" not tried"),
+                };
+                push_age(&mut line, join.at, now);
+            }
+            None => {
+                line.push_str("WiFi: not tried yet").ok();
+            }
+        }
+        lines.push(line).ok();
+
+        if self.address_ms.is_some() || self.connectivity.is_some() {
+            let mut line = Line::new();
+            line.push_str("Address:").ok();
+            if let Some(ms) = self.address_ms {
+                let _ = write!(line, " after {}.{}s", ms / 1000, ms % 1000 / 100);
+            }
+            if let Some(connectivity) = self.connectivity {
+                if self.address_ms.is_some() {
+                    line.push(',').ok();
+                }
+                let _ = line.push_str(match connectivity {
+                    Connectivity::Online => " online",
+                    Connectivity::Captive => " captive portal",
+                    Connectivity::Offline => " no internet",
+                    Connectivity::Unknown => " probe unclear",
+                });
+            }
+            lines.push(line).ok();
+        }
+
+        let endpoints = [
+            ("NTP", &self.ntp),
+            ("Time API", &self.time_api),
+            ("Weather", &self.weather),
+        ]
+        .into_iter()
+        .chain(Region::ALL.iter().map(|r| r.name()).zip(&self.widgets));
+
+        for (name, endpoint) in endpoints {
+            if let Some(line) = endpoint.describe(name, now) {
+                lines.push(line).ok();
+            }
+        }
+
+        lines
+    }
+
+    pub fn encode<'a>(&self, buf: &'a mut [u8]) -> Option<&'a mut [u8]> {
+        postcard::to_slice(self, buf).ok()
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        postcard::from_bytes(buf).ok()
+    }
+}
+
+pub async fn load<S: Store>(store: &mut S) -> Option<Diagnostics> {
+    let mut buf = [0u8; DIAGNOSTICS_SIZE];
+    store.read(&mut buf).await.ok()?;
+    Diagnostics::decode(&buf)
+}
+
+pub async fn save<S: Store>(store: &mut S, diagnostics: &Diagnostics) -> bool {
+    let mut buf = [0u8; DIAGNOSTICS_SIZE];
+
+    match diagnostics.encode(&mut buf) {
+        Some(slice) => store.write(slice).await.is_ok(),
+        None => false,
+    }
+}
+
+/// ` 5m ago` and the like, nothing if either time isn't known
+fn push_age(line: &mut Line, at: Option<i64>, now: Option<i64>) {
+    let (Some(at), Some(now)) = (at, now) else {
+        return;
+    };
+
+    let _ = match now.saturating_sub(at).max(0) {
+        secs if secs < 60 => line.push_str(" just now").map_err(|_| core::fmt::Error),
+        secs if secs < 3600 => write!(line, " {}m ago", secs / 60),
+        secs if secs < 86400 => write!(line, " {}h ago", secs / 3600),
+        secs => write!(line, " {}d ago", secs / 86400),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sntp::Measurement;
+    use time::{Date, Month, PrimitiveDateTime, Time};
+
+    const NOW: i64 = 1_760_709_900;
+
+    fn synced_at() -> PrimitiveDateTime {
+        PrimitiveDateTime::new(
+            Date::from_calendar_date(2025, Month::October, 17).unwrap(),
+            Time::from_hms(14, 5, 0).unwrap(),
+        )
+    }
+
+    fn synced(
+        ntp: Result<Measurement, SntpError<Failure>>,
+        weather: Result<(), HttpError>,
+    ) -> Synced<HttpError, Failure> {
+        Synced {
+            time: match ntp {
+                Ok(_) => Ok(synced_at()),
+                Err(_) => Err(FetchError::Http(HttpError::Dns)),
+            },
+            time_micros: 0,
+            utc_offset: None,
+            ntp,
+            weather: weather
+                .map(|_| crate::weather::CurrentWeather {
+                    temperature: 21.5,
+                    weathercode: 3,
+                })
+                .map_err(FetchError::Http),
+            widgets: [(
+                Region::TopRight,
+                Err(FetchError::Http(HttpError::Status(404))),
+            )]
+            .into_iter()
+            .collect(),
+            connectivity: Some(Connectivity::Online),
+        }
+    }
+
+    fn measurement() -> Measurement {
+        Measurement {
+            time: synced_at(),
+            offset_micros: None,
+            delay_micros: 0,
+        }
+    }
+
+    #[test]
+    fn records_each_endpoint() {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.record_sync(&synced(Ok(measurement()), Ok(())), Some(NOW - 7200));
+        diagnostics.record_sync(
+            &synced(Err(SntpError::Udp(Failure::Timeout)), Err(HttpError::Dns)),
+            Some(NOW - 300),
+        );
+
+        assert_eq!(diagnostics.ntp.result, Some(Err(Failure::Timeout)));
+        assert_eq!(diagnostics.ntp.ok_at, Some(NOW - 7200));
+        assert_eq!(diagnostics.time_api.result, Some(Err(Failure::Dns)));
+        assert_eq!(diagnostics.weather.result, Some(Err(Failure::Dns)));
+        assert_eq!(
+            diagnostics.widgets[Region::TopRight as usize].result,
+            Some(Err(Failure::Status(404)))
+        );
+        assert_eq!(diagnostics.widgets[Region::TopLeft as usize].result, None);
+    }
+
+    #[test]
+    fn time_api_is_left_alone_when_ntp_answers() {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.record_sync(&synced(Ok(measurement()), Ok(())), Some(NOW));
+
+        assert_eq!(diagnostics.ntp.result, Some(Ok(())));
+        assert_eq!(diagnostics.time_api, Endpoint::default());
+    }
+
+    #[test]
+    fn lines() {
+        let mut diagnostics = Diagnostics {
+            join: Some(Join {
+                ssid: String::try_from("Conference").unwrap(),
+                status: JoinStatus::Joined,
+                rssi: Some(-67),
+                at: Some(NOW - 300),
+            }),
+            address_ms: Some(2340),
+            ..Diagnostics::new()
+        };
+        diagnostics.record_sync(&synced(Ok(measurement()), Ok(())), Some(NOW - 7200));
+        diagnostics.record_sync(
+            &synced(Err(SntpError::KissOfDeath), Err(HttpError::Dns)),
+            Some(NOW - 300),
+        );
+
+        let lines = diagnostics.lines(Some(NOW));
+        assert_eq!(
+            lines,
+            [
+                "WiFi: Conference -67dBm joined 5m ago",
+                "Address: after 2.3s, online",
+                "NTP: refused 5m ago, ok 2h ago",
+                "Time API: DNS 5m ago",
+                "Weather: DNS 5m ago, ok 2h ago",
+                "top-right: HTTP 404 5m ago",
+            ]
+        );
+
+        // Without the time, there are no ages
+        let lines = Diagnostics::new().lines(None);
+        assert_eq!(lines, ["WiFi: not tried yet"]);
+    }
+
+    #[test]
+    fn captive_portal() {
+        let mut diagnostics = Diagnostics {
+            address_ms: Some(900),
+            ..Diagnostics::new()
+        };
+        diagnostics.record_sync(
+            &Synced {
+                time: Err(FetchError::Captive),
+                weather: Err(FetchError::Captive),
+                widgets: Vec::new(),
+                connectivity: Some(Connectivity::Captive),
+                ..synced(Err(SntpError::Udp(Failure::Timeout)), Ok(()))
+            },
+            Some(NOW - 60),
+        );
+
+        assert_eq!(diagnostics.weather.result, Some(Err(Failure::Captive)));
+        assert_eq!(
+            &diagnostics.lines(Some(NOW))[1..],
+            [
+                "Address: after 0.9s, captive portal",
+                "NTP: timed out 1m ago",
+                "Time API: skipped 1m ago",
+                "Weather: skipped 1m ago",
+            ]
+        );
+    }
+
+    #[test]
+    fn longest_record_fits() {
+        let failed = Endpoint {
+            result: Some(Err(Failure::Status(504))),
+            at: Some(i64::MIN),
+            ok_at: Some(i64::MAX),
+        };
+        let diagnostics = Diagnostics {
+            join: Some(Join {
+                ssid: String::try_from("x".repeat(32).as_str()).unwrap(),
+                status: JoinStatus::Failed(u32::MAX),
+                rssi: Some(i16::MIN),
+                at: Some(i64::MIN),
+            }),
+            address_ms: Some(u32::MAX),
+            connectivity: Some(Connectivity::Captive),
+            ntp: failed,
+            time_api: failed,
+            weather: failed,
+            widgets: [failed; MAX_WIDGETS],
+        };
+
+        let mut buf = [0; DIAGNOSTICS_SIZE];
+        let encoded = diagnostics.encode(&mut buf).unwrap();
+        assert_eq!(Diagnostics::decode(encoded), Some(diagnostics.clone()));
+
+        assert_eq!(diagnostics.lines(Some(0)).len(), MAX_LINES);
+        assert!(Diagnostics::decode(&[0xFF; DIAGNOSTICS_SIZE]).is_none());
+    }
+}
@@ -0,0 +1,312 @@
+//! Answers every DNS query for an IPv4 address with the badge's own, so
+//! whatever a phone looks up during setup lands on the setup page. Also
+//! the few queries the badge makes itself when DNS servers are set on the
+//! setup page, see [`crate::ipconfig::IpConfig::dns`].
+
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+pub const PORT: u16 = 53;
+
+const HEADER: usize = 12;
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const TTL_SECS: u32 = 60;
+
+/// Writes the response to `query` into `out`, `None` if it isn't a
+/// standard query or `out` is too small
+pub fn captive_answer(query: &[u8], address: [u8; 4], out: &mut [u8]) -> Option<usize> {
+    if query.len() < HEADER {
+        return None;
+    }
+
+    let flags = u16::from_be_bytes([query[2], query[3]]);
+    let questions = u16::from_be_bytes([query[4], query[5]]);
+
+    // A response, or an opcode other than a standard query
+    if flags & 0xF800 != 0 || questions != 1 {
+        return None;
+    }
+
+    let name_end = name_end(query, HEADER)?;
+    let question_end = name_end + 4;
+    let question = query.get(HEADER..question_end)?;
+
+    let qtype = u16::from_be_bytes([query[name_end], query[name_end + 1]]);
+    let qclass = u16::from_be_bytes([query[name_end + 2], query[name_end + 3]]);
+    let answer = qtype == TYPE_A && qclass == CLASS_IN;
+
+    let len = question_end + if answer { 16 } else { 0 };
+    let out = out.get_mut(..len)?;
+
+    out[..2].copy_from_slice(&query[..2]);
+    // Response, authoritative, recursion desired copied across and available
+    let flags = 0x8480 | (flags & 0x0100);
+    out[2..4].copy_from_slice(&flags.to_be_bytes());
+    out[4..6].copy_from_slice(&1u16.to_be_bytes());
+    out[6..8].copy_from_slice(&(answer as u16).to_be_bytes());
+    out[8..HEADER].fill(0);
+    out[HEADER..question_end].copy_from_slice(question);
+
+    if answer {
+        let record = &mut out[question_end..];
+        // Name is a pointer back to the question
+        record[..2].copy_from_slice(&[0xC0, HEADER as u8]);
+        record[2..4].copy_from_slice(&TYPE_A.to_be_bytes());
+        record[4..6].copy_from_slice(&CLASS_IN.to_be_bytes());
+        record[6..10].copy_from_slice(&TTL_SECS.to_be_bytes());
+        record[10..12].copy_from_slice(&4u16.to_be_bytes());
+        record[12..16].copy_from_slice(&address);
+    }
+
+    Some(len)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueryType {
+    A,
+    Aaaa,
+}
+
+impl QueryType {
+    fn code(self) -> u16 {
+        match self {
+            QueryType::A => TYPE_A,
+            QueryType::Aaaa => TYPE_AAAA,
+        }
+    }
+}
+
+/// Writes a recursive query for `host` into `out`, `None` if `host` isn't
+/// a name or `out` is too small
+pub fn query(id: u16, host: &str, qtype: QueryType, out: &mut [u8]) -> Option<usize> {
+    let host = host.strip_suffix('.').unwrap_or(host);
+    if host.is_empty() || host.len() > 253 {
+        return None;
+    }
+
+    let len = HEADER + host.len() + 2 + 4;
+    let out = out.get_mut(..len)?;
+
+    out[..2].copy_from_slice(&id.to_be_bytes());
+    // Recursion desired
+    out[2..4].copy_from_slice(&0x0100u16.to_be_bytes());
+    out[4..6].copy_from_slice(&1u16.to_be_bytes());
+    out[6..HEADER].fill(0);
+
+    let mut at = HEADER;
+    for label in host.split('.') {
+        if !(1..=63).contains(&label.len()) {
+            return None;
+        }
+        out[at] = label.len() as u8;
+        out[at + 1..at + 1 + label.len()].copy_from_slice(label.as_bytes());
+        at += 1 + label.len();
+    }
+    out[at] = 0;
+
+    out[at + 1..at + 3].copy_from_slice(&qtype.code().to_be_bytes());
+    out[at + 3..at + 5].copy_from_slice(&CLASS_IN.to_be_bytes());
+
+    Some(len)
+}
+
+/// The first address of type `qtype` in the response to query `id`.
+/// Servers put the address after any CNAMEs leading to it.
+pub fn address(response: &[u8], id: u16, qtype: QueryType) -> Option<IpAddr> {
+    if response.len() < HEADER || response[..2] != id.to_be_bytes() {
+        return None;
+    }
+
+    let flags = u16::from_be_bytes([response[2], response[3]]);
+    // Not a response, or an error
+    if flags & 0x8000 == 0 || flags & 0x000F != 0 {
+        return None;
+    }
+
+    let questions = u16::from_be_bytes([response[4], response[5]]);
+    let answers = u16::from_be_bytes([response[6], response[7]]);
+
+    let mut at = HEADER;
+    for _ in 0..questions {
+        at = skip_name(response, at)? + 4;
+    }
+
+    for _ in 0..answers {
+        at = skip_name(response, at)?;
+        let record = response.get(at..at + 10)?;
+        let rtype = u16::from_be_bytes([record[0], record[1]]);
+        let class = u16::from_be_bytes([record[2], record[3]]);
+        let len = u16::from_be_bytes([record[8], record[9]]) as usize;
+        let data = response.get(at + 10..at + 10 + len)?;
+        at += 10 + len;
+
+        if rtype != qtype.code() || class != CLASS_IN {
+            continue;
+        }
+
+        return match qtype {
+            QueryType::A => <[u8; 4]>::try_from(data)
+                .ok()
+                .map(|a| Ipv4Addr::from(a).into()),
+            QueryType::Aaaa => <[u8; 16]>::try_from(data)
+                .ok()
+                .map(|a| Ipv6Addr::from(a).into()),
+        };
+    }
+
+    None
+}
+
+/// Where the name starting at `at` ends, compressed or not
+fn skip_name(packet: &[u8], mut at: usize) -> Option<usize> {
+    loop {
+        let len = *packet.get(at)? as usize;
+
+        match len {
+            0 => return Some(at + 1),
+            1..=63 => at += 1 + len,
+            // A pointer ends the name
+            0xC0..=0xFF => return packet.get(at + 1).map(|_| at + 2),
+            _ => return None,
+        }
+    }
+}
+
+/// Where the uncompressed name starting at `at` ends
+fn name_end(packet: &[u8], mut at: usize) -> Option<usize> {
+    loop {
+        let len = *packet.get(at)? as usize;
+        at += 1;
+
+        match len {
+            0 => return Some(at),
+            1..=63 => at += len,
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BADGE: [u8; 4] = [192, 168, 4, 1];
+
+    fn query(qtype: u16) -> Vec<u8> {
+        let mut q = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        for label in ["captive", "apple", "com"] {
+            q.push(label.len() as u8);
+            q.extend_from_slice(label.as_bytes());
+        }
+        q.push(0);
+        q.extend_from_slice(&qtype.to_be_bytes());
+        q.extend_from_slice(&CLASS_IN.to_be_bytes());
+        q
+    }
+
+    #[test]
+    fn answers_with_the_badge() {
+        let query = query(TYPE_A);
+        let mut out = [0; 512];
+        let len = captive_answer(&query, BADGE, &mut out).unwrap();
+        let response = &out[..len];
+
+        assert_eq!(response[..2], [0x12, 0x34]);
+        assert_eq!(response[2..4], [0x85, 0x80]);
+        assert_eq!(response[4..8], [0, 1, 0, 1]);
+        assert_eq!(response[HEADER..query.len()], query[HEADER..]);
+        assert_eq!(
+            response[query.len()..],
+            [0xC0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 168, 4, 1]
+        );
+    }
+
+    #[test]
+    fn other_types_get_no_answer() {
+        let query = query(28);
+        let mut out = [0; 512];
+        let len = captive_answer(&query, BADGE, &mut out).unwrap();
+
+        assert_eq!(len, query.len());
+        assert_eq!(out[6..8], [0, 0]);
+    }
+
+    /// `query`'s question, answered with a CNAME and then `records`
+    fn response(id: u16, rcode: u8, records: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut out = [0; 512];
+        let len = super::query(id, "api.example.com", QueryType::A, &mut out).unwrap();
+        let mut r = out[..len].to_vec();
+
+        r[2..4].copy_from_slice(&[0x81, 0x80 | rcode]);
+        r[6..8].copy_from_slice(&(records.len() as u16 + 1).to_be_bytes());
+
+        r.extend_from_slice(&[0xC0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 6]);
+        r.extend_from_slice(&[3, b'w', b'e', b'b', 0xC0, 16]);
+
+        for (rtype, data) in records {
+            // Points at the CNAME's target
+            r.extend_from_slice(&[0xC0, 45]);
+            r.extend_from_slice(&rtype.to_be_bytes());
+            r.extend_from_slice(&[0, 1, 0, 0, 0, 60]);
+            r.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            r.extend_from_slice(data);
+        }
+        r
+    }
+
+    #[test]
+    fn builds_queries() {
+        let mut out = [0; 512];
+        let len = super::query(0x1234, "captive.apple.com.", QueryType::A, &mut out).unwrap();
+        assert_eq!(out[..len], query(TYPE_A));
+
+        let len = super::query(7, "example.com", QueryType::Aaaa, &mut out).unwrap();
+        assert_eq!(out[len - 4..len], [0, 28, 0, 1]);
+
+        assert!(super::query(7, "", QueryType::A, &mut out).is_none());
+        assert!(super::query(7, "a..b", QueryType::A, &mut out).is_none());
+        assert!(super::query(7, "example.com", QueryType::A, &mut out[..20]).is_none());
+    }
+
+    #[test]
+    fn reads_addresses() {
+        let v6 = "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets();
+        let r = response(9, 0, &[(TYPE_AAAA, &v6), (TYPE_A, &[93, 184, 216, 34])]);
+
+        assert_eq!(
+            address(&r, 9, QueryType::A),
+            Some(IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34)))
+        );
+        assert_eq!(
+            address(&r, 9, QueryType::Aaaa),
+            Some("2001:db8::1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn rejects_other_responses() {
+        let r = response(9, 0, &[(TYPE_A, &[93, 184, 216, 34])]);
+        assert_eq!(address(&r, 10, QueryType::A), None);
+        assert_eq!(address(&r, 9, QueryType::Aaaa), None);
+        assert_eq!(address(&r[..r.len() - 1], 9, QueryType::A), None);
+
+        // NXDOMAIN
+        let r = response(9, 3, &[]);
+        assert_eq!(address(&r, 9, QueryType::A), None);
+    }
+
+    #[test]
+    fn ignores_what_isnt_a_query() {
+        let mut out = [0; 512];
+
+        let mut response = query(TYPE_A);
+        response[2] |= 0x80;
+        assert!(captive_answer(&response, BADGE, &mut out).is_none());
+
+        let truncated = &query(TYPE_A)[..20];
+        assert!(captive_answer(truncated, BADGE, &mut out).is_none());
+
+        assert!(captive_answer(&query(TYPE_A), BADGE, &mut out[..20]).is_none());
+    }
+}
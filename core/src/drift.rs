@@ -0,0 +1,203 @@
+//! Learns how fast or slow the RTC crystal runs from successive network
+//! syncs, and turns that into a PCF85063 offset register value
+
+use serde::{Deserialize, Serialize};
+use time::PrimitiveDateTime;
+
+/// Correction per offset register step, in normal (every two hours) mode
+pub const PPM_PER_STEP: f32 = 4.34;
+
+/// The RTC only counts whole seconds, so errors are left to reach one
+/// before being measured, unless it's been this long
+pub const MAX_SPAN_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// Older estimates count for this many samples at most
+const MAX_WEIGHT: u8 = 8;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Calibration {
+    /// Unix seconds when the RTC was last set from the network
+    pub anchor: Option<i64>,
+    /// How fast the crystal runs before correction, in parts per million
+    pub ppm: f32,
+    pub samples: u8,
+}
+
+impl Calibration {
+    /// Register steps, negative speeds the clock up
+    pub fn steps(&self) -> i8 {
+        let steps = self.ppm / PPM_PER_STEP;
+        let rounded = (steps + 0.5 * steps.signum()) as i32;
+        rounded.clamp(-64, 63) as i8
+    }
+
+    /// Offset register (0x02) with the mode bit clear
+    pub fn offset_register(&self) -> u8 {
+        self.steps() as u8 & 0x7F
+    }
+
+    fn applied_ppm(&self) -> f32 {
+        self.steps() as f32 * PPM_PER_STEP
+    }
+
+    /// Takes a sync's UTC time and, from SNTP, how far ahead of the RTC
+    /// it was. Returns true if the RTC should be set to `now`.
+    ///
+    /// The RTC was read in whole seconds, so `offset_micros` runs up to a
+    /// second high, half a second on average.
+    pub fn record(&mut self, now: PrimitiveDateTime, offset_micros: Option<i64>) -> bool {
+        let now_secs = now.assume_utc().unix_timestamp();
+
+        let (Some(anchor), Some(offset)) = (self.anchor, offset_micros) else {
+            self.anchor = Some(now_secs);
+            return true;
+        };
+
+        let span = now_secs - anchor;
+        let offset = offset - 500_000;
+
+        if span <= 0 {
+            self.anchor = Some(now_secs);
+            return true;
+        }
+
+        // Leaving a small error alone lets the span grow long enough to measure
+        if offset.abs() < 1_000_000 && span < MAX_SPAN_SECS {
+            return false;
+        }
+
+        // Ahead of the server is positive, on top of what's already corrected
+        let residual = -offset as f32 / span as f32;
+        let crystal = self.applied_ppm() + residual;
+
+        let weight = self.samples.min(MAX_WEIGHT - 1) as f32;
+        self.ppm = (self.ppm * weight + crystal) / (weight + 1.0);
+        self.samples = self.samples.saturating_add(1);
+        self.anchor = Some(now_secs);
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::{Date, Duration, Month, Time};
+
+    fn start() -> PrimitiveDateTime {
+        PrimitiveDateTime::new(
+            Date::from_calendar_date(2025, Month::October, 17).unwrap(),
+            Time::from_hms(12, 0, 0).unwrap(),
+        )
+    }
+
+    /// Syncs every `hours` against an RTC running `ppm` fast, set on a
+    /// second boundary but read in whole seconds at any point in one
+    fn simulate(ppm: f32, hours: i64, syncs: usize) -> Calibration {
+        let mut calibration = Calibration::default();
+        let mut rtc_error = 0.0f64;
+        let mut now = start();
+
+        calibration.record(now, None);
+
+        for i in 0..syncs {
+            now += Duration::hours(hours);
+
+            let corrected = ppm - calibration.applied_ppm();
+            rtc_error += corrected as f64 * 1e-6 * (hours * 3600) as f64;
+
+            let phase = (i * 7919 % 1000) as f64 / 1000.0;
+            let offset = ((phase - (phase + rtc_error).floor()) * 1e6) as i64;
+
+            if calibration.record(now, Some(offset)) {
+                rtc_error = 0.0;
+            }
+        }
+
+        calibration
+    }
+
+    #[test]
+    fn first_sync_only_anchors() {
+        let mut calibration = Calibration::default();
+
+        assert!(calibration.record(start(), None));
+        assert_eq!(
+            calibration.anchor,
+            Some(start().assume_utc().unix_timestamp())
+        );
+        assert_eq!(calibration.samples, 0);
+        assert_eq!(calibration.offset_register(), 0);
+    }
+
+    #[test]
+    fn small_errors_are_left_to_grow() {
+        let mut calibration = Calibration::default();
+        calibration.record(start(), None);
+
+        assert!(!calibration.record(start() + Duration::hours(1), Some(900_000)));
+        assert!(!calibration.record(start() + Duration::hours(2), Some(-400_000)));
+        assert_eq!(calibration.samples, 0);
+
+        // A week without reaching a second still counts, as almost no drift
+        assert!(calibration.record(start() + Duration::days(7), Some(500_000)));
+        assert_eq!(calibration.samples, 1);
+        assert_eq!(calibration.steps(), 0);
+    }
+
+    #[test]
+    fn measures_over_long_spans() {
+        let mut calibration = Calibration::default();
+        calibration.record(start(), None);
+
+        // 2.16s slow over 12 hours is 50ppm slow
+        assert!(calibration.record(start() + Duration::hours(12), Some(2_660_000)));
+        assert_eq!(calibration.samples, 1);
+        assert!((calibration.ppm + 50.0).abs() < 0.01);
+        assert_eq!(calibration.steps(), -12);
+        assert_eq!(calibration.offset_register(), 0x74);
+    }
+
+    #[test]
+    fn converges_with_hourly_syncs() {
+        for ppm in [-30.0, -8.0, 12.5, 40.0] {
+            let calibration = simulate(ppm, 1, 24 * 28);
+
+            assert!(calibration.samples >= 3, "{ppm}: {calibration:?}");
+            assert!(
+                (calibration.applied_ppm() - ppm).abs() <= PPM_PER_STEP,
+                "{ppm}: {calibration:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn register_saturates() {
+        let calibration = Calibration {
+            ppm: 1000.0,
+            ..Calibration::default()
+        };
+        assert_eq!(calibration.steps(), 63);
+        assert_eq!(calibration.offset_register(), 0x3F);
+
+        let calibration = Calibration {
+            ppm: -1000.0,
+            ..Calibration::default()
+        };
+        assert_eq!(calibration.steps(), -64);
+        assert_eq!(calibration.offset_register(), 0x40);
+    }
+
+    #[test]
+    fn clock_going_backwards_re_anchors() {
+        let mut calibration = Calibration::default();
+        calibration.record(start(), None);
+
+        assert!(calibration.record(start() - Duration::hours(1), Some(0)));
+        assert_eq!(
+            calibration.anchor,
+            Some((start() - Duration::hours(1)).assume_utc().unix_timestamp())
+        );
+    }
+}
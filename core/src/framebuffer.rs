@@ -0,0 +1,125 @@
+use core::convert::Infallible;
+
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+
+use crate::layout::{HEIGHT, WIDTH};
+
+const ROW_BYTES: usize = WIDTH.div_ceil(8) as usize;
+const BUFFER_SIZE: usize = ROW_BYTES * HEIGHT as usize;
+
+/// In-memory copy of the e-ink panel, packed one bit per pixel, MSB first,
+/// with a set bit meaning black (`BinaryColor::Off`). That is the same
+/// layout as a binary PBM raster.
+pub struct Framebuffer {
+    buf: [u8; BUFFER_SIZE],
+}
+
+impl Framebuffer {
+    /// A blank (white) panel
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; BUFFER_SIZE],
+        }
+    }
+
+    /// Loads a binary (`P4`) PBM of exactly the panel size
+    pub fn from_pbm(data: &[u8]) -> Option<Self> {
+        let mut fields = [0usize; 2];
+        let mut rest = data.strip_prefix(b"P4")?;
+
+        for field in fields.iter_mut() {
+            rest = skip_pbm_whitespace(rest);
+            let digits = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+            *field = core::str::from_utf8(&rest[..digits]).ok()?.parse().ok()?;
+            rest = &rest[digits..];
+        }
+
+        // Exactly one whitespace byte separates the header from the raster
+        let raster = rest.get(1..)?;
+
+        if fields[0] != WIDTH as usize
+            || fields[1] != HEIGHT as usize
+            || raster.len() != BUFFER_SIZE
+        {
+            return None;
+        }
+
+        let mut fb = Self::new();
+        fb.buf.copy_from_slice(raster);
+        Some(fb)
+    }
+
+    pub fn pixel(&self, point: Point) -> Option<BinaryColor> {
+        let (index, mask) = Self::locate(point)?;
+
+        Some(if self.buf[index] & mask != 0 {
+            BinaryColor::Off
+        } else {
+            BinaryColor::On
+        })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    pub fn row_bytes(&self) -> usize {
+        ROW_BYTES
+    }
+
+    fn locate(point: Point) -> Option<(usize, u8)> {
+        if point.x < 0 || point.y < 0 || point.x >= WIDTH as i32 || point.y >= HEIGHT as i32 {
+            return None;
+        }
+
+        let (x, y) = (point.x as usize, point.y as usize);
+
+        Some((y * ROW_BYTES + x / 8, 0x80 >> (x % 8)))
+    }
+}
+
+fn skip_pbm_whitespace(mut data: &[u8]) -> &[u8] {
+    loop {
+        match data.first() {
+            Some(b'#') => {
+                let end = data.iter().position(|b| *b == b'\n').unwrap_or(data.len());
+                data = &data[end..];
+            }
+            Some(b) if b.is_ascii_whitespace() => data = &data[1..],
+            _ => return data,
+        }
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OriginDimensions for Framebuffer {
+    fn size(&self) -> Size {
+        Size::new(WIDTH, HEIGHT)
+    }
+}
+
+impl DrawTarget for Framebuffer {
+    type Color = BinaryColor;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if let Some((index, mask)) = Self::locate(point) {
+                match color {
+                    BinaryColor::Off => self.buf[index] |= mask,
+                    BinaryColor::On => self.buf[index] &= !mask,
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
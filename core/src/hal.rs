@@ -0,0 +1,128 @@
+//! The hardware the badge logic talks to, so it can run against mocks on the host
+
+use core::net::{IpAddr, SocketAddr};
+
+use time::PrimitiveDateTime;
+
+use crate::http::{BodySink, HttpError, Response};
+
+/// Alarm fields that must all match for the RTC to fire, `None` is a wildcard
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Alarm {
+    pub second: Option<u8>,
+    pub minute: Option<u8>,
+    pub hour: Option<u8>,
+}
+
+#[allow(async_fn_in_trait)]
+pub trait Rtc {
+    type Error;
+
+    async fn datetime(&mut self) -> Result<PrimitiveDateTime, Self::Error>;
+
+    async fn set_datetime(&mut self, now: &PrimitiveDateTime) -> Result<(), Self::Error>;
+
+    /// True if the oscillator stopped since the time was last set,
+    /// meaning the time can't be trusted
+    async fn oscillator_stopped(&mut self) -> Result<bool, Self::Error>;
+
+    /// The one byte of battery backed ram
+    async fn ram_byte(&mut self) -> Result<u8, Self::Error>;
+
+    async fn set_ram_byte(&mut self, value: u8) -> Result<(), Self::Error>;
+
+    /// Trims the crystal, see [`crate::drift::Calibration::offset_register`]
+    async fn set_offset(&mut self, offset: u8) -> Result<(), Self::Error>;
+
+    /// Clears any pending alarm and arms the next one
+    async fn set_alarm(&mut self, alarm: Alarm) -> Result<(), Self::Error>;
+}
+
+/// Somewhere to keep a single record across power cycles
+#[allow(async_fn_in_trait)]
+pub trait Store {
+    type Error;
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<(), Self::Error>;
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// The partition an update is downloaded into, see [`crate::ota`]
+#[allow(async_fn_in_trait)]
+pub trait Dfu {
+    type Error;
+
+    /// In bytes
+    fn capacity(&self) -> u32;
+
+    /// Erases and writes one [`crate::ota::BLOCK_SIZE`] block, `offset` is a
+    /// multiple of it
+    async fn write_block(&mut self, offset: u32, block: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// A link that has to be brought up before anything can be fetched
+#[allow(async_fn_in_trait)]
+pub trait Network {
+    type Error;
+
+    async fn connect(&mut self) -> Result<(), Self::Error>;
+
+    async fn disconnect(&mut self);
+}
+
+/// Takes `&self` so several requests can share a connection at once
+#[allow(async_fn_in_trait)]
+pub trait Fetcher {
+    type Error;
+
+    /// Streams the body into `sink`, `buf` is only room to work in
+    async fn get(
+        &self,
+        url: &str,
+        buf: &mut [u8],
+        sink: &mut impl BodySink,
+    ) -> Result<(), Self::Error>;
+}
+
+/// One GET, without retries or following redirects, see [`crate::http::get`].
+/// The body is only streamed into `sink` for a 2xx.
+#[allow(async_fn_in_trait)]
+pub trait HttpTransport {
+    async fn request(
+        &self,
+        url: &str,
+        buf: &mut [u8],
+        sink: &mut impl BodySink,
+    ) -> Result<Response, HttpError>;
+}
+
+/// Asks the probe URL, see [`crate::captive`]
+#[allow(async_fn_in_trait)]
+pub trait Probe {
+    /// One try with a short timeout, redirects not followed. The body is
+    /// only streamed into `sink` for a 2xx.
+    async fn probe(
+        &self,
+        url: &str,
+        buf: &mut [u8],
+        sink: &mut impl BodySink,
+    ) -> Result<Response, HttpError>;
+}
+
+/// Single datagram request/response, for protocols like SNTP
+#[allow(async_fn_in_trait)]
+pub trait Udp {
+    type Error;
+
+    async fn resolve(&self, host: &str) -> Result<IpAddr, Self::Error>;
+
+    /// Sends `request` and waits a short while for the first reply
+    async fn exchange(
+        &self,
+        to: SocketAddr,
+        request: &[u8],
+        response: &mut [u8],
+    ) -> Result<usize, Self::Error>;
+}
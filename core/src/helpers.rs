@@ -1,5 +1,6 @@
 use core::fmt::Arguments;
 use heapless::String;
+use time::PrimitiveDateTime;
 
 /// Makes it easier to format strings in a single line method
 pub fn easy_format<const N: usize>(args: Arguments<'_>) -> String<N> {
@@ -12,3 +13,16 @@ pub fn easy_format<const N: usize>(args: Arguments<'_>) -> String<N> {
         }
     }
 }
+
+/// `YYYY-MM-DDTHH:MM:SSZ`, for a time already in UTC
+pub fn utc_timestamp(t: PrimitiveDateTime) -> String<20> {
+    easy_format::<20>(format_args!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        t.year(),
+        t.month() as u8,
+        t.day(),
+        t.hour(),
+        t.minute(),
+        t.second()
+    ))
+}
@@ -0,0 +1,248 @@
+//! Home Assistant MQTT discovery, so the badge turns up as a device without
+//! any YAML. Each entity's config is published retained to
+//! `homeassistant/<component>/<node id>/<object id>/config`, and points at
+//! the topics in [`crate::mqtt`]: sensors read `prefix/state`, the
+//! controls publish to the command topics.
+
+use heapless::String;
+use serde::Serialize;
+
+use crate::{control::STATUS_SIZE, image::IMAGES, mqtt::Topic};
+
+pub const DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// Home Assistant publishes `online` here when it starts, and everything
+/// has to be announced again
+pub const STATUS_TOPIC: &str = "homeassistant/status";
+
+/// Big enough for any entity's config
+pub const CONFIG_SIZE: usize = 512;
+
+/// Select options, one per image
+const IMAGE_OPTIONS: [&str; 8] = ["0", "1", "2", "3", "4", "5", "6", "7"];
+const _: () = assert!(IMAGES.len() <= IMAGE_OPTIONS.len());
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Entity {
+    pub component: &'static str,
+    pub object_id: &'static str,
+    pub name: &'static str,
+    /// Template over `prefix/state`
+    value_template: Option<&'static str>,
+    /// Under `prefix`, see [`crate::mqtt::command`]
+    command: Option<&'static str>,
+    unit: Option<&'static str>,
+    device_class: Option<&'static str>,
+    state_class: Option<&'static str>,
+}
+
+const SENSOR: Entity = Entity {
+    component: "sensor",
+    object_id: "",
+    name: "",
+    value_template: None,
+    command: None,
+    unit: None,
+    device_class: None,
+    state_class: None,
+};
+
+pub const ENTITIES: [Entity; 6] = [
+    Entity {
+        object_id: "temperature",
+        name: "Temperature",
+        value_template: Some("{{value_json.temperature}}"),
+        unit: Some("°C"),
+        device_class: Some("temperature"),
+        state_class: Some("measurement"),
+        ..SENSOR
+    },
+    Entity {
+        object_id: "battery",
+        name: "Battery",
+        value_template: Some("{{value_json.battery_mv}}"),
+        unit: Some("mV"),
+        device_class: Some("voltage"),
+        state_class: Some("measurement"),
+        ..SENSOR
+    },
+    Entity {
+        object_id: "wakes",
+        name: "Wake count",
+        value_template: Some("{{value_json.wakes}}"),
+        state_class: Some("total_increasing"),
+        ..SENSOR
+    },
+    Entity {
+        component: "select",
+        object_id: "image",
+        name: "Image",
+        value_template: Some("{{value_json.image}}"),
+        command: Some("image/set"),
+        ..SENSOR
+    },
+    Entity {
+        component: "button",
+        object_id: "redraw",
+        name: "Refresh display",
+        command: Some("redraw"),
+        ..SENSOR
+    },
+    Entity {
+        component: "text",
+        object_id: "message",
+        name: "Message",
+        value_template: Some("{{value_json.status}}"),
+        command: Some("message"),
+        ..SENSOR
+    },
+];
+
+#[derive(Serialize)]
+struct Device<'a> {
+    ids: [&'a str; 1],
+    name: &'a str,
+    mf: &'a str,
+    mdl: &'a str,
+}
+
+/// In Home Assistant's abbreviations, `~` standing for `prefix`
+#[derive(Serialize)]
+struct Config<'a> {
+    name: &'a str,
+    uniq_id: &'a str,
+    #[serde(rename = "~")]
+    base: &'a str,
+    avty_t: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stat_t: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    val_tpl: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cmd_t: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit_of_meas: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dev_cla: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stat_cla: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ops: Option<&'a [&'a str]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max: Option<usize>,
+    dev: Device<'a>,
+}
+
+impl Entity {
+    /// Where its config goes, `node_id` being the MQTT client id
+    pub fn config_topic(&self, node_id: &str) -> Topic {
+        let mut topic = Topic::new();
+        for part in [
+            DISCOVERY_PREFIX,
+            "/",
+            self.component,
+            "/",
+            node_id,
+            "/",
+            self.object_id,
+            "/config",
+        ] {
+            topic.push_str(part).ok();
+        }
+        topic
+    }
+
+    pub fn config(&self, prefix: &str, node_id: &str) -> String<CONFIG_SIZE> {
+        let mut unique_id = String::<64>::new();
+        for part in [node_id, "_", self.object_id] {
+            unique_id.push_str(part).ok();
+        }
+
+        let mut command = String::<32>::new();
+        if let Some(leaf) = self.command {
+            command.push_str("~/").ok();
+            command.push_str(leaf).ok();
+        }
+
+        let config = Config {
+            name: self.name,
+            uniq_id: &unique_id,
+            base: prefix,
+            avty_t: "~/online",
+            stat_t: self.value_template.map(|_| "~/state"),
+            val_tpl: self.value_template,
+            cmd_t: self.command.map(|_| command.as_str()),
+            unit_of_meas: self.unit,
+            dev_cla: self.device_class,
+            stat_cla: self.state_class,
+            ops: (self.component == "select").then_some(&IMAGE_OPTIONS[..IMAGES.len()]),
+            max: (self.component == "text").then_some(STATUS_SIZE),
+            dev: Device {
+                ids: [node_id],
+                name: node_id,
+                mf: "Pimoroni",
+                mdl: "Badger 2040 W",
+            },
+        };
+
+        serde_json_core::to_string(&config).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mqtt::PACKET_SIZE;
+
+    #[test]
+    fn sensor_config() {
+        let temperature = &ENTITIES[0];
+
+        assert_eq!(
+            temperature.config_topic("badger"),
+            "homeassistant/sensor/badger/temperature/config"
+        );
+        assert_eq!(
+            temperature.config("badger", "badger"),
+            concat!(
+                r#"{"name":"Temperature","uniq_id":"badger_temperature","~":"badger","#,
+                r#""avty_t":"~/online","stat_t":"~/state","val_tpl":"{{value_json.temperature}}","#,
+                r#""unit_of_meas":"°C","dev_cla":"temperature","stat_cla":"measurement","#,
+                r#""dev":{"ids":["badger"],"name":"badger","mf":"Pimoroni","mdl":"Badger 2040 W"}}"#
+            )
+        );
+    }
+
+    #[test]
+    fn control_configs() {
+        let select = ENTITIES.iter().find(|e| e.component == "select").unwrap();
+        let config = select.config("badger", "badger");
+        assert!(config.contains(r#""cmd_t":"~/image/set""#));
+        assert!(config.contains(r#""ops":["0","1","2"]"#));
+
+        let button = ENTITIES.iter().find(|e| e.component == "button").unwrap();
+        let config = button.config("badger", "badger");
+        assert!(config.contains(r#""cmd_t":"~/redraw""#));
+        assert!(!config.contains("stat_t"));
+
+        let text = ENTITIES.iter().find(|e| e.component == "text").unwrap();
+        let config = text.config("badger", "badger");
+        assert!(config.contains(r#""cmd_t":"~/message","#));
+        assert!(config.contains(r#""max":64"#));
+    }
+
+    #[test]
+    fn every_config_fits_a_packet() {
+        let node_id = "badger-office-2040w";
+        let prefix = "office/badges/badger-office-2040w";
+
+        for entity in &ENTITIES {
+            let topic = entity.config_topic(node_id);
+            let config = entity.config(prefix, node_id);
+
+            assert!(topic.ends_with("/config"));
+            assert!(config.ends_with("}}"));
+            assert!(topic.len() + config.len() + 7 <= PACKET_SIZE);
+        }
+    }
+}
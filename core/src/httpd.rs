@@ -0,0 +1,354 @@
+//! Just enough of an HTTP/1.1 server for a form or a small JSON API, one
+//! request per connection
+
+use embedded_io_async::{Read, Write};
+use heapless::String;
+
+use crate::helpers::easy_format;
+
+pub const HTML: &str = "text/html; charset=utf-8";
+pub const JSON: &str = "application/json";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HttpdError {
+    /// The connection failed, or closed before the request was complete
+    Io,
+    /// Headers and body don't fit the buffer
+    TooLarge,
+    Malformed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Method {
+    Get,
+    Post,
+    Other,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Request<'a> {
+    pub method: Method,
+    pub path: &'a str,
+    /// After the `?`, empty if there wasn't one
+    pub query: &'a str,
+    pub body: &'a [u8],
+}
+
+/// Reads one request, headers and body, into `buf`
+pub async fn read_request<'b, C: Read>(
+    conn: &mut C,
+    buf: &'b mut [u8],
+) -> Result<Request<'b>, HttpdError> {
+    let mut len = 0;
+
+    let head_len = loop {
+        if let Some(end) = find(&buf[..len], b"\r\n\r\n") {
+            break end + 4;
+        }
+        if len == buf.len() {
+            return Err(HttpdError::TooLarge);
+        }
+
+        match conn.read(&mut buf[len..]).await {
+            Ok(0) | Err(_) => return Err(HttpdError::Io),
+            Ok(n) => len += n,
+        }
+    };
+
+    let content_length = content_length(&buf[..head_len])?;
+    if content_length > buf.len() - head_len {
+        return Err(HttpdError::TooLarge);
+    }
+    let total = head_len + content_length;
+
+    while len < total {
+        match conn.read(&mut buf[len..total]).await {
+            Ok(0) | Err(_) => return Err(HttpdError::Io),
+            Ok(n) => len += n,
+        }
+    }
+
+    let (head, rest) = buf.split_at(head_len);
+    let head = core::str::from_utf8(head).map_err(|_| HttpdError::Malformed)?;
+
+    let line = head.lines().next().unwrap_or_default();
+    let mut parts = line.split(' ');
+    let method = match parts.next() {
+        Some("GET") => Method::Get,
+        Some("POST") => Method::Post,
+        Some(_) => Method::Other,
+        None => return Err(HttpdError::Malformed),
+    };
+    let target = parts.next().ok_or(HttpdError::Malformed)?;
+    if !parts.next().is_some_and(|v| v.starts_with("HTTP/1.")) {
+        return Err(HttpdError::Malformed);
+    }
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    Ok(Request {
+        method,
+        path,
+        query,
+        body: &rest[..content_length],
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn content_length(head: &[u8]) -> Result<usize, HttpdError> {
+    let head = core::str::from_utf8(head).map_err(|_| HttpdError::Malformed)?;
+
+    for line in head.lines().skip(1) {
+        if let Some((name, value)) = line.split_once(':')
+            && name.trim().eq_ignore_ascii_case("content-length")
+        {
+            return value.trim().parse().map_err(|_| HttpdError::Malformed);
+        }
+    }
+
+    Ok(0)
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        302 => "Found",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        _ => "Error",
+    }
+}
+
+pub async fn respond<C: Write>(
+    conn: &mut C,
+    status: u16,
+    content_type: &str,
+    body: &[u8],
+) -> Result<(), HttpdError> {
+    let head: String<160> = easy_format::<160>(format_args!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason(status),
+        content_type,
+        body.len()
+    ));
+
+    conn.write_all(head.as_bytes())
+        .await
+        .map_err(|_| HttpdError::Io)?;
+    conn.write_all(body).await.map_err(|_| HttpdError::Io)?;
+    conn.flush().await.map_err(|_| HttpdError::Io)
+}
+
+pub async fn redirect<C: Write>(conn: &mut C, location: &str) -> Result<(), HttpdError> {
+    let head: String<192> = easy_format::<192>(format_args!(
+        "HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        location
+    ));
+
+    conn.write_all(head.as_bytes())
+        .await
+        .map_err(|_| HttpdError::Io)?;
+    conn.flush().await.map_err(|_| HttpdError::Io)
+}
+
+/// One field of an `application/x-www-form-urlencoded` body or query,
+/// `None` if it's missing, not UTF-8 or longer than `N`
+pub fn form_field<const N: usize>(form: &[u8], name: &str) -> Option<String<N>> {
+    for pair in form.split(|&b| b == b'&') {
+        let (key, value) = match pair.iter().position(|&b| b == b'=') {
+            Some(i) => (&pair[..i], &pair[i + 1..]),
+            None => (pair, &[][..]),
+        };
+
+        if key == name.as_bytes() {
+            return url_decode(value);
+        }
+    }
+
+    None
+}
+
+fn url_decode<const N: usize>(encoded: &[u8]) -> Option<String<N>> {
+    let mut bytes = heapless::Vec::<u8, N>::new();
+    let mut i = 0;
+
+    while i < encoded.len() {
+        let byte = match encoded[i] {
+            b'+' => b' ',
+            b'%' => {
+                let hex = encoded.get(i + 1..i + 3)?;
+                if !hex.iter().all(u8::is_ascii_hexdigit) {
+                    return None;
+                }
+                i += 2;
+                u8::from_str_radix(core::str::from_utf8(hex).ok()?, 16).ok()?
+            }
+            b => b,
+        };
+
+        bytes.push(byte).ok()?;
+        i += 1;
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+/// Appends `text` with the characters HTML cares about escaped
+pub(crate) fn push_escaped<const N: usize>(out: &mut String<N>, text: &str) -> Result<(), ()> {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;")?,
+            '<' => out.push_str("&lt;")?,
+            '>' => out.push_str("&gt;")?,
+            '"' => out.push_str("&quot;")?,
+            '\'' => out.push_str("&#39;")?,
+            c => out.push(c)?,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_futures::block_on;
+
+    /// Hands out what it was given a few bytes at a time
+    struct Trickle<'a> {
+        input: &'a [u8],
+        output: std::vec::Vec<u8>,
+    }
+
+    impl embedded_io_async::ErrorType for Trickle<'_> {
+        type Error = embedded_io_async::ErrorKind;
+    }
+
+    impl Read for Trickle<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let n = buf.len().min(self.input.len()).min(7);
+            buf[..n].copy_from_slice(&self.input[..n]);
+            self.input = &self.input[n..];
+            Ok(n)
+        }
+    }
+
+    impl Write for Trickle<'_> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    fn trickle(input: &[u8]) -> Trickle<'_> {
+        Trickle {
+            input,
+            output: std::vec::Vec::new(),
+        }
+    }
+
+    #[test]
+    fn reads_a_post_in_pieces() {
+        let mut conn = trickle(
+            b"POST /save?from=form HTTP/1.1\r\nHost: 192.168.4.1\r\ncontent-length: 9\r\n\r\nssid=Home",
+        );
+        let mut buf = [0; 256];
+
+        let request = block_on(read_request(&mut conn, &mut buf)).unwrap();
+        assert_eq!(
+            request,
+            Request {
+                method: Method::Post,
+                path: "/save",
+                query: "from=form",
+                body: b"ssid=Home",
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_bad_requests() {
+        let mut buf = [0; 64];
+
+        let mut conn =
+            trickle(b"GET / HTTP/1.1\r\nHost: a-much-longer-host-name.badger.example.com\r\n\r\n");
+        assert_eq!(
+            block_on(read_request(&mut conn, &mut buf)),
+            Err(HttpdError::TooLarge)
+        );
+
+        let mut conn = trickle(b"GET / HTTP/1.1\r\nHost: x");
+        assert_eq!(
+            block_on(read_request(&mut conn, &mut buf)),
+            Err(HttpdError::Io)
+        );
+
+        let mut conn = trickle(b"POST / HTTP/1.1\r\nContent-Length: 200\r\n\r\n");
+        assert_eq!(
+            block_on(read_request(&mut conn, &mut buf)),
+            Err(HttpdError::TooLarge)
+        );
+
+        // Would wrap around `head_len + content_length`
+        let huge = std::format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", usize::MAX);
+        let mut conn = trickle(huge.as_bytes());
+        assert_eq!(
+            block_on(read_request(&mut conn, &mut buf)),
+            Err(HttpdError::TooLarge)
+        );
+
+        let mut conn = trickle(b"hello\r\n\r\n");
+        assert_eq!(
+            block_on(read_request(&mut conn, &mut buf)),
+            Err(HttpdError::Malformed)
+        );
+    }
+
+    #[test]
+    fn writes_responses() {
+        let mut conn = trickle(b"");
+        block_on(respond(&mut conn, 200, JSON, b"{}")).unwrap();
+        assert_eq!(
+            conn.output,
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}"
+        );
+
+        let mut conn = trickle(b"");
+        block_on(redirect(&mut conn, "http://192.168.4.1/")).unwrap();
+        assert!(
+            conn.output
+                .starts_with(b"HTTP/1.1 302 Found\r\nLocation: http://192.168.4.1/\r\n")
+        );
+    }
+
+    #[test]
+    fn decodes_form_fields() {
+        let form = b"ssid=Caf%C3%A9+Wifi&password=p%26ss%3Dword&empty=&flag";
+
+        assert_eq!(form_field::<32>(form, "ssid").unwrap(), "Café Wifi");
+        assert_eq!(form_field::<32>(form, "password").unwrap(), "p&ss=word");
+        assert_eq!(form_field::<32>(form, "empty").unwrap(), "");
+        assert_eq!(form_field::<32>(form, "flag").unwrap(), "");
+        assert!(form_field::<32>(form, "missing").is_none());
+        assert!(form_field::<4>(form, "ssid").is_none());
+        assert!(form_field::<32>(b"bad=%zz", "bad").is_none());
+        assert!(form_field::<32>(b"bad=%+1", "bad").is_none());
+        assert!(form_field::<32>(b"bad=%ff", "bad").is_none());
+    }
+
+    #[test]
+    fn escapes_html() {
+        let mut out = String::<64>::new();
+        push_escaped(&mut out, r#"<b>"Tom's" & co</b>"#).unwrap();
+        assert_eq!(out, "&lt;b&gt;&quot;Tom&#39;s&quot; &amp; co&lt;/b&gt;");
+    }
+}
@@ -0,0 +1,57 @@
+pub static IMAGES: [&[u8]; 3] = [
+    include_bytes!("../../images/julian.bmp"),
+    include_bytes!("../../images/tropical.bmp"),
+    include_bytes!("../../images/2026.bmp"),
+];
+
+pub const POSITION: (i32, i32) = (0, 24);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shift {
+    None,
+    Next,
+    Prev,
+}
+
+pub fn next(index: usize) -> usize {
+    (index + 1) % IMAGES.len()
+}
+
+pub fn prev(index: usize) -> usize {
+    (if index == 0 { IMAGES.len() } else { index }) - 1
+}
+
+pub fn shift(index: usize, dir: Shift) -> usize {
+    match dir {
+        Shift::Next => next(index),
+        Shift::Prev => prev(index),
+        Shift::None => index,
+    }
+}
+
+/// Keeps indices read back from RTC ram or flash in range
+pub fn clamp(index: usize) -> usize {
+    index.clamp(0, IMAGES.len() - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycles_through_all_images() {
+        let last = IMAGES.len() - 1;
+
+        assert_eq!(shift(0, Shift::Next), 1);
+        assert_eq!(shift(last, Shift::Next), 0);
+        assert_eq!(shift(0, Shift::Prev), last);
+        assert_eq!(shift(1, Shift::Prev), 0);
+        assert_eq!(shift(1, Shift::None), 1);
+    }
+
+    #[test]
+    fn clamps_out_of_range_index() {
+        assert_eq!(clamp(0), 0);
+        assert_eq!(clamp(0xFF), IMAGES.len() - 1);
+    }
+}
@@ -0,0 +1,305 @@
+//! How the badge gets its addresses, saved with the rest of the
+//! [`crate::settings::Settings`]. Left alone it's DHCP, without IPv6.
+
+use core::fmt::Write;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use core::str::FromStr;
+
+use heapless::{String, Vec};
+use serde::{Deserialize, Serialize};
+
+/// As many as embassy-net holds
+pub const MAX_DNS: usize = 3;
+
+/// Long enough for any of the fields on the setup page
+pub const FIELD_SIZE: usize = 128;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum Ipv4Mode {
+    #[default]
+    Dhcp,
+    Static {
+        address: Ipv4Addr,
+        prefix_len: u8,
+        gateway: Option<Ipv4Addr>,
+    },
+    /// For IPv6 only networks
+    Off,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum Ipv6Mode {
+    #[default]
+    Off,
+    /// From the prefix the router advertises, see [`crate::slaac`]
+    Slaac,
+    Static {
+        address: Ipv6Addr,
+        prefix_len: u8,
+        gateway: Option<Ipv6Addr>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct IpConfig {
+    pub ipv4: Ipv4Mode,
+    pub ipv6: Ipv6Mode,
+    /// Used instead of the servers DHCP or the router hand out, if any
+    pub dns: Vec<IpAddr, MAX_DNS>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum IpConfigError {
+    Ipv4,
+    Ipv6,
+    /// A gateway without a static address to go with it
+    Gateway,
+    Dns,
+}
+
+impl IpConfig {
+    pub const fn new() -> Self {
+        Self {
+            ipv4: Ipv4Mode::Dhcp,
+            ipv6: Ipv6Mode::Off,
+            dns: Vec::new(),
+        }
+    }
+
+    /// From the setup page's fields. `ipv4` is blank for DHCP, `off` or an
+    /// `address/prefix`, `ipv6` is blank, `slaac` or an `address/prefix`
+    /// and `dns` is a comma separated list
+    pub fn parse(
+        ipv4: &str,
+        gateway: &str,
+        ipv6: &str,
+        gateway6: &str,
+        dns: &str,
+    ) -> Result<Self, IpConfigError> {
+        let gateway = optional::<Ipv4Addr>(gateway).ok_or(IpConfigError::Gateway)?;
+        let gateway6 = optional::<Ipv6Addr>(gateway6).ok_or(IpConfigError::Gateway)?;
+
+        let ipv4 = match ipv4.trim() {
+            "" | "dhcp" => Ipv4Mode::Dhcp,
+            "off" => Ipv4Mode::Off,
+            cidr => {
+                let (address, prefix_len) = parse_cidr(cidr, 32).ok_or(IpConfigError::Ipv4)?;
+                Ipv4Mode::Static {
+                    address,
+                    prefix_len,
+                    gateway,
+                }
+            }
+        };
+
+        let ipv6 = match ipv6.trim() {
+            "" | "off" => Ipv6Mode::Off,
+            "slaac" => Ipv6Mode::Slaac,
+            cidr => {
+                let (address, prefix_len) = parse_cidr(cidr, 128).ok_or(IpConfigError::Ipv6)?;
+                Ipv6Mode::Static {
+                    address,
+                    prefix_len,
+                    gateway: gateway6,
+                }
+            }
+        };
+
+        let static_v4 = matches!(ipv4, Ipv4Mode::Static { .. });
+        let static_v6 = matches!(ipv6, Ipv6Mode::Static { .. });
+        if (gateway.is_some() && !static_v4) || (gateway6.is_some() && !static_v6) {
+            return Err(IpConfigError::Gateway);
+        }
+
+        let mut servers = Vec::new();
+        for server in dns.split([',', ' ']).filter(|s| !s.is_empty()) {
+            let server = server.parse().map_err(|_| IpConfigError::Dns)?;
+            servers.push(server).map_err(|_| IpConfigError::Dns)?;
+        }
+
+        Ok(Self {
+            ipv4,
+            ipv6,
+            dns: servers,
+        })
+    }
+
+    /// The setup page's fields, the other way round from [`Self::parse`]:
+    /// `ipv4`, `gateway`, `ipv6`, `gateway6` and `dns`
+    pub fn fields(&self) -> [String<FIELD_SIZE>; 5] {
+        let mut fields = [const { String::new() }; 5];
+
+        match self.ipv4 {
+            Ipv4Mode::Dhcp => {}
+            Ipv4Mode::Static {
+                address,
+                prefix_len,
+                gateway,
+            } => {
+                let _ = write!(fields[0], "{}/{}", address, prefix_len);
+                if let Some(gateway) = gateway {
+                    let _ = write!(fields[1], "{}", gateway);
+                }
+            }
+            Ipv4Mode::Off => {
+                fields[0].push_str("off").ok();
+            }
+        }
+
+        match self.ipv6 {
+            Ipv6Mode::Off => {}
+            Ipv6Mode::Slaac => {
+                fields[2].push_str("slaac").ok();
+            }
+            Ipv6Mode::Static {
+                address,
+                prefix_len,
+                gateway,
+            } => {
+                let _ = write!(fields[2], "{}/{}", address, prefix_len);
+                if let Some(gateway) = gateway {
+                    let _ = write!(fields[3], "{}", gateway);
+                }
+            }
+        }
+
+        for (i, server) in self.dns.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            let _ = write!(fields[4], "{}{}", separator, server);
+        }
+
+        fields
+    }
+
+    pub fn dns_v4(&self) -> impl Iterator<Item = Ipv4Addr> + '_ {
+        self.dns.iter().filter_map(|server| match server {
+            IpAddr::V4(server) => Some(*server),
+            IpAddr::V6(_) => None,
+        })
+    }
+
+    pub fn dns_v6(&self) -> impl Iterator<Item = Ipv6Addr> + '_ {
+        self.dns.iter().filter_map(|server| match server {
+            IpAddr::V4(_) => None,
+            IpAddr::V6(server) => Some(*server),
+        })
+    }
+}
+
+/// `None` if it's there but isn't an address
+fn optional<A: FromStr>(text: &str) -> Option<Option<A>> {
+    match text.trim() {
+        "" => Some(None),
+        text => text.parse().ok().map(Some),
+    }
+}
+
+/// `address/prefix`
+fn parse_cidr<A: FromStr>(text: &str, max_prefix: u8) -> Option<(A, u8)> {
+    let (address, prefix_len) = text.split_once('/')?;
+    let prefix_len: u8 = prefix_len.parse().ok()?;
+
+    (prefix_len <= max_prefix).then_some((address.parse().ok()?, prefix_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_to_dhcp() {
+        assert_eq!(IpConfig::parse("", "", "", "", ""), Ok(IpConfig::new()));
+        assert_eq!(IpConfig::new(), IpConfig::default());
+    }
+
+    #[test]
+    fn static_addresses() {
+        let config = IpConfig::parse(
+            "192.168.10.50/24",
+            "192.168.10.1",
+            "2001:db8::50/64",
+            "fe80::1",
+            "192.168.10.2, 2001:db8::53",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.ipv4,
+            Ipv4Mode::Static {
+                address: Ipv4Addr::new(192, 168, 10, 50),
+                prefix_len: 24,
+                gateway: Some(Ipv4Addr::new(192, 168, 10, 1)),
+            }
+        );
+        assert_eq!(
+            config.ipv6,
+            Ipv6Mode::Static {
+                address: "2001:db8::50".parse().unwrap(),
+                prefix_len: 64,
+                gateway: Some("fe80::1".parse().unwrap()),
+            }
+        );
+        assert_eq!(
+            config.dns_v4().collect::<std::vec::Vec<_>>(),
+            [Ipv4Addr::new(192, 168, 10, 2)]
+        );
+        assert_eq!(config.dns_v6().count(), 1);
+    }
+
+    #[test]
+    fn dhcp_with_other_dns_and_ipv6_only() {
+        let config = IpConfig::parse("", "", "slaac", "", "1.1.1.1,9.9.9.9").unwrap();
+        assert_eq!(config.ipv4, Ipv4Mode::Dhcp);
+        assert_eq!(config.ipv6, Ipv6Mode::Slaac);
+        assert_eq!(config.dns.len(), 2);
+
+        let config = IpConfig::parse("off", "", "slaac", "", "").unwrap();
+        assert_eq!(config.ipv4, Ipv4Mode::Off);
+    }
+
+    #[test]
+    fn rejects_bad_fields() {
+        for (fields, error) in [
+            (["192.168.1.5", "", "", "", ""], IpConfigError::Ipv4),
+            (["192.168.1.5/33", "", "", "", ""], IpConfigError::Ipv4),
+            (["", "", "2001:db8::5/129", "", ""], IpConfigError::Ipv6),
+            (["", "192.168.1.1", "", "", ""], IpConfigError::Gateway),
+            (
+                ["10.0.0.2/8", "10.0.0.300", "", "", ""],
+                IpConfigError::Gateway,
+            ),
+            (["", "", "slaac", "fe80::1", ""], IpConfigError::Gateway),
+            (["", "", "", "", "dns.google"], IpConfigError::Dns),
+            (
+                ["", "", "", "", "1.1.1.1,1.0.0.1,8.8.8.8,8.8.4.4"],
+                IpConfigError::Dns,
+            ),
+        ] {
+            let [ipv4, gateway, ipv6, gateway6, dns] = fields;
+            assert_eq!(
+                IpConfig::parse(ipv4, gateway, ipv6, gateway6, dns),
+                Err(error)
+            );
+        }
+    }
+
+    #[test]
+    fn fields_round_trip() {
+        for fields in [
+            [
+                "10.0.0.2/8",
+                "10.0.0.1",
+                "2001:db8::2/64",
+                "fe80::1",
+                "10.0.0.1,2001:db8::1",
+            ],
+            ["off", "", "slaac", "", ""],
+            ["", "", "", "", "1.1.1.1"],
+        ] {
+            let [ipv4, gateway, ipv6, gateway6, dns] = fields;
+            let config = IpConfig::parse(ipv4, gateway, ipv6, gateway6, dns).unwrap();
+            assert_eq!(config.fields(), fields);
+        }
+    }
+}
@@ -0,0 +1,558 @@
+//! Pulls a few scalar fields out of a JSON document as it streams past,
+//! so a response never has to fit in memory whole.
+//!
+//! Paths look like `$.current.temperature` or `$.daily.time[0]`. Keys are
+//! plain, without quoting, and only strings, numbers and booleans are
+//! captured, not whole objects or arrays.
+
+use heapless::{String, Vec};
+
+use crate::http::BodySink;
+
+/// Deeper documents are an error rather than silently mismatched
+pub const MAX_DEPTH: usize = 8;
+/// Longer keys are skipped over, and so never match
+pub const KEY_SIZE: usize = 32;
+/// Longer values are dropped, the field reads as missing
+pub const VALUE_SIZE: usize = 48;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum JsonError {
+    Syntax,
+    TooDeep,
+    /// The body ended part way through
+    Incomplete,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PathError;
+
+/// Checks a path the way [`Extract`] reads it, for paths from config
+pub fn check_path(path: &str) -> Result<(), PathError> {
+    let mut depth = 0;
+    for segment in segments(path)? {
+        segment?;
+        depth += 1;
+    }
+
+    match depth {
+        0 => Err(PathError),
+        d if d > MAX_DEPTH => Err(PathError),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Segment<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+/// The segments after the `$`, each `Err` if it can't be read
+fn segments(path: &str) -> Result<impl Iterator<Item = Result<Segment<'_>, PathError>>, PathError> {
+    let mut rest = path.strip_prefix('$').ok_or(PathError)?;
+
+    Ok(core::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+
+        let segment = if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            let key = &after[..end];
+            rest = &after[end..];
+
+            match key.is_empty() || key.len() > KEY_SIZE {
+                true => Err(PathError),
+                false => Ok(Segment::Key(key)),
+            }
+        } else if let Some(after) = rest.strip_prefix('[') {
+            match after.split_once(']') {
+                Some((index, after)) => {
+                    rest = after;
+                    index.parse().map(Segment::Index).map_err(|_| PathError)
+                }
+                None => Err(PathError),
+            }
+        } else {
+            rest = "";
+            Err(PathError)
+        };
+
+        // Stop at the first bad segment
+        if segment.is_err() {
+            rest = "";
+        }
+        Some(segment)
+    }))
+}
+
+#[derive(Debug)]
+enum Container {
+    Object {
+        key: Vec<u8, KEY_SIZE>,
+        /// The key was too long to keep
+        long_key: bool,
+    },
+    Array {
+        index: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    /// Before a value, `first` right after a `[` where `]` may come instead
+    Value {
+        first: bool,
+    },
+    /// After a value, waiting for a `,` or the end of the container
+    After,
+    /// Before a key, `first` right after a `{` where `}` may come instead
+    Key {
+        first: bool,
+    },
+    Colon,
+    String {
+        key: bool,
+        escape: Escape,
+    },
+    Literal,
+    /// The top level value is complete, anything after is ignored
+    Done,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Escape {
+    None,
+    Backslash,
+    /// Hex digits of a `\u` escape still to come, and the code so far
+    Unicode(u8, u16),
+}
+
+pub struct Extract<'p, const N: usize> {
+    paths: [&'p str; N],
+    values: [Option<String<VALUE_SIZE>>; N],
+    stack: Vec<Container, MAX_DEPTH>,
+    state: State,
+    /// Which path the value being read is for
+    capture: Option<usize>,
+    token: Vec<u8, VALUE_SIZE>,
+    long_token: bool,
+    error: Option<JsonError>,
+}
+
+impl<'p, const N: usize> Extract<'p, N> {
+    /// A path that doesn't pass [`check_path`] never matches
+    pub fn new(paths: [&'p str; N]) -> Self {
+        Self {
+            paths,
+            values: [const { None }; N],
+            stack: Vec::new(),
+            state: State::Value { first: false },
+            capture: None,
+            token: Vec::new(),
+            long_token: false,
+            error: None,
+        }
+    }
+
+    /// The value found for `paths[i]`, strings unescaped and numbers and
+    /// booleans as they were written
+    pub fn get(&self, i: usize) -> Option<&str> {
+        self.values.get(i)?.as_deref()
+    }
+
+    /// Whether the whole document was read without a problem
+    pub fn finish(&self) -> Result<(), JsonError> {
+        match (self.error, self.state) {
+            (Some(e), _) => Err(e),
+            (None, State::Done) => Ok(()),
+            // A bare number at the top level only ends with the body
+            (None, State::Literal) if self.stack.is_empty() => Ok(()),
+            _ => Err(JsonError::Incomplete),
+        }
+    }
+
+    fn fail(&mut self, e: JsonError) {
+        self.error = Some(e);
+        self.values = [const { None }; N];
+    }
+
+    fn matching_path(&self) -> Option<usize> {
+        self.paths.iter().position(|path| {
+            let Ok(segments) = segments(path) else {
+                return false;
+            };
+
+            let mut depth = 0;
+            for segment in segments {
+                let matched = match (segment, self.stack.get(depth)) {
+                    (Ok(Segment::Key(k)), Some(Container::Object { key, long_key })) => {
+                        !long_key && key.as_slice() == k.as_bytes()
+                    }
+                    (Ok(Segment::Index(i)), Some(Container::Array { index })) => i == *index,
+                    _ => false,
+                };
+
+                if !matched {
+                    return false;
+                }
+                depth += 1;
+            }
+
+            depth == self.stack.len() && depth > 0
+        })
+    }
+
+    fn start_token(&mut self) {
+        self.capture = self.matching_path();
+        self.token.clear();
+        self.long_token = false;
+    }
+
+    fn push_token(&mut self, byte: u8) {
+        if self.capture.is_some() && self.token.push(byte).is_err() {
+            self.long_token = true;
+        }
+    }
+
+    fn end_token(&mut self) {
+        let Some(i) = self.capture.take() else {
+            return;
+        };
+
+        if !self.long_token
+            && let Ok(text) = core::str::from_utf8(&self.token)
+            && text != "null"
+        {
+            self.values[i] = String::try_from(text).ok();
+        }
+    }
+
+    /// A value finished, what comes next depends on where it was
+    fn after_value(&mut self) {
+        self.state = match self.stack.is_empty() {
+            true => State::Done,
+            false => State::After,
+        };
+    }
+
+    fn open(&mut self, container: Container) {
+        let array = matches!(container, Container::Array { .. });
+
+        if self.stack.push(container).is_err() {
+            self.fail(JsonError::TooDeep);
+            return;
+        }
+
+        self.state = match array {
+            true => State::Value { first: true },
+            false => State::Key { first: true },
+        };
+    }
+
+    fn close(&mut self, array: bool) {
+        match self.stack.pop() {
+            Some(Container::Array { .. }) if array => self.after_value(),
+            Some(Container::Object { .. }) if !array => self.after_value(),
+            _ => self.fail(JsonError::Syntax),
+        }
+    }
+
+    fn byte(&mut self, b: u8) {
+        match self.state {
+            State::Done => {}
+            State::Value { first } => match b {
+                b' ' | b'\t' | b'\r' | b'\n' => {}
+                b'{' => self.open(Container::Object {
+                    key: Vec::new(),
+                    long_key: false,
+                }),
+                b'[' => self.open(Container::Array { index: 0 }),
+                b']' if first => self.close(true),
+                b'"' => {
+                    self.start_token();
+                    self.state = State::String {
+                        key: false,
+                        escape: Escape::None,
+                    };
+                }
+                b'-' | b'0'..=b'9' | b't' | b'f' | b'n' => {
+                    self.start_token();
+                    self.push_token(b);
+                    self.state = State::Literal;
+                }
+                _ => self.fail(JsonError::Syntax),
+            },
+            State::Literal => match b {
+                b'-' | b'+' | b'.' | b'0'..=b'9' | b'a'..=b'z' | b'E' => self.push_token(b),
+                _ => {
+                    self.end_token();
+                    self.after_value();
+                    self.byte(b);
+                }
+            },
+            State::After => match b {
+                b' ' | b'\t' | b'\r' | b'\n' => {}
+                b',' => match self.stack.last_mut() {
+                    Some(Container::Array { index }) => {
+                        *index += 1;
+                        self.state = State::Value { first: false };
+                    }
+                    _ => self.state = State::Key { first: false },
+                },
+                b']' => self.close(true),
+                b'}' => self.close(false),
+                _ => self.fail(JsonError::Syntax),
+            },
+            State::Key { first } => match b {
+                b' ' | b'\t' | b'\r' | b'\n' => {}
+                b'"' => {
+                    if let Some(Container::Object { key, long_key }) = self.stack.last_mut() {
+                        key.clear();
+                        *long_key = false;
+                    }
+                    self.state = State::String {
+                        key: true,
+                        escape: Escape::None,
+                    };
+                }
+                b'}' if first => self.close(false),
+                _ => self.fail(JsonError::Syntax),
+            },
+            State::Colon => match b {
+                b' ' | b'\t' | b'\r' | b'\n' => {}
+                b':' => self.state = State::Value { first: false },
+                _ => self.fail(JsonError::Syntax),
+            },
+            State::String { key, escape } => self.string_byte(key, escape, b),
+        }
+    }
+
+    /// Unescaped bytes of a key or a value
+    fn push_string(&mut self, key: bool, bytes: &[u8]) {
+        if !key {
+            bytes.iter().for_each(|&b| self.push_token(b));
+        } else if let Some(Container::Object { key, long_key }) = self.stack.last_mut()
+            && key.extend_from_slice(bytes).is_err()
+        {
+            *long_key = true;
+        }
+    }
+
+    fn string_byte(&mut self, key: bool, escape: Escape, b: u8) {
+        let mut utf8 = [0; 4];
+        let mut push = |this: &mut Self, c: char| {
+            this.push_string(key, c.encode_utf8(&mut utf8).as_bytes());
+        };
+
+        let escape = match (escape, b) {
+            (Escape::None, b'"') => {
+                if key {
+                    self.state = State::Colon;
+                } else {
+                    self.end_token();
+                    self.after_value();
+                }
+                return;
+            }
+            (Escape::None, b'\\') => Escape::Backslash,
+            (Escape::None, 0x00..=0x1F) => return self.fail(JsonError::Syntax),
+            (Escape::None, b) => {
+                // Raw UTF-8 passes straight through
+                self.push_string(key, &[b]);
+                Escape::None
+            }
+            (Escape::Backslash, b'u') => Escape::Unicode(4, 0),
+            (Escape::Backslash, b) => {
+                let c = match b {
+                    b'"' => '"',
+                    b'\\' => '\\',
+                    b'/' => '/',
+                    b'b' => '\u{8}',
+                    b'f' => '\u{c}',
+                    b'n' => '\n',
+                    b'r' => '\r',
+                    b't' => '\t',
+                    _ => return self.fail(JsonError::Syntax),
+                };
+                push(self, c);
+                Escape::None
+            }
+            (Escape::Unicode(left, code), b) => {
+                let Some(digit) = (b as char).to_digit(16) else {
+                    return self.fail(JsonError::Syntax);
+                };
+                let code = code << 4 | digit as u16;
+
+                match left {
+                    1 => {
+                        // Surrogate pairs are rare enough in API values to
+                        // not be worth putting back together
+                        push(self, char::from_u32(code as u32).unwrap_or('\u{FFFD}'));
+                        Escape::None
+                    }
+                    _ => Escape::Unicode(left - 1, code),
+                }
+            }
+        };
+
+        if self.error.is_none() {
+            self.state = State::String { key, escape };
+        }
+    }
+}
+
+impl<const N: usize> BodySink for Extract<'_, N> {
+    fn reset(&mut self) {
+        *self = Self::new(self.paths);
+    }
+
+    fn write(&mut self, chunk: &[u8]) {
+        for &b in chunk {
+            if self.error.is_some() {
+                return;
+            }
+            self.byte(b);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORECAST: &str = r#"{
+        "latitude": 20.66, "longitude": -87.04,
+        "current_units": {"temperature": "°C"},
+        "current": {"time": "2025-10-17T14:00", "temperature": 29.4, "weathercode": 2, "is_day": true},
+        "daily": {
+            "time": ["2025-10-17", "2025-10-18"],
+            "temperature_2m_max": [31.2, 30.8],
+            "summary": [{"text": "Sunny \"mostly\" · warm"}, {"text": null}]
+        }
+    }"#;
+
+    /// Feeds `body` in `size` byte chunks
+    fn extract<'p, const N: usize>(paths: [&'p str; N], body: &str, size: usize) -> Extract<'p, N> {
+        let mut fields = Extract::new(paths);
+        for chunk in body.as_bytes().chunks(size) {
+            fields.write(chunk);
+        }
+        fields
+    }
+
+    #[test]
+    fn finds_fields_however_the_body_is_split() {
+        let paths = [
+            "$.current.temperature",
+            "$.current.weathercode",
+            "$.current.is_day",
+            "$.daily.time[1]",
+            "$.daily.temperature_2m_max[0]",
+            "$.daily.summary[0].text",
+            "$.current_units.temperature",
+        ];
+
+        for size in [1, 2, 7, 64, FORECAST.len()] {
+            let fields = extract(paths, FORECAST, size);
+
+            assert_eq!(fields.finish(), Ok(()));
+            assert_eq!(fields.get(0), Some("29.4"));
+            assert_eq!(fields.get(1), Some("2"));
+            assert_eq!(fields.get(2), Some("true"));
+            assert_eq!(fields.get(3), Some("2025-10-18"));
+            assert_eq!(fields.get(4), Some("31.2"));
+            assert_eq!(fields.get(5), Some("Sunny \"mostly\" \u{b7} warm"));
+            assert_eq!(fields.get(6), Some("°C"));
+        }
+    }
+
+    #[test]
+    fn missing_null_and_container_fields_are_none() {
+        let fields = extract(
+            [
+                "$.current.wind",
+                "$.daily.summary[1].text",
+                "$.current",
+                "$.daily.time[5]",
+                "$.latitude.deeper",
+            ],
+            FORECAST,
+            16,
+        );
+
+        assert_eq!(fields.finish(), Ok(()));
+        for i in 0..5 {
+            assert_eq!(fields.get(i), None, "{i}");
+        }
+    }
+
+    #[test]
+    fn long_values_are_dropped() {
+        let body = format!(
+            r#"{{"short":"ok","long":"{}"}}"#,
+            "x".repeat(VALUE_SIZE + 1)
+        );
+        let fields = extract(["$.short", "$.long"], &body, 5);
+
+        assert_eq!(fields.get(0), Some("ok"));
+        assert_eq!(fields.get(1), None);
+    }
+
+    #[test]
+    fn ignores_trailing_bytes() {
+        let fields = extract(["$.a"], "{\"a\":1}\r\n\r\n0\r\n", 3);
+
+        assert_eq!(fields.finish(), Ok(()));
+        assert_eq!(fields.get(0), Some("1"));
+    }
+
+    #[test]
+    fn rejects_what_isnt_json() {
+        for (body, error) in [
+            ("<html>Login</html>", JsonError::Syntax),
+            (r#"{"a":1,}"#, JsonError::Syntax),
+            (r#"{"a" 1}"#, JsonError::Syntax),
+            (r#"{"a":[1,2}"#, JsonError::Syntax),
+            (r#"{"a":"\q"}"#, JsonError::Syntax),
+            (r#"{"a":1"#, JsonError::Incomplete),
+            ("[[[[[[[[[1]]]]]]]]]", JsonError::TooDeep),
+        ] {
+            let fields = extract(["$.a"], body, 4);
+            assert_eq!(fields.finish(), Err(error), "{body}");
+            assert_eq!(fields.get(0), None, "{body}");
+        }
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let mut fields = extract(["$.a"], r#"{"a":"#, 8);
+        fields.reset();
+        fields.write(br#"{"a":2}"#);
+
+        assert_eq!(fields.finish(), Ok(()));
+        assert_eq!(fields.get(0), Some("2"));
+    }
+
+    #[test]
+    fn checks_paths() {
+        for good in ["$.a", "$.current.value", "$.daily.time[0]", "$[2].name"] {
+            assert_eq!(check_path(good), Ok(()), "{good}");
+        }
+
+        for bad in [
+            "",
+            "$",
+            "current.value",
+            "$..a",
+            "$.a[x]",
+            "$.a[1",
+            "$.a b[0]x",
+        ] {
+            assert_eq!(check_path(bad), Err(PathError), "{bad}");
+        }
+    }
+}
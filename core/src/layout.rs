@@ -0,0 +1,384 @@
+use embedded_graphics::{
+    image::Image,
+    mono_font::{MonoTextStyle, ascii::*},
+    pixelcolor::BinaryColor,
+    prelude::*,
+    primitives::{PrimitiveStyle, PrimitiveStyleBuilder, Rectangle},
+    text::{Alignment, Baseline, Text, renderer::TextRenderer},
+};
+use heapless::String;
+use time::PrimitiveDateTime;
+use tinybmp::Bmp;
+
+use crate::{
+    clock::ClockFormat,
+    diagnostics::Line,
+    helpers::easy_format,
+    image, portal,
+    weather::{CurrentWeather, weather_description},
+    widgets::{MAX_WIDGETS, Region},
+};
+
+pub const WIDTH: u32 = 296;
+pub const HEIGHT: u32 = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Screen {
+    None,
+    TopBar,
+    Image,
+    Full,
+    /// Instructions for the setup access point, see [`crate::portal`]
+    Setup,
+    /// What the last syncs ran into, see [`crate::diagnostics`]
+    Diagnostics,
+    Shutdown,
+}
+
+/// Everything a redraw needs, captured before drawing starts
+#[derive(Clone, Copy)]
+pub struct Frame<'a> {
+    pub weather: Option<CurrentWeather>,
+    /// Local time
+    pub time: Option<PrimitiveDateTime>,
+    pub clock: ClockFormat,
+    /// Unique access points seen, shown before the clock
+    pub bssids: Option<u32>,
+    pub image: &'a [u8],
+    /// Set over the control API, shown along the bottom of the image
+    pub status: Option<&'a str>,
+    /// By region, see [`crate::widgets::Shown::texts`]
+    pub widgets: [Option<&'a str>; MAX_WIDGETS],
+    /// Only filled in for [`Screen::Diagnostics`]
+    pub diagnostics: &'a [Line],
+    /// The last sync found a login page rather than the internet, so the
+    /// weather is old. Shown in its place.
+    pub captive: bool,
+}
+
+/// The top bar's weather, or what's shown in its place
+fn weather_text(frame: &Frame) -> Option<String<64>> {
+    if frame.captive {
+        return Some(easy_format::<64>(format_args!("Captive portal")));
+    }
+
+    let data = frame.weather?;
+    Some(easy_format::<64>(format_args!(
+        "{}C | {}",
+        data.temperature,
+        weather_description(data.weathercode)
+    )))
+}
+
+/// Just past the weather, where the clock may start at the earliest
+fn weather_end(frame: &Frame) -> i32 {
+    let character_style = MonoTextStyle::new(&FONT_9X18_BOLD, BinaryColor::Off);
+
+    match weather_text(frame) {
+        Some(text) => {
+            let bounds =
+                Text::new(text.as_str(), Point::new(8, 16), character_style).bounding_box();
+            bounds.top_left.x + bounds.size.width as i32 + 9
+        }
+        None => 8,
+    }
+}
+
+/// Each draw function returns the area it touched, for partial updates
+pub fn draw_weather<D>(display: &mut D, frame: &Frame) -> Option<Rectangle>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let character_style = MonoTextStyle::new(&FONT_9X18_BOLD, BinaryColor::Off);
+
+    let top_text = weather_text(frame)?;
+    let text = Text::new(top_text.as_str(), Point::new(8, 16), character_style);
+    text.draw(display).ok();
+
+    Some(text.bounding_box())
+}
+
+/// The clock, right aligned up to the weather but never over it. Without
+/// room the date is left out, and the access point count goes in what's
+/// left between the two, if anything.
+pub fn draw_time<D>(display: &mut D, frame: &Frame) -> Option<Rectangle>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let character_style = MonoTextStyle::new(&FONT_9X18_BOLD, BinaryColor::Off);
+    let count_style = MonoTextStyle::new(&FONT_6X10, BinaryColor::Off);
+
+    let when = frame.time?;
+    let start = weather_end(frame);
+    let end = WIDTH as i32 - 8;
+
+    let width = |style: &MonoTextStyle<BinaryColor>, text: &str| {
+        style
+            .measure_string(text, Point::zero(), Baseline::Alphabetic)
+            .bounding_box
+            .size
+            .width as i32
+    };
+
+    let mut clock = frame.clock.format(when);
+    if width(&character_style, &clock) > end - start {
+        let clock_format = ClockFormat {
+            date: false,
+            ..frame.clock
+        };
+        clock = clock_format.format(when);
+    }
+    let x = end
+        .saturating_sub(width(&character_style, &clock))
+        .max(start);
+
+    let count = frame
+        .bssids
+        .map(|count| easy_format::<16>(format_args!("{} APs", count)));
+    let count_x = count
+        .as_ref()
+        .map(|count| x - 3 - width(&count_style, count))
+        .filter(|&count_x| count_x >= start);
+
+    // Clear from just before the text to inside the top bar's border,
+    // the text can run right up to the edge of the panel
+    let left = count_x.unwrap_or(x) - 6;
+    let bounds = Rectangle::new(
+        Point::new(left, 0),
+        Size::new(WIDTH.saturating_sub(left as u32), 24),
+    );
+    bounds
+        .offset(-1)
+        .into_styled(
+            PrimitiveStyleBuilder::default()
+                .stroke_color(BinaryColor::On)
+                .fill_color(BinaryColor::On)
+                .build(),
+        )
+        .draw(display)
+        .ok();
+
+    if let (Some(count), Some(count_x)) = (&count, count_x) {
+        Text::new(count.as_str(), Point::new(count_x, 16), count_style)
+            .draw(display)
+            .ok();
+    }
+
+    Text::new(clock.as_str(), Point::new(x, 16), character_style)
+        .draw(display)
+        .ok();
+
+    Some(bounds)
+}
+
+pub fn draw_top_bar<D>(display: &mut D, frame: &Frame) -> Rectangle
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let top_bounds = Rectangle::new(Point::new(0, 0), Size::new(WIDTH, 24));
+
+    top_bounds
+        .into_styled(
+            PrimitiveStyleBuilder::default()
+                .stroke_color(BinaryColor::Off)
+                .fill_color(BinaryColor::On)
+                .stroke_width(1)
+                .build(),
+        )
+        .draw(display)
+        .ok();
+
+    draw_weather(display, frame);
+    draw_time(display, frame);
+
+    top_bounds
+}
+
+pub fn draw_current_image<D>(display: &mut D, frame: &Frame) -> Rectangle
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    // clear image location by writing a white rectangle over previous image location
+    let clear_rectangle = Rectangle::new(Point::new(0, 24), Size::new(WIDTH, HEIGHT - 24));
+    clear_rectangle
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+        .draw(display)
+        .ok();
+
+    if let Ok(bmp) = Bmp::<BinaryColor>::from_slice(frame.image) {
+        Image::new(&bmp, image::POSITION.into()).draw(display).ok();
+    }
+
+    for (region, text) in Region::ALL.into_iter().zip(frame.widgets) {
+        if let Some(text) = text {
+            draw_widget(display, region, text);
+        }
+    }
+
+    // Over the bottom widgets, it's the more pressing of the two
+    if let Some(status) = frame.status.filter(|s| !s.is_empty()) {
+        draw_status(display, status);
+    }
+
+    clear_rectangle
+}
+
+/// Half the width of the image, in one of its corners
+pub fn widget_bounds(region: Region) -> Rectangle {
+    let x = match region {
+        Region::TopLeft | Region::BottomLeft => 0,
+        Region::TopRight | Region::BottomRight => WIDTH as i32 / 2,
+    };
+    let y = match region {
+        Region::TopLeft | Region::TopRight => 24,
+        Region::BottomLeft | Region::BottomRight => HEIGHT as i32 - 18,
+    };
+
+    Rectangle::new(Point::new(x, y), Size::new(WIDTH / 2, 18))
+}
+
+/// White on black like the status, the text cut off at the edge
+pub fn draw_widget<D>(display: &mut D, region: Region, text: &str) -> Rectangle
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let bounds = widget_bounds(region);
+    bounds
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+        .draw(display)
+        .ok();
+
+    let character_style = MonoTextStyle::new(&FONT_7X13, BinaryColor::On);
+    let width = character_style
+        .measure_string(text, Point::zero(), Baseline::Alphabetic)
+        .bounding_box
+        .size
+        .width;
+
+    // Right aligned on the right, unless that would cut off the start
+    let (x, alignment) = match region {
+        Region::TopRight | Region::BottomRight if width + 12 <= bounds.size.width => (
+            bounds.top_left.x + bounds.size.width as i32 - 6,
+            Alignment::Right,
+        ),
+        _ => (bounds.top_left.x + 6, Alignment::Left),
+    };
+
+    Text::with_alignment(
+        text,
+        Point::new(x, bounds.top_left.y + 13),
+        character_style,
+        alignment,
+    )
+    .draw(&mut display.clipped(&bounds))
+    .ok();
+
+    bounds
+}
+
+/// White on black along the bottom edge, over whatever the image has there
+pub fn draw_status<D>(display: &mut D, status: &str) -> Rectangle
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let bounds = Rectangle::new(Point::new(0, HEIGHT as i32 - 18), Size::new(WIDTH, 18));
+    bounds
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+        .draw(display)
+        .ok();
+
+    let character_style = MonoTextStyle::new(&FONT_7X13, BinaryColor::On);
+    Text::new(status, Point::new(6, HEIGHT as i32 - 5), character_style)
+        .draw(display)
+        .ok();
+
+    bounds
+}
+
+pub fn draw_badge<D>(display: &mut D, frame: &Frame) -> Rectangle
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    draw_top_bar(display, frame);
+    draw_current_image(display, frame);
+
+    Rectangle::new(Point::zero(), Size::new(WIDTH, HEIGHT))
+}
+
+pub fn draw_setup<D>(display: &mut D) -> Rectangle
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let bounds = Rectangle::new(Point::zero(), Size::new(WIDTH, HEIGHT));
+    bounds
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+        .draw(display)
+        .ok();
+
+    let title = MonoTextStyle::new(&FONT_9X18_BOLD, BinaryColor::Off);
+    let body = MonoTextStyle::new(&FONT_7X13, BinaryColor::Off);
+
+    Text::new("Setup", Point::new(8, 20), title)
+        .draw(display)
+        .ok();
+
+    let join: String<64> = easy_format::<64>(format_args!("1. Join the wifi {}", portal::AP_SSID));
+    let open: String<64> = easy_format::<64>(format_args!("2. Open {}", portal::AP_URL));
+
+    for (line, text) in [
+        join.as_str(),
+        open.as_str(),
+        "3. Save, and the badge restarts",
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        Text::new(text, Point::new(8, 52 + 22 * line as i32), body)
+            .draw(display)
+            .ok();
+    }
+
+    bounds
+}
+
+pub fn draw_diagnostics<D>(display: &mut D, lines: &[Line]) -> Rectangle
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let bounds = Rectangle::new(Point::zero(), Size::new(WIDTH, HEIGHT));
+    bounds
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+        .draw(display)
+        .ok();
+
+    let title = MonoTextStyle::new(&FONT_9X18_BOLD, BinaryColor::Off);
+    let body = MonoTextStyle::new(&FONT_6X10, BinaryColor::Off);
+
+    Text::new("Diagnostics", Point::new(8, 16), title)
+        .draw(display)
+        .ok();
+
+    for (line, text) in lines.iter().enumerate() {
+        Text::new(text, Point::new(8, 34 + 11 * line as i32), body)
+            .draw(display)
+            .ok();
+    }
+
+    bounds
+}
+
+/// Draws one screen, returning the area that needs refreshing
+pub fn draw_screen<D>(display: &mut D, screen: Screen, frame: &Frame) -> Option<Rectangle>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    match screen {
+        Screen::Full => Some(draw_badge(display, frame)),
+        Screen::TopBar => Some(draw_top_bar(display, frame)),
+        Screen::Image => Some(draw_current_image(display, frame)),
+        Screen::Setup => Some(draw_setup(display)),
+        Screen::Diagnostics => Some(draw_diagnostics(display, frame.diagnostics)),
+        Screen::None | Screen::Shutdown => None,
+    }
+}
@@ -0,0 +1,38 @@
+#![cfg_attr(not(test), no_std)]
+
+pub mod api;
+pub mod bssids;
+pub mod captive;
+pub mod clock;
+pub mod control;
+pub mod dhcp;
+pub mod diagnostics;
+pub mod dns;
+pub mod drift;
+pub mod framebuffer;
+pub mod hal;
+pub mod helpers;
+pub mod homeassistant;
+pub mod http;
+pub mod httpd;
+pub mod image;
+pub mod ipconfig;
+pub mod json;
+pub mod layout;
+pub mod mdns;
+pub mod mqtt;
+pub mod networks;
+pub mod ota;
+pub mod portal;
+pub mod rfc3339;
+pub mod schedule;
+pub mod settings;
+pub mod slaac;
+pub mod sntp;
+pub mod storage;
+pub mod sync;
+pub mod tls;
+pub mod tz;
+pub mod wake;
+pub mod weather;
+pub mod widgets;
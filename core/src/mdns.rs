@@ -0,0 +1,530 @@
+//! Multicast DNS (RFC 6762), so the badge can be found as `<host>.local`,
+//! with its JSON API advertised as an `_http._tcp` service (RFC 6763)
+
+use heapless::String;
+
+pub const PORT: u16 = 5353;
+pub const GROUP: [u8; 4] = [224, 0, 0, 251];
+
+/// Its MAC address, for the radio's multicast filter
+pub const GROUP_MAC: [u8; 6] = [0x01, 0x00, 0x5E, 0x00, 0x00, 0xFB];
+
+const HEADER: usize = 12;
+const MAX_NAME: usize = 255;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// Set in a question to ask for a unicast reply, and in a record to say
+/// it replaces whatever was cached for the name
+const TOP_BIT: u16 = 0x8000;
+
+/// RFC 6762's suggestions, names tied to the address go stale quicker
+const HOST_TTL: u32 = 120;
+const OTHER_TTL: u32 = 4500;
+
+const SERVICE: &str = "_http._tcp.local";
+const SERVICES: &str = "_services._dns-sd._udp.local";
+
+/// `host` without `.local`, if it's a valid label
+pub fn hostname(host: &str) -> Option<&str> {
+    let host = host.strip_suffix(".local").unwrap_or(host);
+    let valid = (1..=63).contains(&host.len())
+        && host.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        && !host.starts_with('-')
+        && !host.ends_with('-');
+
+    valid.then_some(host)
+}
+
+/// Which records a reply carries
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct Records {
+    a: bool,
+    ptr: bool,
+    srv: bool,
+    txt: bool,
+    services: bool,
+}
+
+impl Records {
+    const ALL: Self = Self {
+        a: true,
+        ptr: true,
+        srv: true,
+        txt: true,
+        services: false,
+    };
+
+    fn any(&self) -> bool {
+        self.a || self.ptr || self.srv || self.txt || self.services
+    }
+
+    fn count(&self) -> u16 {
+        [self.a, self.ptr, self.srv, self.txt, self.services]
+            .iter()
+            .filter(|&&r| r)
+            .count() as u16
+    }
+
+    /// What a client asking for these will want next
+    fn additional(&self) -> Self {
+        Self {
+            a: (self.ptr || self.srv) && !self.a,
+            srv: self.ptr && !self.srv,
+            txt: self.ptr && !self.txt,
+            ..Self::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reply {
+    pub len: usize,
+    /// To the asker rather than the group
+    pub unicast: bool,
+}
+
+/// Answers for `host.local`, and `host._http._tcp.local` on `port`
+pub struct Responder<'a> {
+    host: &'a str,
+    port: u16,
+    /// `key=value` pairs, each its own TXT string
+    txt: &'a [&'a str],
+}
+
+impl<'a> Responder<'a> {
+    pub fn new(host: &'a str, port: u16, txt: &'a [&'a str]) -> Self {
+        Self { host, port, txt }
+    }
+
+    /// The reply to `query`, `None` if it asks nothing of ours. `legacy` is
+    /// for a query that wasn't sent from port 5353, by a plain resolver,
+    /// which gets a unicast DNS reply instead
+    pub fn answer(
+        &self,
+        query: &[u8],
+        address: [u8; 4],
+        legacy: bool,
+        out: &mut [u8],
+    ) -> Option<Reply> {
+        if query.len() < HEADER {
+            return None;
+        }
+
+        let flags = u16::from_be_bytes([query[2], query[3]]);
+        let questions = u16::from_be_bytes([query[4], query[5]]);
+
+        // Responses, and anything but a standard query
+        if flags & 0xF800 != 0 {
+            return None;
+        }
+
+        let mut wanted = Records::default();
+        let mut unicast = legacy;
+        let mut at = HEADER;
+
+        for _ in 0..questions {
+            let (name, next) = read_name(query, at)?;
+            let qtype = u16::from_be_bytes([*query.get(next)?, *query.get(next + 1)?]);
+            let qclass = u16::from_be_bytes([*query.get(next + 2)?, *query.get(next + 3)?]);
+            at = next + 4;
+
+            if qclass & !TOP_BIT != CLASS_IN {
+                continue;
+            }
+
+            let before = wanted;
+            let asks = |rtype| qtype == rtype || qtype == TYPE_ANY;
+
+            if self.is_host(&name) {
+                wanted.a |= asks(TYPE_A);
+            } else if name.eq_ignore_ascii_case(SERVICE) {
+                wanted.ptr |= asks(TYPE_PTR);
+            } else if self.is_instance(&name) {
+                wanted.srv |= asks(TYPE_SRV);
+                wanted.txt |= asks(TYPE_TXT);
+            } else if name.eq_ignore_ascii_case(SERVICES) {
+                wanted.services |= asks(TYPE_PTR);
+            }
+
+            if wanted != before && qclass & TOP_BIT != 0 {
+                unicast = true;
+            }
+        }
+
+        if !wanted.any() {
+            return None;
+        }
+
+        let mut writer = Writer { out, len: 0 };
+        let id = if legacy { [query[0], query[1]] } else { [0, 0] };
+        writer.bytes(&id)?;
+        writer.u16(0x8400)?;
+
+        // A legacy reply repeats the questions, pointers into them included
+        let echoed = if legacy { questions } else { 0 };
+        let additional = wanted.additional();
+        for count in [echoed, wanted.count(), 0, additional.count()] {
+            writer.u16(count)?;
+        }
+        if legacy {
+            writer.bytes(&query[HEADER..at])?;
+        }
+
+        self.records(&mut writer, wanted, address, !legacy)?;
+        self.records(&mut writer, additional, address, !legacy)?;
+
+        Some(Reply {
+            len: writer.len,
+            unicast,
+        })
+    }
+
+    /// Everything at once, sent to the group when the address is new
+    pub fn announce(&self, address: [u8; 4], out: &mut [u8]) -> Option<usize> {
+        let mut writer = Writer { out, len: 0 };
+        writer.bytes(&[0, 0])?;
+        writer.u16(0x8400)?;
+        for count in [0, Records::ALL.count(), 0, 0] {
+            writer.u16(count)?;
+        }
+
+        self.records(&mut writer, Records::ALL, address, true)?;
+        Some(writer.len)
+    }
+
+    fn is_host(&self, name: &str) -> bool {
+        self.is_under(name, "local")
+    }
+
+    fn is_instance(&self, name: &str) -> bool {
+        self.is_under(name, SERVICE)
+    }
+
+    /// Whether `name` is our host's label, then `domain`
+    fn is_under(&self, name: &str, domain: &str) -> bool {
+        name.split_once('.').is_some_and(|(label, rest)| {
+            label.eq_ignore_ascii_case(self.host) && rest.eq_ignore_ascii_case(domain)
+        })
+    }
+
+    fn records(
+        &self,
+        writer: &mut Writer,
+        records: Records,
+        address: [u8; 4],
+        cache_flush: bool,
+    ) -> Option<()> {
+        let unique = CLASS_IN | if cache_flush { TOP_BIT } else { 0 };
+
+        if records.services {
+            writer.record(&[SERVICES], TYPE_PTR, CLASS_IN, OTHER_TTL, |w| {
+                w.name(&[SERVICE])
+            })?;
+        }
+        if records.ptr {
+            writer.record(&[SERVICE], TYPE_PTR, CLASS_IN, OTHER_TTL, |w| {
+                w.name(&[self.host, SERVICE])
+            })?;
+        }
+        if records.srv {
+            writer.record(&[self.host, SERVICE], TYPE_SRV, unique, HOST_TTL, |w| {
+                // Priority and weight
+                w.u16(0)?;
+                w.u16(0)?;
+                w.u16(self.port)?;
+                w.name(&[self.host, "local"])
+            })?;
+        }
+        if records.txt {
+            writer.record(&[self.host, SERVICE], TYPE_TXT, unique, OTHER_TTL, |w| {
+                for pair in self.txt {
+                    w.bytes(&[pair.len() as u8])?;
+                    w.bytes(pair.as_bytes())?;
+                }
+                // An empty TXT record still needs one string
+                if self.txt.is_empty() {
+                    w.bytes(&[0])?;
+                }
+                Some(())
+            })?;
+        }
+        if records.a {
+            writer.record(&[self.host, "local"], TYPE_A, unique, HOST_TTL, |w| {
+                w.bytes(&address)
+            })?;
+        }
+
+        Some(())
+    }
+}
+
+struct Writer<'o> {
+    out: &'o mut [u8],
+    len: usize,
+}
+
+impl Writer<'_> {
+    fn bytes(&mut self, bytes: &[u8]) -> Option<()> {
+        self.out
+            .get_mut(self.len..self.len + bytes.len())?
+            .copy_from_slice(bytes);
+        self.len += bytes.len();
+        Some(())
+    }
+
+    fn u16(&mut self, value: u16) -> Option<()> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    /// The dotted `parts` as one name, uncompressed
+    fn name(&mut self, parts: &[&str]) -> Option<()> {
+        for label in parts.iter().flat_map(|part| part.split('.')) {
+            self.bytes(&[label.len() as u8])?;
+            self.bytes(label.as_bytes())?;
+        }
+        self.bytes(&[0])
+    }
+
+    fn record(
+        &mut self,
+        name: &[&str],
+        rtype: u16,
+        class: u16,
+        ttl: u32,
+        data: impl FnOnce(&mut Self) -> Option<()>,
+    ) -> Option<()> {
+        self.name(name)?;
+        self.u16(rtype)?;
+        self.u16(class)?;
+        self.bytes(&ttl.to_be_bytes())?;
+
+        let len_at = self.len;
+        self.u16(0)?;
+        data(self)?;
+
+        let len = (self.len - len_at - 2) as u16;
+        self.out[len_at..len_at + 2].copy_from_slice(&len.to_be_bytes());
+        Some(())
+    }
+}
+
+/// The dotted name at `at`, following compression pointers, and where it
+/// ends in the packet
+fn read_name(packet: &[u8], mut at: usize) -> Option<(String<MAX_NAME>, usize)> {
+    let mut name = String::new();
+    let mut end = None;
+    // Each pointer has to go backwards, so a loop can't go on forever
+    let mut limit = at;
+
+    loop {
+        let len = *packet.get(at)? as usize;
+
+        match len {
+            0 => return Some((name, end.unwrap_or(at + 1))),
+            1..=63 => {
+                let label = core::str::from_utf8(packet.get(at + 1..at + 1 + len)?).ok()?;
+                if !name.is_empty() {
+                    name.push('.').ok()?;
+                }
+                name.push_str(label).ok()?;
+                at += 1 + len;
+            }
+            0xC0..=0xFF => {
+                let target =
+                    u16::from_be_bytes([len as u8, *packet.get(at + 1)?]) as usize & 0x3FFF;
+                end.get_or_insert(at + 2);
+                if target >= limit {
+                    return None;
+                }
+                limit = target;
+                at = target;
+            }
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BADGE: [u8; 4] = [192, 168, 1, 42];
+    const TXT: &[&str] = &["path=/api/state"];
+
+    fn responder() -> Responder<'static> {
+        Responder::new("badger-julian", 80, TXT)
+    }
+
+    fn query(questions: &[(&str, u16, u16)]) -> Vec<u8> {
+        let mut q = vec![0x12, 0x34, 0, 0, 0, questions.len() as u8, 0, 0, 0, 0, 0, 0];
+        for (name, qtype, qclass) in questions {
+            for label in name.split('.') {
+                q.push(label.len() as u8);
+                q.extend_from_slice(label.as_bytes());
+            }
+            q.push(0);
+            q.extend_from_slice(&qtype.to_be_bytes());
+            q.extend_from_slice(&qclass.to_be_bytes());
+        }
+        q
+    }
+
+    /// Name, type, class and data of each record
+    fn records(
+        response: &[u8],
+        skip_questions: bool,
+    ) -> Vec<(std::string::String, u16, u16, Vec<u8>)> {
+        let count = |i: usize| u16::from_be_bytes([response[i], response[i + 1]]) as usize;
+        let mut at = HEADER;
+
+        if skip_questions {
+            for _ in 0..count(4) {
+                at = read_name(response, at).unwrap().1 + 4;
+            }
+        }
+
+        let mut records = Vec::new();
+        for _ in 0..count(6) + count(8) + count(10) {
+            let (name, next) = read_name(response, at).unwrap();
+            let rtype = count(next) as u16;
+            let class = count(next + 2) as u16;
+            let len = count(next + 8);
+            let data = response[next + 10..next + 10 + len].to_vec();
+            records.push((name.as_str().into(), rtype, class, data));
+            at = next + 10 + len;
+        }
+
+        assert_eq!(at, response.len());
+        records
+    }
+
+    fn answer(query: &[u8], legacy: bool) -> Option<(Reply, Vec<u8>)> {
+        let mut out = [0; 512];
+        let reply = responder().answer(query, BADGE, legacy, &mut out)?;
+        Some((reply, out[..reply.len].to_vec()))
+    }
+
+    #[test]
+    fn hostnames() {
+        assert_eq!(hostname("badger-julian.local"), Some("badger-julian"));
+        assert_eq!(hostname("badger"), Some("badger"));
+        assert_eq!(hostname(""), None);
+        assert_eq!(hostname("-badger"), None);
+        assert_eq!(hostname("bad.ger"), None);
+        assert_eq!(hostname("badger julian"), None);
+    }
+
+    #[test]
+    fn answers_for_the_host() {
+        let (reply, response) =
+            answer(&query(&[("Badger-Julian.local", TYPE_A, CLASS_IN)]), false).unwrap();
+
+        assert!(!reply.unicast);
+        assert_eq!(response[..12], [0, 0, 0x84, 0, 0, 0, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(
+            records(&response, false),
+            [(
+                "badger-julian.local".into(),
+                TYPE_A,
+                CLASS_IN | TOP_BIT,
+                BADGE.to_vec()
+            )]
+        );
+    }
+
+    #[test]
+    fn advertises_the_service() {
+        let (_, response) = answer(&query(&[(SERVICE, TYPE_PTR, CLASS_IN)]), false).unwrap();
+        let records = records(&response, false);
+
+        // The PTR answers, the rest come along so there's no second round
+        assert_eq!(response[6..12], [0, 1, 0, 0, 0, 3]);
+        assert_eq!(records[0].0, SERVICE);
+        assert_eq!(records[0].1, TYPE_PTR);
+        assert_eq!(
+            read_name(&records[0].3, 0).unwrap().0,
+            "badger-julian._http._tcp.local"
+        );
+
+        let srv = &records[1];
+        assert_eq!(
+            (srv.0.as_str(), srv.1),
+            ("badger-julian._http._tcp.local", TYPE_SRV)
+        );
+        assert_eq!(srv.3[..6], [0, 0, 0, 0, 0, 80]);
+        assert_eq!(read_name(&srv.3, 6).unwrap().0, "badger-julian.local");
+
+        let txt = &records[2];
+        assert_eq!(txt.1, TYPE_TXT);
+        assert_eq!(txt.3, b"\x0fpath=/api/state");
+
+        assert_eq!(records[3].1, TYPE_A);
+    }
+
+    #[test]
+    fn lists_its_services() {
+        let (_, response) = answer(&query(&[(SERVICES, TYPE_PTR, CLASS_IN)]), false).unwrap();
+        let records = records(&response, false);
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(read_name(&records[0].3, 0).unwrap().0, SERVICE);
+    }
+
+    #[test]
+    fn follows_compressed_questions() {
+        // The second question is `badger-julian` and a pointer to the
+        // first's `local`
+        let mut q = query(&[("printer.local", TYPE_A, CLASS_IN)]);
+        q[5] = 2;
+        q.push(13);
+        q.extend_from_slice(b"badger-julian");
+        q.extend_from_slice(&[0xC0, 20]);
+        q.extend_from_slice(&TYPE_A.to_be_bytes());
+        q.extend_from_slice(&(CLASS_IN | TOP_BIT).to_be_bytes());
+
+        let (reply, response) = answer(&q, false).unwrap();
+        assert!(reply.unicast);
+        assert_eq!(records(&response, false)[0].3, BADGE);
+    }
+
+    #[test]
+    fn legacy_queries_get_a_plain_reply() {
+        let q = query(&[("badger-julian.local", TYPE_ANY, CLASS_IN)]);
+        let (reply, response) = answer(&q, true).unwrap();
+
+        assert!(reply.unicast);
+        assert_eq!(response[..2], [0x12, 0x34]);
+        assert_eq!(response[4..6], [0, 1]);
+        assert_eq!(response[HEADER..q.len()], q[HEADER..]);
+        assert_eq!(records(&response, true)[0].2, CLASS_IN);
+    }
+
+    #[test]
+    fn ignores_what_isnt_ours() {
+        assert!(answer(&query(&[("printer.local", TYPE_A, CLASS_IN)]), false).is_none());
+        assert!(answer(&query(&[("badger-julian.local", 28, CLASS_IN)]), false).is_none());
+
+        let mut response = query(&[("badger-julian.local", TYPE_A, CLASS_IN)]);
+        response[2] = 0x84;
+        assert!(answer(&response, false).is_none());
+
+        let looped = [0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0xC0, 12, 0, 1, 0, 1];
+        assert!(answer(&looped, false).is_none());
+        assert!(answer(&[0; 5], false).is_none());
+    }
+
+    #[test]
+    fn announces_everything() {
+        let mut out = [0; 512];
+        let len = responder().announce(BADGE, &mut out).unwrap();
+        let types: Vec<u16> = records(&out[..len], false).iter().map(|r| r.1).collect();
+
+        assert_eq!(types, [TYPE_PTR, TYPE_SRV, TYPE_TXT, TYPE_A]);
+        assert!(responder().announce(BADGE, &mut out[..40]).is_none());
+    }
+}
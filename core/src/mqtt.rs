@@ -0,0 +1,630 @@
+//! Just enough of an MQTT 3.1.1 client to publish the badge's state and
+//! hear about messages for it, everything at QoS 0.
+//!
+//! Under `prefix` (`badger` unless set):
+//!
+//! * `badger/state`, retained, see [`Telemetry`]
+//! * `badger/online`, retained, `online` or, from the broker once the
+//!   connection drops, `offline`
+//! * `badger/message`, subscribed, shown along the bottom of the image
+//! * `badger/image/set`, subscribed, an image index or `next` / `prev`
+//! * `badger/redraw`, subscribed, anything redraws the screen
+//!
+//! What comes in maps onto the control API's [`Command`]s.
+
+use embedded_io_async::{Read, Write};
+use heapless::String;
+use serde::Serialize;
+use time::PrimitiveDateTime;
+
+use crate::{
+    control::{Command, STATUS_SIZE},
+    helpers::utc_timestamp,
+    image::{self, IMAGES, Shift},
+    weather::CurrentWeather,
+};
+
+pub const PORT: u16 = 1883;
+
+/// A ping goes out halfway through, so the broker never has to wonder
+pub const KEEP_ALIVE_SECS: u16 = 60;
+
+/// Room for any packet we send, and the messages we expect back
+pub const PACKET_SIZE: usize = 768;
+
+pub const TOPIC_SIZE: usize = 128;
+
+/// Big enough for any `Telemetry`, even a status that's all escapes
+pub const STATE_SIZE: usize = 640;
+
+pub type Topic = String<TOPIC_SIZE>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MqttError {
+    /// The connection failed or closed
+    Io,
+    /// The broker turned the connection down, with the CONNACK return code.
+    /// 4 and 5 are bad credentials.
+    Refused(u8),
+    /// Something that isn't MQTT, or not in answer to what we asked
+    Protocol,
+    /// What we tried to send doesn't fit the buffer
+    TooLarge,
+}
+
+/// `prefix/leaf`
+pub fn topic(prefix: &str, leaf: &str) -> Topic {
+    let mut topic = Topic::new();
+    topic.push_str(prefix).ok();
+    topic.push('/').ok();
+    topic.push_str(leaf).ok();
+    topic
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Message<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+    pub retain: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Options<'a> {
+    pub client_id: &'a str,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a str>,
+    pub keep_alive_secs: u16,
+    /// Published by the broker if we go without a DISCONNECT
+    pub will: Option<Message<'a>>,
+}
+
+/// What the badge publishes to `prefix/state`
+#[derive(Clone, Copy)]
+pub struct Telemetry<'a> {
+    pub weather: Option<CurrentWeather>,
+    /// `None` when it can't be measured
+    pub battery_mv: Option<u16>,
+    pub external_power: bool,
+    pub image: usize,
+    /// UTC, the last sync that brought back the time
+    pub last_sync: Option<PrimitiveDateTime>,
+    /// Since the badge was first flashed, see [`crate::wake::RamByte`]
+    pub wakes: u32,
+    pub status: &'a str,
+}
+
+#[derive(Serialize)]
+struct Body<'a> {
+    temperature: Option<f32>,
+    weathercode: Option<u8>,
+    battery_mv: Option<u16>,
+    external_power: bool,
+    image: usize,
+    last_sync: Option<String<20>>,
+    wakes: u32,
+    status: &'a str,
+}
+
+impl Telemetry<'_> {
+    pub fn to_json(&self) -> String<STATE_SIZE> {
+        let body = Body {
+            temperature: self.weather.map(|w| w.temperature),
+            weathercode: self.weather.map(|w| w.weathercode),
+            battery_mv: self.battery_mv,
+            external_power: self.external_power,
+            image: self.image,
+            last_sync: self.last_sync.map(utc_timestamp),
+            wakes: self.wakes,
+            status: self.status,
+        };
+
+        // Every field is bounded, so this always fits
+        serde_json_core::to_string(&body).unwrap_or_default()
+    }
+}
+
+/// What a message on one of our topics under `prefix` asks for. The image
+/// shown now is `current`, for `next` and `prev`.
+pub fn command(prefix: &str, topic: &str, payload: &[u8], current: usize) -> Option<Command> {
+    let leaf = topic.strip_prefix(prefix)?.strip_prefix('/')?;
+    let text = core::str::from_utf8(payload).ok()?.trim();
+
+    match leaf {
+        "message" => {
+            let mut status = String::<STATUS_SIZE>::new();
+            for c in text.chars() {
+                if status.push(c).is_err() {
+                    break;
+                }
+            }
+            Some(Command::Status(status))
+        }
+        "image/set" => match text {
+            "next" => Some(Command::Image(image::shift(current, Shift::Next))),
+            "prev" => Some(Command::Image(image::shift(current, Shift::Prev))),
+            index => index
+                .parse()
+                .ok()
+                .filter(|&index| index < IMAGES.len())
+                .map(Command::Image),
+        },
+        "redraw" => Some(Command::Redraw),
+        _ => None,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Packet<'a> {
+    ConnAck {
+        code: u8,
+    },
+    Publish {
+        topic: &'a str,
+        payload: &'a [u8],
+    },
+    SubAck {
+        granted: bool,
+    },
+    PingResp,
+    /// Any other type, or a packet too big for the buffer, which is skipped
+    Ignored(u8),
+}
+
+/// Builds a packet's variable header and payload after room for the
+/// longest fixed header
+struct Encoder<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+/// Packet type byte, and up to four bytes of remaining length
+const HEADER_ROOM: usize = 5;
+
+impl<'b> Encoder<'b> {
+    fn new(buf: &'b mut [u8]) -> Self {
+        Self {
+            buf,
+            len: HEADER_ROOM,
+        }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> Result<&mut Self, MqttError> {
+        let end = self.len + bytes.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(MqttError::TooLarge)?
+            .copy_from_slice(bytes);
+        self.len = end;
+        Ok(self)
+    }
+
+    fn u16(&mut self, value: u16) -> Result<&mut Self, MqttError> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    /// Length prefixed
+    fn string(&mut self, value: &[u8]) -> Result<&mut Self, MqttError> {
+        let len = u16::try_from(value.len()).map_err(|_| MqttError::TooLarge)?;
+        self.u16(len)?.bytes(value)
+    }
+
+    /// The whole packet, fixed header in front
+    fn finish(self, kind: u8) -> Result<&'b [u8], MqttError> {
+        let mut remaining = self.len - HEADER_ROOM;
+        let mut length = [0u8; 4];
+        let mut n = 0;
+
+        loop {
+            let byte = (remaining % 128) as u8;
+            remaining /= 128;
+            length[n] = if remaining > 0 { byte | 0x80 } else { byte };
+            n += 1;
+
+            if remaining == 0 {
+                break;
+            }
+            if n == length.len() {
+                return Err(MqttError::TooLarge);
+            }
+        }
+
+        let start = HEADER_ROOM - 1 - n;
+        self.buf[start] = kind;
+        self.buf[start + 1..HEADER_ROOM].copy_from_slice(&length[..n]);
+        Ok(&self.buf[start..self.len])
+    }
+}
+
+fn connect_packet<'b>(buf: &'b mut [u8], options: &Options) -> Result<&'b [u8], MqttError> {
+    let mut flags = 0x02; // clean session
+    if let Some(will) = options.will {
+        flags |= 0x04 | if will.retain { 0x20 } else { 0 };
+    }
+    if options.username.is_some() {
+        flags |= 0x80;
+    }
+    if options.password.is_some() {
+        flags |= 0x40;
+    }
+
+    let mut packet = Encoder::new(buf);
+    packet
+        .string(b"MQTT")?
+        .bytes(&[4, flags])?
+        .u16(options.keep_alive_secs)?
+        .string(options.client_id.as_bytes())?;
+
+    if let Some(will) = options.will {
+        packet.string(will.topic.as_bytes())?.string(will.payload)?;
+    }
+    if let Some(username) = options.username {
+        packet.string(username.as_bytes())?;
+    }
+    if let Some(password) = options.password {
+        packet.string(password.as_bytes())?;
+    }
+
+    packet.finish(0x10)
+}
+
+fn publish_packet<'b>(buf: &'b mut [u8], message: &Message) -> Result<&'b [u8], MqttError> {
+    let mut packet = Encoder::new(buf);
+    packet
+        .string(message.topic.as_bytes())?
+        .bytes(message.payload)?;
+
+    packet.finish(0x30 | message.retain as u8)
+}
+
+fn subscribe_packet<'b>(buf: &'b mut [u8], id: u16, filter: &str) -> Result<&'b [u8], MqttError> {
+    let mut packet = Encoder::new(buf);
+    packet.u16(id)?.string(filter.as_bytes())?.bytes(&[0])?;
+
+    packet.finish(0x82)
+}
+
+fn parse(kind: u8, body: &[u8]) -> Result<Packet<'_>, MqttError> {
+    let packet = match kind >> 4 {
+        2 => Packet::ConnAck {
+            code: *body.get(1).ok_or(MqttError::Protocol)?,
+        },
+        3 => {
+            let len = u16::from_be_bytes(
+                body.get(..2)
+                    .ok_or(MqttError::Protocol)?
+                    .try_into()
+                    .unwrap_or_default(),
+            ) as usize;
+            let topic = body.get(2..2 + len).ok_or(MqttError::Protocol)?;
+            let topic = core::str::from_utf8(topic).map_err(|_| MqttError::Protocol)?;
+
+            // QoS 1 and 2 have a packet id before the payload
+            let skip = if kind & 0x06 != 0 { 2 } else { 0 };
+            let payload = body.get(2 + len + skip..).ok_or(MqttError::Protocol)?;
+
+            Packet::Publish { topic, payload }
+        }
+        9 => Packet::SubAck {
+            granted: body.get(2).is_some_and(|&code| code < 0x80),
+        },
+        13 => Packet::PingResp,
+        _ => Packet::Ignored(kind),
+    };
+
+    Ok(packet)
+}
+
+pub struct Client<C> {
+    conn: C,
+    packet_id: u16,
+}
+
+impl<C: Read + Write> Client<C> {
+    /// Sends CONNECT over `conn` and waits for the broker to accept it
+    pub async fn connect(
+        conn: C,
+        buf: &mut [u8],
+        options: &Options<'_>,
+    ) -> Result<Self, MqttError> {
+        let mut client = Self { conn, packet_id: 0 };
+
+        let packet = connect_packet(buf, options)?;
+        client.send(packet).await?;
+
+        let first = client.wait().await?;
+        match client.receive(first, buf).await? {
+            Packet::ConnAck { code: 0 } => Ok(client),
+            Packet::ConnAck { code } => Err(MqttError::Refused(code)),
+            _ => Err(MqttError::Protocol),
+        }
+    }
+
+    async fn send(&mut self, packet: &[u8]) -> Result<(), MqttError> {
+        self.conn
+            .write_all(packet)
+            .await
+            .map_err(|_| MqttError::Io)?;
+        self.conn.flush().await.map_err(|_| MqttError::Io)
+    }
+
+    pub async fn publish(
+        &mut self,
+        buf: &mut [u8],
+        message: &Message<'_>,
+    ) -> Result<(), MqttError> {
+        let packet = publish_packet(buf, message)?;
+        self.send(packet).await
+    }
+
+    /// The SUBACK comes back through [`Client::receive`]
+    pub async fn subscribe(&mut self, buf: &mut [u8], filter: &str) -> Result<(), MqttError> {
+        self.packet_id = self.packet_id.wrapping_add(1).max(1);
+        let packet = subscribe_packet(buf, self.packet_id, filter)?;
+        self.send(packet).await
+    }
+
+    pub async fn ping(&mut self) -> Result<(), MqttError> {
+        self.send(&[0xC0, 0]).await
+    }
+
+    /// Says goodbye, so the broker doesn't publish the will
+    pub async fn disconnect(mut self) -> C {
+        self.send(&[0xE0, 0]).await.ok();
+        self.conn
+    }
+
+    /// Waits for the first byte of the next packet. Nothing is lost if
+    /// this is dropped before it returns, so it can race a timer.
+    pub async fn wait(&mut self) -> Result<u8, MqttError> {
+        let mut first = [0];
+        match self.conn.read(&mut first).await {
+            Ok(1) => Ok(first[0]),
+            _ => Err(MqttError::Io),
+        }
+    }
+
+    /// Reads the rest of the packet [`Client::wait`] started. Not to be
+    /// dropped part way through.
+    pub async fn receive<'b>(
+        &mut self,
+        first: u8,
+        buf: &'b mut [u8],
+    ) -> Result<Packet<'b>, MqttError> {
+        let mut remaining = 0usize;
+        for shift in [0, 7, 14, 21] {
+            let mut byte = [0];
+            self.conn
+                .read_exact(&mut byte)
+                .await
+                .map_err(|_| MqttError::Io)?;
+
+            remaining |= ((byte[0] & 0x7F) as usize) << shift;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+
+        if remaining > buf.len() {
+            // Read it through anyway, so the next packet lines up
+            while remaining > 0 {
+                let n = remaining.min(buf.len());
+                self.conn
+                    .read_exact(&mut buf[..n])
+                    .await
+                    .map_err(|_| MqttError::Io)?;
+                remaining -= n;
+            }
+            return Ok(Packet::Ignored(first));
+        }
+
+        let body = &mut buf[..remaining];
+        self.conn
+            .read_exact(body)
+            .await
+            .map_err(|_| MqttError::Io)?;
+        parse(first, body)
+    }
+}
+
+/// Seconds to wait before reconnecting, after `failures` in a row
+pub fn reconnect_delay(failures: u32) -> u32 {
+    5u32.saturating_mul(1 << failures.min(6)).min(300)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> Options<'static> {
+        Options {
+            client_id: "badger",
+            username: None,
+            password: None,
+            keep_alive_secs: 60,
+            will: None,
+        }
+    }
+
+    #[test]
+    fn connect() {
+        let mut buf = [0; PACKET_SIZE];
+        assert_eq!(
+            connect_packet(&mut buf, &options()).unwrap(),
+            b"\x10\x12\x00\x04MQTT\x04\x02\x00\x3c\x00\x06badger"
+        );
+
+        let full = Options {
+            username: Some("u"),
+            password: Some("p"),
+            will: Some(Message {
+                topic: "b/online",
+                payload: b"offline",
+                retain: true,
+            }),
+            ..options()
+        };
+        let packet = connect_packet(&mut buf, &full).unwrap();
+        assert_eq!(packet[..2], [0x10, 0x2B]);
+        assert_eq!(packet[9], 0x80 | 0x40 | 0x20 | 0x04 | 0x02);
+        assert!(packet.ends_with(b"\x00\x08b/online\x00\x07offline\x00\x01u\x00\x01p"));
+    }
+
+    #[test]
+    fn publish_and_subscribe() {
+        let mut buf = [0; PACKET_SIZE];
+        let message = Message {
+            topic: "a/b",
+            payload: b"hi",
+            retain: true,
+        };
+
+        assert_eq!(
+            publish_packet(&mut buf, &message).unwrap(),
+            b"\x31\x07\x00\x03a/bhi"
+        );
+        assert_eq!(
+            subscribe_packet(&mut buf, 1, "a/m").unwrap(),
+            b"\x82\x08\x00\x01\x00\x03a/m\x00"
+        );
+    }
+
+    #[test]
+    fn long_packets_have_longer_lengths() {
+        let mut buf = [0; PACKET_SIZE];
+        let payload = [b'x'; 200];
+        let message = Message {
+            topic: "t",
+            payload: &payload,
+            retain: false,
+        };
+
+        let packet = publish_packet(&mut buf, &message).unwrap();
+        // 203 remaining, in two bytes
+        assert_eq!(packet[..3], [0x30, 0xCB, 0x01]);
+        assert_eq!(packet.len(), 3 + 203);
+
+        let huge = [b'x'; PACKET_SIZE];
+        let message = Message {
+            payload: &huge,
+            ..message
+        };
+        assert_eq!(publish_packet(&mut buf, &message), Err(MqttError::TooLarge));
+    }
+
+    #[test]
+    fn parses_packets() {
+        assert_eq!(parse(0x20, &[0, 5]), Ok(Packet::ConnAck { code: 5 }));
+        assert_eq!(
+            parse(0x30, b"\x00\x03a/mhello"),
+            Ok(Packet::Publish {
+                topic: "a/m",
+                payload: b"hello"
+            })
+        );
+        assert_eq!(
+            parse(0x32, b"\x00\x03a/m\x00\x07hello"),
+            Ok(Packet::Publish {
+                topic: "a/m",
+                payload: b"hello"
+            })
+        );
+        assert_eq!(
+            parse(0x90, &[0, 1, 0]),
+            Ok(Packet::SubAck { granted: true })
+        );
+        assert_eq!(
+            parse(0x90, &[0, 1, 0x80]),
+            Ok(Packet::SubAck { granted: false })
+        );
+        assert_eq!(parse(0xD0, &[]), Ok(Packet::PingResp));
+        assert_eq!(parse(0x30, b"\x00\x09a"), Err(MqttError::Protocol));
+    }
+
+    #[test]
+    fn telemetry_as_json() {
+        use time::{Date, Month, Time};
+
+        let telemetry = Telemetry {
+            weather: Some(CurrentWeather {
+                temperature: 18.5,
+                weathercode: 61,
+            }),
+            battery_mv: None,
+            external_power: true,
+            image: 2,
+            last_sync: Some(PrimitiveDateTime::new(
+                Date::from_calendar_date(2025, Month::October, 17).unwrap(),
+                Time::from_hms(19, 5, 0).unwrap(),
+            )),
+            wakes: 1234,
+            status: "Back at 3",
+        };
+
+        assert_eq!(
+            telemetry.to_json(),
+            r#"{"temperature":18.5,"weathercode":61,"battery_mv":null,"external_power":true,"image":2,"last_sync":"2025-10-17T19:05:00Z","wakes":1234,"status":"Back at 3"}"#
+        );
+
+        let escapes = "\u{1}".repeat(STATUS_SIZE);
+        let worst = Telemetry {
+            weather: Some(CurrentWeather {
+                temperature: -1.234_567_8e-30,
+                weathercode: 255,
+            }),
+            battery_mv: Some(u16::MAX),
+            image: usize::MAX,
+            wakes: u32::MAX,
+            status: &escapes,
+            ..telemetry
+        };
+        assert!(!worst.to_json().is_empty());
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(
+            command("badger", "badger/message", b"Back at 3", 0),
+            Some(Command::Status("Back at 3".try_into().unwrap()))
+        );
+        assert_eq!(
+            command("badger", "badger/message", b"", 0),
+            Some(Command::Status(String::new()))
+        );
+        let long = "\u{e9}".repeat(STATUS_SIZE);
+        assert!(matches!(
+            command("badger", "badger/message", long.as_bytes(), 0),
+            Some(Command::Status(status)) if status.len() == STATUS_SIZE
+        ));
+
+        assert_eq!(
+            command("badger", "badger/image/set", b"1", 0),
+            Some(Command::Image(1))
+        );
+        assert_eq!(
+            command("badger", "badger/image/set", b"next", 0),
+            Some(Command::Image(1))
+        );
+        assert_eq!(
+            command("badger", "badger/image/set", b"prev", 0),
+            Some(Command::Image(IMAGES.len() - 1))
+        );
+        assert_eq!(command("badger", "badger/image/set", b"99", 0), None);
+
+        assert_eq!(
+            command("badger", "badger/redraw", b"PRESS", 0),
+            Some(Command::Redraw)
+        );
+        assert_eq!(command("badger", "badger/state", b"{}", 0), None);
+        assert_eq!(command("badger", "badgers/redraw", b"", 0), None);
+    }
+
+    #[test]
+    fn backs_off() {
+        assert_eq!(
+            (0..8).map(reconnect_delay).collect::<std::vec::Vec<_>>(),
+            [5, 10, 20, 40, 80, 160, 300, 300]
+        );
+        assert_eq!(topic("badger", "state"), "badger/state");
+    }
+}
@@ -0,0 +1,209 @@
+//! WiFi credential profiles, and which order to try them in after a scan
+
+use heapless::Vec;
+
+pub const MAX_PROFILES: usize = 8;
+
+/// How much signal, in dB, the network that last worked is worth
+pub const LAST_SUCCESS_BONUS: i16 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Profile<'a> {
+    pub ssid: &'a str,
+    /// Empty for an open network
+    pub password: &'a [u8],
+}
+
+/// Reads the `.wifi` file, either one `ssid<TAB>password` per line, or,
+/// with no tabs at all, just the password for `ssid`. WPA passwords are
+/// printable ASCII, so they can't hold a tab or a newline.
+pub fn profiles<'a>(ssid: Option<&'a str>, file: &'a [u8]) -> Vec<Profile<'a>, MAX_PROFILES> {
+    let mut profiles = Vec::new();
+
+    if !file.contains(&b'\t') {
+        if let Some(ssid) = ssid {
+            profiles
+                .push(Profile {
+                    ssid,
+                    password: file.trim_ascii(),
+                })
+                .ok();
+        }
+        return profiles;
+    }
+
+    for line in file.split(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        let Some(tab) = line.iter().position(|&b| b == b'\t') else {
+            continue;
+        };
+        let Ok(ssid) = core::str::from_utf8(&line[..tab]) else {
+            continue;
+        };
+
+        if ssid.is_empty() || ssid.len() > 32 {
+            continue;
+        }
+
+        let profile = Profile {
+            ssid,
+            password: &line[tab + 1..],
+        };
+        if profiles.push(profile).is_err() {
+            break;
+        }
+    }
+
+    profiles
+}
+
+/// Stands in for an SSID in flash, FNV-1a
+pub fn ssid_hash(ssid: &str) -> u32 {
+    ssid.bytes().fold(0x811c_9dc5, |hash, b| {
+        (hash ^ b as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Collects scan results for a set of profiles
+pub struct Candidates<'a> {
+    profiles: Vec<Profile<'a>, MAX_PROFILES>,
+    rssi: [Option<i16>; MAX_PROFILES],
+}
+
+impl<'a> Candidates<'a> {
+    pub fn new(profiles: Vec<Profile<'a>, MAX_PROFILES>) -> Self {
+        Self {
+            profiles,
+            rssi: [None; MAX_PROFILES],
+        }
+    }
+
+    /// A scan found `ssid`, keeps the strongest of its access points
+    pub fn seen(&mut self, ssid: &[u8], rssi: i16) {
+        for (profile, best) in self.profiles.iter().zip(self.rssi.iter_mut()) {
+            if profile.ssid.as_bytes() == ssid && best.is_none_or(|best| rssi > best) {
+                *best = Some(rssi);
+            }
+        }
+    }
+
+    /// The strongest access point seen for `ssid`, `None` if it wasn't
+    pub fn rssi(&self, ssid: &str) -> Option<i16> {
+        let i = self.profiles.iter().position(|p| p.ssid == ssid)?;
+        self.rssi[i]
+    }
+
+    /// Networks in range first, strongest first with a head start for the
+    /// one that last worked. The rest follow in file order, since hidden
+    /// networks don't show up in a scan.
+    pub fn order(&self, last_success: Option<u32>) -> Vec<Profile<'a>, MAX_PROFILES> {
+        let score = |i: usize| {
+            self.rssi[i].map(
+                |rssi| match last_success == Some(ssid_hash(self.profiles[i].ssid)) {
+                    true => rssi.saturating_add(LAST_SUCCESS_BONUS),
+                    false => rssi,
+                },
+            )
+        };
+
+        let mut order: Vec<usize, MAX_PROFILES> = (0..self.profiles.len()).collect();
+        // Ties keep file order
+        order.sort_unstable_by_key(|&i| (core::cmp::Reverse(score(i)), i));
+
+        order.iter().map(|&i| self.profiles[i]).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &[u8] = b"Office\tcorp-pass\nHome\thome pass\r\n\nConference\t\nbroken line\n";
+
+    fn ssids<'a>(order: &[Profile<'a>]) -> std::vec::Vec<&'a str> {
+        order.iter().map(|p| p.ssid).collect()
+    }
+
+    #[test]
+    fn parses_profile_list() {
+        let profiles = profiles(Some("Ignored"), FILE);
+
+        assert_eq!(
+            profiles,
+            [
+                Profile {
+                    ssid: "Office",
+                    password: b"corp-pass"
+                },
+                Profile {
+                    ssid: "Home",
+                    password: b"home pass"
+                },
+                Profile {
+                    ssid: "Conference",
+                    password: b""
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn bare_password_is_for_wifi_ssid() {
+        assert_eq!(
+            profiles(Some("Your wifi"), b"yourwifipassword\n"),
+            [Profile {
+                ssid: "Your wifi",
+                password: b"yourwifipassword"
+            }]
+        );
+        assert!(profiles(None, b"yourwifipassword").is_empty());
+    }
+
+    #[test]
+    fn strongest_in_range_first() {
+        let mut candidates = Candidates::new(profiles(None, FILE));
+        candidates.seen(b"Home", -71);
+        candidates.seen(b"Conference", -60);
+        candidates.seen(b"Home", -52);
+        candidates.seen(b"Neighbours", -30);
+
+        assert_eq!(
+            ssids(&candidates.order(None)),
+            ["Home", "Conference", "Office"]
+        );
+        assert_eq!(candidates.rssi[1], Some(-52));
+        assert_eq!(candidates.rssi("Home"), Some(-52));
+        assert_eq!(candidates.rssi("Office"), None);
+    }
+
+    #[test]
+    fn last_success_gets_a_head_start() {
+        let mut candidates = Candidates::new(profiles(None, FILE));
+        candidates.seen(b"Home", -62);
+        candidates.seen(b"Conference", -55);
+
+        let home = Some(ssid_hash("Home"));
+        assert_eq!(
+            ssids(&candidates.order(home)),
+            ["Home", "Conference", "Office"]
+        );
+
+        // But not over a much stronger network
+        candidates.seen(b"Conference", -40);
+        assert_eq!(
+            ssids(&candidates.order(home)),
+            ["Conference", "Home", "Office"]
+        );
+    }
+
+    #[test]
+    fn nothing_seen_keeps_file_order() {
+        let candidates = Candidates::new(profiles(None, FILE));
+
+        assert_eq!(
+            ssids(&candidates.order(Some(ssid_hash("Home")))),
+            ["Office", "Home", "Conference"]
+        );
+    }
+}
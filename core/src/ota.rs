@@ -0,0 +1,375 @@
+//! Over-the-air updates. An update is the firmware binary behind a small
+//! header with its version, its length and an ed25519 signature of its
+//! SHA-512. The binary ends in a trailer with the version again, so the
+//! signature covers it. [`download`] streams the binary into the DFU
+//! partition, checking the two versions agree, and embassy-boot checks the
+//! signature before marking it for the swap.
+
+use core::fmt;
+
+use embedded_io_async::{Read, ReadExactError};
+use serde::{Deserialize, Serialize};
+
+use crate::{hal::Dfu, tls::from_hex};
+
+pub const MAGIC: [u8; 4] = *b"BDGR";
+
+/// Magic, version, a zero byte, length and signature
+pub const HEADER_SIZE: usize = 4 + 4 + 4 + 64;
+
+/// Magic, version and a zero byte, the last bytes of the signed binary
+pub const TRAILER_SIZE: usize = 4 + 4;
+
+/// The RP2040's flash erase sector, the DFU partition is written in these
+pub const BLOCK_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OtaError {
+    Io,
+    /// Not an update, or one that's been cut short
+    BadHeader,
+    Truncated,
+    /// Bigger than the DFU partition
+    TooLarge,
+    /// The header's version isn't the one in the signed trailer
+    VersionMismatch,
+    Flash,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+}
+
+impl Version {
+    /// `major.minor.patch`, anything after a `-` or `+` is ignored
+    pub fn parse(version: &str) -> Option<Self> {
+        let core = version.split(['-', '+']).next()?;
+        let mut parts = core.split('.').map(|part| part.parse().ok());
+
+        let version = Self {
+            major: parts.next()??,
+            minor: parts.next()??,
+            patch: parts.next()??,
+        };
+
+        parts.next().is_none().then_some(version)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Header {
+    pub version: Version,
+    /// Of the binary that follows
+    pub len: u32,
+    pub signature: [u8; 64],
+}
+
+impl Header {
+    pub fn decode(buf: &[u8; HEADER_SIZE]) -> Result<Self, OtaError> {
+        if buf[..4] != MAGIC || buf[7] != 0 {
+            return Err(OtaError::BadHeader);
+        }
+
+        let mut signature = [0; 64];
+        signature.copy_from_slice(&buf[12..]);
+
+        Ok(Self {
+            version: Version {
+                major: buf[4],
+                minor: buf[5],
+                patch: buf[6],
+            },
+            len: u32::from_le_bytes([buf[8], buf[9], buf[10], buf[11]]),
+            signature,
+        })
+    }
+
+    pub fn encode(&self) -> [u8; HEADER_SIZE] {
+        let mut buf = [0; HEADER_SIZE];
+        buf[..4].copy_from_slice(&MAGIC);
+        buf[4..7].copy_from_slice(&[self.version.major, self.version.minor, self.version.patch]);
+        buf[8..12].copy_from_slice(&self.len.to_le_bytes());
+        buf[12..].copy_from_slice(&self.signature);
+        buf
+    }
+}
+
+/// The version in a binary's trailer
+pub fn trailer_version(trailer: &[u8; TRAILER_SIZE]) -> Option<Version> {
+    (trailer[..4] == MAGIC && trailer[7] == 0).then(|| Version {
+        major: trailer[4],
+        minor: trailer[5],
+        patch: trailer[6],
+    })
+}
+
+pub fn encode_trailer(version: Version) -> [u8; TRAILER_SIZE] {
+    let mut buf = [0; TRAILER_SIZE];
+    buf[..4].copy_from_slice(&MAGIC);
+    buf[4..7].copy_from_slice(&[version.major, version.minor, version.patch]);
+    buf
+}
+
+/// The ed25519 key updates are signed with, from 64 hex digits
+pub fn public_key(hex: &str) -> Option<[u8; 32]> {
+    from_hex(hex)?.as_slice().try_into().ok()
+}
+
+/// Whether to install `offered` over `running`. `tried` is the last update
+/// installed, so one that rolled back isn't installed again every sync
+pub fn wanted(offered: Version, running: Version, tried: Option<Version>) -> bool {
+    offered > running && tried != Some(offered)
+}
+
+fn read_error<E>(e: ReadExactError<E>) -> OtaError {
+    match e {
+        ReadExactError::UnexpectedEof => OtaError::Truncated,
+        ReadExactError::Other(_) => OtaError::Io,
+    }
+}
+
+/// Reads the header from `body` and, if `want` agrees, streams the binary
+/// into `dfu` a block at a time, the last one padded out with 0xFF.
+/// Returns the header of what was written, `None` if it wasn't wanted. The
+/// header isn't signed, so `want` only decides whether to download, and the
+/// trailer has to match it before the signature can vouch for the version.
+pub async fn download<R: Read, D: Dfu>(
+    body: &mut R,
+    dfu: &mut D,
+    buf: &mut [u8; BLOCK_SIZE],
+    want: impl FnOnce(&Header) -> bool,
+) -> Result<Option<Header>, OtaError> {
+    let mut header = [0; HEADER_SIZE];
+    body.read_exact(&mut header).await.map_err(read_error)?;
+    let header = Header::decode(&header)?;
+
+    if !want(&header) {
+        return Ok(None);
+    }
+    if header.len < TRAILER_SIZE as u32 {
+        return Err(OtaError::BadHeader);
+    }
+    if header.len > dfu.capacity() {
+        return Err(OtaError::TooLarge);
+    }
+
+    let mut trailer = [0; TRAILER_SIZE];
+    let mut offset = 0;
+    while offset < header.len {
+        let n = (header.len - offset).min(BLOCK_SIZE as u32) as usize;
+
+        body.read_exact(&mut buf[..n]).await.map_err(read_error)?;
+        // The trailer can straddle the last two blocks
+        let kept = TRAILER_SIZE.saturating_sub(n);
+        trailer.copy_within(TRAILER_SIZE - kept.., 0);
+        trailer[kept..].copy_from_slice(&buf[n - (TRAILER_SIZE - kept)..n]);
+        buf[n..].fill(0xFF);
+
+        dfu.write_block(offset, buf)
+            .await
+            .map_err(|_| OtaError::Flash)?;
+        offset += n as u32;
+    }
+
+    if trailer_version(&trailer) != Some(header.version) {
+        return Err(OtaError::VersionMismatch);
+    }
+
+    Ok(Some(header))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_futures::block_on;
+
+    /// Hands out what it was given a few hundred bytes at a time
+    struct Trickle<'a>(&'a [u8]);
+
+    impl embedded_io_async::ErrorType for Trickle<'_> {
+        type Error = embedded_io_async::ErrorKind;
+    }
+
+    impl Read for Trickle<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let n = buf.len().min(self.0.len()).min(300);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    struct Partition {
+        capacity: u32,
+        blocks: std::vec::Vec<(u32, std::vec::Vec<u8>)>,
+    }
+
+    impl Dfu for Partition {
+        type Error = ();
+
+        fn capacity(&self) -> u32 {
+            self.capacity
+        }
+
+        async fn write_block(&mut self, offset: u32, block: &[u8]) -> Result<(), ()> {
+            self.blocks.push((offset, block.to_vec()));
+            Ok(())
+        }
+    }
+
+    const V1: Version = Version {
+        major: 0,
+        minor: 1,
+        patch: 0,
+    };
+    const V2: Version = Version {
+        major: 0,
+        minor: 2,
+        patch: 0,
+    };
+
+    fn update(len: u32, binary: &[u8]) -> std::vec::Vec<u8> {
+        let header = Header {
+            version: V2,
+            len,
+            signature: [0xA5; 64],
+        };
+        [&header.encode()[..], binary].concat()
+    }
+
+    /// `binary` signed as `version`, offered as 0.2.0
+    fn signed(binary: &[u8], version: Version) -> std::vec::Vec<u8> {
+        let binary = [binary, &encode_trailer(version)].concat();
+        update(binary.len() as u32, &binary)
+    }
+
+    fn run(
+        image: &[u8],
+        capacity: u32,
+        want: bool,
+    ) -> (Result<Option<Header>, OtaError>, Partition) {
+        let mut dfu = Partition {
+            capacity,
+            blocks: std::vec::Vec::new(),
+        };
+        let mut buf = [0; BLOCK_SIZE];
+        let result = block_on(download(&mut Trickle(image), &mut dfu, &mut buf, |_| want));
+        (result, dfu)
+    }
+
+    #[test]
+    fn versions() {
+        assert_eq!(Version::parse("0.1.0"), Some(V1));
+        assert_eq!(Version::parse("0.2.0-rc.1"), Some(V2));
+        assert_eq!(Version::parse("0.1"), None);
+        assert_eq!(Version::parse("0.1.0.0"), None);
+        assert_eq!(Version::parse("0.x.0"), None);
+        assert!(Version::parse("1.0.0") > Version::parse("0.10.3"));
+        assert!(Version::parse("0.10.0") > Version::parse("0.9.9"));
+    }
+
+    #[test]
+    fn public_keys() {
+        let hex = "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a";
+        let key = public_key(hex).unwrap();
+        assert_eq!(key[..2], [0xD7, 0x5A]);
+        assert_eq!(key[31], 0x1A);
+
+        assert_eq!(public_key(&hex[..62]), None);
+        assert_eq!(public_key(""), None);
+    }
+
+    #[test]
+    fn only_newer_untried_versions_are_wanted() {
+        assert!(wanted(V2, V1, None));
+        assert!(!wanted(V1, V1, None));
+        assert!(!wanted(V1, V2, None));
+        // 0.2.0 was installed and rolled back
+        assert!(!wanted(V2, V1, Some(V2)));
+        assert!(wanted(V2, V1, Some(V1)));
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let image = update(1234, &[]);
+        let header = Header::decode(image[..HEADER_SIZE].try_into().unwrap()).unwrap();
+        assert_eq!(header.version, V2);
+        assert_eq!(header.len, 1234);
+        assert_eq!(header.signature, [0xA5; 64]);
+
+        let mut bad = image.clone();
+        bad[0] = b'X';
+        assert_eq!(
+            Header::decode(bad[..HEADER_SIZE].try_into().unwrap()),
+            Err(OtaError::BadHeader)
+        );
+    }
+
+    #[test]
+    fn streams_whole_blocks() {
+        let binary: std::vec::Vec<u8> = (0..9992u32).map(|i| i as u8).collect();
+        let image = signed(&binary, V2);
+        let (result, dfu) = run(&image, 64 * 1024, true);
+
+        assert_eq!(result.unwrap().unwrap().len, 10_000);
+        let offsets: std::vec::Vec<u32> = dfu.blocks.iter().map(|(offset, _)| *offset).collect();
+        assert_eq!(offsets, [0, 4096, 8192]);
+        assert!(
+            dfu.blocks
+                .iter()
+                .all(|(_, block)| block.len() == BLOCK_SIZE)
+        );
+
+        let written: std::vec::Vec<u8> = dfu.blocks.iter().flat_map(|(_, b)| b.clone()).collect();
+        assert_eq!(written[..10_000], image[HEADER_SIZE..]);
+        assert!(written[10_000..].iter().all(|&b| b == 0xFF));
+    }
+
+    #[test]
+    fn checks_the_signed_version() {
+        // The trailer straddles the first two blocks
+        let (result, _) = run(&signed(&[0; 4092], V2), 64 * 1024, true);
+        assert_eq!(result.unwrap().unwrap().version, V2);
+
+        // An older signed binary behind a header claiming it's newer
+        let (result, _) = run(&signed(&[0; 4092], V1), 64 * 1024, true);
+        assert_eq!(result, Err(OtaError::VersionMismatch));
+
+        let (result, _) = run(&update(100, &[0; 100]), 64 * 1024, true);
+        assert_eq!(result, Err(OtaError::VersionMismatch));
+    }
+
+    #[test]
+    fn unwanted_updates_stop_at_the_header() {
+        let (result, dfu) = run(&update(100, &[0; 100]), 64 * 1024, false);
+        assert_eq!(result, Ok(None));
+        assert!(dfu.blocks.is_empty());
+    }
+
+    #[test]
+    fn refuses_bad_updates() {
+        let (result, dfu) = run(&update(100_000, &[]), 64 * 1024, true);
+        assert_eq!(result, Err(OtaError::TooLarge));
+        assert!(dfu.blocks.is_empty());
+
+        let (result, _) = run(&update(5000, &[0; 4500]), 64 * 1024, true);
+        assert_eq!(result, Err(OtaError::Truncated));
+
+        let (result, _) = run(&update(TRAILER_SIZE as u32 - 1, &[]), 64 * 1024, true);
+        assert_eq!(result, Err(OtaError::BadHeader));
+
+        let (result, _) = run(b"<html>Not found</html>", 64 * 1024, true);
+        assert_eq!(result, Err(OtaError::Truncated));
+    }
+}
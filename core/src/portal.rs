@@ -0,0 +1,376 @@
+//! The setup page the badge serves from its own access point, holding A
+//! and C at power up. Any page a phone asks for leads here.
+
+use embedded_io_async::{Read, Write};
+use heapless::String;
+
+use crate::{
+    httpd::{self, HTML, HttpdError, Method, form_field, push_escaped},
+    ipconfig::{FIELD_SIZE, IpConfig, IpConfigError},
+    settings::{Location, Settings},
+    tz::TimeZone,
+};
+
+/// Open, there's nothing on it worth protecting and typing a password
+/// into a phone is the thing setup is meant to avoid
+pub const AP_SSID: &str = "Badger-Setup";
+pub const AP_ADDRESS: [u8; 4] = [192, 168, 4, 1];
+pub const AP_URL: &str = "http://192.168.4.1/";
+
+/// Big enough for the page with every field full
+pub const PAGE_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FormError {
+    Ssid,
+    Password,
+    TimeZone,
+    Location,
+    Network(IpConfigError),
+}
+
+impl FormError {
+    fn message(&self) -> &'static str {
+        match self {
+            FormError::Ssid => "The network name needs 1 to 32 characters.",
+            FormError::Password => {
+                "WPA passwords are 8 to 63 characters, or empty for an open network."
+            }
+            FormError::TimeZone => {
+                "That isn't a POSIX TZ rule, try something like CST6CDT,M3.2.0,M11.1.0."
+            }
+            FormError::Location => "Latitude and longitude need to be numbers, both or neither.",
+            FormError::Network(IpConfigError::Ipv4) => {
+                "The IPv4 address is blank for DHCP, off, or an address/prefix like 192.168.1.50/24."
+            }
+            FormError::Network(IpConfigError::Ipv6) => {
+                "The IPv6 address is blank, slaac, or an address/prefix like 2001:db8::50/64."
+            }
+            FormError::Network(IpConfigError::Gateway) => {
+                "A gateway needs to be an address, and only goes with a static address."
+            }
+            FormError::Network(IpConfigError::Dns) => {
+                "DNS servers are up to three addresses, separated by commas."
+            }
+        }
+    }
+}
+
+/// Checks a submitted form. A blank password keeps the one saved for the
+/// same network, so changing the time zone doesn't mean typing it again.
+pub fn apply_form(form: &[u8], current: &Settings) -> Result<Settings, FormError> {
+    let ssid: String<32> = form_field(form, "ssid").ok_or(FormError::Ssid)?;
+    if ssid.is_empty() {
+        return Err(FormError::Ssid);
+    }
+
+    let mut password: String<64> = form_field(form, "password").ok_or(FormError::Password)?;
+    if password.is_empty() && ssid == current.ssid {
+        password = current.password.clone();
+    } else if !password.is_empty() && !(8..=63).contains(&password.len()) {
+        return Err(FormError::Password);
+    }
+
+    let timezone: String<64> = form_field(form, "timezone").ok_or(FormError::TimeZone)?;
+    if !timezone.is_empty() && TimeZone::parse(&timezone).is_err() {
+        return Err(FormError::TimeZone);
+    }
+
+    let latitude: String<16> = form_field(form, "latitude").ok_or(FormError::Location)?;
+    let longitude: String<16> = form_field(form, "longitude").ok_or(FormError::Location)?;
+
+    let location = match (latitude.trim(), longitude.trim()) {
+        ("", "") => None,
+        (latitude, longitude) => {
+            let latitude: f32 = latitude.parse().map_err(|_| FormError::Location)?;
+            let longitude: f32 = longitude.parse().map_err(|_| FormError::Location)?;
+
+            if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+                return Err(FormError::Location);
+            }
+
+            Some(Location {
+                latitude,
+                longitude,
+            })
+        }
+    };
+
+    // Left out by pages saved before these fields, which means DHCP
+    let field = |name| form_field::<FIELD_SIZE>(form, name).unwrap_or_default();
+    let network = IpConfig::parse(
+        &field("ipv4"),
+        &field("gateway"),
+        &field("ipv6"),
+        &field("gateway6"),
+        &field("dns"),
+    )
+    .map_err(FormError::Network)?;
+
+    Ok(Settings {
+        ssid,
+        password,
+        timezone,
+        location,
+        network,
+    })
+}
+
+/// The form, filled in with what's saved apart from the password
+pub fn render_form(current: &Settings, error: Option<FormError>) -> String<PAGE_SIZE> {
+    let mut page = String::new();
+    // Every field is bounded, so this always fits
+    let _ = fill_form(&mut page, current, error);
+    page
+}
+
+fn fill_form(
+    page: &mut String<PAGE_SIZE>,
+    current: &Settings,
+    error: Option<FormError>,
+) -> Result<(), ()> {
+    page.push_str(
+        "<!DOCTYPE html><html><head><meta name=\"viewport\" content=\"width=device-width\">\
+         <title>Badger setup</title></head><body><h1>Badger setup</h1>",
+    )?;
+
+    if let Some(error) = error {
+        page.push_str("<p><b>")?;
+        page.push_str(error.message())?;
+        page.push_str("</b></p>")?;
+    }
+
+    page.push_str("<form method=\"post\" action=\"/save\"><p>WiFi network<br><input name=\"ssid\" maxlength=\"32\" value=\"")?;
+    push_escaped(page, &current.ssid)?;
+    page.push_str(
+        "\"></p><p>Password, blank keeps the saved one<br>\
+         <input name=\"password\" type=\"password\" maxlength=\"63\"></p>\
+         <p>Time zone, as a POSIX TZ rule<br><input name=\"timezone\" maxlength=\"64\" value=\"",
+    )?;
+    push_escaped(page, &current.timezone)?;
+    page.push_str("\"></p><p>Weather location<br><input name=\"latitude\" placeholder=\"latitude\" maxlength=\"16\" value=\"")?;
+
+    let (latitude, longitude): (String<16>, String<16>) = match current.location {
+        Some(location) => (
+            crate::helpers::easy_format(format_args!("{}", location.latitude)),
+            crate::helpers::easy_format(format_args!("{}", location.longitude)),
+        ),
+        None => (String::new(), String::new()),
+    };
+
+    page.push_str(&latitude)?;
+    page.push_str(
+        "\"> <input name=\"longitude\" placeholder=\"longitude\" maxlength=\"16\" value=\"",
+    )?;
+    page.push_str(&longitude)?;
+    page.push_str("\"></p>")?;
+
+    let [ipv4, gateway, ipv6, gateway6, dns] = current.network.fields();
+    for (label, name, value) in [
+        ("IPv4 address/prefix, blank for DHCP or off", "ipv4", ipv4),
+        ("IPv4 gateway", "gateway", gateway),
+        ("IPv6 address/prefix, blank, slaac or off", "ipv6", ipv6),
+        ("IPv6 gateway", "gateway6", gateway6),
+        ("DNS servers, blank for the network's", "dns", dns),
+    ] {
+        page.push_str("<p>")?;
+        page.push_str(label)?;
+        page.push_str("<br><input name=\"")?;
+        page.push_str(name)?;
+        page.push_str("\" maxlength=\"128\" value=\"")?;
+        page.push_str(&value)?;
+        page.push_str("\"></p>")?;
+    }
+
+    page.push_str("<p><button>Save and restart</button></p></form></body></html>")?;
+
+    Ok(())
+}
+
+const SAVED: &str = "<!DOCTYPE html><html><head><meta name=\"viewport\" content=\"width=device-width\">\
+    <title>Badger setup</title></head><body><h1>Saved</h1>\
+    <p>The badge is restarting and will join the network.</p></body></html>";
+
+/// Serves one request. Returns the new settings once the form is saved,
+/// which are up to the caller to store before restarting.
+pub async fn handle<C: Read + Write>(
+    conn: &mut C,
+    buf: &mut [u8],
+    current: &Settings,
+) -> Result<Option<Settings>, HttpdError> {
+    let request = match httpd::read_request(conn, buf).await {
+        Ok(request) => request,
+        Err(HttpdError::TooLarge) => {
+            httpd::respond(conn, 413, HTML, b"").await?;
+            return Err(HttpdError::TooLarge);
+        }
+        Err(e) => return Err(e),
+    };
+
+    match (request.method, request.path) {
+        (Method::Post, "/save") => match apply_form(request.body, current) {
+            Ok(settings) => {
+                httpd::respond(conn, 200, HTML, SAVED.as_bytes()).await?;
+                Ok(Some(settings))
+            }
+            Err(error) => {
+                let page = render_form(current, Some(error));
+                httpd::respond(conn, 400, HTML, page.as_bytes()).await?;
+                Ok(None)
+            }
+        },
+        (Method::Get, "/") => {
+            let page = render_form(current, None);
+            httpd::respond(conn, 200, HTML, page.as_bytes()).await?;
+            Ok(None)
+        }
+        // Phones probe a known page to spot a captive portal, so send
+        // those, and anything else, to the form
+        (Method::Get, _) => {
+            httpd::redirect(conn, AP_URL).await?;
+            Ok(None)
+        }
+        _ => {
+            httpd::respond(conn, 405, HTML, b"").await?;
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn saved() -> Settings {
+        Settings {
+            ssid: String::try_from("Home").unwrap(),
+            password: String::try_from("home pass").unwrap(),
+            timezone: String::new(),
+            location: None,
+            network: IpConfig::new(),
+        }
+    }
+
+    #[test]
+    fn accepts_a_full_form() {
+        let form = b"ssid=Office&password=corp-pass&timezone=CET-1CEST%2CM3.5.0%2CM10.5.0%2F3&latitude=52.52&longitude=13.405";
+        let settings = apply_form(form, &saved()).unwrap();
+
+        assert_eq!(settings.ssid, "Office");
+        assert_eq!(settings.password, "corp-pass");
+        assert_eq!(settings.timezone, "CET-1CEST,M3.5.0,M10.5.0/3");
+        assert_eq!(
+            settings.location,
+            Some(Location {
+                latitude: 52.52,
+                longitude: 13.405
+            })
+        );
+    }
+
+    #[test]
+    fn blank_password_keeps_the_saved_one() {
+        let form = b"ssid=Home&password=&timezone=&latitude=&longitude=";
+        assert_eq!(apply_form(form, &saved()).unwrap().password, "home pass");
+
+        // Unless it's a different network, which must then be open
+        let form = b"ssid=Cafe&password=&timezone=&latitude=&longitude=";
+        assert_eq!(apply_form(form, &saved()).unwrap().password, "");
+    }
+
+    #[test]
+    fn rejects_bad_fields() {
+        for (form, error) in [
+            (
+                &b"ssid=&password=&timezone=&latitude=&longitude="[..],
+                FormError::Ssid,
+            ),
+            (b"password=x", FormError::Ssid),
+            (
+                b"ssid=Cafe&password=short&timezone=&latitude=&longitude=",
+                FormError::Password,
+            ),
+            (
+                b"ssid=Cafe&password=&timezone=Mars&latitude=&longitude=",
+                FormError::TimeZone,
+            ),
+            (
+                b"ssid=Cafe&password=&timezone=&latitude=52&longitude=",
+                FormError::Location,
+            ),
+            (
+                b"ssid=Cafe&password=&timezone=&latitude=95&longitude=10",
+                FormError::Location,
+            ),
+        ] {
+            assert_eq!(apply_form(form, &saved()), Err(error));
+        }
+    }
+
+    #[test]
+    fn network_settings() {
+        let form = b"ssid=Lab&password=&timezone=&latitude=&longitude=&ipv4=off&gateway=&ipv6=2001%3Adb8%3A%3A50%2F64&gateway6=fe80%3A%3A1&dns=2001%3Adb8%3A%3A53";
+        let settings = apply_form(form, &saved()).unwrap();
+        assert_eq!(
+            settings.network,
+            IpConfig::parse("off", "", "2001:db8::50/64", "fe80::1", "2001:db8::53").unwrap()
+        );
+
+        let page = render_form(&settings, None);
+        assert!(page.contains("name=\"ipv4\" maxlength=\"128\" value=\"off\""));
+        assert!(page.contains("value=\"2001:db8::50/64\""));
+
+        let form = b"ssid=Lab&password=&timezone=&latitude=&longitude=&ipv4=10.0.0.5&gateway=&ipv6=&gateway6=&dns=";
+        assert_eq!(
+            apply_form(form, &saved()),
+            Err(FormError::Network(IpConfigError::Ipv4))
+        );
+    }
+
+    #[test]
+    fn form_is_prefilled_and_escaped() {
+        let settings = Settings {
+            ssid: String::try_from("\"><script>").unwrap(),
+            timezone: String::try_from("EST5EDT").unwrap(),
+            location: Some(Location {
+                latitude: 20.661,
+                longitude: -87.039,
+            }),
+            ..saved()
+        };
+
+        let page = render_form(&settings, Some(FormError::TimeZone));
+
+        assert!(page.contains("value=\"&quot;&gt;&lt;script&gt;\""));
+        assert!(page.contains("value=\"EST5EDT\""));
+        assert!(page.contains("value=\"-87.039\""));
+        assert!(page.contains("POSIX TZ rule, try"));
+        assert!(!page.contains("home pass"));
+        assert!(page.ends_with("</html>"));
+    }
+
+    #[test]
+    fn longest_form_fits() {
+        let settings = Settings {
+            ssid: String::try_from("&".repeat(32).as_str()).unwrap(),
+            password: String::new(),
+            timezone: String::try_from("&".repeat(64).as_str()).unwrap(),
+            location: Some(Location {
+                latitude: -89.12345,
+                longitude: -179.1234,
+            }),
+            network: IpConfig::parse(
+                "255.255.255.255/32",
+                "255.255.255.254",
+                "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff/128",
+                "ffff:ffff:ffff:ffff:ffff:ffff:ffff:fffe",
+                "ffff:ffff:ffff:ffff:ffff:ffff:ffff:fff1,ffff:ffff:ffff:ffff:ffff:ffff:ffff:fff2,ffff:ffff:ffff:ffff:ffff:ffff:ffff:fff3",
+            )
+            .unwrap(),
+        };
+
+        let page = render_form(&settings, Some(FormError::Password));
+        assert!(page.ends_with("</html>"), "{}", page.len());
+    }
+}
@@ -0,0 +1,269 @@
+//! RFC 3339 timestamps, e.g. `2025-10-17T14:05:09.123456-05:00`, a bit
+//! more forgiving than the spec: seconds may be missing and a space can
+//! stand in for the `T`
+
+use time::{Date, Month, OffsetDateTime, Time, UtcOffset};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Rfc3339Error {
+    Date,
+    Time,
+    Fraction,
+    Offset,
+    /// Something left over after the offset
+    Trailing,
+}
+
+pub fn parse(s: &str) -> Result<OffsetDateTime, Rfc3339Error> {
+    let mut p = Parser(s.as_bytes());
+
+    let year = p.digits(4, Rfc3339Error::Date)?;
+    p.expect(b'-', Rfc3339Error::Date)?;
+    let month = p.digits(2, Rfc3339Error::Date)?;
+    p.expect(b'-', Rfc3339Error::Date)?;
+    let day = p.digits(2, Rfc3339Error::Date)?;
+
+    let month = Month::try_from(month as u8).map_err(|_| Rfc3339Error::Date)?;
+    let date =
+        Date::from_calendar_date(year as i32, month, day as u8).map_err(|_| Rfc3339Error::Date)?;
+
+    match p.next() {
+        Some(b'T' | b't' | b' ') => {}
+        _ => return Err(Rfc3339Error::Time),
+    }
+
+    let hour = p.digits(2, Rfc3339Error::Time)?;
+    p.expect(b':', Rfc3339Error::Time)?;
+    let minute = p.digits(2, Rfc3339Error::Time)?;
+
+    let mut second = 0;
+    let mut nanos = 0;
+
+    if p.peek() == Some(b':') {
+        p.next();
+        second = p.digits(2, Rfc3339Error::Time)?;
+
+        if p.peek() == Some(b'.') {
+            p.next();
+            nanos = p.fraction()?;
+        }
+    }
+
+    // A leap second is as close to the next one as we can get
+    if second == 60 {
+        second = 59;
+        nanos = 999_999_999;
+    }
+
+    let time = Time::from_hms_nano(hour as u8, minute as u8, second as u8, nanos)
+        .map_err(|_| Rfc3339Error::Time)?;
+
+    let offset = p.offset()?;
+
+    if !p.0.is_empty() {
+        return Err(Rfc3339Error::Trailing);
+    }
+
+    Ok(date.with_time(time).assume_offset(offset))
+}
+
+/// `Z`, `+05:30`, `-0500` or `-05`
+pub fn parse_offset(s: &str) -> Result<UtcOffset, Rfc3339Error> {
+    let mut p = Parser(s.as_bytes());
+    let offset = p.offset()?;
+
+    match p.0.is_empty() {
+        true => Ok(offset),
+        false => Err(Rfc3339Error::Trailing),
+    }
+}
+
+struct Parser<'a>(&'a [u8]);
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.0.first().copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.0 = &self.0[1..];
+        Some(byte)
+    }
+
+    fn expect(&mut self, byte: u8, err: Rfc3339Error) -> Result<(), Rfc3339Error> {
+        match self.next() {
+            Some(b) if b == byte => Ok(()),
+            _ => Err(err),
+        }
+    }
+
+    /// Exactly `count` digits
+    fn digits(&mut self, count: usize, err: Rfc3339Error) -> Result<u32, Rfc3339Error> {
+        let mut n = 0;
+
+        for _ in 0..count {
+            match self.next() {
+                Some(d @ b'0'..=b'9') => n = n * 10 + (d - b'0') as u32,
+                _ => return Err(err),
+            }
+        }
+
+        Ok(n)
+    }
+
+    /// Any number of digits, past nanoseconds are dropped
+    fn fraction(&mut self) -> Result<u32, Rfc3339Error> {
+        let mut nanos = 0;
+        let mut scale = 100_000_000;
+        let mut any = false;
+
+        while let Some(d @ b'0'..=b'9') = self.peek() {
+            self.next();
+            nanos += (d - b'0') as u32 * scale;
+            scale /= 10;
+            any = true;
+        }
+
+        match any {
+            true => Ok(nanos),
+            false => Err(Rfc3339Error::Fraction),
+        }
+    }
+
+    fn offset(&mut self) -> Result<UtcOffset, Rfc3339Error> {
+        let sign = match self.next() {
+            Some(b'Z' | b'z') => return Ok(UtcOffset::UTC),
+            Some(b'+') => 1,
+            Some(b'-') => -1,
+            _ => return Err(Rfc3339Error::Offset),
+        };
+
+        let hours = self.digits(2, Rfc3339Error::Offset)? as i8;
+
+        let minutes = match self.peek() {
+            Some(b':') => {
+                self.next();
+                self.digits(2, Rfc3339Error::Offset)?
+            }
+            Some(b'0'..=b'9') => self.digits(2, Rfc3339Error::Offset)?,
+            _ => 0,
+        } as i8;
+
+        if hours > 14 || minutes > 59 {
+            return Err(Rfc3339Error::Offset);
+        }
+
+        UtcOffset::from_hms(sign * hours, sign * minutes, 0).map_err(|_| Rfc3339Error::Offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> (Date, Time) {
+        let when = parse(s).unwrap().to_offset(UtcOffset::UTC);
+        (when.date(), when.time())
+    }
+
+    fn date(day: u8) -> Date {
+        Date::from_calendar_date(2025, Month::October, day).unwrap()
+    }
+
+    #[test]
+    fn offsets() {
+        let local = parse("2025-10-17T14:05:09-05:00").unwrap();
+        assert_eq!(local.offset(), UtcOffset::from_hms(-5, 0, 0).unwrap());
+        assert_eq!(local.hour(), 14);
+
+        assert_eq!(
+            utc("2025-10-17T14:05:09-05:00"),
+            (date(17), Time::from_hms(19, 5, 9).unwrap())
+        );
+        assert_eq!(
+            utc("2025-10-17T14:05:09Z"),
+            (date(17), Time::from_hms(14, 5, 9).unwrap())
+        );
+        assert_eq!(
+            utc("2025-10-17T02:05:09+05:30"),
+            (date(16), Time::from_hms(20, 35, 9).unwrap())
+        );
+        assert_eq!(
+            utc("2025-10-17t14:05:09z"),
+            (date(17), Time::from_hms(14, 5, 9).unwrap())
+        );
+    }
+
+    #[test]
+    fn fractional_seconds() {
+        for (s, nanos) in [
+            ("2025-10-17T14:05:09.5Z", 500_000_000),
+            ("2025-10-17T14:05:09.517Z", 517_000_000),
+            ("2025-10-17T14:05:09.123456Z", 123_456_000),
+            ("2025-10-17T14:05:09.123456789123Z", 123_456_789),
+        ] {
+            assert_eq!(parse(s).unwrap().nanosecond(), nanos, "{s}");
+        }
+    }
+
+    #[test]
+    fn lenient_forms() {
+        assert_eq!(
+            utc("2025-10-17T14:05Z"),
+            (date(17), Time::from_hms(14, 5, 0).unwrap())
+        );
+        assert_eq!(
+            utc("2025-10-17 14:05:09+0000"),
+            (date(17), Time::from_hms(14, 5, 9).unwrap())
+        );
+        assert_eq!(
+            utc("2025-12-31T23:59:60Z"),
+            (
+                Date::from_calendar_date(2025, Month::December, 31).unwrap(),
+                Time::from_hms_nano(23, 59, 59, 999_999_999).unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_malformed() {
+        for (s, err) in [
+            ("", Rfc3339Error::Date),
+            ("2025-13-17T14:05:09Z", Rfc3339Error::Date),
+            ("2025-02-30T14:05:09Z", Rfc3339Error::Date),
+            ("25-10-17T14:05:09Z", Rfc3339Error::Date),
+            ("2025-10-17", Rfc3339Error::Time),
+            ("2025-10-17T24:05:09Z", Rfc3339Error::Time),
+            ("2025-10-17T14:5:09Z", Rfc3339Error::Time),
+            ("2025-10-17T14:05:09.Z", Rfc3339Error::Fraction),
+            ("2025-10-17T14:05:09", Rfc3339Error::Offset),
+            ("2025-10-17T14:05:09+25:00", Rfc3339Error::Offset),
+            ("2025-10-17T14:05:09Z junk", Rfc3339Error::Trailing),
+            ("<html>Sign in</html>", Rfc3339Error::Date),
+        ] {
+            assert_eq!(parse(s), Err(err), "{s}");
+        }
+    }
+
+    #[test]
+    fn utc_offsets() {
+        assert_eq!(parse_offset("Z"), Ok(UtcOffset::UTC));
+        assert_eq!(
+            parse_offset("+05:30"),
+            UtcOffset::from_hms(5, 30, 0).map_err(|_| Rfc3339Error::Offset)
+        );
+        assert_eq!(
+            parse_offset("-05:00"),
+            UtcOffset::from_hms(-5, 0, 0).map_err(|_| Rfc3339Error::Offset)
+        );
+        assert_eq!(
+            parse_offset("-03"),
+            UtcOffset::from_hms(-3, 0, 0).map_err(|_| Rfc3339Error::Offset)
+        );
+        assert_eq!(parse_offset("05:00"), Err(Rfc3339Error::Offset));
+        assert_eq!(parse_offset("+25:00"), Err(Rfc3339Error::Offset));
+        assert_eq!(parse_offset("+05:00x"), Err(Rfc3339Error::Trailing));
+    }
+}
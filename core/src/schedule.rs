@@ -0,0 +1,273 @@
+//! When the badge wakes itself up, and what it does when it does
+
+use time::{Duration, PrimitiveDateTime, Time};
+
+use crate::{hal::Alarm, layout::Screen, tz::TimeZone};
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+/// No wakes from `start` until `end`, local time, which may span midnight.
+/// The wake at `end` syncs and redraws everything.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct QuietHours {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl QuietHours {
+    /// `HH:MM-HH:MM`
+    pub fn parse(s: &str) -> Option<Self> {
+        let (start, end) = s.split_once('-')?;
+        let quiet = Self {
+            start: parse_minute(start)?,
+            end: parse_minute(end)?,
+        };
+
+        (quiet.start != quiet.end).then_some(quiet)
+    }
+
+    pub fn contains(&self, minute: u16) -> bool {
+        if self.start < self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+fn parse_minute(s: &str) -> Option<u16> {
+    let (hour, minute) = s.trim().split_once(':')?;
+    let time = Time::from_hms(hour.parse().ok()?, minute.parse().ok()?, 0).ok()?;
+    Some(minute_of_day(time))
+}
+
+fn minute_of_day(time: Time) -> u16 {
+    time.hour() as u16 * 60 + time.minute() as u16
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Schedule {
+    /// Minutes between top bar refreshes, counted from midnight
+    pub refresh_minutes: u16,
+    /// Minutes between wifi syncs, also counted from midnight
+    pub sync_minutes: u16,
+    pub quiet: Option<QuietHours>,
+}
+
+impl Schedule {
+    /// Every minute, syncing on the hour
+    pub const DEFAULT: Self = Self {
+        refresh_minutes: 1,
+        sync_minutes: 60,
+        quiet: None,
+    };
+
+    /// Whether a wake at `local` syncs, and what it redraws. The first
+    /// wake at or after each sync boundary syncs, so the intervals
+    /// don't need to line up.
+    pub fn plan(&self, local: PrimitiveDateTime) -> (Screen, bool) {
+        let minute = minute_of_day(local.time());
+        let refresh = self.refresh_minutes.clamp(1, MINUTES_PER_DAY);
+        let sync = self.sync_minutes.clamp(1, MINUTES_PER_DAY);
+
+        let morning = self.quiet.is_some_and(|quiet| quiet.end == minute);
+
+        if morning || minute % sync < refresh {
+            (Screen::Full, true)
+        } else {
+            (Screen::TopBar, false)
+        }
+    }
+
+    /// The next wake strictly after `local`, skipping quiet hours
+    pub fn next_wake(&self, local: PrimitiveDateTime) -> PrimitiveDateTime {
+        let refresh = self.refresh_minutes.clamp(1, MINUTES_PER_DAY);
+        let minute = minute_of_day(local.time());
+        let midnight = local.replace_time(Time::MIDNIGHT);
+
+        // Wakes fall on multiples of `refresh` from midnight, and midnight itself
+        let next = (minute / refresh + 1) * refresh;
+        let mut next = match next < MINUTES_PER_DAY {
+            true => midnight + Duration::minutes(next as i64),
+            false => midnight + Duration::days(1),
+        };
+
+        if let Some(quiet) = self.quiet
+            && quiet.contains(minute_of_day(next.time()))
+        {
+            let end = next.replace_time(Time::MIDNIGHT) + Duration::minutes(quiet.end as i64);
+            next = match end > local {
+                true => end,
+                false => end + Duration::days(1),
+            };
+        }
+
+        next
+    }
+
+    /// The RTC alarm for the next wake after `utc`. A DST change before
+    /// then moves it by the difference, which the wake after corrects.
+    pub fn next_alarm(&self, utc: PrimitiveDateTime, tz: &TimeZone) -> Alarm {
+        let local = tz.to_local(utc);
+        let next = utc + (self.next_wake(local) - local);
+
+        Alarm {
+            second: Some(0),
+            minute: Some(next.minute()),
+            hour: Some(next.hour()),
+        }
+    }
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::{Date, Month};
+
+    fn at(day: u8, hour: u8, minute: u8, second: u8) -> PrimitiveDateTime {
+        PrimitiveDateTime::new(
+            Date::from_calendar_date(2025, Month::October, day).unwrap(),
+            Time::from_hms(hour, minute, second).unwrap(),
+        )
+    }
+
+    fn overnight() -> Schedule {
+        Schedule {
+            refresh_minutes: 5,
+            sync_minutes: 60,
+            quiet: QuietHours::parse("23:00-07:00"),
+        }
+    }
+
+    #[test]
+    fn default_wakes_every_minute() {
+        let schedule = Schedule::DEFAULT;
+
+        assert_eq!(schedule.next_wake(at(17, 14, 5, 0)), at(17, 14, 6, 0));
+        assert_eq!(schedule.next_wake(at(17, 14, 5, 59)), at(17, 14, 6, 0));
+        assert_eq!(schedule.next_wake(at(17, 23, 59, 30)), at(18, 0, 0, 0));
+
+        assert_eq!(schedule.plan(at(17, 14, 0, 2)), (Screen::Full, true));
+        assert_eq!(schedule.plan(at(17, 14, 1, 2)), (Screen::TopBar, false));
+    }
+
+    #[test]
+    fn refresh_interval() {
+        let schedule = Schedule {
+            refresh_minutes: 15,
+            ..Schedule::DEFAULT
+        };
+
+        assert_eq!(schedule.next_wake(at(17, 14, 0, 3)), at(17, 14, 15, 0));
+        assert_eq!(schedule.next_wake(at(17, 14, 14, 59)), at(17, 14, 15, 0));
+        assert_eq!(schedule.next_wake(at(17, 23, 45, 0)), at(18, 0, 0, 0));
+
+        // Not dividing the day evenly, midnight still gets a wake
+        let schedule = Schedule {
+            refresh_minutes: 7 * 60,
+            ..Schedule::DEFAULT
+        };
+        assert_eq!(schedule.next_wake(at(17, 14, 0, 0)), at(17, 21, 0, 0));
+        assert_eq!(schedule.next_wake(at(17, 21, 0, 0)), at(18, 0, 0, 0));
+    }
+
+    #[test]
+    fn sync_interval_that_doesnt_line_up() {
+        let schedule = Schedule {
+            refresh_minutes: 7,
+            sync_minutes: 30,
+            quiet: None,
+        };
+
+        // Wakes at 14:00 (840), 14:07, ... 14:28, 14:35 (875 % 30 = 5)
+        assert_eq!(schedule.plan(at(17, 14, 0, 0)), (Screen::Full, true));
+        assert_eq!(schedule.plan(at(17, 14, 28, 0)), (Screen::TopBar, false));
+        assert_eq!(schedule.plan(at(17, 14, 35, 0)), (Screen::Full, true));
+        assert_eq!(schedule.plan(at(17, 14, 42, 0)), (Screen::TopBar, false));
+    }
+
+    #[test]
+    fn quiet_hours_skip_to_morning() {
+        let schedule = overnight();
+
+        assert_eq!(schedule.next_wake(at(17, 22, 50, 0)), at(17, 22, 55, 0));
+        assert_eq!(schedule.next_wake(at(17, 22, 55, 0)), at(18, 7, 0, 0));
+        assert_eq!(schedule.next_wake(at(17, 23, 30, 0)), at(18, 7, 0, 0));
+        assert_eq!(schedule.next_wake(at(18, 3, 0, 0)), at(18, 7, 0, 0));
+        assert_eq!(schedule.next_wake(at(18, 6, 59, 59)), at(18, 7, 0, 0));
+        assert_eq!(schedule.next_wake(at(18, 7, 0, 4)), at(18, 7, 5, 0));
+
+        assert_eq!(schedule.plan(at(18, 7, 0, 4)), (Screen::Full, true));
+    }
+
+    #[test]
+    fn quiet_hours_within_a_day() {
+        let schedule = Schedule {
+            quiet: QuietHours::parse("12:30-13:15"),
+            ..overnight()
+        };
+
+        assert_eq!(schedule.next_wake(at(17, 12, 25, 0)), at(17, 13, 15, 0));
+        assert_eq!(schedule.next_wake(at(17, 13, 15, 0)), at(17, 13, 20, 0));
+        assert_eq!(schedule.plan(at(17, 13, 15, 0)), (Screen::Full, true));
+        assert_eq!(schedule.plan(at(17, 13, 20, 0)), (Screen::TopBar, false));
+    }
+
+    #[test]
+    fn parses_quiet_hours() {
+        assert_eq!(
+            QuietHours::parse("23:00-07:30"),
+            Some(QuietHours {
+                start: 23 * 60,
+                end: 7 * 60 + 30
+            })
+        );
+        assert_eq!(QuietHours::parse("7:00 - 8:00").map(|q| q.end), Some(480));
+        assert_eq!(QuietHours::parse("23:00"), None);
+        assert_eq!(QuietHours::parse("24:00-07:00"), None);
+        assert_eq!(QuietHours::parse("07:00-07:00"), None);
+    }
+
+    #[test]
+    fn alarm_is_in_rtc_time() {
+        let chicago = TimeZone::parse("CST6CDT,M3.2.0,M11.1.0").unwrap();
+
+        // 03:50 UTC is 22:50 CDT, next wake 22:55 local
+        assert_eq!(
+            overnight().next_alarm(at(17, 3, 50, 10), &chicago),
+            Alarm {
+                second: Some(0),
+                minute: Some(55),
+                hour: Some(3),
+            }
+        );
+
+        // 22:55 CDT sleeps until 07:00 CDT, which is 12:00 UTC
+        assert_eq!(
+            overnight().next_alarm(at(17, 3, 55, 0), &chicago),
+            Alarm {
+                second: Some(0),
+                minute: Some(0),
+                hour: Some(12),
+            }
+        );
+
+        let india = TimeZone::parse("<+0530>-5:30").unwrap();
+        assert_eq!(
+            Schedule::DEFAULT.next_alarm(at(17, 8, 29, 59), &india),
+            Alarm {
+                second: Some(0),
+                minute: Some(30),
+                hour: Some(8),
+            }
+        );
+    }
+}
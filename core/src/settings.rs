@@ -0,0 +1,122 @@
+//! Settings entered on the setup page, kept in their own flash sector.
+//! Anything left empty falls back to what the firmware was built with.
+
+use heapless::String;
+use serde::{Deserialize, Serialize};
+
+use crate::{hal::Store, helpers::easy_format, ipconfig::IpConfig};
+
+/// Big enough for any `Settings`
+pub const SETTINGS_SIZE: usize = 512;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Location {
+    pub latitude: f32,
+    pub longitude: f32,
+}
+
+impl Location {
+    /// Current weather from Open-Meteo, the same request `TEMP_API` makes
+    pub fn weather_url(&self) -> String<160> {
+        easy_format::<160>(format_args!(
+            "http://api.open-meteo.com/v1/forecast?latitude={}&longitude={}&current=temperature,weathercode,is_day",
+            self.latitude, self.longitude
+        ))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Settings {
+    pub ssid: String<32>,
+    /// Empty for an open network
+    pub password: String<64>,
+    /// POSIX TZ rule, see [`crate::tz::TimeZone::parse`]
+    pub timezone: String<64>,
+    pub location: Option<Location>,
+    pub network: IpConfig,
+}
+
+impl Settings {
+    pub const fn new() -> Self {
+        Self {
+            ssid: String::new(),
+            password: String::new(),
+            timezone: String::new(),
+            location: None,
+            network: IpConfig::new(),
+        }
+    }
+
+    pub fn encode<'a>(&self, buf: &'a mut [u8]) -> Option<&'a mut [u8]> {
+        postcard::to_slice(self, buf).ok()
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        postcard::from_bytes(buf).ok()
+    }
+}
+
+pub async fn load<S: Store>(store: &mut S) -> Option<Settings> {
+    let mut buf = [0u8; SETTINGS_SIZE];
+    store.read(&mut buf).await.ok()?;
+    Settings::decode(&buf)
+}
+
+/// Returns false if the settings didn't fit or the store refused them
+pub async fn save<S: Store>(store: &mut S, settings: &Settings) -> bool {
+    let mut buf = [0u8; SETTINGS_SIZE];
+
+    match settings.encode(&mut buf) {
+        Some(slice) => store.write(slice).await.is_ok(),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let settings = Settings {
+            ssid: String::try_from("Conference Guest").unwrap(),
+            password: String::try_from("a".repeat(63).as_str()).unwrap(),
+            timezone: String::try_from("<+0530>-5:30").unwrap(),
+            location: Some(Location {
+                latitude: 20.661,
+                longitude: -87.039,
+            }),
+            network: IpConfig::parse(
+                "192.168.100.200/24",
+                "192.168.100.1",
+                "2001:db8:ffff:ffff::1234/64",
+                "fe80::ffff:ffff:ffff:ffff",
+                "2001:db8::1,2001:db8::2,2001:db8::3",
+            )
+            .unwrap(),
+        };
+
+        let mut buf = [0u8; SETTINGS_SIZE];
+        let encoded = settings.encode(&mut buf).unwrap();
+        assert_eq!(Settings::decode(encoded), Some(settings));
+    }
+
+    #[test]
+    fn erased_flash_is_not_settings() {
+        assert!(Settings::decode(&[0xFF; SETTINGS_SIZE]).is_none());
+    }
+
+    #[test]
+    fn weather_url() {
+        let location = Location {
+            latitude: 20.661,
+            longitude: -87.039,
+        };
+
+        assert_eq!(
+            location.weather_url(),
+            "http://api.open-meteo.com/v1/forecast?latitude=20.661&longitude=-87.039&current=temperature,weathercode,is_day"
+        );
+    }
+}
@@ -0,0 +1,292 @@
+//! IPv6 stateless address autoconfiguration (RFC 4862): ask the routers
+//! on the link to advertise themselves, then make an address from the
+//! advertised prefix and the MAC address. Packets are whole IPv6 packets,
+//! as a raw socket sees them.
+
+use core::net::Ipv6Addr;
+
+pub const ALL_ROUTERS: Ipv6Addr = Ipv6Addr::new(0xFF02, 0, 0, 0, 0, 0, 0, 2);
+pub const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xFF02, 0, 0, 0, 0, 0, 0, 1);
+
+const IPV6_HEADER: usize = 40;
+const NEXT_HEADER_ICMPV6: u8 = 58;
+/// Neighbour discovery only takes packets that haven't crossed a router
+const HOP_LIMIT: u8 = 255;
+
+const ROUTER_SOLICITATION: u8 = 133;
+const ROUTER_ADVERTISEMENT: u8 = 134;
+
+const OPTION_PREFIX: u8 = 3;
+const OPTION_RDNSS: u8 = 25;
+/// Prefix flag saying addresses may be made from it
+const AUTONOMOUS: u8 = 0x40;
+
+pub const SOLICITATION_SIZE: usize = IPV6_HEADER + 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Advertisement {
+    /// Link local, the default gateway
+    pub router: Ipv6Addr,
+    /// How long it stays the default gateway, zero for not at all
+    pub router_lifetime_secs: u16,
+    /// The first /64 marked for autoconfiguration
+    pub prefix: Option<Ipv6Addr>,
+    /// The first recursive DNS server (RFC 8106)
+    pub dns: Option<Ipv6Addr>,
+}
+
+/// A router solicitation to all routers, from the unspecified address
+pub fn solicitation() -> [u8; SOLICITATION_SIZE] {
+    let mut packet = [0; SOLICITATION_SIZE];
+
+    packet[0] = 0x60;
+    packet[4..6].copy_from_slice(&8u16.to_be_bytes());
+    packet[6] = NEXT_HEADER_ICMPV6;
+    packet[7] = HOP_LIMIT;
+    // The source stays ::
+    packet[24..40].copy_from_slice(&ALL_ROUTERS.octets());
+
+    packet[IPV6_HEADER] = ROUTER_SOLICITATION;
+    let checksum = checksum(&packet);
+    packet[IPV6_HEADER + 2..IPV6_HEADER + 4].copy_from_slice(&checksum.to_be_bytes());
+
+    packet
+}
+
+/// The ICMPv6 checksum over the pseudo header and `packet`'s payload,
+/// zero when a received one is right
+fn checksum(packet: &[u8]) -> u16 {
+    let payload = &packet[IPV6_HEADER..];
+    let mut sum: u32 = 0;
+
+    let mut add = |bytes: &[u8]| {
+        for pair in bytes.chunks(2) {
+            sum += u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]) as u32;
+        }
+    };
+
+    // Source and destination, then the length and next header
+    add(&packet[8..40]);
+    add(&(payload.len() as u32).to_be_bytes());
+    add(&[0, 0, 0, NEXT_HEADER_ICMPV6]);
+    add(payload);
+
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// `None` if `packet` isn't a router advertisement, or a forged one
+pub fn advertisement(packet: &[u8]) -> Option<Advertisement> {
+    if packet.len() < IPV6_HEADER + 16
+        || packet[0] >> 4 != 6
+        || packet[6] != NEXT_HEADER_ICMPV6
+        || packet[7] != HOP_LIMIT
+    {
+        return None;
+    }
+
+    let payload_len = u16::from_be_bytes([packet[4], packet[5]]) as usize;
+    let packet = packet.get(..IPV6_HEADER + payload_len)?;
+    let icmp = &packet[IPV6_HEADER..];
+
+    if icmp[0] != ROUTER_ADVERTISEMENT || icmp[1] != 0 || checksum(packet) != 0 {
+        return None;
+    }
+
+    let router = Ipv6Addr::from(<[u8; 16]>::try_from(&packet[8..24]).ok()?);
+    let mut advertisement = Advertisement {
+        router,
+        router_lifetime_secs: u16::from_be_bytes([icmp[6], icmp[7]]),
+        prefix: None,
+        dns: None,
+    };
+
+    // Type, length in 8 byte units, then the rest
+    let mut options = &icmp[16..];
+    while options.len() >= 8 {
+        let len = options[1] as usize * 8;
+        if len == 0 || len > options.len() {
+            return None;
+        }
+        let option = &options[..len];
+
+        match option[0] {
+            OPTION_PREFIX if len == 32 && option[2] == 64 && option[3] & AUTONOMOUS != 0 => {
+                let prefix = Ipv6Addr::from(<[u8; 16]>::try_from(&option[16..32]).ok()?);
+                advertisement.prefix.get_or_insert(prefix);
+            }
+            OPTION_RDNSS if len >= 24 => {
+                let dns = Ipv6Addr::from(<[u8; 16]>::try_from(&option[8..24]).ok()?);
+                advertisement.dns.get_or_insert(dns);
+            }
+            _ => {}
+        }
+
+        options = &options[len..];
+    }
+
+    Some(advertisement)
+}
+
+/// The /64 `prefix` with an interface id made from `mac` (modified EUI-64)
+pub fn address(prefix: Ipv6Addr, mac: [u8; 6]) -> Ipv6Addr {
+    let mut octets = prefix.octets();
+    octets[8..].copy_from_slice(&[
+        mac[0] ^ 0x02,
+        mac[1],
+        mac[2],
+        0xFF,
+        0xFE,
+        mac[3],
+        mac[4],
+        mac[5],
+    ]);
+    Ipv6Addr::from(octets)
+}
+
+/// Ethernet multicast for an IPv6 multicast group, for the radio's filter
+pub fn multicast_mac(group: Ipv6Addr) -> [u8; 6] {
+    let octets = group.octets();
+    [0x33, 0x33, octets[12], octets[13], octets[14], octets[15]]
+}
+
+/// Where neighbours ask for `address`'s MAC (RFC 4291)
+pub fn solicited_node(address: Ipv6Addr) -> Ipv6Addr {
+    let octets = address.octets();
+    Ipv6Addr::new(
+        0xFF02,
+        0,
+        0,
+        0,
+        0,
+        1,
+        0xFF00 | octets[13] as u16,
+        u16::from_be_bytes([octets[14], octets[15]]),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x28, 0xCD, 0xC1, 0x0A, 0x0B, 0x0C];
+    const ROUTER: Ipv6Addr = Ipv6Addr::new(0xFE80, 0, 0, 0, 0x1, 0x2, 0x3, 0x4);
+
+    fn advertised(options: &[&[u8]]) -> std::vec::Vec<u8> {
+        let body: std::vec::Vec<u8> = [
+            &[
+                ROUTER_ADVERTISEMENT,
+                0,
+                0,
+                0,
+                64,
+                0,
+                0x07,
+                0x08,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ][..],
+            &options.concat(),
+        ]
+        .concat();
+
+        let mut packet = vec![0x60, 0, 0, 0];
+        packet.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&[NEXT_HEADER_ICMPV6, HOP_LIMIT]);
+        packet.extend_from_slice(&ROUTER.octets());
+        packet.extend_from_slice(&ALL_NODES.octets());
+        packet.extend_from_slice(&body);
+
+        let checksum = checksum(&packet);
+        packet[IPV6_HEADER + 2..IPV6_HEADER + 4].copy_from_slice(&checksum.to_be_bytes());
+        packet
+    }
+
+    fn prefix(prefix_len: u8, flags: u8) -> std::vec::Vec<u8> {
+        let mut option = vec![OPTION_PREFIX, 4, prefix_len, flags];
+        option.extend_from_slice(&[0, 0, 0x0E, 0x10, 0, 0, 0x07, 0x08, 0, 0, 0, 0]);
+        option.extend_from_slice(&"2001:db8:1:2::".parse::<Ipv6Addr>().unwrap().octets());
+        option
+    }
+
+    fn rdnss() -> std::vec::Vec<u8> {
+        let mut option = vec![OPTION_RDNSS, 3, 0, 0, 0, 0, 0x0E, 0x10];
+        option.extend_from_slice(&"2001:db8::53".parse::<Ipv6Addr>().unwrap().octets());
+        option
+    }
+
+    #[test]
+    fn solicits_routers() {
+        let packet = solicitation();
+
+        assert_eq!(packet[..8], [0x60, 0, 0, 0, 0, 8, 58, 255]);
+        assert_eq!(packet[8..24], [0; 16]);
+        assert_eq!(packet[24..40], ALL_ROUTERS.octets());
+        assert_eq!(packet[40], ROUTER_SOLICITATION);
+        assert_eq!(checksum(&packet), 0);
+    }
+
+    #[test]
+    fn reads_advertisements() {
+        // A source link layer address option, which is skipped
+        let source: &[u8] = &[1, 1, 0x28, 0xCD, 0xC1, 0, 0, 1];
+        let packet = advertised(&[source, &prefix(64, 0xC0), &rdnss()]);
+
+        assert_eq!(
+            advertisement(&packet),
+            Some(Advertisement {
+                router: ROUTER,
+                router_lifetime_secs: 0x0708,
+                prefix: Some("2001:db8:1:2::".parse().unwrap()),
+                dns: Some("2001:db8::53".parse().unwrap()),
+            })
+        );
+    }
+
+    #[test]
+    fn only_autonomous_64s_make_addresses() {
+        let packet = advertised(&[&prefix(64, 0x80), &prefix(56, 0xC0)]);
+        assert_eq!(advertisement(&packet).unwrap().prefix, None);
+    }
+
+    #[test]
+    fn ignores_other_packets() {
+        let mut packet = advertised(&[&prefix(64, 0xC0)]);
+        packet[7] = 64;
+        assert_eq!(advertisement(&packet), None);
+
+        let mut packet = advertised(&[&prefix(64, 0xC0)]);
+        packet[IPV6_HEADER + 20] ^= 1;
+        assert_eq!(advertisement(&packet), None, "bad checksum");
+
+        let mut packet = advertised(&[&prefix(64, 0xC0)]);
+        packet.truncate(60);
+        assert_eq!(advertisement(&packet), None);
+
+        assert_eq!(advertisement(&solicitation()), None);
+    }
+
+    #[test]
+    fn addresses() {
+        let address = address("2001:db8:1:2::".parse().unwrap(), MAC);
+        assert_eq!(
+            address,
+            "2001:db8:1:2:2acd:c1ff:fe0a:b0c"
+                .parse::<Ipv6Addr>()
+                .unwrap()
+        );
+
+        let group = solicited_node(address);
+        assert_eq!(group, "ff02::1:ff0a:b0c".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(multicast_mac(group), [0x33, 0x33, 0xFF, 0x0A, 0x0B, 0x0C]);
+        assert_eq!(multicast_mac(ALL_NODES), [0x33, 0x33, 0, 0, 0, 1]);
+    }
+}
@@ -0,0 +1,341 @@
+//! A minimal SNTP (RFC 4330) client
+
+use core::net::{IpAddr, SocketAddr};
+
+use time::{Date, Duration, Month, PrimitiveDateTime, Time};
+
+use crate::hal::Udp;
+
+pub const NTP_PORT: u16 = 123;
+pub const PACKET_SIZE: usize = 48;
+
+const MICROS: i64 = 1_000_000;
+
+/// LI = 0, VN = 4, Mode = 3 (client)
+const CLIENT_HEADER: u8 = 0b00_100_011;
+const MODE_SERVER: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SntpError<E> {
+    Udp(E),
+    /// Nothing usable in the server list
+    NoServers,
+    Malformed,
+    /// Stratum 0, the server wants us to go away
+    KissOfDeath,
+    /// The reply doesn't echo our request, or isn't from a server
+    Unexpected,
+}
+
+/// NTP 32.32 fixed point seconds since 1900-01-01
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamp(pub u64);
+
+impl Timestamp {
+    fn epoch() -> PrimitiveDateTime {
+        PrimitiveDateTime::new(
+            Date::from_calendar_date(1900, Month::January, 1).unwrap(),
+            Time::MIDNIGHT,
+        )
+    }
+
+    pub fn from_datetime(when: PrimitiveDateTime) -> Self {
+        let since = when - Self::epoch();
+        let seconds = since.whole_seconds() as u64;
+        let fraction = ((since.subsec_microseconds() as u64) << 32) / MICROS as u64;
+
+        Self((seconds << 32) | fraction)
+    }
+
+    /// Timestamps before 1968 are taken to be from the era after
+    /// the 32 bit seconds counter wraps in 2036
+    pub fn micros_since_epoch(self) -> i64 {
+        let mut seconds = (self.0 >> 32) as i64;
+        if seconds < 0x8000_0000 {
+            seconds += 1 << 32;
+        }
+
+        let fraction = (((self.0 & 0xFFFF_FFFF) * MICROS as u64 + (1 << 31)) >> 32) as i64;
+
+        seconds * MICROS + fraction
+    }
+
+    pub fn to_datetime(self) -> PrimitiveDateTime {
+        Self::epoch() + Duration::microseconds(self.micros_since_epoch())
+    }
+
+    fn read(buf: &[u8]) -> Self {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&buf[..8]);
+        Self(u64::from_be_bytes(bytes))
+    }
+}
+
+pub fn request(transmit: Timestamp) -> [u8; PACKET_SIZE] {
+    let mut packet = [0; PACKET_SIZE];
+    packet[0] = CLIENT_HEADER;
+    packet[40..48].copy_from_slice(&transmit.0.to_be_bytes());
+    packet
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Reply {
+    pub stratum: u8,
+    pub originate: Timestamp,
+    pub receive: Timestamp,
+    pub transmit: Timestamp,
+}
+
+pub fn parse_reply<E>(buf: &[u8], sent: Timestamp) -> Result<Reply, SntpError<E>> {
+    if buf.len() < PACKET_SIZE {
+        return Err(SntpError::Malformed);
+    }
+
+    if buf[0] & 0b111 != MODE_SERVER {
+        return Err(SntpError::Unexpected);
+    }
+
+    let reply = Reply {
+        stratum: buf[1],
+        originate: Timestamp::read(&buf[24..]),
+        receive: Timestamp::read(&buf[32..]),
+        transmit: Timestamp::read(&buf[40..]),
+    };
+
+    if reply.stratum == 0 {
+        return Err(SntpError::KissOfDeath);
+    }
+
+    if reply.originate != sent {
+        return Err(SntpError::Unexpected);
+    }
+
+    if reply.transmit.0 == 0 {
+        return Err(SntpError::Malformed);
+    }
+
+    Ok(reply)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Measurement {
+    /// UTC when the reply arrived
+    pub time: PrimitiveDateTime,
+    /// Round trip time, less the time the server held on to the request
+    pub delay_micros: i64,
+    /// How far ahead the server is of our own clock, if we had one
+    pub offset_micros: Option<i64>,
+}
+
+/// `t1` and `t4` are our own monotonic send and receive times, `local` is
+/// our wall clock (UTC) at `t1`
+pub fn measure(reply: &Reply, t1: u64, t4: u64, local: Option<PrimitiveDateTime>) -> Measurement {
+    let server_hold = reply.transmit.micros_since_epoch() - reply.receive.micros_since_epoch();
+    let round_trip = (t4 - t1) as i64;
+    let delay_micros = (round_trip - server_hold).max(0);
+
+    let time = reply.transmit.to_datetime() + Duration::microseconds(delay_micros / 2);
+
+    let offset_micros = local.map(|local| {
+        let t1 = Timestamp::from_datetime(local).micros_since_epoch();
+        let t4 = t1 + round_trip;
+
+        ((reply.receive.micros_since_epoch() - t1) + (reply.transmit.micros_since_epoch() - t4)) / 2
+    });
+
+    Measurement {
+        time,
+        delay_micros,
+        offset_micros,
+    }
+}
+
+/// Splits `host`, `host:port` or `[v6]:port`, defaulting to the NTP port
+pub fn split_server(server: &str) -> (&str, u16) {
+    let server = server.trim();
+
+    if let Ok(addr) = server.parse::<IpAddr>()
+        && addr.is_ipv6()
+    {
+        return (server, NTP_PORT);
+    }
+
+    match server.rsplit_once(':') {
+        Some((host, port)) => match port.parse() {
+            Ok(port) => (host.trim_start_matches('[').trim_end_matches(']'), port),
+            Err(_) => (server, NTP_PORT),
+        },
+        None => (server, NTP_PORT),
+    }
+}
+
+/// Asks each server in the comma separated list in turn,
+/// returning the first good answer
+pub async fn query<U: Udp>(
+    udp: &U,
+    servers: &str,
+    now_micros: impl Fn() -> u64,
+    local: Option<PrimitiveDateTime>,
+) -> Result<Measurement, SntpError<U::Error>> {
+    let mut result = Err(SntpError::NoServers);
+
+    for server in servers.split(',').filter(|s| !s.trim().is_empty()) {
+        let (host, port) = split_server(server);
+
+        let ip = match host.parse::<IpAddr>() {
+            Ok(ip) => ip,
+            Err(_) => match udp.resolve(host).await {
+                Ok(ip) => ip,
+                Err(e) => {
+                    result = Err(SntpError::Udp(e));
+                    continue;
+                }
+            },
+        };
+
+        result = query_server(udp, SocketAddr::new(ip, port), &now_micros, local).await;
+
+        if result.is_ok() {
+            break;
+        }
+    }
+
+    result
+}
+
+async fn query_server<U: Udp>(
+    udp: &U,
+    server: SocketAddr,
+    now_micros: &impl Fn() -> u64,
+    local: Option<PrimitiveDateTime>,
+) -> Result<Measurement, SntpError<U::Error>> {
+    let t1 = now_micros();
+
+    // Without a clock worth sending, any unique value will do,
+    // the server just echoes it back
+    let sent = match local {
+        Some(local) => Timestamp::from_datetime(local),
+        None => Timestamp(t1),
+    };
+
+    let mut response = [0; PACKET_SIZE];
+    let len = udp
+        .exchange(server, &request(sent), &mut response)
+        .await
+        .map_err(SntpError::Udp)?;
+
+    let t4 = now_micros();
+    let reply = parse_reply(&response[..len], sent)?;
+
+    Ok(measure(&reply, t1, t4, local))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hour: u8, minute: u8, second: u8, micros: u32) -> PrimitiveDateTime {
+        PrimitiveDateTime::new(
+            Date::from_calendar_date(2025, Month::October, 17).unwrap(),
+            Time::from_hms_micro(hour, minute, second, micros).unwrap(),
+        )
+    }
+
+    fn reply(receive: PrimitiveDateTime, transmit: PrimitiveDateTime) -> Reply {
+        Reply {
+            stratum: 2,
+            originate: Timestamp(0),
+            receive: Timestamp::from_datetime(receive),
+            transmit: Timestamp::from_datetime(transmit),
+        }
+    }
+
+    #[test]
+    fn timestamp_round_trip() {
+        let when = at(14, 5, 9, 250_000);
+        let timestamp = Timestamp::from_datetime(when);
+
+        // 2025-10-17 14:05:09 UTC is 3969698709 seconds after 1900
+        assert_eq!(timestamp.0 >> 32, 3_969_698_709);
+        assert_eq!(timestamp.0 & 0xFFFF_FFFF, 1 << 30);
+        assert_eq!(timestamp.to_datetime(), when);
+    }
+
+    #[test]
+    fn timestamp_after_2036_rollover() {
+        // Era 1 starts 2036-02-07 06:28:16 UTC
+        let timestamp = Timestamp(16 << 32);
+
+        assert_eq!(
+            timestamp.to_datetime(),
+            PrimitiveDateTime::new(
+                Date::from_calendar_date(2036, Month::February, 7).unwrap(),
+                Time::from_hms(6, 28, 32).unwrap(),
+            )
+        );
+    }
+
+    #[test]
+    fn delay_excludes_server_hold_time() {
+        // 80ms round trip, the server sat on it for 20ms
+        let reply = reply(at(12, 0, 0, 0), at(12, 0, 0, 20_000));
+        let m = measure(&reply, 1_000_000, 1_080_000, None);
+
+        assert_eq!(m.delay_micros, 60_000);
+        assert_eq!(m.time, at(12, 0, 0, 50_000));
+        assert_eq!(m.offset_micros, None);
+    }
+
+    #[test]
+    fn offset_against_local_clock() {
+        // Local clock is 2s slow, symmetric 30ms each way
+        let reply = reply(at(12, 0, 2, 30_000), at(12, 0, 2, 30_000));
+        let m = measure(&reply, 0, 60_000, Some(at(12, 0, 0, 0)));
+
+        assert_eq!(m.delay_micros, 60_000);
+        assert_eq!(m.offset_micros, Some(2_000_000));
+    }
+
+    #[test]
+    fn rejects_bad_replies() {
+        let sent = Timestamp(42);
+        let mut packet = [0u8; PACKET_SIZE];
+        packet[0] = 0b00_100_100;
+        packet[1] = 1;
+        packet[24..32].copy_from_slice(&42u64.to_be_bytes());
+        packet[40..48].copy_from_slice(&(1u64 << 63).to_be_bytes());
+
+        assert!(parse_reply::<()>(&packet, sent).is_ok());
+        assert_eq!(
+            parse_reply::<()>(&packet[..47], sent).unwrap_err(),
+            SntpError::Malformed
+        );
+        assert_eq!(
+            parse_reply::<()>(&packet, Timestamp(43)).unwrap_err(),
+            SntpError::Unexpected
+        );
+
+        packet[1] = 0;
+        assert_eq!(
+            parse_reply::<()>(&packet, sent).unwrap_err(),
+            SntpError::KissOfDeath
+        );
+
+        packet[1] = 1;
+        packet[0] = CLIENT_HEADER;
+        assert_eq!(
+            parse_reply::<()>(&packet, sent).unwrap_err(),
+            SntpError::Unexpected
+        );
+    }
+
+    #[test]
+    fn server_list_entries() {
+        assert_eq!(split_server("pool.ntp.org"), ("pool.ntp.org", 123));
+        assert_eq!(split_server(" time.test:1123"), ("time.test", 1123));
+        assert_eq!(split_server("127.0.0.1:5000"), ("127.0.0.1", 5000));
+        assert_eq!(split_server("2001:db8::1"), ("2001:db8::1", 123));
+        assert_eq!(split_server("[2001:db8::1]:5000"), ("2001:db8::1", 5000));
+    }
+}
@@ -0,0 +1,131 @@
+use embedded_storage_async::nor_flash::NorFlash;
+use serde::{Deserialize, Serialize};
+
+use crate::{drift::Calibration, hal::Store, ota::Version, weather::CurrentWeather};
+
+/// Big enough for any `Postcard`, and small enough to read in one go
+pub const POSTCARD_SIZE: usize = 128;
+
+/// Everything we keep in flash between boots
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+pub struct Postcard {
+    pub weather: Option<CurrentWeather>,
+    pub image: usize,
+    pub calibration: Calibration,
+    /// [`crate::networks::ssid_hash`] of the last network joined
+    pub last_network: Option<u32>,
+    /// Moved over from the RTC in batches, see [`crate::wake::RamByte`]
+    pub wakes: u32,
+    /// The last update installed, see [`crate::ota::wanted`]
+    pub tried_update: Option<Version>,
+}
+
+impl Postcard {
+    pub fn encode<'a>(&self, buf: &'a mut [u8]) -> Option<&'a mut [u8]> {
+        postcard::to_slice(self, buf).ok()
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        postcard::from_bytes(buf).ok()
+    }
+}
+
+pub async fn load<S: Store>(store: &mut S) -> Option<Postcard> {
+    let mut buf = [0u8; POSTCARD_SIZE];
+    store.read(&mut buf).await.ok()?;
+    Postcard::decode(&buf)
+}
+
+/// Returns false if the postcard didn't fit or the store refused it
+pub async fn save<S: Store>(store: &mut S, postcard: &Postcard) -> bool {
+    let mut buf = [0u8; POSTCARD_SIZE];
+
+    match postcard.encode(&mut buf) {
+        Some(slice) => store.write(slice).await.is_ok(),
+        None => false,
+    }
+}
+
+/// Keeps the record in one erase sector of a NOR flash
+pub struct FlashStore<F> {
+    flash: F,
+    offset: u32,
+}
+
+impl<F: NorFlash> FlashStore<F> {
+    pub fn new(flash: F, offset: u32) -> Self {
+        Self { flash, offset }
+    }
+
+    /// The whole flash, for what doesn't fit in a record
+    pub fn flash(&mut self) -> &mut F {
+        &mut self.flash
+    }
+
+    /// Another record, at `offset` in the same flash
+    pub fn region(&mut self, offset: u32) -> FlashStore<&mut F> {
+        FlashStore {
+            flash: &mut self.flash,
+            offset,
+        }
+    }
+}
+
+impl<F: NorFlash> Store for FlashStore<F> {
+    type Error = F::Error;
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+        self.flash.read(self.offset, buf).await
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.flash
+            .erase(self.offset, self.offset + F::ERASE_SIZE as u32)
+            .await?;
+        self.flash.write(self.offset, data).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let postcard = Postcard {
+            weather: Some(CurrentWeather {
+                temperature: -3.5,
+                weathercode: 71,
+            }),
+            image: 2,
+            calibration: Calibration {
+                anchor: Some(1_760_709_900),
+                ppm: -12.5,
+                samples: 3,
+            },
+            last_network: Some(0x1234_5678),
+            wakes: 4321,
+            tried_update: Some(Version {
+                major: 0,
+                minor: 2,
+                patch: 0,
+            }),
+        };
+
+        let mut buf = [0u8; POSTCARD_SIZE];
+        let encoded = postcard.encode(&mut buf).unwrap();
+        let decoded = Postcard::decode(encoded).unwrap();
+
+        assert_eq!(decoded.image, 2);
+        assert_eq!(decoded.weather.unwrap().temperature, -3.5);
+        assert_eq!(decoded.weather.unwrap().weathercode, 71);
+        assert_eq!(decoded.calibration, postcard.calibration);
+        assert_eq!(decoded.last_network, Some(0x1234_5678));
+        assert_eq!(decoded.tried_update, postcard.tried_update);
+    }
+
+    #[test]
+    fn erased_flash_is_not_a_postcard() {
+        assert!(Postcard::decode(&[0xFF; POSTCARD_SIZE]).is_none());
+    }
+}
@@ -0,0 +1,204 @@
+use embassy_futures::join::join;
+use heapless::Vec;
+use time::{Duration, OffsetDateTime, PrimitiveDateTime, UtcOffset};
+
+use crate::{
+    api,
+    captive::{self, Connectivity},
+    hal::{Fetcher, Network, Probe, Udp},
+    json::Extract,
+    rfc3339::{self, Rfc3339Error},
+    sntp::{self, Measurement, SntpError},
+    weather::CurrentWeather,
+    widgets::{MAX_WIDGETS, Region, Text, Widget},
+};
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FetchError<E> {
+    Http(E),
+    Parse,
+    Time(Rfc3339Error),
+    /// Not asked, a captive portal would only answer with its login page
+    Captive,
+}
+
+/// What came back from a sync, each request succeeds or fails on its own
+pub struct Synced<E, U> {
+    /// UTC
+    pub time: Result<PrimitiveDateTime, FetchError<E>>,
+    /// `now_micros` when `time` was right, the weather may have taken a while longer
+    pub time_micros: u64,
+    /// The time API's local offset, when it was asked
+    pub utc_offset: Option<UtcOffset>,
+    /// The SNTP answer, when it was used for `time`
+    pub ntp: Result<Measurement, SntpError<U>>,
+    pub weather: Result<CurrentWeather, FetchError<E>>,
+    /// One for each of `SyncConfig::widgets`, in the same order
+    pub widgets: Vec<(Region, Result<Text, FetchError<E>>), MAX_WIDGETS>,
+    /// What the probe found, `None` without one
+    pub connectivity: Option<Connectivity>,
+}
+
+pub struct SyncConfig<'a> {
+    /// Comma separated, tried in order before falling back to `time_api`
+    pub ntp_servers: &'a str,
+    pub time_api: &'a str,
+    pub weather_api: &'a str,
+    /// Those due a fetch, see [`crate::widgets::Shown::due`]
+    pub widgets: &'a [Widget<'a>],
+    /// Asked first, see [`crate::captive`]. Empty to skip the check.
+    pub probe: &'a str,
+}
+
+/// Streams `url` through an extractor for `paths`, so only the fields
+/// are kept rather than the whole body
+pub async fn fetch_fields<'p, F: Fetcher, const N: usize>(
+    fetcher: &F,
+    rx_buf: &mut [u8],
+    url: &str,
+    paths: [&'p str; N],
+) -> Result<Extract<'p, N>, FetchError<F::Error>> {
+    let mut fields = Extract::new(paths);
+    fetcher
+        .get(url, rx_buf, &mut fields)
+        .await
+        .map_err(FetchError::Http)?;
+
+    Ok(fields)
+}
+
+pub async fn fetch_time<F: Fetcher>(
+    fetcher: &F,
+    rx_buf: &mut [u8],
+    url: &str,
+) -> Result<OffsetDateTime, FetchError<F::Error>> {
+    let fields = fetch_fields(fetcher, rx_buf, url, api::TIME_API).await?;
+    let datetime = api::datetime(&fields).ok_or(FetchError::Parse)?;
+
+    rfc3339::parse(datetime).map_err(FetchError::Time)
+}
+
+pub async fn fetch_weather<F: Fetcher>(
+    fetcher: &F,
+    rx_buf: &mut [u8],
+    url: &str,
+) -> Result<CurrentWeather, FetchError<F::Error>> {
+    let fields = fetch_fields(fetcher, rx_buf, url, api::OPEN_METEO).await?;
+
+    api::current_weather(&fields).ok_or(FetchError::Parse)
+}
+
+pub async fn fetch_widget<F: Fetcher>(
+    fetcher: &F,
+    rx_buf: &mut [u8],
+    widget: &Widget<'_>,
+) -> Result<Text, FetchError<F::Error>> {
+    let fields = fetch_fields(fetcher, rx_buf, widget.url, [widget.path]).await?;
+    fields.finish().map_err(|_| FetchError::Parse)?;
+
+    let value = fields.get(0).ok_or(FetchError::Parse)?;
+    Ok(widget.render(value))
+}
+
+/// Prefers SNTP, falling back to the time API if no server answers and
+/// the network isn't `captive`. `now` is what the RTC says, in UTC.
+pub async fn sync_time<N>(
+    net: &N,
+    rx_buf: &mut [u8],
+    config: &SyncConfig<'_>,
+    now: Option<PrimitiveDateTime>,
+    now_micros: impl Fn() -> u64,
+    captive: bool,
+) -> (
+    Result<OffsetDateTime, FetchError<<N as Fetcher>::Error>>,
+    Result<Measurement, SntpError<<N as Udp>::Error>>,
+    u64,
+)
+where
+    N: Fetcher + Udp,
+{
+    match sntp::query(net, config.ntp_servers, &now_micros, now).await {
+        Ok(measurement) => (
+            Ok(measurement.time.assume_utc()),
+            Ok(measurement),
+            now_micros(),
+        ),
+        Err(e) if captive => (Err(FetchError::Captive), Err(e), now_micros()),
+        Err(e) => {
+            let time = fetch_time(net, rx_buf, config.time_api).await;
+            (time, Err(e), now_micros())
+        }
+    }
+}
+
+/// Connects, checks for a captive portal, fetches time and weather side by
+/// side, then any widgets one at a time, and disconnects again. Behind a
+/// portal only SNTP is tried. `now` is what the RTC said just before, in
+/// UTC, if it can be trusted.
+pub async fn sync<N>(
+    net: &mut N,
+    rx_buffer: &mut [u8],
+    config: &SyncConfig<'_>,
+    now: Option<PrimitiveDateTime>,
+    now_micros: impl Fn() -> u64,
+) -> Result<Synced<<N as Fetcher>::Error, <N as Udp>::Error>, <N as Network>::Error>
+where
+    N: Network + Fetcher + Probe + Udp,
+{
+    let read_at = now_micros();
+    net.connect().await?;
+
+    // Joining can take seconds, which would otherwise look like drift
+    let now = now.map(|now| now + Duration::microseconds((now_micros() - read_at) as i64));
+
+    let connectivity = match config.probe {
+        "" => None,
+        url => Some(captive::probe(&*net, rx_buffer, url).await),
+    };
+    let captive = connectivity == Some(Connectivity::Captive);
+
+    let (time_buf, weather_buf) = rx_buffer.split_at_mut(rx_buffer.len() / 2);
+
+    let ((time, ntp, time_micros), weather) = join(
+        sync_time(&*net, time_buf, config, now, now_micros, captive),
+        async {
+            match captive {
+                true => Err(FetchError::Captive),
+                false => fetch_weather(&*net, weather_buf, config.weather_api).await,
+            }
+        },
+    )
+    .await;
+
+    let mut widgets = Vec::new();
+    for widget in config.widgets.iter().take(MAX_WIDGETS) {
+        let text = match captive {
+            true => Err(FetchError::Captive),
+            false => fetch_widget(&*net, rx_buffer, widget).await,
+        };
+        widgets.push((widget.region, text)).ok();
+    }
+
+    net.disconnect().await;
+
+    let utc_offset = match (&time, &ntp) {
+        (Ok(time), Err(_)) => Some(time.offset()),
+        _ => None,
+    };
+
+    let time = time.map(|time| {
+        let utc = time.to_offset(UtcOffset::UTC);
+        PrimitiveDateTime::new(utc.date(), utc.time())
+    });
+
+    Ok(Synced {
+        time,
+        time_micros,
+        utc_offset,
+        ntp,
+        weather,
+        widgets,
+        connectivity,
+    })
+}
@@ -0,0 +1,155 @@
+//! How much to trust an `https://` server. The TLS itself is reqwless's,
+//! through `embedded-tls`, which can't check a certificate chain, so the
+//! choice is between pinning a pre-shared key or, only when asked for, accepting
+//! any server.
+
+use heapless::Vec;
+
+/// One whole TLS record, servers don't all honour a smaller fragment length
+pub const READ_BUFFER_SIZE: usize = 16_640;
+/// Our requests are a line and a few headers
+pub const WRITE_BUFFER_SIZE: usize = 4096;
+
+/// SHA-384 sized, the longest key TLS 1.3 PSK suites use
+pub const MAX_PSK: usize = 48;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TlsConfigError {
+    /// A key without an identity, or the other way around
+    Incomplete,
+    /// Not an even number of hex digits, or longer than `MAX_PSK`
+    Key,
+    /// No key, and `TLS_INSECURE` isn't set to accept any server
+    Unverified,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trust<'a> {
+    /// Encrypted, but any certificate is taken at its word. Only when
+    /// `TLS_INSECURE` is set, see [`enabled`]
+    Any,
+    /// Only a server holding the same key, e.g. our own endpoints
+    Psk {
+        identity: &'a str,
+        key: Vec<u8, MAX_PSK>,
+    },
+}
+
+pub fn is_https(url: &str) -> bool {
+    url.get(..8)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("https://"))
+}
+
+/// Whether a flag in .env is set: `1`, `true`, `yes` or `on`, in any case
+pub fn enabled(value: Option<&str>) -> bool {
+    value.is_some_and(|v| {
+        ["1", "true", "yes", "on"]
+            .iter()
+            .any(|on| v.trim().eq_ignore_ascii_case(on))
+    })
+}
+
+/// From `TLS_PSK_IDENTITY` and `TLS_PSK`, the key in hex, and `TLS_INSECURE`.
+/// A key wins over `TLS_INSECURE`.
+pub fn trust<'a>(
+    identity: Option<&'a str>,
+    key: Option<&str>,
+    insecure: Option<&str>,
+) -> Result<Trust<'a>, TlsConfigError> {
+    let identity = identity.filter(|v| !v.is_empty());
+    let key = key.filter(|v| !v.is_empty());
+
+    match (identity, key) {
+        (None, None) if enabled(insecure) => Ok(Trust::Any),
+        (None, None) => Err(TlsConfigError::Unverified),
+        (Some(identity), Some(key)) => Ok(Trust::Psk {
+            identity,
+            key: from_hex(key).ok_or(TlsConfigError::Key)?,
+        }),
+        _ => Err(TlsConfigError::Incomplete),
+    }
+}
+
+pub(crate) fn from_hex(hex: &str) -> Option<Vec<u8, MAX_PSK>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let mut key = Vec::new();
+    for pair in hex.as_bytes().chunks(2) {
+        let pair = core::str::from_utf8(pair).ok()?;
+        key.push(u8::from_str_radix(pair, 16).ok()?).ok()?;
+    }
+
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spots_https() {
+        assert!(is_https("https://api.open-meteo.com/v1/forecast"));
+        assert!(is_https("HTTPS://example.com"));
+        assert!(!is_https("http://worldtimeapi.org/api/ip"));
+        assert!(!is_https("https:/"));
+    }
+
+    #[test]
+    fn trusts_any_server_only_when_asked() {
+        assert_eq!(trust(None, None, None), Err(TlsConfigError::Unverified));
+        assert_eq!(
+            trust(Some(""), Some(""), Some("")),
+            Err(TlsConfigError::Unverified)
+        );
+        for off in ["0", "false", "no", "off", "insecure"] {
+            assert_eq!(
+                trust(None, None, Some(off)),
+                Err(TlsConfigError::Unverified),
+                "{off}"
+            );
+        }
+
+        for on in ["1", "true", "TRUE", "Yes", "on"] {
+            assert_eq!(trust(None, None, Some(on)), Ok(Trust::Any), "{on}");
+        }
+        assert_eq!(trust(Some(""), Some(""), Some("1")), Ok(Trust::Any));
+    }
+
+    #[test]
+    fn pins_a_key() {
+        let trust = trust(Some("badge-17"), Some("00ff10Ab"), Some("1")).unwrap();
+
+        assert_eq!(
+            trust,
+            Trust::Psk {
+                identity: "badge-17",
+                key: Vec::from_slice(&[0x00, 0xff, 0x10, 0xab]).unwrap(),
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_half_a_config() {
+        assert_eq!(
+            trust(Some("badge-17"), None, Some("1")),
+            Err(TlsConfigError::Incomplete)
+        );
+        assert_eq!(
+            trust(None, Some("00ff"), Some("1")),
+            Err(TlsConfigError::Incomplete)
+        );
+
+        for key in ["abc", "zz", "+1", "é0"] {
+            assert_eq!(trust(Some("id"), Some(key), None), Err(TlsConfigError::Key));
+        }
+
+        let long = "00".repeat(MAX_PSK + 1);
+        assert_eq!(
+            trust(Some("id"), Some(&long), None),
+            Err(TlsConfigError::Key)
+        );
+    }
+}
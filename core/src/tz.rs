@@ -0,0 +1,563 @@
+//! POSIX `TZ` strings, e.g. `CST6CDT,M3.2.0,M11.1.0`, for turning the
+//! UTC kept in the RTC into local wall-clock time
+
+use heapless::String;
+use time::{Date, Duration, Month, PrimitiveDateTime, Time, UtcOffset};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TzError {
+    Name,
+    Offset,
+    Rule,
+    /// Something left over after the end rule
+    Trailing,
+}
+
+/// When a transition happens, as a day of the year plus seconds after
+/// local midnight (which may be negative or past 24h)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rule {
+    /// `Jn`: 1 to 365, February 29th is never counted
+    Julian1(u16),
+    /// `n`: 0 to 365, February 29th is counted
+    Julian0(u16),
+    /// `Mm.w.d`: day `d` (0 is Sunday) of week `w` (5 is the last) of month `m`
+    MonthWeekDay { month: u8, week: u8, weekday: u8 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Zone {
+    name: String<8>,
+    /// Seconds east of UTC, the opposite sign to the TZ string
+    offset: i32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Dst {
+    zone: Zone,
+    start: (Rule, i32),
+    end: (Rule, i32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeZone {
+    std: Zone,
+    dst: Option<Dst>,
+}
+
+impl TimeZone {
+    pub fn utc() -> Self {
+        Self {
+            std: Zone {
+                name: String::try_from("UTC").unwrap(),
+                offset: 0,
+            },
+            dst: None,
+        }
+    }
+
+    pub fn parse(tz: &str) -> Result<Self, TzError> {
+        let mut p = Parser(tz.as_bytes());
+
+        let std = Zone {
+            name: p.name()?,
+            offset: -p.offset()?,
+        };
+
+        if p.done() {
+            return Ok(Self { std, dst: None });
+        }
+
+        let name = p.name()?;
+        let offset = match p.peek() {
+            Some(b',') | None => std.offset + 3600,
+            _ => -p.offset()?,
+        };
+
+        // Without rules, do what glibc does and assume the US ones
+        let (start, end) = if p.done() {
+            (
+                (
+                    Rule::MonthWeekDay {
+                        month: 3,
+                        week: 2,
+                        weekday: 0,
+                    },
+                    7200,
+                ),
+                (
+                    Rule::MonthWeekDay {
+                        month: 11,
+                        week: 1,
+                        weekday: 0,
+                    },
+                    7200,
+                ),
+            )
+        } else {
+            p.expect(b',', TzError::Rule)?;
+            let start = p.rule()?;
+            p.expect(b',', TzError::Rule)?;
+            let end = p.rule()?;
+            (start, end)
+        };
+
+        if !p.done() {
+            return Err(TzError::Trailing);
+        }
+
+        Ok(Self {
+            std,
+            dst: Some(Dst {
+                zone: Zone { name, offset },
+                start,
+                end,
+            }),
+        })
+    }
+
+    fn zone(&self, utc: PrimitiveDateTime) -> &Zone {
+        let Some(dst) = &self.dst else {
+            return &self.std;
+        };
+
+        let year = (utc + Duration::seconds(self.std.offset as i64)).year();
+        let start = transition(year, dst.start, self.std.offset);
+        let end = transition(year, dst.end, dst.zone.offset);
+
+        let in_dst = if start < end {
+            utc >= start && utc < end
+        } else {
+            // Southern hemisphere, DST spans the new year
+            utc < end || utc >= start
+        };
+
+        if in_dst { &dst.zone } else { &self.std }
+    }
+
+    pub fn is_dst(&self, utc: PrimitiveDateTime) -> bool {
+        self.dst
+            .as_ref()
+            .is_some_and(|dst| self.zone(utc) == &dst.zone)
+    }
+
+    pub fn offset_at(&self, utc: PrimitiveDateTime) -> UtcOffset {
+        UtcOffset::from_whole_seconds(self.zone(utc).offset).unwrap_or(UtcOffset::UTC)
+    }
+
+    pub fn abbreviation(&self, utc: PrimitiveDateTime) -> &str {
+        &self.zone(utc).name
+    }
+
+    pub fn to_local(&self, utc: PrimitiveDateTime) -> PrimitiveDateTime {
+        utc + Duration::seconds(self.zone(utc).offset as i64)
+    }
+}
+
+impl Default for TimeZone {
+    fn default() -> Self {
+        Self::utc()
+    }
+}
+
+/// The UTC instant of a rule's transition in `year`, `offset` being
+/// the one in force just before it
+fn transition(year: i32, (rule, time): (Rule, i32), offset: i32) -> PrimitiveDateTime {
+    let midnight = PrimitiveDateTime::new(rule_date(year, rule), Time::MIDNIGHT);
+    midnight + Duration::seconds(time as i64 - offset as i64)
+}
+
+fn rule_date(year: i32, rule: Rule) -> Date {
+    let jan1 = Date::from_calendar_date(year, Month::January, 1).unwrap();
+
+    match rule {
+        Rule::Julian1(day) => {
+            let leap = time::util::is_leap_year(year) && day >= 60;
+            jan1 + Duration::days(day as i64 - 1 + leap as i64)
+        }
+        Rule::Julian0(day) => jan1 + Duration::days(day as i64),
+        Rule::MonthWeekDay {
+            month,
+            week,
+            weekday,
+        } => {
+            let month = Month::try_from(month).unwrap();
+            let first = Date::from_calendar_date(year, month, 1).unwrap();
+            let first_weekday = first.weekday().number_days_from_sunday();
+            let mut day = 1 + (weekday + 7 - first_weekday) % 7 + (week - 1) * 7;
+
+            let days_in_month = month.length(year);
+            while day > days_in_month {
+                day -= 7;
+            }
+
+            Date::from_calendar_date(year, month, day).unwrap()
+        }
+    }
+}
+
+struct Parser<'a>(&'a [u8]);
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.0.first().copied()
+    }
+
+    fn done(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn take_while(&mut self, f: impl Fn(u8) -> bool) -> &'a [u8] {
+        let len = self.0.iter().take_while(|b| f(**b)).count();
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        taken
+    }
+
+    fn expect(&mut self, byte: u8, err: TzError) -> Result<(), TzError> {
+        match self.peek() {
+            Some(b) if b == byte => {
+                self.0 = &self.0[1..];
+                Ok(())
+            }
+            _ => Err(err),
+        }
+    }
+
+    /// `CST`, or quoted like `<+0530>`
+    fn name(&mut self) -> Result<String<8>, TzError> {
+        let name = if self.peek() == Some(b'<') {
+            self.0 = &self.0[1..];
+            let name = self.take_while(|b| b.is_ascii_alphanumeric() || b == b'+' || b == b'-');
+            let name = core::str::from_utf8(name).map_err(|_| TzError::Name)?;
+            self.expect(b'>', TzError::Name)?;
+            name
+        } else {
+            let name = self.take_while(|b| b.is_ascii_alphabetic());
+            core::str::from_utf8(name).map_err(|_| TzError::Name)?
+        };
+
+        if name.len() < 3 {
+            return Err(TzError::Name);
+        }
+
+        String::try_from(name).map_err(|_| TzError::Name)
+    }
+
+    fn number(&mut self, err: TzError) -> Result<i32, TzError> {
+        let digits = self.take_while(|b| b.is_ascii_digit());
+
+        if digits.is_empty() || digits.len() > 3 {
+            return Err(err);
+        }
+
+        Ok(digits.iter().fold(0, |n, d| n * 10 + (d - b'0') as i32))
+    }
+
+    /// `[+-]hh[:mm[:ss]]` in seconds
+    fn hms(&mut self, max_hours: i32, err: TzError) -> Result<i32, TzError> {
+        let sign = match self.peek() {
+            Some(b'-') => {
+                self.0 = &self.0[1..];
+                -1
+            }
+            Some(b'+') => {
+                self.0 = &self.0[1..];
+                1
+            }
+            _ => 1,
+        };
+
+        let hours = self.number(err)?;
+        let mut minutes = 0;
+        let mut seconds = 0;
+
+        if self.peek() == Some(b':') {
+            self.0 = &self.0[1..];
+            minutes = self.number(err)?;
+
+            if self.peek() == Some(b':') {
+                self.0 = &self.0[1..];
+                seconds = self.number(err)?;
+            }
+        }
+
+        if hours > max_hours || minutes > 59 || seconds > 59 {
+            return Err(err);
+        }
+
+        Ok(sign * (hours * 3600 + minutes * 60 + seconds))
+    }
+
+    fn offset(&mut self) -> Result<i32, TzError> {
+        self.hms(24, TzError::Offset)
+    }
+
+    fn rule(&mut self) -> Result<(Rule, i32), TzError> {
+        let rule = match self.peek() {
+            Some(b'J') => {
+                self.0 = &self.0[1..];
+                let day = self.number(TzError::Rule)?;
+                if !(1..=365).contains(&day) {
+                    return Err(TzError::Rule);
+                }
+                Rule::Julian1(day as u16)
+            }
+            Some(b'M') => {
+                self.0 = &self.0[1..];
+                let month = self.number(TzError::Rule)?;
+                self.expect(b'.', TzError::Rule)?;
+                let week = self.number(TzError::Rule)?;
+                self.expect(b'.', TzError::Rule)?;
+                let weekday = self.number(TzError::Rule)?;
+
+                if !(1..=12).contains(&month) || !(1..=5).contains(&week) || weekday > 6 {
+                    return Err(TzError::Rule);
+                }
+
+                Rule::MonthWeekDay {
+                    month: month as u8,
+                    week: week as u8,
+                    weekday: weekday as u8,
+                }
+            }
+            _ => {
+                let day = self.number(TzError::Rule)?;
+                if day > 365 {
+                    return Err(TzError::Rule);
+                }
+                Rule::Julian0(day as u16)
+            }
+        };
+
+        let time = if self.peek() == Some(b'/') {
+            self.0 = &self.0[1..];
+            self.hms(167, TzError::Rule)?
+        } else {
+            7200
+        };
+
+        Ok((rule, time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHICAGO: &str = "CST6CDT,M3.2.0,M11.1.0";
+    const BERLIN: &str = "CET-1CEST,M3.5.0,M10.5.0/3";
+    const SYDNEY: &str = "AEST-10AEDT,M10.1.0,M4.1.0/3";
+
+    fn utc(year: i32, month: u8, day: u8, hour: u8, minute: u8) -> PrimitiveDateTime {
+        PrimitiveDateTime::new(
+            Date::from_calendar_date(year, Month::try_from(month).unwrap(), day).unwrap(),
+            Time::from_hms(hour, minute, 0).unwrap(),
+        )
+    }
+
+    /// Every minute for three hours either side of `at`, which is when
+    /// the offset changes from `before` to `after` hours
+    fn assert_transition(tz: &TimeZone, at: PrimitiveDateTime, before: i32, after: i32) {
+        for minute in -180..180 {
+            let when = at + Duration::minutes(minute);
+            let expected = if minute < 0 { before } else { after };
+
+            assert_eq!(
+                tz.offset_at(when).whole_seconds(),
+                expected * 3600,
+                "{when} around {at}"
+            );
+        }
+    }
+
+    fn dst_hours(tz: &TimeZone, year: i32) -> usize {
+        let mut when = utc(year, 1, 1, 0, 0);
+        let mut hours = 0;
+
+        while when.year() == year {
+            hours += tz.is_dst(when) as usize;
+            when += Duration::hours(1);
+        }
+
+        hours
+    }
+
+    #[test]
+    fn chicago_transitions() {
+        let tz = TimeZone::parse(CHICAGO).unwrap();
+
+        for (spring, autumn) in [
+            (utc(2024, 3, 10, 8, 0), utc(2024, 11, 3, 7, 0)),
+            (utc(2025, 3, 9, 8, 0), utc(2025, 11, 2, 7, 0)),
+            (utc(2026, 3, 8, 8, 0), utc(2026, 11, 1, 7, 0)),
+        ] {
+            assert_transition(&tz, spring, -6, -5);
+            assert_transition(&tz, autumn, -5, -6);
+        }
+
+        for year in 2024..=2026 {
+            assert_eq!(dst_hours(&tz, year), 5711);
+        }
+    }
+
+    #[test]
+    fn berlin_transitions() {
+        let tz = TimeZone::parse(BERLIN).unwrap();
+
+        for (spring, autumn) in [
+            (utc(2024, 3, 31, 1, 0), utc(2024, 10, 27, 1, 0)),
+            (utc(2025, 3, 30, 1, 0), utc(2025, 10, 26, 1, 0)),
+            (utc(2026, 3, 29, 1, 0), utc(2026, 10, 25, 1, 0)),
+        ] {
+            assert_transition(&tz, spring, 1, 2);
+            assert_transition(&tz, autumn, 2, 1);
+        }
+
+        for year in 2024..=2026 {
+            assert_eq!(dst_hours(&tz, year), 5040);
+        }
+    }
+
+    #[test]
+    fn sydney_transitions_span_new_year() {
+        let tz = TimeZone::parse(SYDNEY).unwrap();
+
+        for (autumn, spring) in [
+            (utc(2024, 4, 6, 16, 0), utc(2024, 10, 5, 16, 0)),
+            (utc(2025, 4, 5, 16, 0), utc(2025, 10, 4, 16, 0)),
+            (utc(2026, 4, 4, 16, 0), utc(2026, 10, 3, 16, 0)),
+        ] {
+            assert_transition(&tz, autumn, 11, 10);
+            assert_transition(&tz, spring, 10, 11);
+        }
+
+        assert_eq!(dst_hours(&tz, 2024), 4416);
+        assert_eq!(dst_hours(&tz, 2025), 4392);
+        assert!(tz.is_dst(utc(2025, 1, 1, 0, 0)));
+        assert!(tz.is_dst(utc(2025, 12, 31, 23, 0)));
+    }
+
+    #[test]
+    fn wall_clock_around_transitions() {
+        let tz = TimeZone::parse(CHICAGO).unwrap();
+
+        // Spring forward skips 02:00 to 02:59
+        assert_eq!(tz.to_local(utc(2025, 3, 9, 7, 59)), utc(2025, 3, 9, 1, 59));
+        assert_eq!(tz.to_local(utc(2025, 3, 9, 8, 0)), utc(2025, 3, 9, 3, 0));
+
+        // Fall back repeats 01:00 to 01:59
+        assert_eq!(
+            tz.to_local(utc(2025, 11, 2, 6, 30)),
+            utc(2025, 11, 2, 1, 30)
+        );
+        assert_eq!(
+            tz.to_local(utc(2025, 11, 2, 7, 30)),
+            utc(2025, 11, 2, 1, 30)
+        );
+
+        // Local date lags UTC in the evening
+        assert_eq!(
+            tz.to_local(utc(2025, 10, 18, 2, 0)),
+            utc(2025, 10, 17, 21, 0)
+        );
+
+        assert_eq!(tz.abbreviation(utc(2025, 1, 1, 12, 0)), "CST");
+        assert_eq!(tz.abbreviation(utc(2025, 7, 1, 12, 0)), "CDT");
+    }
+
+    #[test]
+    fn fixed_offsets() {
+        let utc_zone = TimeZone::parse("UTC0").unwrap();
+        assert_eq!(utc_zone, TimeZone::utc());
+        assert_eq!(
+            utc_zone.to_local(utc(2025, 6, 1, 12, 0)),
+            utc(2025, 6, 1, 12, 0)
+        );
+
+        let india = TimeZone::parse("<+0530>-5:30").unwrap();
+        assert_eq!(india.abbreviation(utc(2025, 6, 1, 0, 0)), "+0530");
+        assert_eq!(
+            india.to_local(utc(2025, 6, 1, 8, 30)),
+            utc(2025, 6, 1, 14, 0)
+        );
+        assert!(!india.is_dst(utc(2025, 6, 1, 0, 0)));
+    }
+
+    #[test]
+    fn rule_variants() {
+        // No rules means the US ones, and DST defaults to an hour ahead
+        assert_eq!(
+            TimeZone::parse("EST5EDT").unwrap(),
+            TimeZone::parse("EST5EDT4,M3.2.0/2,M11.1.0/2:00:00").unwrap()
+        );
+
+        // Times before midnight and past 24h, as used by some zones
+        let tz = TimeZone::parse("XST3XDT,M3.2.0/-1,M11.1.0/26").unwrap();
+        assert_transition(&tz, utc(2025, 3, 9, 2, 0), -3, -2);
+        assert_transition(&tz, utc(2025, 11, 3, 4, 0), -2, -3);
+
+        // J60 is always March 1st, 59 is February 29th in leap years
+        let julian = TimeZone::parse("XST3XDT,J60/0,J300/0").unwrap();
+        assert_transition(&julian, utc(2024, 3, 1, 3, 0), -3, -2);
+        assert_transition(&julian, utc(2025, 3, 1, 3, 0), -3, -2);
+        let zero = TimeZone::parse("XST3XDT,59/0,299/0").unwrap();
+        assert_transition(&zero, utc(2024, 2, 29, 3, 0), -3, -2);
+        assert_transition(&zero, utc(2025, 3, 1, 3, 0), -3, -2);
+    }
+
+    #[test]
+    fn fifth_week_is_the_last() {
+        assert_eq!(
+            rule_date(
+                2025,
+                Rule::MonthWeekDay {
+                    month: 10,
+                    week: 5,
+                    weekday: 0
+                }
+            ),
+            Date::from_calendar_date(2025, Month::October, 26).unwrap()
+        );
+        assert_eq!(
+            rule_date(
+                2026,
+                Rule::MonthWeekDay {
+                    month: 3,
+                    week: 5,
+                    weekday: 2
+                }
+            ),
+            Date::from_calendar_date(2026, Month::March, 31).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_bad_strings() {
+        assert_eq!(TimeZone::parse(""), Err(TzError::Name));
+        assert_eq!(TimeZone::parse("C6"), Err(TzError::Name));
+        assert_eq!(TimeZone::parse("<+0530-5:30"), Err(TzError::Name));
+        assert_eq!(TimeZone::parse("CST"), Err(TzError::Offset));
+        assert_eq!(TimeZone::parse("CST25"), Err(TzError::Offset));
+        assert_eq!(TimeZone::parse("CST6:60"), Err(TzError::Offset));
+        assert_eq!(
+            TimeZone::parse("CST6CDT,M13.2.0,M11.1.0"),
+            Err(TzError::Rule)
+        );
+        assert_eq!(
+            TimeZone::parse("CST6CDT,M3.6.0,M11.1.0"),
+            Err(TzError::Rule)
+        );
+        assert_eq!(
+            TimeZone::parse("CST6CDT,M3.2.7,M11.1.0"),
+            Err(TzError::Rule)
+        );
+        assert_eq!(TimeZone::parse("CST6CDT,M3.2.0"), Err(TzError::Rule));
+        assert_eq!(TimeZone::parse("CST6CDT,J0,J300"), Err(TzError::Rule));
+        assert_eq!(
+            TimeZone::parse("CST6CDT,M3.2.0,M11.1.0x"),
+            Err(TzError::Trailing)
+        );
+    }
+}
@@ -0,0 +1,324 @@
+//! The steps of a wake cycle that talk to the RTC, in the order `main`
+//! runs them: [`wake_up`], a sync's [`correct_time`] and [`save_wakes`],
+//! then [`sleep`].
+
+use core::future::Future;
+
+use time::PrimitiveDateTime;
+
+use crate::{
+    drift::Calibration,
+    hal::{Alarm, Rtc},
+    image::{self, IMAGES, Shift},
+    layout::Screen,
+    schedule::Schedule,
+    tz::TimeZone,
+};
+
+/// Why the badge powered up, in the order `main` checks for it
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WakeReason {
+    Up,
+    Down,
+    A,
+    B,
+    C,
+    RtcAlarm,
+    ExternalPower,
+}
+
+/// What a single wake cycle should do
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Wake {
+    pub reason: WakeReason,
+    pub screen: Screen,
+    pub shift: Shift,
+    pub sync_wifi: bool,
+}
+
+impl Wake {
+    pub fn new(reason: WakeReason) -> Self {
+        let (screen, shift, sync_wifi) = match reason {
+            WakeReason::Up => (Screen::Image, Shift::Prev, false),
+            WakeReason::Down => (Screen::Image, Shift::Next, false),
+            WakeReason::A => (Screen::TopBar, Shift::None, true),
+            WakeReason::B => (Screen::Full, Shift::None, false),
+            WakeReason::C => (Screen::Diagnostics, Shift::None, false),
+            WakeReason::RtcAlarm | WakeReason::ExternalPower => (Screen::None, Shift::None, false),
+        };
+
+        Self {
+            reason,
+            screen,
+            shift,
+            sync_wifi,
+        }
+    }
+
+    pub fn external_power(&self) -> bool {
+        self.reason == WakeReason::ExternalPower
+    }
+
+    /// Once the RTC can be read, an alarm wake decides how much to refresh.
+    /// `now` is local time, so half hour zones sync on their own hour.
+    pub fn alarm_fired(&mut self, now: Option<PrimitiveDateTime>, schedule: &Schedule) {
+        if self.reason != WakeReason::RtcAlarm {
+            return;
+        }
+
+        match now {
+            Some(now) => (self.screen, self.sync_wifi) = schedule.plan(now),
+            None => self.screen = Screen::TopBar,
+        }
+    }
+}
+
+/// Wake again at the top of the next minute, for when the time isn't
+/// known well enough to follow a [`Schedule`]
+pub fn sleep_alarm() -> Alarm {
+    Alarm {
+        second: Some(0),
+        ..Default::default()
+    }
+}
+
+/// Wakes the RTC can hold until a sync's save adds them to the count in
+/// flash, enough for an hour between syncs at one wake a minute. Any more
+/// go uncounted, rather than wearing the flash with saves of their own.
+pub const MAX_UNSAVED_WAKES: u8 = 63;
+
+const IMAGE_BITS: u8 = 2;
+const _: () = assert!(IMAGES.len() <= 1 << IMAGE_BITS);
+
+/// The RTC's one byte of RAM, which lasts while the badge is off: the image
+/// shown in the low bits, and wakes not yet saved to flash above them
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RamByte {
+    pub image: usize,
+    pub unsaved_wakes: u8,
+}
+
+impl RamByte {
+    pub fn decode(byte: u8) -> Self {
+        Self {
+            image: (byte & ((1 << IMAGE_BITS) - 1)) as usize,
+            unsaved_wakes: byte >> IMAGE_BITS,
+        }
+    }
+
+    pub fn encode(&self) -> u8 {
+        (self.image as u8 & ((1 << IMAGE_BITS) - 1))
+            | (self.unsaved_wakes.min(MAX_UNSAVED_WAKES) << IMAGE_BITS)
+    }
+
+    /// Counts this wake, if the RTC can hold another
+    pub fn count_wake(&mut self) {
+        self.unsaved_wakes = (self.unsaved_wakes + 1).min(MAX_UNSAVED_WAKES);
+    }
+
+    /// Moves the wakes held over to `saved`, for writing to flash
+    pub fn save_wakes(&mut self, saved: &mut u32) {
+        *saved = self.wakes(*saved);
+        self.unsaved_wakes = 0;
+    }
+
+    /// Every wake so far, given those saved to flash
+    pub fn wakes(&self, saved: u32) -> u32 {
+        saved.saturating_add(self.unsaved_wakes as u32)
+    }
+}
+
+/// What the RTC had to say on waking
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Woken {
+    pub wake: Wake,
+    /// UTC, `None` if the oscillator stopped and the time can't be trusted
+    pub time: Option<PrimitiveDateTime>,
+    /// The image to show, after any button shift
+    pub image: usize,
+    /// Every wake so far
+    pub wakes: u32,
+}
+
+/// The start of every wake, once the state is loaded from flash. Trims the
+/// crystal, which is lost if the RTC ever loses power, reads the time if it
+/// can be trusted, lets an alarm decide what to refresh, and shifts the
+/// image and counts the wake in the RTC's RAM byte. `saved_wakes` is the
+/// count in flash.
+pub async fn wake_up<R: Rtc>(
+    rtc: &mut R,
+    reason: WakeReason,
+    calibration: &Calibration,
+    saved_wakes: u32,
+    tz: &TimeZone,
+    schedule: &Schedule,
+) -> Woken {
+    let mut wake = Wake::new(reason);
+
+    let trusted = !rtc.oscillator_stopped().await.unwrap_or(true);
+    let time = match trusted {
+        true => rtc.datetime().await.ok(),
+        false => None,
+    };
+
+    rtc.set_offset(calibration.offset_register()).await.ok();
+
+    wake.alarm_fired(
+        rtc.datetime().await.ok().map(|utc| tz.to_local(utc)),
+        schedule,
+    );
+
+    let mut ram = RamByte::decode(rtc.ram_byte().await.unwrap_or(0));
+    ram.image = image::shift(image::clamp(ram.image), wake.shift);
+    ram.count_wake();
+    rtc.set_ram_byte(ram.encode()).await.ok();
+
+    Woken {
+        wake,
+        time,
+        image: ram.image,
+        wakes: ram.wakes(saved_wakes),
+    }
+}
+
+/// Takes a sync's UTC time: records it against `calibration`, sets the RTC
+/// if it has drifted and trims the crystal either way. `settle` turns `now`
+/// into the time to set, e.g. catching up and waiting for a whole second.
+/// Returns the time set, if it was.
+pub async fn correct_time<R, F>(
+    rtc: &mut R,
+    calibration: &mut Calibration,
+    now: PrimitiveDateTime,
+    offset_micros: Option<i64>,
+    settle: impl FnOnce(PrimitiveDateTime) -> F,
+) -> Option<PrimitiveDateTime>
+where
+    R: Rtc,
+    F: Future<Output = PrimitiveDateTime>,
+{
+    let set = match calibration.record(now, offset_micros) {
+        true => {
+            let now = settle(now).await;
+            rtc.set_datetime(&now).await.ok().map(|()| now)
+        }
+        false => None,
+    };
+
+    rtc.set_offset(calibration.offset_register()).await.ok();
+    set
+}
+
+/// Moves the wakes the RTC holds over to `saved_wakes`, just before a sync
+/// saves the state to flash. Wakes counted since are lost if the save
+/// fails, which is fewer than the RTC can hold.
+pub async fn save_wakes<R: Rtc>(rtc: &mut R, saved_wakes: &mut u32) {
+    let Ok(byte) = rtc.ram_byte().await else {
+        return;
+    };
+
+    let mut ram = RamByte::decode(byte);
+    ram.save_wakes(saved_wakes);
+    rtc.set_ram_byte(ram.encode()).await.ok();
+}
+
+/// Arms the next wake: from `schedule` while the time can be `trusted`,
+/// otherwise the top of the next minute, since quiet hours could land
+/// anywhere. Returns the alarm set.
+pub async fn sleep<R: Rtc>(
+    rtc: &mut R,
+    trusted: bool,
+    tz: &TimeZone,
+    schedule: &Schedule,
+) -> Alarm {
+    let alarm = match rtc.datetime().await {
+        Ok(now) if trusted => schedule.next_alarm(now, tz),
+        _ => sleep_alarm(),
+    };
+    rtc.set_alarm(alarm).await.ok();
+    alarm
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::{Date, Month, Time};
+
+    fn at(hour: u8, minute: u8) -> Option<PrimitiveDateTime> {
+        Some(PrimitiveDateTime::new(
+            Date::from_calendar_date(2025, Month::October, 17).unwrap(),
+            Time::from_hms(hour, minute, 0).unwrap(),
+        ))
+    }
+
+    #[test]
+    fn buttons() {
+        assert_eq!(Wake::new(WakeReason::Up).shift, Shift::Prev);
+        assert_eq!(Wake::new(WakeReason::Down).shift, Shift::Next);
+        assert!(Wake::new(WakeReason::A).sync_wifi);
+        assert_eq!(Wake::new(WakeReason::B).screen, Screen::Full);
+        assert!(Wake::new(WakeReason::ExternalPower).external_power());
+    }
+
+    #[test]
+    fn alarm_syncs_on_the_hour() {
+        let mut wake = Wake::new(WakeReason::RtcAlarm);
+        wake.alarm_fired(at(14, 0), &Schedule::DEFAULT);
+        assert!(wake.sync_wifi);
+        assert_eq!(wake.screen, Screen::Full);
+
+        let mut wake = Wake::new(WakeReason::RtcAlarm);
+        wake.alarm_fired(at(14, 1), &Schedule::DEFAULT);
+        assert!(!wake.sync_wifi);
+        assert_eq!(wake.screen, Screen::TopBar);
+
+        let mut wake = Wake::new(WakeReason::RtcAlarm);
+        wake.alarm_fired(None, &Schedule::DEFAULT);
+        assert_eq!(wake.screen, Screen::TopBar);
+    }
+
+    #[test]
+    fn buttons_ignore_alarm_time() {
+        let mut wake = Wake::new(WakeReason::C);
+        wake.alarm_fired(at(14, 0), &Schedule::DEFAULT);
+        assert!(!wake.sync_wifi);
+        assert_eq!(wake.screen, Screen::Diagnostics);
+    }
+
+    #[test]
+    fn ram_byte_keeps_the_image_and_wakes() {
+        // Written before wakes were counted
+        assert_eq!(
+            RamByte::decode(2),
+            RamByte {
+                image: 2,
+                unsaved_wakes: 0
+            }
+        );
+
+        let byte = RamByte {
+            image: 1,
+            unsaved_wakes: 30,
+        };
+        assert_eq!(RamByte::decode(byte.encode()), byte);
+    }
+
+    #[test]
+    fn wakes_wait_in_the_rtc_for_a_save() {
+        let mut byte = RamByte::default();
+        let mut saved = 100;
+
+        for _ in 0..MAX_UNSAVED_WAKES {
+            byte.count_wake();
+        }
+        assert_eq!(byte.wakes(saved), 163);
+
+        // A full RTC stops counting rather than writing flash
+        byte.count_wake();
+        assert_eq!(byte.wakes(saved), 163);
+
+        byte.save_wakes(&mut saved);
+        assert_eq!((saved, byte.unsaved_wakes), (163, 0));
+        assert_eq!(byte.wakes(saved), 163);
+    }
+}
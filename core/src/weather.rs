@@ -0,0 +1,33 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Copy, Clone)]
+pub struct CurrentWeather {
+    pub temperature: f32,
+    pub weathercode: u8,
+    // pub is_day: u8,
+}
+
+pub fn weather_description(code: u8) -> &'static str {
+    match code {
+        0 => "Clear",
+        1 => "Mainly Clear",
+        2 => "Part Cloudy",
+        3 => "Cloudy",
+        45..=48 => "Fog",
+        51..=55 => "Drizzle",
+        56 | 57 => "Frizzle",
+        61 => "Light Rain",
+        63 => "Rain",
+        65 => "Heavy Rain",
+        66 | 67 => "Frzing Rain",
+        71 => "Light Snow",
+        73 => "Snow",
+        75 => "Heavy Snow",
+        77 => "Snow Grains",
+        80..=82 => "Rain Showers",
+        85 | 86 => "Snow Showers",
+        95 => "Thunderstorm",
+        96 | 99 => "Hailstorm",
+        _ => "Unknown",
+    }
+}
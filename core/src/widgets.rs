@@ -0,0 +1,315 @@
+//! Values from any JSON API, shown over the image: build status, ticket
+//! counts, prices. Each comes from a line in `.widgets`, fetched during a
+//! sync once its refresh interval has passed.
+
+use heapless::{String, Vec};
+use time::{Duration, PrimitiveDateTime};
+
+use crate::json;
+
+/// One per region
+pub const MAX_WIDGETS: usize = 4;
+
+/// Longest text once formatted, in bytes. A region fits 20 characters.
+pub const TEXT_SIZE: usize = 40;
+
+pub type Text = String<TEXT_SIZE>;
+
+/// Where a widget is drawn, a banner in one corner of the image
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Region {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Region {
+    pub const ALL: [Region; MAX_WIDGETS] = [
+        Region::TopLeft,
+        Region::TopRight,
+        Region::BottomLeft,
+        Region::BottomRight,
+    ];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Region::ALL.into_iter().find(|region| region.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Region::TopLeft => "top-left",
+            Region::TopRight => "top-right",
+            Region::BottomLeft => "bottom-left",
+            Region::BottomRight => "bottom-right",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Widget<'a> {
+    pub region: Region,
+    pub refresh_minutes: u16,
+    pub url: &'a str,
+    /// See [`crate::json`], e.g. `$.current.value`
+    pub path: &'a str,
+    /// `{}` is replaced with the value
+    pub format: &'a str,
+}
+
+impl Widget<'_> {
+    /// `format` with the value in place of its first `{}`, cut short if
+    /// it doesn't fit
+    pub fn render(&self, value: &str) -> Text {
+        let (before, after) = self.format.split_once("{}").unwrap_or((self.format, ""));
+
+        let mut text = Text::new();
+        for c in before.chars().chain(value.chars()).chain(after.chars()) {
+            if text.push(c).is_err() {
+                break;
+            }
+        }
+        text
+    }
+}
+
+/// Reads the `.widgets` file, one
+/// `region<TAB>refresh minutes<TAB>url<TAB>path<TAB>format` per line, the
+/// format optional. Blank lines, `#` comments, lines that don't make sense
+/// and a second widget for a region are skipped.
+pub fn widgets(file: &str) -> Vec<Widget<'_>, MAX_WIDGETS> {
+    let mut widgets: Vec<Widget, MAX_WIDGETS> = Vec::new();
+
+    for line in file.lines() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.split('\t');
+        let (Some(region), Some(minutes), Some(url), Some(path)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+
+        let Some(region) = Region::parse(region.trim()) else {
+            continue;
+        };
+        let Some(refresh_minutes) = minutes.trim().parse().ok().filter(|&m| m > 0) else {
+            continue;
+        };
+
+        if url.is_empty()
+            || json::check_path(path).is_err()
+            || widgets.iter().any(|w| w.region == region)
+        {
+            continue;
+        }
+
+        widgets
+            .push(Widget {
+                region,
+                refresh_minutes,
+                url,
+                path,
+                format: fields.next().unwrap_or("{}"),
+            })
+            .ok();
+    }
+
+    widgets
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Slot {
+    text: Option<Text>,
+    /// UTC, `None` if never, or the time wasn't known
+    fetched: Option<PrimitiveDateTime>,
+}
+
+/// What each region shows, and when it was fetched
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Shown {
+    slots: [Slot; MAX_WIDGETS],
+}
+
+impl Shown {
+    pub const fn new() -> Self {
+        const EMPTY: Slot = Slot {
+            text: None,
+            fetched: None,
+        };
+
+        Self {
+            slots: [EMPTY; MAX_WIDGETS],
+        }
+    }
+
+    /// Those of `widgets` due a fetch at `now`, UTC. Without a time to go
+    /// by, all of them.
+    pub fn due<'a>(
+        &self,
+        widgets: &[Widget<'a>],
+        now: Option<PrimitiveDateTime>,
+    ) -> Vec<Widget<'a>, MAX_WIDGETS> {
+        widgets
+            .iter()
+            .filter(|widget| {
+                let fetched = self.slots[widget.region as usize].fetched;
+
+                match (fetched, now) {
+                    (Some(fetched), Some(now)) => {
+                        now - fetched >= Duration::minutes(widget.refresh_minutes as i64)
+                    }
+                    _ => true,
+                }
+            })
+            .copied()
+            .take(MAX_WIDGETS)
+            .collect()
+    }
+
+    /// Records a fetch. A failed one leaves the last text up, and the
+    /// widget due again. Returns whether the text changed.
+    pub fn update(
+        &mut self,
+        region: Region,
+        text: Option<Text>,
+        now: Option<PrimitiveDateTime>,
+    ) -> bool {
+        let slot = &mut self.slots[region as usize];
+        let Some(text) = text else {
+            return false;
+        };
+
+        slot.fetched = now;
+        let changed = slot.text.as_ref() != Some(&text);
+        slot.text = Some(text);
+        changed
+    }
+
+    /// By region, in the order of [`Region::ALL`]
+    pub fn texts(&self) -> [Option<&str>; MAX_WIDGETS] {
+        core::array::from_fn(|i| self.slots[i].text.as_deref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::{Date, Month, Time};
+
+    const FILE: &str = "# region\tminutes\turl\tpath\tformat\n\
+        top-left\t5\thttps://ci.example.com/api/status\t$.builds[0].state\tCI: {}\n\
+        \n\
+        bottom-right\t15\thttp://tickets.example.com/open\t$.count\t{} open\r\n\
+        top-left\t5\thttp://example.com/dup\t$.x\n\
+        middle\t5\thttp://example.com/\t$.x\n\
+        top-right\t0\thttp://example.com/\t$.x\n\
+        bottom-left\t5\thttp://example.com/\tcount\n\
+        bottom-left\t60\thttp://stocks.example.com/q?s=ACME\t$.price\n";
+
+    fn at(hour: u8, minute: u8) -> PrimitiveDateTime {
+        PrimitiveDateTime::new(
+            Date::from_calendar_date(2025, Month::October, 17).unwrap(),
+            Time::from_hms(hour, minute, 0).unwrap(),
+        )
+    }
+
+    #[test]
+    fn reads_the_file() {
+        let widgets = widgets(FILE);
+
+        assert_eq!(
+            widgets,
+            [
+                Widget {
+                    region: Region::TopLeft,
+                    refresh_minutes: 5,
+                    url: "https://ci.example.com/api/status",
+                    path: "$.builds[0].state",
+                    format: "CI: {}",
+                },
+                Widget {
+                    region: Region::BottomRight,
+                    refresh_minutes: 15,
+                    url: "http://tickets.example.com/open",
+                    path: "$.count",
+                    format: "{} open",
+                },
+                Widget {
+                    region: Region::BottomLeft,
+                    refresh_minutes: 60,
+                    url: "http://stocks.example.com/q?s=ACME",
+                    path: "$.price",
+                    format: "{}",
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn renders_values() {
+        let widget = widgets(FILE)[0];
+
+        assert_eq!(widget.render("passing"), "CI: passing");
+
+        let long = widget.render(&"x".repeat(TEXT_SIZE));
+        assert_eq!(long.len(), TEXT_SIZE);
+        assert!(long.starts_with("CI: xxx"));
+
+        let price = Widget {
+            format: "ACME ${} (delayed)",
+            ..widget
+        };
+        assert_eq!(price.render("12.5"), "ACME $12.5 (delayed)");
+    }
+
+    #[test]
+    fn fetches_when_due() {
+        let widgets = widgets(FILE);
+        let mut shown = Shown::new();
+
+        assert_eq!(shown.due(&widgets, Some(at(9, 0))).len(), 3);
+
+        for widget in &widgets {
+            shown.update(widget.region, Some(widget.render("1")), Some(at(9, 0)));
+        }
+
+        assert!(shown.due(&widgets, Some(at(9, 4))).is_empty());
+        assert_eq!(
+            shown
+                .due(&widgets, Some(at(9, 15)))
+                .iter()
+                .map(|w| w.region)
+                .collect::<std::vec::Vec<_>>(),
+            [Region::TopLeft, Region::BottomRight]
+        );
+        assert_eq!(shown.due(&widgets, None).len(), 3);
+    }
+
+    #[test]
+    fn keeps_the_last_text_when_a_fetch_fails() {
+        let widgets = widgets(FILE);
+        let mut shown = Shown::new();
+
+        assert!(shown.update(
+            Region::TopLeft,
+            Some("CI: ok".try_into().unwrap()),
+            Some(at(9, 0))
+        ));
+        assert!(!shown.update(
+            Region::TopLeft,
+            Some("CI: ok".try_into().unwrap()),
+            Some(at(9, 5))
+        ));
+        assert!(!shown.update(Region::TopLeft, None, Some(at(9, 10))));
+
+        assert_eq!(shown.texts(), [Some("CI: ok"), None, None, None]);
+        assert_eq!(
+            shown.due(&widgets, Some(at(9, 10)))[0].region,
+            Region::TopLeft
+        );
+    }
+}
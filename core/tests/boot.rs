@@ -0,0 +1,704 @@
+//! Simulated wake cycles: wake reason, load state, sync, redraw, sleep,
+//! through the same `wake` steps `main` runs on the badge.
+
+mod common;
+
+use badger_core::captive::Connectivity;
+use badger_core::clock::ClockFormat;
+use badger_core::drift::Calibration;
+use badger_core::framebuffer::Framebuffer;
+use badger_core::hal::{Alarm, Store};
+use badger_core::image::IMAGES;
+use badger_core::layout::{self, Frame, Screen};
+use badger_core::schedule::{QuietHours, Schedule};
+use badger_core::storage::{self, Postcard};
+use badger_core::sync::{self, FetchError, SyncConfig};
+use badger_core::tz::TimeZone;
+use badger_core::wake::{self, MAX_UNSAVED_WAKES, RamByte, Wake, WakeReason};
+use badger_core::weather::CurrentWeather;
+use badger_core::widgets::{self, MAX_WIDGETS, Region};
+use common::{MockNet, MockRtc, MockStore, at};
+use embassy_futures::block_on;
+use time::{Duration, PrimitiveDateTime, Time, UtcOffset};
+
+const CONFIG: SyncConfig = SyncConfig {
+    ntp_servers: "ntp.test",
+    time_api: "http://time.test/now",
+    weather_api: "http://weather.test/current",
+    widgets: &[],
+    probe: "",
+};
+
+const TIME_BODY: &[u8] = br#"{"datetime":"2025-10-17T10:00:02.517-05:00","utc_datetime":"2025-10-17T15:00:02.517+00:00"}"#;
+const WEATHER_BODY: &[u8] = br#"{"current":{"temperature":18.5,"weathercode":61}}"#;
+
+const STORED_WEATHER: CurrentWeather = CurrentWeather {
+    temperature: 21.5,
+    weathercode: 3,
+};
+
+struct Outcome {
+    wake: Wake,
+    display: Framebuffer,
+    utc_offset: Option<UtcOffset>,
+}
+
+fn boot<S: Store>(
+    reason: WakeReason,
+    tz: &TimeZone,
+    rtc: &mut MockRtc,
+    store: &mut S,
+    net: &mut MockNet,
+) -> Outcome {
+    boot_scheduled(reason, tz, &Schedule::DEFAULT, rtc, store, net)
+}
+
+/// The RTC keeps UTC, `tz` is only used for what gets shown and when to wake
+fn boot_scheduled<S: Store>(
+    reason: WakeReason,
+    tz: &TimeZone,
+    schedule: &Schedule,
+    rtc: &mut MockRtc,
+    store: &mut S,
+    net: &mut MockNet,
+) -> Outcome {
+    block_on(async {
+        let mut utc_offset = None;
+
+        let mut postcard = storage::load(store).await.unwrap_or_default();
+
+        let woken = wake::wake_up(
+            rtc,
+            reason,
+            &postcard.calibration,
+            postcard.wakes,
+            tz,
+            schedule,
+        )
+        .await;
+        let mut time = woken.time;
+        postcard.image = woken.image;
+
+        if woken.wake.sync_wifi {
+            let mut rx_buffer = [0; 2048];
+
+            if let Ok(synced) = sync::sync(net, &mut rx_buffer, &CONFIG, time, || 0).await {
+                utc_offset = synced.utc_offset;
+
+                let mut changed = false;
+
+                if let Ok(now) = synced.time {
+                    let offset = synced.ntp.as_ref().ok().and_then(|m| m.offset_micros);
+                    let set =
+                        wake::correct_time(rtc, &mut postcard.calibration, now, offset, |now| {
+                            core::future::ready(now)
+                        })
+                        .await;
+
+                    time = set.or(time);
+                    changed = true;
+                }
+
+                if let Ok(weather) = synced.weather {
+                    postcard.weather = Some(weather);
+                    changed = true;
+                }
+
+                if changed {
+                    wake::save_wakes(rtc, &mut postcard.wakes).await;
+                    storage::save(store, &postcard).await;
+                }
+            }
+        }
+
+        let frame = Frame {
+            weather: postcard.weather,
+            time: time.map(|utc| tz.to_local(utc)),
+            image: IMAGES[postcard.image],
+            ..frame()
+        };
+
+        let mut display = Framebuffer::new();
+        layout::draw_screen(&mut display, woken.wake.screen, &frame);
+
+        wake::sleep(rtc, time.is_some(), tz, schedule).await;
+
+        Outcome {
+            wake: woken.wake,
+            display,
+            utc_offset,
+        }
+    })
+}
+
+/// Nothing to show but the first image, tests fill in what they expect
+fn frame() -> Frame<'static> {
+    Frame {
+        weather: None,
+        time: None,
+        clock: ClockFormat::DEFAULT,
+        bssids: None,
+        image: IMAGES[0],
+        status: None,
+        widgets: [None; MAX_WIDGETS],
+        diagnostics: &[],
+        captive: false,
+    }
+}
+
+fn render(screen: Screen, frame: &Frame) -> Framebuffer {
+    let mut fb = Framebuffer::new();
+    layout::draw_screen(&mut fb, screen, frame);
+    fb
+}
+
+fn stored(weather: Option<CurrentWeather>, image: usize) -> MockStore {
+    let mut store = MockStore::new();
+    block_on(storage::save(
+        &mut store,
+        &Postcard {
+            weather,
+            image,
+            ..Postcard::default()
+        },
+    ));
+    store.writes = 0;
+    store
+}
+
+/// Last set from the network at `anchor`, with no drift measured yet
+fn anchored(anchor: PrimitiveDateTime) -> MockStore {
+    let mut store = MockStore::new();
+    block_on(storage::save(
+        &mut store,
+        &Postcard {
+            calibration: Calibration {
+                anchor: Some(anchor.assume_utc().unix_timestamp()),
+                ..Calibration::default()
+            },
+            ..Postcard::default()
+        },
+    ));
+    store.writes = 0;
+    store
+}
+
+fn persisted(store: &MockStore) -> Postcard {
+    Postcard::decode(&store.data).unwrap()
+}
+
+/// In UTC, as the RTC keeps it
+fn alarm(hour: u8, minute: u8) -> Option<Alarm> {
+    Some(Alarm {
+        second: Some(0),
+        minute: Some(minute),
+        hour: Some(hour),
+    })
+}
+
+#[test]
+fn hourly_alarm_syncs_and_redraws_everything() {
+    let mut rtc = MockRtc::new(at(15, 0));
+    let mut store = stored(Some(STORED_WEATHER), 0);
+    let mut net = MockNet::new(vec![
+        (CONFIG.time_api, TIME_BODY),
+        (CONFIG.weather_api, WEATHER_BODY),
+    ]);
+
+    let outcome = boot(
+        WakeReason::RtcAlarm,
+        &TimeZone::utc(),
+        &mut rtc,
+        &mut store,
+        &mut net,
+    );
+
+    assert_eq!(outcome.wake.screen, Screen::Full);
+    assert!(!net.connected);
+
+    let synced = PrimitiveDateTime::new(
+        at(15, 0).date(),
+        Time::from_hms_milli(15, 0, 2, 517).unwrap(),
+    );
+    assert_eq!(rtc.now, synced);
+    assert_eq!(outcome.utc_offset, UtcOffset::from_hms(-5, 0, 0).ok());
+
+    let weather = persisted(&store).weather.unwrap();
+    assert_eq!(store.writes, 1);
+    assert_eq!(weather.temperature, 18.5);
+    assert_eq!(weather.weathercode, 61);
+
+    let expected = render(
+        Screen::Full,
+        &Frame {
+            weather: Some(weather),
+            time: Some(synced),
+            ..frame()
+        },
+    );
+    assert!(outcome.display.as_bytes() == expected.as_bytes());
+
+    assert_eq!(rtc.alarm, alarm(15, 1));
+}
+
+#[test]
+fn sntp_is_preferred_over_time_api() {
+    let mut rtc = MockRtc::new(at(15, 0));
+    let mut store = stored(Some(STORED_WEATHER), 0);
+    let mut net = MockNet::new(vec![
+        (CONFIG.time_api, TIME_BODY),
+        (CONFIG.weather_api, WEATHER_BODY),
+    ]);
+    net.ntp_time = Some(at(15, 1));
+
+    let outcome = boot(
+        WakeReason::RtcAlarm,
+        &TimeZone::utc(),
+        &mut rtc,
+        &mut store,
+        &mut net,
+    );
+
+    assert_eq!(rtc.now, at(15, 1));
+    assert_eq!(outcome.utc_offset, None);
+    assert!(!net.requests.borrow().contains(&CONFIG.time_api.to_string()));
+    assert!(
+        net.requests
+            .borrow()
+            .contains(&"udp:192.0.2.123:123".to_string())
+    );
+}
+
+#[test]
+fn minute_alarm_only_redraws_top_bar() {
+    let mut rtc = MockRtc::new(at(15, 7));
+    let mut store = stored(Some(STORED_WEATHER), 1);
+    let mut net = MockNet::new(vec![]);
+
+    let outcome = boot(
+        WakeReason::RtcAlarm,
+        &TimeZone::utc(),
+        &mut rtc,
+        &mut store,
+        &mut net,
+    );
+
+    assert_eq!(outcome.wake.screen, Screen::TopBar);
+    assert!(net.requests.borrow().is_empty());
+    assert_eq!(store.writes, 0);
+
+    let expected = render(
+        Screen::TopBar,
+        &Frame {
+            weather: Some(STORED_WEATHER),
+            time: Some(at(15, 7)),
+            ..frame()
+        },
+    );
+    assert!(outcome.display.as_bytes() == expected.as_bytes());
+    assert_eq!(rtc.alarm, alarm(15, 8));
+}
+
+#[test]
+fn wakes_are_saved_to_flash_with_a_sync() {
+    let mut rtc = MockRtc::new(at(15, 7));
+    rtc.ram = RamByte {
+        image: 1,
+        unsaved_wakes: MAX_UNSAVED_WAKES - 1,
+    }
+    .encode();
+    let mut store = stored(Some(STORED_WEATHER), 1);
+    let mut net = MockNet::new(vec![]);
+
+    // Filling the RTC doesn't write flash, and neither does overflowing it
+    for _ in 0..2 {
+        boot(
+            WakeReason::RtcAlarm,
+            &TimeZone::utc(),
+            &mut rtc,
+            &mut store,
+            &mut net,
+        );
+    }
+    assert_eq!(store.writes, 0);
+    assert_eq!(RamByte::decode(rtc.ram).unsaved_wakes, MAX_UNSAVED_WAKES);
+
+    let mut net = MockNet::new(vec![
+        (CONFIG.time_api, TIME_BODY),
+        (CONFIG.weather_api, WEATHER_BODY),
+    ]);
+    boot(
+        WakeReason::A,
+        &TimeZone::utc(),
+        &mut rtc,
+        &mut store,
+        &mut net,
+    );
+    assert_eq!(store.writes, 1);
+
+    let postcard = persisted(&store);
+    assert_eq!(postcard.wakes, MAX_UNSAVED_WAKES as u32);
+    assert_eq!(postcard.weather.map(|w| w.weathercode), Some(61));
+    assert_eq!(
+        RamByte::decode(rtc.ram),
+        RamByte {
+            image: 1,
+            unsaved_wakes: 0
+        }
+    );
+}
+
+#[test]
+fn down_button_shows_next_image() {
+    let mut rtc = MockRtc::new(at(9, 30));
+    rtc.ram = (IMAGES.len() - 1) as u8;
+    let mut store = stored(None, 0);
+    let mut net = MockNet::new(vec![]);
+
+    let outcome = boot(
+        WakeReason::Down,
+        &TimeZone::utc(),
+        &mut rtc,
+        &mut store,
+        &mut net,
+    );
+
+    assert_eq!(outcome.wake.screen, Screen::Image);
+    assert_eq!(RamByte::decode(rtc.ram).image, 0);
+    assert!(net.requests.borrow().is_empty());
+
+    let expected = render(Screen::Image, &frame());
+    assert!(outcome.display.as_bytes() == expected.as_bytes());
+}
+
+#[test]
+fn offline_sync_keeps_stored_weather() {
+    let mut rtc = MockRtc::new(at(15, 0));
+    let mut store = stored(Some(STORED_WEATHER), 0);
+    let mut net = MockNet::offline();
+
+    let outcome = boot(
+        WakeReason::A,
+        &TimeZone::utc(),
+        &mut rtc,
+        &mut store,
+        &mut net,
+    );
+
+    assert!(outcome.wake.sync_wifi);
+    assert!(net.requests.borrow().is_empty());
+    assert_eq!(store.writes, 0);
+    assert_eq!(persisted(&store).weather.unwrap().temperature, 21.5);
+    assert_eq!(rtc.now, at(15, 0));
+}
+
+#[test]
+fn captive_html_is_not_weather() {
+    let mut rtc = MockRtc::new(at(15, 0));
+    let mut store = stored(Some(STORED_WEATHER), 0);
+    let mut net = MockNet::new(vec![
+        (CONFIG.time_api, b"<html>Sign in</html>"),
+        (CONFIG.weather_api, b"<html>Sign in</html>"),
+    ]);
+
+    boot(
+        WakeReason::RtcAlarm,
+        &TimeZone::utc(),
+        &mut rtc,
+        &mut store,
+        &mut net,
+    );
+
+    assert!(
+        net.requests
+            .borrow()
+            .contains(&CONFIG.weather_api.to_string())
+    );
+    assert_eq!(store.writes, 0);
+    assert_eq!(rtc.now, at(15, 0));
+}
+
+#[test]
+fn malformed_time_keeps_rtc_and_weather_still_lands() {
+    let mut rtc = MockRtc::new(at(15, 0));
+    let mut store = stored(Some(STORED_WEATHER), 0);
+    let mut net = MockNet::new(vec![
+        (CONFIG.time_api, br#"{"datetime":"2025-10-17T25:00:00"}"#),
+        (CONFIG.weather_api, WEATHER_BODY),
+    ]);
+
+    let outcome = boot(
+        WakeReason::RtcAlarm,
+        &TimeZone::utc(),
+        &mut rtc,
+        &mut store,
+        &mut net,
+    );
+
+    assert_eq!(rtc.now, at(15, 0));
+    assert_eq!(outcome.utc_offset, None);
+    assert_eq!(store.writes, 1);
+    assert_eq!(persisted(&store).weather.unwrap().weathercode, 61);
+}
+
+#[test]
+fn stopped_oscillator_hides_time_until_synced() {
+    let mut rtc = MockRtc::new(at(0, 0));
+    rtc.oscillator_stopped = true;
+    let mut store = MockStore::new();
+    let mut net = MockNet::offline();
+
+    let outcome = boot(
+        WakeReason::B,
+        &TimeZone::utc(),
+        &mut rtc,
+        &mut store,
+        &mut net,
+    );
+
+    let expected = render(Screen::Full, &frame());
+    assert!(outcome.display.as_bytes() == expected.as_bytes());
+
+    // No schedule to follow without the time
+    assert_eq!(rtc.alarm, Some(wake::sleep_alarm()));
+}
+
+#[test]
+fn hourly_sync_follows_local_time() {
+    let tz = TimeZone::parse("<+0530>-5:30").unwrap();
+
+    // 09:30 UTC is 15:00 in India
+    let mut rtc = MockRtc::new(at(9, 30));
+    let mut store = stored(Some(STORED_WEATHER), 0);
+    let mut net = MockNet::new(vec![
+        (CONFIG.time_api, TIME_BODY),
+        (CONFIG.weather_api, WEATHER_BODY),
+    ]);
+
+    let outcome = boot(WakeReason::RtcAlarm, &tz, &mut rtc, &mut store, &mut net);
+
+    assert!(outcome.wake.sync_wifi);
+    assert_eq!(outcome.wake.screen, Screen::Full);
+
+    // The RTC gets UTC from the time API, the display local time
+    let synced = PrimitiveDateTime::new(
+        at(15, 0).date(),
+        Time::from_hms_milli(15, 0, 2, 517).unwrap(),
+    );
+    assert_eq!(rtc.now, synced);
+
+    let expected = render(
+        Screen::Full,
+        &Frame {
+            weather: Some(persisted(&store).weather.unwrap()),
+            time: Some(tz.to_local(synced)),
+            ..frame()
+        },
+    );
+    assert!(outcome.display.as_bytes() == expected.as_bytes());
+
+    // Whereas on the hour in UTC is half past locally
+    let mut rtc = MockRtc::new(at(10, 0));
+    let mut net = MockNet::new(vec![]);
+
+    let outcome = boot(WakeReason::RtcAlarm, &tz, &mut rtc, &mut store, &mut net);
+
+    assert!(!outcome.wake.sync_wifi);
+    assert_eq!(outcome.wake.screen, Screen::TopBar);
+}
+
+#[test]
+fn drift_is_measured_and_trimmed() {
+    // Set twelve hours ago, and 2.16s behind now, give or take the half
+    // second lost to reading whole seconds
+    let mut rtc = MockRtc::new(at(15, 0));
+    let mut store = anchored(at(3, 0));
+    let mut net = MockNet::new(vec![(CONFIG.weather_api, WEATHER_BODY)]);
+    let server = at(15, 0) + Duration::milliseconds(2660);
+    net.ntp_time = Some(server);
+
+    boot(
+        WakeReason::RtcAlarm,
+        &TimeZone::utc(),
+        &mut rtc,
+        &mut store,
+        &mut net,
+    );
+
+    assert_eq!(rtc.now, server);
+    assert_eq!(rtc.offset, 0x74);
+
+    let calibration = persisted(&store).calibration;
+    assert_eq!(calibration.samples, 1);
+    assert_eq!(calibration.steps(), -12);
+    assert_eq!(
+        calibration.anchor,
+        Some(server.assume_utc().unix_timestamp())
+    );
+}
+
+#[test]
+fn small_drift_leaves_rtc_alone() {
+    let mut rtc = MockRtc::new(at(15, 0));
+    let mut store = anchored(at(14, 0));
+    let mut net = MockNet::new(vec![(CONFIG.weather_api, WEATHER_BODY)]);
+    net.ntp_time = Some(at(15, 0) + Duration::milliseconds(300));
+
+    boot(
+        WakeReason::RtcAlarm,
+        &TimeZone::utc(),
+        &mut rtc,
+        &mut store,
+        &mut net,
+    );
+
+    assert_eq!(rtc.now, at(15, 0));
+    assert_eq!(
+        persisted(&store).calibration.anchor,
+        Some(at(14, 0).assume_utc().unix_timestamp())
+    );
+}
+
+#[test]
+fn quiet_hours_sleep_until_the_morning_refresh() {
+    let tz = TimeZone::parse("CST6CDT,M3.2.0,M11.1.0").unwrap();
+    let schedule = Schedule {
+        refresh_minutes: 5,
+        sync_minutes: 60,
+        quiet: QuietHours::parse("23:00-07:30"),
+    };
+
+    // 22:55 in Chicago, the last wake of the evening
+    let mut rtc = MockRtc::new(at(3, 55));
+    let mut store = stored(Some(STORED_WEATHER), 0);
+    let mut net = MockNet::new(vec![]);
+
+    let outcome = boot_scheduled(
+        WakeReason::RtcAlarm,
+        &tz,
+        &schedule,
+        &mut rtc,
+        &mut store,
+        &mut net,
+    );
+
+    assert!(!outcome.wake.sync_wifi);
+    assert_eq!(outcome.wake.screen, Screen::TopBar);
+    assert_eq!(rtc.alarm, alarm(12, 30));
+
+    // 07:30 isn't on the hour, but the morning wake syncs anyway
+    let mut rtc = MockRtc::new(at(12, 30));
+    let mut net = MockNet::new(vec![
+        (CONFIG.time_api, TIME_BODY),
+        (CONFIG.weather_api, WEATHER_BODY),
+    ]);
+
+    let outcome = boot_scheduled(
+        WakeReason::RtcAlarm,
+        &tz,
+        &schedule,
+        &mut rtc,
+        &mut store,
+        &mut net,
+    );
+
+    assert!(outcome.wake.sync_wifi);
+    assert_eq!(outcome.wake.screen, Screen::Full);
+    assert_eq!(persisted(&store).weather.unwrap().weathercode, 61);
+}
+
+#[test]
+fn widgets_are_fetched_with_the_sync() {
+    let widgets = widgets::widgets(
+        "top-left\t5\thttp://ci.test/status\t$.builds[0].state\tCI: {}\n\
+         bottom-right\t5\thttp://down.test/count\t$.count\n",
+    );
+    let config = SyncConfig {
+        widgets: &widgets,
+        ..CONFIG
+    };
+    let mut net = MockNet::new(vec![
+        ("http://time.test/now", TIME_BODY),
+        ("http://weather.test/current", WEATHER_BODY),
+        (
+            "http://ci.test/status",
+            br#"{"builds":[{"state":"passing","id":41},{"state":"failed"}]}"#,
+        ),
+    ]);
+
+    let synced = block_on(sync::sync(&mut net, &mut [0; 2048], &config, None, || 0)).unwrap();
+
+    assert!(synced.weather.is_ok());
+    assert!(matches!(
+        &synced.widgets[..],
+        [
+            (Region::TopLeft, Ok(text)),
+            (Region::BottomRight, Err(_)),
+        ] if text == "CI: passing"
+    ));
+    assert!(!net.connected);
+    assert!(net.requests.borrow().ends_with(&[
+        "http://ci.test/status".into(),
+        "http://down.test/count".into()
+    ]));
+}
+
+#[test]
+fn captive_portal_skips_the_apis() {
+    // The portal answers everything with its login page
+    const LOGIN: &[u8] = br#"<html><form action="/login"><input name="room">"#;
+
+    let widgets = widgets::widgets("top-left\t5\thttp://ci.test/status\t$.state\n");
+    let config = SyncConfig {
+        widgets: &widgets,
+        probe: "http://probe.test/generate_204",
+        ..CONFIG
+    };
+    let mut net = MockNet::new(vec![
+        ("http://probe.test/generate_204", LOGIN),
+        (CONFIG.time_api, LOGIN),
+        (CONFIG.weather_api, LOGIN),
+        ("http://ci.test/status", LOGIN),
+    ]);
+
+    let synced = block_on(sync::sync(&mut net, &mut [0; 2048], &config, None, || 0)).unwrap();
+
+    assert_eq!(synced.connectivity, Some(Connectivity::Captive));
+    assert!(matches!(synced.time, Err(FetchError::Captive)));
+    assert!(matches!(synced.weather, Err(FetchError::Captive)));
+    assert!(matches!(
+        &synced.widgets[..],
+        [(Region::TopLeft, Err(FetchError::Captive))]
+    ));
+
+    // Only SNTP, which a portal can't answer with a page
+    let requests = net.requests.borrow();
+    assert_eq!(requests[0], config.probe);
+    assert!(requests[1..].iter().all(|r| !r.starts_with("http")));
+}
+
+#[test]
+fn online_after_the_probe() {
+    let config = SyncConfig {
+        probe: "http://probe.test/generate_204",
+        ..CONFIG
+    };
+    let mut net = MockNet::new(vec![
+        (config.probe, b""),
+        (CONFIG.time_api, TIME_BODY),
+        (CONFIG.weather_api, WEATHER_BODY),
+    ]);
+
+    let synced = block_on(sync::sync(&mut net, &mut [0; 2048], &config, None, || 0)).unwrap();
+    assert_eq!(synced.connectivity, Some(Connectivity::Online));
+    assert!(synced.time.is_ok());
+    assert!(synced.weather.is_ok());
+
+    // Without an answer from the probe the APIs are still tried, it may
+    // only be the probe's host that's blocked
+    let mut net = MockNet::new(vec![(CONFIG.weather_api, WEATHER_BODY)]);
+
+    let synced = block_on(sync::sync(&mut net, &mut [0; 2048], &config, None, || 0)).unwrap();
+    assert_eq!(synced.connectivity, Some(Connectivity::Offline));
+    assert!(synced.weather.is_ok());
+}
@@ -0,0 +1,268 @@
+//! In-memory stand-ins for the badge hardware
+
+// Not every test file uses every mock
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+
+use badger_core::hal::{Alarm, Fetcher, Network, Probe, Rtc, Store, Udp};
+use badger_core::http::{BodySink, HttpError, Response};
+use badger_core::sntp::{PACKET_SIZE, Timestamp};
+use badger_core::storage::POSTCARD_SIZE;
+use time::{Date, Month, PrimitiveDateTime, Time};
+
+pub fn at(hour: u8, minute: u8) -> PrimitiveDateTime {
+    PrimitiveDateTime::new(
+        Date::from_calendar_date(2025, Month::October, 17).unwrap(),
+        Time::from_hms(hour, minute, 0).unwrap(),
+    )
+}
+
+pub struct MockRtc {
+    pub now: PrimitiveDateTime,
+    pub oscillator_stopped: bool,
+    pub ram: u8,
+    pub offset: u8,
+    pub alarm: Option<Alarm>,
+}
+
+impl MockRtc {
+    pub fn new(now: PrimitiveDateTime) -> Self {
+        Self {
+            now,
+            oscillator_stopped: false,
+            ram: 0,
+            offset: 0,
+            alarm: None,
+        }
+    }
+}
+
+impl Rtc for MockRtc {
+    type Error = ();
+
+    async fn datetime(&mut self) -> Result<PrimitiveDateTime, ()> {
+        Ok(self.now)
+    }
+
+    async fn set_datetime(&mut self, now: &PrimitiveDateTime) -> Result<(), ()> {
+        self.now = *now;
+        self.oscillator_stopped = false;
+        Ok(())
+    }
+
+    async fn oscillator_stopped(&mut self) -> Result<bool, ()> {
+        Ok(self.oscillator_stopped)
+    }
+
+    async fn ram_byte(&mut self) -> Result<u8, ()> {
+        Ok(self.ram)
+    }
+
+    async fn set_ram_byte(&mut self, value: u8) -> Result<(), ()> {
+        self.ram = value;
+        Ok(())
+    }
+
+    async fn set_offset(&mut self, offset: u8) -> Result<(), ()> {
+        self.offset = offset;
+        Ok(())
+    }
+
+    async fn set_alarm(&mut self, alarm: Alarm) -> Result<(), ()> {
+        self.alarm = Some(alarm);
+        Ok(())
+    }
+}
+
+/// Starts out erased, like a fresh flash sector
+pub struct MockStore {
+    pub data: Vec<u8>,
+    pub writes: usize,
+}
+
+impl MockStore {
+    pub fn new() -> Self {
+        Self {
+            data: vec![0xFF; POSTCARD_SIZE],
+            writes: 0,
+        }
+    }
+}
+
+impl Store for MockStore {
+    type Error = ();
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<(), ()> {
+        let len = buf.len().min(self.data.len());
+        buf[..len].copy_from_slice(&self.data[..len]);
+        Ok(())
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), ()> {
+        self.data = vec![0xFF; POSTCARD_SIZE];
+        self.data[..data.len()].copy_from_slice(data);
+        self.writes += 1;
+        Ok(())
+    }
+}
+
+/// Builds what an NTP server would send back for `request`
+pub fn ntp_reply(request: &[u8], now: PrimitiveDateTime) -> [u8; PACKET_SIZE] {
+    let now = Timestamp::from_datetime(now).0.to_be_bytes();
+
+    let mut reply = [0u8; PACKET_SIZE];
+    reply[0] = 0b00_100_100;
+    reply[1] = 2;
+    reply[24..32].copy_from_slice(&request[40..48]);
+    reply[32..40].copy_from_slice(&now);
+    reply[40..48].copy_from_slice(&now);
+    reply
+}
+
+/// Serves canned bodies by URL a few bytes at a time, and records what was
+/// asked for.
+/// Answers SNTP with `ntp_time` when set.
+pub struct MockNet {
+    pub online: bool,
+    pub connected: bool,
+    pub responses: Vec<(&'static str, &'static [u8])>,
+    pub ntp_time: Option<PrimitiveDateTime>,
+    pub requests: RefCell<Vec<String>>,
+}
+
+impl MockNet {
+    pub fn new(responses: Vec<(&'static str, &'static [u8])>) -> Self {
+        Self {
+            online: true,
+            connected: false,
+            responses,
+            ntp_time: None,
+            requests: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn offline() -> Self {
+        Self {
+            online: false,
+            ..Self::new(Vec::new())
+        }
+    }
+}
+
+impl Network for MockNet {
+    type Error = ();
+
+    async fn connect(&mut self) -> Result<(), ()> {
+        self.connected = self.online;
+        self.online.then_some(()).ok_or(())
+    }
+
+    async fn disconnect(&mut self) {
+        self.connected = false;
+    }
+}
+
+impl Fetcher for MockNet {
+    type Error = HttpError;
+
+    /// Anything not in `responses` can't be reached
+    async fn get(
+        &self,
+        url: &str,
+        buf: &mut [u8],
+        sink: &mut impl BodySink,
+    ) -> Result<(), HttpError> {
+        assert!(self.connected, "fetched {url} while disconnected");
+        self.requests.borrow_mut().push(url.to_string());
+
+        let (_, body) = self
+            .responses
+            .iter()
+            .find(|(u, _)| *u == url)
+            .ok_or(HttpError::Connection)?;
+        for chunk in body.chunks(buf.len().min(64)) {
+            sink.write(chunk);
+        }
+        Ok(())
+    }
+}
+
+/// The same bodies, every one a 200
+impl Probe for MockNet {
+    async fn probe(
+        &self,
+        url: &str,
+        buf: &mut [u8],
+        sink: &mut impl BodySink,
+    ) -> Result<Response, HttpError> {
+        self.get(url, buf, sink).await?;
+
+        Ok(Response {
+            status: 200,
+            location: None,
+        })
+    }
+}
+
+impl Udp for MockNet {
+    type Error = ();
+
+    async fn resolve(&self, host: &str) -> Result<IpAddr, ()> {
+        self.requests.borrow_mut().push(format!("dns:{host}"));
+        Ok(IpAddr::from([192, 0, 2, 123]))
+    }
+
+    async fn exchange(
+        &self,
+        to: SocketAddr,
+        request: &[u8],
+        response: &mut [u8],
+    ) -> Result<usize, ()> {
+        assert!(self.connected, "sent to {to} while disconnected");
+        self.requests.borrow_mut().push(format!("udp:{to}"));
+
+        let reply = ntp_reply(request, self.ntp_time.ok_or(())?);
+        response[..reply.len()].copy_from_slice(&reply);
+        Ok(reply.len())
+    }
+}
+
+/// A real localhost connection, blocking under the async traits
+pub struct HostTcp(pub std::net::TcpStream);
+
+impl embedded_io_async::ErrorType for HostTcp {
+    type Error = embedded_io_async::ErrorKind;
+}
+
+impl embedded_io_async::Read for HostTcp {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.0
+            .read(buf)
+            .map_err(|_| embedded_io_async::ErrorKind::Other)
+    }
+}
+
+impl embedded_io_async::Write for HostTcp {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.0
+            .write(buf)
+            .map_err(|_| embedded_io_async::ErrorKind::Other)
+    }
+}
+
+/// Sends `request` in two writes, like a browser sending headers first
+pub fn send(addr: SocketAddr, request: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let split = request.find("\r\n\r\n").map_or(request.len(), |i| i + 4);
+
+    stream.write_all(&request.as_bytes()[..split]).unwrap();
+    stream.flush().unwrap();
+    stream.write_all(&request.as_bytes()[split..]).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
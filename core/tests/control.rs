@@ -0,0 +1,131 @@
+//! The control API, driven by a real TCP client over localhost
+
+mod common;
+
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+
+use badger_core::control::{self, Command, State};
+use badger_core::httpd::HttpdError;
+use badger_core::weather::CurrentWeather;
+use common::{HostTcp, at, send};
+use embassy_futures::block_on;
+
+type Handled = Vec<Result<Option<Command>, HttpdError>>;
+
+/// Serves `requests` connections against the same state, returning what
+/// each `control::handle` call came back with
+fn serve(requests: usize) -> (SocketAddr, thread::JoinHandle<Handled>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let handle = thread::spawn(move || {
+        let state = State {
+            weather: Some(CurrentWeather {
+                temperature: 18.5,
+                weathercode: 61,
+            }),
+            time: Some(at(19, 5)),
+            image: 1,
+            status: "",
+        };
+
+        (0..requests)
+            .map(|_| {
+                let (stream, _) = listener.accept().unwrap();
+                let mut conn = HostTcp(stream);
+                let mut buf = [0; 1024];
+                block_on(control::handle(&mut conn, &mut buf, &state))
+            })
+            .collect()
+    });
+
+    (addr, handle)
+}
+
+fn post(addr: SocketAddr, path: &str, json: &str) -> String {
+    send(
+        addr,
+        &format!(
+            "POST {path} HTTP/1.1\r\nHost: badger\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n{json}",
+            json.len()
+        ),
+    )
+}
+
+fn body(response: &str) -> &str {
+    response.split_once("\r\n\r\n").unwrap().1
+}
+
+#[test]
+fn reports_state() {
+    let (addr, server) = serve(1);
+
+    let response = send(addr, "GET /api/state HTTP/1.1\r\nHost: badger\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n"));
+    assert_eq!(
+        body(&response),
+        r#"{"weather":{"temperature":18.5,"weathercode":61},"time":"2025-10-17T19:05:00Z","image":1,"images":3,"status":""}"#
+    );
+
+    assert_eq!(server.join().unwrap(), [Ok(None)]);
+}
+
+#[test]
+fn commands_answer_with_the_new_state() {
+    let (addr, server) = serve(4);
+
+    let redraw = post(addr, "/api/redraw", "");
+    assert!(body(&redraw).contains(r#""image":1"#));
+
+    let image = post(addr, "/api/image", r#"{"index":2}"#);
+    assert!(body(&image).contains(r#""image":2"#));
+
+    let next = post(addr, "/api/image", r#"{"shift":"next"}"#);
+    assert!(body(&next).contains(r#""image":2"#));
+
+    let status = post(
+        addr,
+        "/api/status",
+        r#"{"message":"Talk at 3pm, room \"B\""}"#,
+    );
+    assert!(body(&status).ends_with(r#""status":"Talk at 3pm, room \"B\""}"#));
+
+    assert_eq!(
+        server.join().unwrap(),
+        [
+            Ok(Some(Command::Redraw)),
+            Ok(Some(Command::Image(2))),
+            Ok(Some(Command::Image(2))),
+            Ok(Some(Command::Status(
+                "Talk at 3pm, room \"B\"".try_into().unwrap()
+            ))),
+        ]
+    );
+}
+
+#[test]
+fn bad_requests_change_nothing() {
+    let (addr, server) = serve(4);
+
+    let index = post(addr, "/api/image", r#"{"index":7}"#);
+    assert!(index.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    assert!(body(&index).starts_with(r#"{"error":"#));
+
+    let long = format!(r#"{{"message":"{}"}}"#, "x".repeat(65));
+    let status = post(addr, "/api/status", &long);
+    assert!(status.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+    let method = send(addr, "GET /api/redraw HTTP/1.1\r\nHost: badger\r\n\r\n");
+    assert!(method.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+
+    let missing = send(addr, "GET / HTTP/1.1\r\nHost: badger\r\n\r\n");
+    assert!(missing.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+    assert_eq!(
+        server.join().unwrap(),
+        [Ok(None), Ok(None), Ok(None), Ok(None)]
+    );
+}
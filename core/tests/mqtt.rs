@@ -0,0 +1,178 @@
+//! The MQTT client against a stand-in for mosquitto over localhost
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+
+use badger_core::mqtt::{self, Client, Message, MqttError, Options, Packet};
+use common::HostTcp;
+use embassy_futures::block_on;
+
+/// What the stand-in saw, one `(type byte, body)` per packet
+type Seen = Vec<(u8, Vec<u8>)>;
+
+fn read_packet(stream: &mut TcpStream) -> Option<(u8, Vec<u8>)> {
+    let mut byte = [0];
+    stream.read_exact(&mut byte).ok()?;
+    let kind = byte[0];
+
+    let mut remaining = 0;
+    for shift in [0, 7, 14, 21] {
+        stream.read_exact(&mut byte).ok()?;
+        remaining |= ((byte[0] & 0x7F) as usize) << shift;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+
+    let mut body = vec![0; remaining];
+    stream.read_exact(&mut body).ok()?;
+    Some((kind, body))
+}
+
+/// Accepts one client and answers like a broker would: CONNACK with
+/// `code`, SUBACK, PINGRESP, and once subscribed, one message on
+/// `badger/message`. Stops at DISCONNECT or once the client goes away.
+fn broker(code: u8) -> (SocketAddr, thread::JoinHandle<Seen>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let handle = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut seen = Vec::new();
+
+        while let Some((kind, body)) = read_packet(&mut stream) {
+            seen.push((kind, body.clone()));
+
+            match kind >> 4 {
+                1 => {
+                    stream.write_all(&[0x20, 2, 0, code]).unwrap();
+                    if code != 0 {
+                        break;
+                    }
+                }
+                8 => {
+                    stream.write_all(&[0x90, 3, body[0], body[1], 0]).unwrap();
+                    stream
+                        .write_all(b"\x30\x19\x00\x0ebadger/messageBack at 3")
+                        .unwrap();
+                }
+                12 => stream.write_all(&[0xD0, 0]).unwrap(),
+                14 => break,
+                _ => {}
+            }
+        }
+
+        seen
+    });
+
+    (addr, handle)
+}
+
+fn options() -> Options<'static> {
+    Options {
+        client_id: "badger-17",
+        username: Some("badge"),
+        password: Some("secret"),
+        keep_alive_secs: mqtt::KEEP_ALIVE_SECS,
+        will: Some(Message {
+            topic: "badger/online",
+            payload: b"offline",
+            retain: true,
+        }),
+    }
+}
+
+#[test]
+fn publishes_and_hears_messages() {
+    let (addr, broker) = broker(0);
+
+    block_on(async {
+        let conn = HostTcp(TcpStream::connect(addr).unwrap());
+        let mut buf = [0; mqtt::PACKET_SIZE];
+
+        let mut client = Client::connect(conn, &mut buf, &options()).await.unwrap();
+
+        client.subscribe(&mut buf, "badger/message").await.unwrap();
+        client
+            .publish(
+                &mut buf,
+                &Message {
+                    topic: "badger/state",
+                    payload: br#"{"image":1}"#,
+                    retain: true,
+                },
+            )
+            .await
+            .unwrap();
+
+        let first = client.wait().await.unwrap();
+        assert_eq!(
+            client.receive(first, &mut buf).await,
+            Ok(Packet::SubAck { granted: true })
+        );
+
+        let first = client.wait().await.unwrap();
+        assert_eq!(
+            client.receive(first, &mut buf).await,
+            Ok(Packet::Publish {
+                topic: "badger/message",
+                payload: b"Back at 3",
+            })
+        );
+
+        client.ping().await.unwrap();
+        let first = client.wait().await.unwrap();
+        assert_eq!(client.receive(first, &mut buf).await, Ok(Packet::PingResp));
+
+        client.disconnect().await;
+    });
+
+    let seen = broker.join().unwrap();
+    let kinds: Vec<u8> = seen.iter().map(|(kind, _)| *kind).collect();
+    assert_eq!(kinds, [0x10, 0x82, 0x31, 0xC0, 0xE0]);
+
+    let connect = &seen[0].1;
+    assert!(connect.starts_with(b"\x00\x04MQTT\x04\xE6\x00\x3c\x00\x09badger-17"));
+    assert!(connect.ends_with(b"\x00\x05badge\x00\x06secret"));
+
+    assert_eq!(seen[2].1, b"\x00\x0cbadger/state{\"image\":1}");
+}
+
+#[test]
+fn refused_credentials_are_an_error() {
+    let (addr, broker) = broker(5);
+
+    let result = block_on(async {
+        let conn = HostTcp(TcpStream::connect(addr).unwrap());
+        let mut buf = [0; mqtt::PACKET_SIZE];
+        Client::connect(conn, &mut buf, &options()).await.err()
+    });
+
+    assert_eq!(result, Some(MqttError::Refused(5)));
+    broker.join().unwrap();
+}
+
+#[test]
+fn a_dropped_broker_is_noticed() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // Accepts, then goes away right after the CONNACK
+    let broker = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        read_packet(&mut stream).unwrap();
+        stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+    });
+
+    block_on(async {
+        let conn = HostTcp(TcpStream::connect(addr).unwrap());
+        let mut buf = [0; mqtt::PACKET_SIZE];
+        let mut client = Client::connect(conn, &mut buf, &options()).await.unwrap();
+
+        broker.join().unwrap();
+        assert_eq!(client.wait().await, Err(MqttError::Io));
+    });
+}
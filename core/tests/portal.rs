@@ -0,0 +1,123 @@
+//! The setup page, served to a real TCP client over localhost
+
+mod common;
+
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+
+use badger_core::httpd::HttpdError;
+use badger_core::portal;
+use badger_core::settings::{Location, Settings};
+use common::{HostTcp, send};
+use embassy_futures::block_on;
+
+type Handled = Vec<Result<Option<Settings>, HttpdError>>;
+
+/// Serves `requests` connections one after another, returning what each
+/// `portal::handle` call came back with
+fn serve(current: Settings, requests: usize) -> (SocketAddr, thread::JoinHandle<Handled>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let handle = thread::spawn(move || {
+        (0..requests)
+            .map(|_| {
+                let (stream, _) = listener.accept().unwrap();
+                let mut conn = HostTcp(stream);
+                let mut buf = [0; 1024];
+                block_on(portal::handle(&mut conn, &mut buf, &current))
+            })
+            .collect()
+    });
+
+    (addr, handle)
+}
+
+fn post(addr: SocketAddr, form: &str) -> String {
+    send(
+        addr,
+        &format!(
+            "POST /save HTTP/1.1\r\nHost: 192.168.4.1\r\n\
+             Content-Type: application/x-www-form-urlencoded\r\n\
+             Content-Length: {}\r\n\r\n{form}",
+            form.len()
+        ),
+    )
+}
+
+#[test]
+fn form_is_served_and_saved() {
+    let (addr, server) = serve(Settings::new(), 2);
+
+    let page = send(addr, "GET / HTTP/1.1\r\nHost: 192.168.4.1\r\n\r\n");
+    assert!(page.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(page.contains("<form method=\"post\" action=\"/save\">"));
+
+    let saved = post(
+        addr,
+        "ssid=Conference+Guest&password=welcome2025&timezone=EST5EDT%2CM3.2.0%2CM11.1.0\
+         &latitude=40.7128&longitude=-74.006",
+    );
+    assert!(saved.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(saved.contains("restarting"));
+
+    let results = server.join().unwrap();
+    assert_eq!(results[0], Ok(None));
+
+    let settings = results[1].clone().unwrap().unwrap();
+    assert_eq!(settings.ssid, "Conference Guest");
+    assert_eq!(settings.password, "welcome2025");
+    assert_eq!(settings.timezone, "EST5EDT,M3.2.0,M11.1.0");
+    assert_eq!(
+        settings.location,
+        Some(Location {
+            latitude: 40.7128,
+            longitude: -74.006
+        })
+    );
+}
+
+#[test]
+fn bad_form_is_shown_again() {
+    let (addr, server) = serve(Settings::new(), 1);
+
+    let response = post(
+        addr,
+        "ssid=Cafe&password=short&timezone=&latitude=&longitude=",
+    );
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    assert!(response.contains("8 to 63 characters"));
+    assert!(response.contains("value=\"\""));
+
+    assert_eq!(server.join().unwrap(), [Ok(None)]);
+}
+
+#[test]
+fn captive_probes_are_sent_to_the_form() {
+    let (addr, server) = serve(Settings::new(), 2);
+
+    for probe in ["/hotspot-detect.html", "/generate_204"] {
+        let response = send(
+            addr,
+            &format!("GET {probe} HTTP/1.1\r\nHost: captive.apple.com\r\n\r\n"),
+        );
+        assert!(response.starts_with("HTTP/1.1 302 Found\r\n"));
+        assert!(response.contains("Location: http://192.168.4.1/\r\n"));
+    }
+
+    assert_eq!(server.join().unwrap(), [Ok(None), Ok(None)]);
+}
+
+#[test]
+fn oversized_request_is_refused() {
+    let (addr, server) = serve(Settings::new(), 1);
+
+    // Refused on the headers alone, before any of the body is sent
+    let response = send(
+        addr,
+        "POST /save HTTP/1.1\r\nHost: 192.168.4.1\r\nContent-Length: 4096\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+
+    assert_eq!(server.join().unwrap(), [Err(HttpdError::TooLarge)]);
+}
@@ -0,0 +1,347 @@
+//! Golden-image tests for each screen. References live in
+//! `tests/snapshots/*.pbm`; regenerate them after an intended layout change
+//! with `UPDATE_SNAPSHOTS=1 cargo test-host`.
+
+use std::fs;
+use std::path::PathBuf;
+
+use badger_core::clock::{ClockFormat, HourFormat};
+use badger_core::diagnostics::{Diagnostics, Endpoint, Failure, Join, JoinStatus};
+use badger_core::framebuffer::Framebuffer;
+use badger_core::image::IMAGES;
+use badger_core::layout::{self, Frame, HEIGHT, Screen, WIDTH};
+use badger_core::weather::CurrentWeather;
+use badger_core::widgets::MAX_WIDGETS;
+use embedded_graphics::prelude::*;
+use time::{Date, Month, PrimitiveDateTime, Time};
+
+fn at(hour: u8, minute: u8) -> PrimitiveDateTime {
+    PrimitiveDateTime::new(
+        Date::from_calendar_date(2025, Month::October, 17).unwrap(),
+        Time::from_hms(hour, minute, 0).unwrap(),
+    )
+}
+
+fn fixture() -> Frame<'static> {
+    Frame {
+        weather: Some(CurrentWeather {
+            temperature: 21.5,
+            weathercode: 3,
+        }),
+        time: Some(at(14, 5)),
+        clock: ClockFormat::DEFAULT,
+        bssids: None,
+        image: IMAGES[0],
+        status: None,
+        widgets: [None; MAX_WIDGETS],
+        diagnostics: &[],
+        captive: false,
+    }
+}
+
+fn to_pbm(fb: &Framebuffer) -> Vec<u8> {
+    let mut out = format!("P4\n{WIDTH} {HEIGHT}\n").into_bytes();
+    out.extend_from_slice(fb.as_bytes());
+    out
+}
+
+fn assert_snapshot(name: &str, actual: &Framebuffer) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(name)
+        .with_extension("pbm");
+
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::write(&path, to_pbm(actual)).unwrap();
+        return;
+    }
+
+    let expected = fs::read(&path)
+        .ok()
+        .and_then(|data| Framebuffer::from_pbm(&data))
+        .unwrap_or_else(|| panic!("Missing or invalid reference {}", path.display()));
+
+    let mut diff = 0;
+    let (mut min, mut max) = (
+        Point::new(i32::MAX, i32::MAX),
+        Point::new(i32::MIN, i32::MIN),
+    );
+
+    for y in 0..HEIGHT as i32 {
+        for x in 0..WIDTH as i32 {
+            let point = Point::new(x, y);
+            if actual.pixel(point) != expected.pixel(point) {
+                diff += 1;
+                min = min.component_min(point);
+                max = max.component_max(point);
+            }
+        }
+    }
+
+    if diff > 0 {
+        let out = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{name}.actual.pbm"));
+        fs::write(&out, to_pbm(actual)).unwrap();
+
+        panic!(
+            "{name}: {diff} pixels differ between {min:?} and {max:?}, actual output written to {}",
+            out.display()
+        );
+    }
+}
+
+#[test]
+fn full() {
+    let mut fb = Framebuffer::new();
+    layout::draw_badge(&mut fb, &fixture());
+    assert_snapshot("full", &fb);
+}
+
+#[test]
+fn top_bar() {
+    let mut fb = Framebuffer::new();
+    layout::draw_top_bar(&mut fb, &fixture());
+    assert_snapshot("top_bar", &fb);
+}
+
+#[test]
+fn top_bar_without_data() {
+    let frame = Frame {
+        weather: None,
+        time: None,
+        ..fixture()
+    };
+
+    let mut fb = Framebuffer::new();
+    layout::draw_top_bar(&mut fb, &frame);
+    assert_snapshot("top_bar_without_data", &fb);
+}
+
+#[test]
+fn top_bar_24h_with_date() {
+    // With the weather there's no room for the date
+    let frame = Frame {
+        weather: None,
+        clock: ClockFormat {
+            hours: HourFormat::TwentyFour,
+            date: true,
+            ..ClockFormat::DEFAULT
+        },
+        ..fixture()
+    };
+
+    let mut fb = Framebuffer::new();
+    layout::draw_top_bar(&mut fb, &frame);
+    assert_snapshot("top_bar_24h_with_date", &fb);
+}
+
+#[test]
+fn top_bar_date_with_long_weather() {
+    let frame = Frame {
+        weather: Some(CurrentWeather {
+            temperature: -12.5,
+            weathercode: 95,
+        }),
+        clock: ClockFormat {
+            date: true,
+            ..ClockFormat::DEFAULT
+        },
+        ..fixture()
+    };
+
+    let mut fb = Framebuffer::new();
+    let time = layout::draw_time(&mut fb, &frame).unwrap();
+    let weather = layout::draw_weather(&mut fb, &frame).unwrap();
+    assert!(time.top_left.x > weather.top_left.x + weather.size.width as i32);
+
+    let mut fb = Framebuffer::new();
+    layout::draw_top_bar(&mut fb, &frame);
+    assert_snapshot("top_bar_date_with_long_weather", &fb);
+}
+
+#[test]
+fn top_bar_with_bssid_count() {
+    let frame = Frame {
+        bssids: Some(1234),
+        ..fixture()
+    };
+
+    let mut fb = Framebuffer::new();
+    layout::draw_top_bar(&mut fb, &frame);
+    assert_snapshot("top_bar_with_bssid_count", &fb);
+}
+
+#[test]
+fn top_bar_date_with_many_aps() {
+    // No room for the count, or the date
+    let frame = Frame {
+        bssids: Some(12345),
+        clock: ClockFormat {
+            date: true,
+            ..ClockFormat::DEFAULT
+        },
+        ..fixture()
+    };
+
+    let mut fb = Framebuffer::new();
+    layout::draw_top_bar(&mut fb, &frame);
+    assert_snapshot("top_bar_date_with_many_aps", &fb);
+
+    // And never over the weather, however long the count
+    let weather = layout::draw_weather(&mut fb, &frame).unwrap();
+    let time = layout::draw_time(
+        &mut fb,
+        &Frame {
+            bssids: Some(u32::MAX),
+            ..frame
+        },
+    )
+    .unwrap();
+    assert!(time.top_left.x >= weather.top_left.x + weather.size.width as i32);
+}
+
+#[test]
+fn top_bar_behind_captive_portal() {
+    let frame = Frame {
+        bssids: Some(12),
+        captive: true,
+        ..fixture()
+    };
+
+    let mut fb = Framebuffer::new();
+    layout::draw_top_bar(&mut fb, &frame);
+    assert_snapshot("top_bar_behind_captive_portal", &fb);
+}
+
+#[test]
+fn image_with_status() {
+    let frame = Frame {
+        status: Some("Talk at 3pm in room B"),
+        ..fixture()
+    };
+
+    let mut fb = Framebuffer::new();
+    layout::draw_current_image(&mut fb, &frame);
+    assert_snapshot("image_with_status", &fb);
+}
+
+#[test]
+fn image_with_widgets() {
+    let frame = Frame {
+        status: Some("Talk at 3pm in room B"),
+        widgets: [
+            Some("CI: passing"),
+            Some("ACME $12.50 (delayed 15 min)"),
+            Some("hidden by the status"),
+            None,
+        ],
+        ..fixture()
+    };
+
+    let mut fb = Framebuffer::new();
+    layout::draw_current_image(&mut fb, &frame);
+    assert_snapshot("image_with_widgets", &fb);
+}
+
+#[test]
+fn setup() {
+    let mut fb = Framebuffer::new();
+    layout::draw_screen(&mut fb, Screen::Setup, &fixture());
+    assert_snapshot("setup", &fb);
+}
+
+#[test]
+fn diagnostics() {
+    const NOW: i64 = 1_760_709_900;
+
+    let failed = Endpoint {
+        result: Some(Err(Failure::Dns)),
+        at: Some(NOW - 300),
+        ok_at: Some(NOW - 7200),
+    };
+    let record = Diagnostics {
+        join: Some(Join {
+            ssid: "Conference".try_into().unwrap(),
+            status: JoinStatus::Joined,
+            rssi: Some(-78),
+            at: Some(NOW - 300),
+        }),
+        address_ms: Some(4210),
+        ntp: Endpoint {
+            result: Some(Err(Failure::Timeout)),
+            ..failed
+        },
+        time_api: failed,
+        weather: failed,
+        ..Diagnostics::new()
+    };
+    let lines = record.lines(Some(NOW));
+
+    let frame = Frame {
+        diagnostics: &lines,
+        ..fixture()
+    };
+
+    let mut fb = Framebuffer::new();
+    layout::draw_screen(&mut fb, Screen::Diagnostics, &frame);
+    assert_snapshot("diagnostics", &fb);
+}
+
+#[test]
+fn image() {
+    for (index, image) in IMAGES.iter().copied().enumerate() {
+        let frame = Frame { image, ..fixture() };
+
+        let mut fb = Framebuffer::new();
+        layout::draw_current_image(&mut fb, &frame);
+        assert_snapshot(&format!("image_{index}"), &fb);
+    }
+}
+
+#[test]
+fn time_redraw_clears_previous_time() {
+    // Redrawing only the clock over a stale one must leave
+    // exactly what a fresh top bar would show
+    let mut stale = Framebuffer::new();
+    layout::draw_badge(
+        &mut stale,
+        &Frame {
+            time: Some(at(23, 58)),
+            ..fixture()
+        },
+    );
+    layout::draw_time(&mut stale, &fixture());
+
+    let mut fresh = Framebuffer::new();
+    layout::draw_badge(&mut fresh, &fixture());
+
+    assert!(stale.as_bytes() == fresh.as_bytes());
+}
+
+#[test]
+fn time_clear_area_fits_the_text() {
+    // Without weather, so the date has room
+    let frame = Frame {
+        weather: None,
+        ..fixture()
+    };
+
+    let mut fb = Framebuffer::new();
+    let short = layout::draw_time(&mut fb, &frame).unwrap();
+
+    let long = layout::draw_time(
+        &mut fb,
+        &Frame {
+            clock: ClockFormat {
+                date: true,
+                ..ClockFormat::DEFAULT
+            },
+            ..frame
+        },
+    )
+    .unwrap();
+
+    // "| 02:05 PM" is 10 characters of 9 pixels, plus margins
+    assert_eq!(short.size.width, 104);
+    assert_eq!(long.size.width, short.size.width + 11 * 9);
+    assert_eq!(short.bottom_right(), long.bottom_right());
+}
@@ -0,0 +1,155 @@
+//! SNTP against stand-in servers on real localhost UDP sockets
+
+mod common;
+
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use badger_core::hal::Udp;
+use badger_core::sntp::{self, SntpError};
+use common::{at, ntp_reply};
+use embassy_futures::block_on;
+use time::PrimitiveDateTime;
+
+/// Client side, std sockets with a short timeout
+struct HostUdp;
+
+impl Udp for HostUdp {
+    type Error = std::io::ErrorKind;
+
+    async fn resolve(&self, _host: &str) -> Result<IpAddr, Self::Error> {
+        Err(std::io::ErrorKind::NotFound)
+    }
+
+    async fn exchange(
+        &self,
+        to: SocketAddr,
+        request: &[u8],
+        response: &mut [u8],
+    ) -> Result<usize, Self::Error> {
+        let socket = UdpSocket::bind("127.0.0.1:0").map_err(|e| e.kind())?;
+        socket
+            .set_read_timeout(Some(Duration::from_millis(300)))
+            .unwrap();
+        socket.send_to(request, to).map_err(|e| e.kind())?;
+        socket.recv(response).map_err(|e| e.kind())
+    }
+}
+
+/// Answers a single request after `hold`, with `mangle` applied to the reply
+fn serve(now: PrimitiveDateTime, hold: Duration, mangle: fn(&mut [u8])) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut request = [0u8; 64];
+        let (_, from) = socket.recv_from(&mut request).unwrap();
+        thread::sleep(hold);
+
+        let mut reply = ntp_reply(&request, now);
+        mangle(&mut reply);
+        socket.send_to(&reply, from).unwrap();
+    });
+
+    addr
+}
+
+fn micros(start: Instant) -> impl Fn() -> u64 {
+    move || start.elapsed().as_micros() as u64
+}
+
+#[test]
+fn queries_stand_in_server() {
+    let server = serve(at(12, 0), Duration::ZERO, |_| {});
+
+    let m = block_on(sntp::query(
+        &HostUdp,
+        &server.to_string(),
+        micros(Instant::now()),
+        None,
+    ))
+    .unwrap();
+
+    assert!(m.delay_micros < 100_000);
+    assert!(m.time >= at(12, 0));
+    assert!(m.time < at(12, 0) + time::Duration::milliseconds(100));
+    assert_eq!(m.offset_micros, None);
+}
+
+#[test]
+fn measures_offset_from_local_clock() {
+    let server = serve(at(12, 0), Duration::ZERO, |_| {});
+
+    // We think it's a minute earlier than the server does
+    let m = block_on(sntp::query(
+        &HostUdp,
+        &server.to_string(),
+        micros(Instant::now()),
+        Some(at(11, 59)),
+    ))
+    .unwrap();
+
+    let offset = m.offset_micros.unwrap();
+    assert!((59_900_000..=60_000_000).contains(&offset), "{offset}");
+}
+
+#[test]
+fn slow_server_still_counts_as_one_way_delay() {
+    // The stand-in holds the request without saying so, which looks like network delay
+    let server = serve(at(12, 0), Duration::from_millis(100), |_| {});
+
+    let m = block_on(sntp::query(
+        &HostUdp,
+        &server.to_string(),
+        micros(Instant::now()),
+        None,
+    ))
+    .unwrap();
+
+    assert!(m.delay_micros >= 100_000);
+    assert!(m.time >= at(12, 0) + time::Duration::milliseconds(50));
+}
+
+#[test]
+fn falls_through_to_next_server() {
+    let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let kiss = serve(at(9, 0), Duration::ZERO, |reply| reply[1] = 0);
+    let good = serve(at(12, 0), Duration::ZERO, |_| {});
+
+    let servers = format!(
+        "{}, ntp.invalid, {kiss}, {good}",
+        silent.local_addr().unwrap()
+    );
+
+    let m = block_on(sntp::query(
+        &HostUdp,
+        &servers,
+        micros(Instant::now()),
+        None,
+    ))
+    .unwrap();
+
+    assert!(m.time >= at(12, 0));
+}
+
+#[test]
+fn rejects_reply_to_someone_else() {
+    let server = serve(at(12, 0), Duration::ZERO, |reply| reply[31] ^= 1);
+
+    let result = block_on(sntp::query(
+        &HostUdp,
+        &server.to_string(),
+        micros(Instant::now()),
+        None,
+    ));
+
+    assert!(matches!(result, Err(SntpError::Unexpected)));
+}
+
+#[test]
+fn empty_server_list() {
+    let result = block_on(sntp::query(&HostUdp, " , ", || 0, None));
+
+    assert!(matches!(result, Err(SntpError::NoServers)));
+}
@@ -1,6 +1,11 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+    /* The first 24K hold the bootloader, see bootloader/memory.x, which must agree with these */
+    BOOTLOADER_STATE : ORIGIN = 0x10006000, LENGTH = 4K
+    FLASH : ORIGIN = 0x10007000, LENGTH = 1000K
+    /* Where updates are downloaded to, a sector bigger than FLASH for the swap */
+    DFU : ORIGIN = 0x10101000, LENGTH = 1004K
+    /* Then four 4K sectors for the diagnostics, the settings, the BSSID set and the saved state, see flash.rs */
 
     /* Pick one of the two options for RAM layout     */
 
@@ -15,3 +20,9 @@ MEMORY {
     /* SCRATCH_A: ORIGIN = 0x20040000, LENGTH = 4K    */
     /* SCRATCH_B: ORIGIN = 0x20041000, LENGTH = 4K    */
 }
+
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE) - ORIGIN(BOOT2);
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE) - ORIGIN(BOOT2);
+
+__bootloader_dfu_start = ORIGIN(DFU) - ORIGIN(BOOT2);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - ORIGIN(BOOT2);
@@ -0,0 +1,269 @@
+//! Renders badge screens on the host and writes them out as PBM and PNG
+//! snapshots, so layouts can be reviewed without flashing a Badger.
+//!
+//! cargo sim -- --out snapshots --time 2025-10-17T14:05 --temp 21.5 --code 3
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use badger_core::clock::{self, ClockFormat, HourFormat};
+use badger_core::diagnostics::{Diagnostics, Endpoint, Failure, Join, JoinStatus};
+use badger_core::framebuffer::Framebuffer;
+use badger_core::image::IMAGES;
+use badger_core::layout::{self, Frame, HEIGHT, Screen, WIDTH};
+use badger_core::weather::CurrentWeather;
+use badger_core::widgets::{MAX_WIDGETS, Region};
+use time::{Date, Month, PrimitiveDateTime, Time};
+
+const SCREENS: [&str; 5] = ["full", "top-bar", "image", "setup", "diagnostics"];
+
+struct Options {
+    out: PathBuf,
+    screens: Vec<&'static str>,
+    frame: Frame<'static>,
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{e}");
+            eprintln!(
+                "usage: badger-simulator [--out DIR] [--screen full|top-bar|image|setup|diagnostics|all] \
+                 [--time YYYY-MM-DDTHH:MM] [--clock 12h|24h] [--date] [--lang en|es|de] [--bssids N] \
+                 [--temp C] [--code WMO] [--captive] [--image N] [--status TEXT] \
+                 [--widget top-left|top-right|bottom-left|bottom-right=TEXT]"
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Err(e) = fs::create_dir_all(&options.out) {
+        eprintln!("Failed to create {}: {e}", options.out.display());
+        return ExitCode::FAILURE;
+    }
+
+    for screen in &options.screens {
+        let fb = render(screen, &options.frame);
+        let base = options.out.join(screen);
+
+        let result = write_pbm(&fb, &base.with_extension("pbm"))
+            .and_then(|_| write_png(&fb, &base.with_extension("png")));
+
+        match result {
+            Ok(_) => println!("Wrote {}.{{pbm,png}}", base.display()),
+            Err(e) => {
+                eprintln!("Failed to write {}: {e}", base.display());
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+fn render(screen: &str, frame: &Frame) -> Framebuffer {
+    let screen = match screen {
+        "top-bar" => Screen::TopBar,
+        "image" => Screen::Image,
+        "setup" => Screen::Setup,
+        "diagnostics" => Screen::Diagnostics,
+        _ => Screen::Full,
+    };
+
+    let mut fb = Framebuffer::new();
+    layout::draw_screen(&mut fb, screen, frame);
+    fb
+}
+
+fn write_pbm(fb: &Framebuffer, path: &Path) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "P4\n{WIDTH} {HEIGHT}\n")?;
+    file.write_all(fb.as_bytes())?;
+    file.flush()
+}
+
+fn write_png(fb: &Framebuffer, path: &Path) -> std::io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+
+    let mut encoder = png::Encoder::new(file, WIDTH, HEIGHT);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::One);
+
+    // PNG greyscale treats a set bit as white, the framebuffer as black
+    let data: Vec<u8> = fb.as_bytes().iter().map(|b| !b).collect();
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut out = PathBuf::from("snapshots");
+    let mut screens = SCREENS.to_vec();
+    let mut time = Some(PrimitiveDateTime::new(
+        Date::from_calendar_date(2025, Month::October, 17).unwrap(),
+        Time::from_hms(14, 5, 0).unwrap(),
+    ));
+    let mut weather = Some(CurrentWeather {
+        temperature: 21.5,
+        weathercode: 3,
+    });
+    let mut image = 0;
+    let mut clock = ClockFormat::DEFAULT;
+    let mut bssids = None;
+    let mut status = None;
+    let mut widgets = [None; MAX_WIDGETS];
+    let mut captive = false;
+
+    while let Some(flag) = args.next() {
+        let mut value = || args.next().ok_or(format!("Missing value for {flag}"));
+
+        match flag.as_str() {
+            "--out" => out = PathBuf::from(value()?),
+            "--screen" => {
+                let v = value()?;
+                screens = match SCREENS.iter().find(|s| **s == v) {
+                    Some(s) => vec![*s],
+                    None if v == "all" => SCREENS.to_vec(),
+                    None => return Err(format!("Unknown screen {v}")),
+                };
+            }
+            "--time" => {
+                let v = value()?;
+                time = match v.as_str() {
+                    "none" => None,
+                    v => Some(parse_time(v).ok_or(format!("Bad time {v}"))?),
+                };
+            }
+            "--clock" => {
+                let v = value()?;
+                clock.hours = HourFormat::parse(&v).ok_or(format!("Bad clock {v}"))?;
+            }
+            "--date" => clock.date = true,
+            "--lang" => {
+                let v = value()?;
+                clock.names = clock::names(&v).ok_or(format!("Unknown language {v}"))?;
+            }
+            // Lives as long as the frame, which is the rest of the run
+            "--status" => status = Some(&*value()?.leak()),
+            "--widget" => {
+                let v = value()?.leak();
+                let (region, text) = v.split_once('=').ok_or(format!("Bad widget {v}"))?;
+                let region = Region::parse(region).ok_or(format!("Unknown region {region}"))?;
+                widgets[region as usize] = Some(text);
+            }
+            "--bssids" => {
+                let v = value()?;
+                bssids = Some(v.parse().map_err(|_| format!("Bad count {v}"))?);
+            }
+            "--temp" => {
+                let v = value()?;
+                let temperature = v.parse().map_err(|_| format!("Bad temperature {v}"))?;
+                weather = weather
+                    .map(|w| CurrentWeather { temperature, ..w })
+                    .or(Some(CurrentWeather {
+                        temperature,
+                        weathercode: 0,
+                    }));
+            }
+            "--code" => {
+                let v = value()?;
+                let weathercode = v.parse().map_err(|_| format!("Bad weather code {v}"))?;
+                weather = weather
+                    .map(|w| CurrentWeather { weathercode, ..w })
+                    .or(Some(CurrentWeather {
+                        temperature: 0.0,
+                        weathercode,
+                    }));
+            }
+            "--no-weather" => weather = None,
+            "--captive" => captive = true,
+            "--image" => {
+                let v = value()?;
+                image = v
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|i| *i < IMAGES.len())
+                    .ok_or(format!("Image index must be below {}", IMAGES.len()))?;
+            }
+            _ => return Err(format!("Unknown argument {flag}")),
+        }
+    }
+
+    let now = time.map(|time| time.assume_utc().unix_timestamp());
+    let diagnostics = sample_diagnostics(now).lines(now).to_vec().leak();
+
+    Ok(Options {
+        out,
+        screens,
+        frame: Frame {
+            weather,
+            time,
+            clock,
+            bssids,
+            image: IMAGES[image],
+            status,
+            widgets,
+            diagnostics,
+            captive,
+        },
+    })
+}
+
+/// A sync that lost its DNS a few minutes ago
+fn sample_diagnostics(now: Option<i64>) -> Diagnostics {
+    let ago = |secs| now.map(|now| now - secs);
+    let failed = Endpoint {
+        result: Some(Err(Failure::Dns)),
+        at: ago(300),
+        ok_at: ago(7200),
+    };
+    let ok = Endpoint {
+        result: Some(Ok(())),
+        at: ago(300),
+        ok_at: ago(300),
+    };
+
+    let mut diagnostics = Diagnostics {
+        join: Some(Join {
+            ssid: "Conference".try_into().unwrap(),
+            status: JoinStatus::Joined,
+            rssi: Some(-67),
+            at: ago(300),
+        }),
+        address_ms: Some(2340),
+        ntp: ok,
+        weather: failed,
+        ..Diagnostics::new()
+    };
+    diagnostics.widgets[Region::TopRight as usize] = Endpoint {
+        result: Some(Err(Failure::Status(404))),
+        ..failed
+    };
+    diagnostics
+}
+
+/// Accepts `YYYY-MM-DDTHH:MM[:SS]`
+fn parse_time(s: &str) -> Option<PrimitiveDateTime> {
+    let (date, time) = s.split_once('T')?;
+
+    let mut date = date.splitn(3, '-');
+    let year = date.next()?.parse().ok()?;
+    let month = Month::try_from(date.next()?.parse::<u8>().ok()?).ok()?;
+    let day = date.next()?.parse().ok()?;
+
+    let mut time = time.splitn(3, ':');
+    let hour = time.next()?.parse().ok()?;
+    let minute = time.next()?.parse().ok()?;
+    let second = time.next().map(|s| s.parse().ok()).unwrap_or(Some(0))?;
+
+    Some(PrimitiveDateTime::new(
+        Date::from_calendar_date(year, month, day).ok()?,
+        Time::from_hms(hour, minute, second).ok()?,
+    ))
+}
@@ -1,10 +1,12 @@
+use core::sync::atomic::Ordering;
+
 use embassy_rp::gpio::Input;
 use embassy_time::Timer;
 
 use crate::{
     FlashDevice, UserLed, flash, image,
     led::blink,
-    state::{BUTTON_PRESSED, Button, DISPLAY_CHANGED, Screen, UPDATE_WEATHER},
+    state::{BUTTON_PRESSED, Button, DISPLAY_CHANGED, SHOWING_DIAGNOSTICS, Screen, UPDATE_WEATHER},
 };
 
 #[embassy_executor::task(pool_size = 5)]
@@ -33,7 +35,15 @@ pub async fn handle_presses(user_led: &'static UserLed, flash: &'static FlashDev
 
                 DISPLAY_CHANGED.signal(Screen::Full);
             }
-            Button::C => {}
+            Button::C => {
+                blink(user_led, 1).await;
+
+                // Goes back to the badge when pressed again
+                DISPLAY_CHANGED.signal(match SHOWING_DIAGNOSTICS.load(Ordering::Relaxed) {
+                    true => Screen::Full,
+                    false => Screen::Diagnostics,
+                });
+            }
             Button::Down => {
                 blink(user_led, 1).await;
 
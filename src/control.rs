@@ -0,0 +1,83 @@
+//! Serves `badger_core::control` on external power
+
+use badger_core::control::{self, Command, State};
+use core::sync::atomic::Ordering;
+use embassy_net::Stack;
+use embassy_net::tcp::TcpSocket;
+use embassy_time::Duration;
+use log::info;
+
+use crate::{
+    FlashDevice, flash, image,
+    state::{
+        DISPLAY_CHANGED, IMAGE_UNSAVED, POWER_MUTEX, PUBLISH_STATE, RTC_TIME, STATUS, Screen,
+        WEATHER,
+    },
+};
+
+#[embassy_executor::task]
+pub async fn run(stack: Stack<'static>, flash_device: &'static FlashDevice) -> ! {
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+    let mut buf = [0; 1024];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(Duration::from_secs(10)));
+
+        if socket.accept(control::PORT).await.is_err() {
+            continue;
+        }
+
+        // A client that sends nothing mustn't hold everything else up
+        let reply = control::read(&mut socket, &mut buf, image::get()).await;
+
+        let result = match reply {
+            Ok(reply) => {
+                let _guard = POWER_MUTEX.lock().await;
+
+                let status = STATUS.lock().await.clone();
+                let state = State {
+                    weather: *WEATHER.lock().await,
+                    time: *RTC_TIME.lock().await,
+                    image: image::get(),
+                    status: &status,
+                };
+
+                let result = control::respond(&mut socket, reply, &state).await;
+
+                socket.close();
+                socket.flush().await.ok();
+                result
+            }
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(Some(command)) => apply(command, flash_device).await,
+            Ok(None) => {}
+            Err(e) => info!("control request failed: {:?}", e),
+        }
+    }
+}
+
+/// Also how commands over MQTT are carried out
+pub async fn apply(command: Command, flash_device: &'static FlashDevice) {
+    match command {
+        Command::Redraw => DISPLAY_CHANGED.signal(Screen::Full),
+        // Saved with the state after the next sync, so a client looping on
+        // this can't wear the flash out
+        Command::Image(index) if index != image::get() => {
+            image::set(index);
+            IMAGE_UNSAVED.store(true, Ordering::Relaxed);
+            DISPLAY_CHANGED.signal(Screen::Image);
+        }
+        Command::Image(_) => {}
+        Command::Status(message) => {
+            *STATUS.lock().await = message;
+            DISPLAY_CHANGED.signal(Screen::Image);
+        }
+    }
+
+    PUBLISH_STATE.signal(());
+}
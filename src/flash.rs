@@ -1,67 +1,107 @@
-use core::sync::atomic::Ordering;
-
+use badger_core::diagnostics;
+use badger_core::settings::{self, Settings};
+use badger_core::storage::{self, FlashStore, Postcard};
 use embassy_rp::flash::{Async, Flash};
 use embassy_rp::peripherals::FLASH;
-use embedded_storage_async::nor_flash::NorFlash;
-use serde::{Deserialize, Serialize};
 
-use crate::FlashDevice;
-use crate::state::{CURRENT_IMAGE, CurrentWeather, WEATHER};
+use core::sync::atomic::Ordering;
+
+use crate::state::{
+    BSSIDS, CALIBRATION, DIAGNOSTICS, IMAGE_UNSAVED, LAST_NETWORK, SAVED_WAKES, SETTINGS,
+    TRIED_UPDATE, WEATHER,
+};
+use crate::{FlashDevice, image};
 
 // The type signature for Async Flash (size is 2MB = 2097152)
 pub type FlashDriver = Flash<'static, FLASH, Async, 2097152>;
+pub type FlashStorage = FlashStore<FlashDriver>;
 
-// Define Flash Constants
-const FLASH_OFFSET: u32 = 0x200000 - 0x1000; // Top of 2MB
-const FLASH_SIZE: u32 = 4096;
-
-#[derive(Serialize, Deserialize)]
-struct Postcard {
-    weather: Option<CurrentWeather>,
-    image: usize,
-}
+// Define Flash Constants, below them are the firmware partitions in memory.x
+pub const FLASH_OFFSET: u32 = 0x200000 - 0x1000; // Top of 2MB
+pub const BSSID_OFFSET: u32 = FLASH_OFFSET - 0x1000; // The sector below
+pub const SETTINGS_OFFSET: u32 = BSSID_OFFSET - 0x1000; // And the one below that
+pub const DIAGNOSTICS_OFFSET: u32 = SETTINGS_OFFSET - 0x1000; // The last one above DFU
 
 pub async fn save_state(flash: &'static FlashDevice) {
-    let image = CURRENT_IMAGE.load(Ordering::Relaxed);
+    IMAGE_UNSAVED.store(false, Ordering::Relaxed);
+    let image = image::get();
     let weather = *WEATHER.lock().await;
+    let calibration = *CALIBRATION.lock().await;
+    let last_network = *LAST_NETWORK.lock().await;
+    let wakes = SAVED_WAKES.load(Ordering::Relaxed);
+    let tried_update = *TRIED_UPDATE.lock().await;
 
-    let postcard = Postcard { weather, image };
-
-    // 1. Serialize to RAM
-    let mut buf = [0u8; 128];
-    let slice = match postcard::to_slice(&postcard, &mut buf) {
-        Ok(s) => s,
-        Err(_) => {
-            defmt::error!("Serialization failed - buffer too small?");
-            return;
-        }
+    let postcard = Postcard {
+        weather,
+        image,
+        calibration,
+        last_network,
+        wakes,
+        tried_update,
     };
 
-    let mut flash = flash.lock().await;
-
-    // 2. Erase & Write
-    let _ = flash.erase(FLASH_OFFSET, FLASH_OFFSET + FLASH_SIZE).await;
-    let _ = flash.write(FLASH_OFFSET, slice).await;
+    if !storage::save(&mut *flash.lock().await, &postcard).await {
+        defmt::error!("Failed to save state to flash");
+    }
 }
 
 pub async fn load_state(flash: &'static FlashDevice) {
-    let mut buf = [0u8; 128];
+    let postcard = storage::load(&mut *flash.lock().await).await;
 
-    // 1. Read (Async - uses DMA)
-    if flash
+    if let Some(postcard) = postcard {
+        let mut weather = WEATHER.lock().await;
+        *weather = postcard.weather;
+        image::set(postcard.image);
+        *CALIBRATION.lock().await = postcard.calibration;
+        *LAST_NETWORK.lock().await = postcard.last_network;
+        SAVED_WAKES.store(postcard.wakes, Ordering::Relaxed);
+        *TRIED_UPDATE.lock().await = postcard.tried_update;
+    }
+
+    let mut flash = flash.lock().await;
+    BSSIDS
         .lock()
         .await
-        .read(FLASH_OFFSET, &mut buf)
+        .load(&mut flash.region(BSSID_OFFSET))
+        .await;
+
+    let saved = settings::load(&mut flash.region(SETTINGS_OFFSET)).await;
+    SETTINGS.get_or_init(|| saved.unwrap_or_default());
+
+    if let Some(saved) = diagnostics::load(&mut flash.region(DIAGNOSTICS_OFFSET)).await {
+        *DIAGNOSTICS.lock().await = saved;
+    }
+}
+
+/// From the setup page, empty until something has been saved there
+pub fn settings() -> &'static Settings {
+    SETTINGS.get_or_init(Settings::new)
+}
+
+pub async fn save_settings(flash: &'static FlashDevice, settings: &Settings) -> bool {
+    settings::save(&mut flash.lock().await.region(SETTINGS_OFFSET), settings).await
+}
+
+pub async fn save_bssids(flash: &'static FlashDevice) {
+    let bssids = BSSIDS.lock().await;
+
+    if !bssids
+        .save(&mut flash.lock().await.region(BSSID_OFFSET))
         .await
-        .is_err()
     {
-        return;
+        defmt::error!("Failed to save bssids to flash");
     }
+}
 
-    // 2. Deserialize (Sync)
-    if let Ok(postcard) = postcard::from_bytes::<Postcard>(&buf) {
-        let mut weather = WEATHER.lock().await;
-        *weather = postcard.weather;
-        CURRENT_IMAGE.store(postcard.image, core::sync::atomic::Ordering::Relaxed);
+pub async fn save_diagnostics(flash: &'static FlashDevice) {
+    let diagnostics = DIAGNOSTICS.lock().await.clone();
+
+    if !diagnostics::save(
+        &mut flash.lock().await.region(DIAGNOSTICS_OFFSET),
+        &diagnostics,
+    )
+    .await
+    {
+        defmt::error!("Failed to save diagnostics to flash");
     }
 }
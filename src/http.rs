@@ -1,151 +1,294 @@
-use defmt::{Format, error};
+use badger_core::captive::{self, Connectivity};
+use badger_core::diagnostics::Failure;
+use badger_core::http::{self, BodySink, HttpError, Response};
+use badger_core::sync::{SyncConfig, Synced};
+use badger_core::tls::{self, READ_BUFFER_SIZE, Trust, WRITE_BUFFER_SIZE};
+use badger_core::wake;
+use badger_core::widgets::{self, MAX_WIDGETS, Widget};
+use core::sync::atomic::Ordering;
+use defmt::error;
 use embassy_net::Stack;
-use embassy_net::dns::DnsSocket;
 use embassy_net::tcp::client::{TcpClient, TcpClientState};
-use heapless::Vec;
+use embassy_rp::clocks::RoscRng;
+use embassy_sync::mutex::Mutex;
+use embassy_sync::once_lock::OnceLock;
+use embedded_io_async::Read;
+use heapless::{String, Vec};
 use log::info;
-use reqwless::client::HttpClient;
+use reqwless::client::{HttpClient, TlsConfig, TlsVerify};
 use reqwless::request::{Method, RequestBuilder};
-use serde::Deserialize;
-use time::{Date, Month, PrimitiveDateTime, Time};
 
-use crate::state::{CurrentWeather, POWER_MUTEX, WEATHER};
-use crate::time::set_time;
-use crate::{FlashDevice, RtcDevice, flash};
+use crate::flash::settings;
+use crate::net::Resolver;
+use crate::state::{IMAGE_UNSAVED, LAST_SYNC, SAVED_WAKES, WEATHER, WIDGETS};
+use crate::time::correct_time;
+use crate::{FlashDevice, MutexObj, RtcDevice, flash, ota};
 
-static TIME_API: &str = env!("TIME_API");
-static TEMP_API: &str = env!("TEMP_API");
+/// Built from the location on the setup page, when there is one
+static WEATHER_URL: OnceLock<String<160>> = OnceLock::new();
 
-#[derive(Format)]
-pub struct HttpError;
+/// Copied in by build.rs, empty without a `.widgets`
+static WIDGET_FILE: &str = include_str!(concat!(env!("OUT_DIR"), "/widgets"));
+static WIDGET_LIST: OnceLock<Vec<Widget<'static>, MAX_WIDGETS>> = OnceLock::new();
 
-pub async fn http_get<'a, 'b>(
-    stack: &Stack<'a>,
-    url: &str,
-    buf: &'b mut [u8],
-) -> Result<&'b [u8], HttpError> {
-    let dns_client = DnsSocket::new(*stack);
-
-    let client_state = TcpClientState::<1, 1024, 1024>::new();
-    let client = TcpClient::<'_, 1>::new(*stack, &client_state);
-
-    let mut http_client = HttpClient::new(&client, &dns_client);
+pub fn widgets() -> &'static [Widget<'static>] {
+    WIDGET_LIST.get_or_init(|| widgets::widgets(WIDGET_FILE))
+}
 
-    let req = http_client.request(Method::GET, url).await;
+/// `widgets` are those due a fetch this time
+pub fn sync_config<'a>(widgets: &'a [Widget<'a>]) -> SyncConfig<'a> {
+    let weather_api = match settings().location {
+        Some(location) => WEATHER_URL.get_or_init(|| location.weather_url()),
+        None => env!("TEMP_API"),
+    };
 
-    if let Err(e) = req {
-        error!("Failed to send HTTP request: {:?}", e);
-        return Err(HttpError);
+    SyncConfig {
+        ntp_servers: option_env!("NTP_SERVERS").unwrap_or("pool.ntp.org"),
+        time_api: env!("TIME_API"),
+        weather_api,
+        widgets,
+        probe: option_env!("PROBE_URL").unwrap_or(captive::DEFAULT_PROBE),
     }
+}
 
-    let mut req = req.unwrap().headers(&[
-        ("Accept", "*/*"),
-        ("User-Agent", "Rusty-Badger/1.0"),
-        ("Connection", "close"),
-    ]);
+/// Requests take turns on `POWER_MUTEX`, so one set is enough
+static TLS_BUFFERS: MutexObj<([u8; READ_BUFFER_SIZE], [u8; WRITE_BUFFER_SIZE])> =
+    Mutex::new(([0; READ_BUFFER_SIZE], [0; WRITE_BUFFER_SIZE]));
 
-    let response = req.send(buf).await;
+/// `TLS_PSK_IDENTITY`, `TLS_PSK` and `TLS_INSECURE` from .env, `None` if
+/// they're broken or leave https unverified without asking for it
+static TRUST: OnceLock<Option<Trust<'static>>> = OnceLock::new();
 
-    if let Err(e) = response {
-        error!("Failed to send HTTP request: {:?}", e);
-        return Err(HttpError);
+fn trust() -> Option<&'static Trust<'static>> {
+    TRUST
+        .get_or_init(|| {
+            tls::trust(
+                option_env!("TLS_PSK_IDENTITY"),
+                option_env!("TLS_PSK"),
+                option_env!("TLS_INSECURE"),
+            )
+            .inspect_err(|e| error!("Refusing https, bad TLS settings: {:?}", e))
+            .ok()
+        })
+        .as_ref()
+}
+
+fn tls_verify() -> Option<TlsVerify<'static>> {
+    // A broken pin mustn't quietly become trusting anyone
+    match trust()? {
+        Trust::Any => Some(TlsVerify::None),
+        Trust::Psk { identity, key } => Some(TlsVerify::Psk {
+            identity: identity.as_bytes(),
+            psk: key,
+        }),
     }
+}
 
-    let response = response.unwrap();
+/// Names each https host `TLS_INSECURE` leaves unverified, once at startup
+/// rather than with every request. Only the host, URLs can carry API keys.
+pub fn warn_untrusted() {
+    if trust() != Some(&Trust::Any) {
+        return;
+    }
 
-    let body_bytes = response.body().read_to_end().await;
+    let config = sync_config(widgets());
+    let urls = [config.time_api, config.weather_api]
+        .into_iter()
+        .chain(config.widgets.iter().map(|widget| widget.url))
+        .chain(ota::url());
 
-    match body_bytes {
-        Ok(bytes) => Ok(bytes),
-        Err(e) => {
-            error!("Failed to read HTTP response body: {:?}", e);
-            Err(HttpError)
+    for url in urls.filter(|url| tls::is_https(url)) {
+        if let Some(host) = http::host(url) {
+            error!("{} isn't verified, TLS_INSECURE accepts any server", host);
         }
     }
 }
 
-pub async fn fetch_api<'a, T>(stack: &Stack<'_>, rx_buf: &'a mut [u8], url: &str) -> Result<T, ()>
-where
-    T: Deserialize<'a>,
-{
-    match http_get(stack, url, rx_buf).await {
-        Ok(bytes) => match serde_json_core::de::from_slice::<T>(bytes) {
-            Ok((response, _)) => Ok(response),
-            Err(_e) => {
-                error!("Failed to parse response body");
-                Err(())
+fn transport_error(e: reqwless::Error) -> HttpError {
+    error!("HTTP request failed: {:?}", e);
+
+    match e {
+        reqwless::Error::Dns => HttpError::Dns,
+        reqwless::Error::BufferTooSmall => HttpError::TooLarge,
+        _ => HttpError::Connection,
+    }
+}
+
+/// The end of the buffer the body is read through, the rest holds headers
+const CHUNK_SIZE: usize = 256;
+
+/// What a 2xx body is read into
+#[allow(async_fn_in_trait)]
+pub trait ReadBody {
+    /// `chunk` is room to read into
+    async fn read_body(
+        &mut self,
+        body: &mut impl Read<Error = reqwless::Error>,
+        chunk: &mut [u8],
+    ) -> Result<(), HttpError>;
+}
+
+struct Sink<'a, S>(&'a mut S);
+
+impl<S: BodySink> ReadBody for Sink<'_, S> {
+    async fn read_body(
+        &mut self,
+        body: &mut impl Read<Error = reqwless::Error>,
+        chunk: &mut [u8],
+    ) -> Result<(), HttpError> {
+        loop {
+            match body.read(chunk).await.map_err(transport_error)? {
+                0 => return Ok(()),
+                n => self.0.write(&chunk[..n]),
             }
-        },
-        Err(e) => {
-            error!("Failed to make API request: {:?}", e);
-            Err(())
         }
     }
 }
 
-pub async fn fetch_time(stack: &Stack<'_>, rx_buf: &mut [u8], rtc_device: &'static RtcDevice) {
-    let _guard = POWER_MUTEX.lock().await;
+/// A single exchange, see `badger_core::http::get` for the rest
+pub async fn http_request(
+    stack: &Stack<'_>,
+    url: &str,
+    buf: &mut [u8],
+    sink: &mut impl BodySink,
+) -> Result<Response, HttpError> {
+    request(stack, url, buf, &mut Sink(sink)).await
+}
+
+/// The same, with the body read some other way
+pub async fn request(
+    stack: &Stack<'_>,
+    url: &str,
+    buf: &mut [u8],
+    reader: &mut impl ReadBody,
+) -> Result<Response, HttpError> {
+    let dns_client = Resolver(*stack);
 
-    if let Ok(response) = fetch_api::<TimeApiResponse>(stack, rx_buf, TIME_API).await {
-        set_time(rtc_device, response.into()).await;
+    let client_state = TcpClientState::<1, 1024, 1024>::new();
+    let client = TcpClient::<'_, 1>::new(*stack, &client_state);
+
+    let mut tls_buffers = TLS_BUFFERS.lock().await;
+    let (read_buffer, write_buffer) = &mut *tls_buffers;
+
+    let mut http_client = if tls::is_https(url) {
+        let verify = tls_verify().ok_or(HttpError::Config)?;
+
+        let config = TlsConfig::new(RoscRng.next_u64(), read_buffer, write_buffer, verify);
+        HttpClient::new_with_tls(&client, &dns_client, config)
+    } else {
+        HttpClient::new(&client, &dns_client)
+    };
+
+    let mut req = http_client
+        .request(Method::GET, url)
+        .await
+        .map_err(transport_error)?
+        .headers(&[
+            ("Accept", "*/*"),
+            ("User-Agent", "Rusty-Badger/1.0"),
+            ("Connection", "close"),
+        ]);
+
+    let (header_buf, chunk) = buf.split_at_mut(buf.len().saturating_sub(CHUNK_SIZE));
+
+    let response = req.send(header_buf).await.map_err(transport_error)?;
+    let status = response.status.0;
+
+    if !(200..300).contains(&status) {
+        let location = response
+            .headers()
+            .find(|(name, _)| name.eq_ignore_ascii_case("location"))
+            .and_then(|(_, value)| core::str::from_utf8(value).ok())
+            .and_then(|value| String::try_from(value.trim()).ok());
+
+        info!("HTTP {} from {}", status, url);
+        return Ok(Response { status, location });
     }
+
+    let mut body = response.body().reader();
+    reader.read_body(&mut body, chunk).await?;
+
+    Ok(Response {
+        status,
+        location: None,
+    })
 }
 
-pub async fn fetch_weather(
-    stack: &Stack<'_>,
-    rx_buf: &mut [u8],
+/// Applies whatever a sync brought back, and saves it for the next boot.
+/// Returns whether a widget changed, and so the image needs redrawing.
+pub async fn apply_sync(
+    synced: Synced<HttpError, Failure>,
+    rtc_device: &'static RtcDevice,
     flash_device: &'static FlashDevice,
-) {
-    let _guard = POWER_MUTEX.lock().await;
+) -> bool {
+    match synced.ntp {
+        Ok(ntp) => info!(
+            "SNTP delay: {}us, offset: {:?}us",
+            ntp.delay_micros, ntp.offset_micros
+        ),
+        Err(e) => error!("SNTP failed, falling back to time API: {:?}", e),
+    }
+
+    match synced.connectivity {
+        Some(Connectivity::Captive) => error!("Behind a captive portal, skipped the APIs"),
+        Some(Connectivity::Offline) => error!("The probe URL didn't answer"),
+        Some(Connectivity::Unknown) => info!("The probe URL answered with an error, carried on"),
+        _ => {}
+    }
 
-    if let Ok(response) = fetch_api::<OpenMeteoResponse>(stack, rx_buf, TEMP_API).await {
-        let weather = response.current;
+    if let Some(offset) = synced.utc_offset {
+        info!("Time API UTC offset: {}s", offset.whole_seconds());
+    }
 
-        info!(
-            "Temp: {}C, Code: {}",
-            weather.temperature, weather.weathercode
-        );
+    let mut changed = false;
+    let now = synced.time.as_ref().ok().copied();
 
-        {
-            let mut data = WEATHER.lock().await;
-            *data = Some(weather);
+    match synced.time {
+        Ok(now) => {
+            let offset = synced.ntp.as_ref().ok().and_then(|ntp| ntp.offset_micros);
+            correct_time(rtc_device, now, offset, synced.time_micros).await;
+            *LAST_SYNC.lock().await = Some(now);
+            changed = true;
         }
+        Err(e) => error!("Failed to fetch time: {:?}", e),
+    }
+
+    match synced.weather {
+        Ok(weather) => {
+            info!(
+                "Temp: {}C, Code: {}",
+                weather.temperature, weather.weathercode
+            );
+
+            {
+                let mut data = WEATHER.lock().await;
+                *data = Some(weather);
+            }
+
+            changed = true;
+        }
+        Err(e) => error!("Failed to fetch weather: {:?}", e),
+    }
+
+    // Weather, and the drift calibration's anchor, or an image picked since.
+    // The wakes counted since the last save go along with it.
+    if changed || IMAGE_UNSAVED.load(Ordering::Relaxed) {
+        let mut saved = SAVED_WAKES.load(Ordering::Relaxed);
+        wake::save_wakes(&mut *rtc_device.lock().await, &mut saved).await;
+        SAVED_WAKES.store(saved, Ordering::Relaxed);
 
         flash::save_state(flash_device).await;
     }
-}
 
-#[derive(Deserialize)]
-struct TimeApiResponse<'a> {
-    datetime: &'a str,
-}
+    let mut shown = WIDGETS.lock().await;
+    let mut redraw = false;
 
-impl<'a> From<TimeApiResponse<'a>> for PrimitiveDateTime {
-    fn from(response: TimeApiResponse) -> Self {
-        info!("Datetime: {:?}", response.datetime);
-        //split at T
-        let datetime = response.datetime.split('T').collect::<Vec<&str, 2>>();
-        //split at -
-        let date = datetime[0].split('-').collect::<Vec<&str, 3>>();
-        let year = date[0].parse::<i32>().unwrap();
-        let month = date[1].parse::<u8>().unwrap();
-        let day = date[2].parse::<u8>().unwrap();
-        //split at :
-        let time = datetime[1].split(':').collect::<Vec<&str, 4>>();
-        let hour = time[0].parse::<u8>().unwrap();
-        let minute = time[1].parse::<u8>().unwrap();
-        //split at .
-        let second_split = time[2].split('.').collect::<Vec<&str, 2>>();
-        let second = second_split[0].parse::<u8>().unwrap();
-
-        let date = Date::from_calendar_date(year, Month::try_from(month).unwrap(), day).unwrap();
-        let time = Time::from_hms(hour, minute, second).unwrap();
-
-        PrimitiveDateTime::new(date, time)
+    for (region, text) in synced.widgets {
+        let text = text
+            .inspect_err(|e| error!("Failed to fetch the {:?} widget: {:?}", region, e))
+            .ok();
+        redraw |= shown.update(region, text, now);
     }
-}
 
-#[derive(Deserialize)]
-pub struct OpenMeteoResponse {
-    pub current: CurrentWeather,
+    redraw
 }
@@ -2,22 +2,34 @@
 #![no_main]
 
 mod buttons;
+mod control;
 mod display;
 mod flash;
-mod helpers;
 mod http;
 mod image;
 mod led;
+mod mdns;
+mod mqtt;
+mod net;
+mod ota;
+mod provision;
+mod rtc;
 mod state;
 mod time;
 mod wifi;
 
 use crate::buttons::{handle_presses, listen_to_button};
-use crate::flash::FlashDriver;
-use crate::image::Shift;
+use crate::flash::{FlashDriver, FlashStorage};
 use crate::led::blink;
-use crate::state::{Button, DISPLAY_CHANGED, POWER_MUTEX, Screen};
-use crate::time::{check_trust_time, get_time, update_time};
+use crate::rtc::BadgerRtc;
+use crate::state::{
+    Button, CALIBRATION, DISPLAY_CHANGED, POWER_MUTEX, RTC_TIME, SAVED_WAKES, Screen, WAKES,
+};
+use crate::time::{TRUST_TIME, schedule, timezone, update_time};
+use badger_core::hal::Rtc;
+use badger_core::storage::FlashStore;
+use badger_core::wake::{self, Wake, WakeReason};
+use core::sync::atomic::Ordering;
 use cyw43_pio::{DEFAULT_CLOCK_DIVIDER, PioSpi};
 use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
 use embassy_executor::Spawner;
@@ -30,12 +42,12 @@ use embassy_rp::peripherals::{self, DMA_CH0, I2C0, PIO0, SPI0};
 use embassy_rp::pio::Pio;
 use embassy_rp::pwm::{Config, Pwm};
 use embassy_rp::spi::Spi;
+use embassy_rp::watchdog::Watchdog;
 use embassy_rp::{bind_interrupts, gpio, i2c, pio, spi};
 use embassy_sync::blocking_mutex::raw::{NoopRawMutex, ThreadModeRawMutex};
 use embassy_sync::mutex::Mutex;
 use embassy_time::Timer;
 use gpio::{Level, Output, Pull};
-use pcf85063a::{Control, PCF85063};
 use static_cell::StaticCell;
 
 use {defmt_rtt as _, panic_reset as _};
@@ -47,21 +59,24 @@ type Spi0Bus = Mutex<NoopRawMutex, Spi<'static, SPI0, spi::Async>>;
 type AsyncI2c0 = I2c<'static, I2C0, i2c::Async>;
 type I2c0Bus = MutexObj<AsyncI2c0>;
 type SharedI2c = I2cDevice<'static, ThreadModeRawMutex, AsyncI2c0>;
-type RtcDriver = PCF85063<SharedI2c>;
 
-pub type RtcDevice = MutexObj<RtcDriver>;
+pub type RtcDevice = MutexObj<BadgerRtc>;
 static RTC_DEVICE: StaticCell<RtcDevice> = StaticCell::new();
 
 pub type UserLed = MutexObj<Pwm<'static>>;
 static USER_LED: StaticCell<UserLed> = StaticCell::new();
 
-pub type FlashDevice = MutexObj<FlashDriver>;
+pub type FlashDevice = MutexObj<FlashStorage>;
 static FLASH_DEVICE: StaticCell<FlashDevice> = StaticCell::new();
 
 static I2C_BUS: StaticCell<I2c0Bus> = StaticCell::new();
 static SPI_BUS: StaticCell<Spi0Bus> = StaticCell::new();
 static STATE: StaticCell<cyw43::State> = StaticCell::new();
-static RESOURCES: StaticCell<StackResources<5>> = StaticCell::new();
+// One slot per socket open at once: the stack's own DHCP and DNS, the control
+// and MQTT connections, mDNS, SLAAC's raw socket, then during a sync the HTTP
+// connection or time exchange (they take turns on `POWER_MUTEX`) and the query
+// to an overridden DNS server. Setup mode needs fewer.
+static RESOURCES: StaticCell<StackResources<8>> = StaticCell::new();
 
 bind_interrupts!(struct Irqs {
     PIO0_IRQ_0 => pio::InterruptHandler<peripherals::PIO0>;
@@ -79,12 +94,6 @@ async fn main(spawner: Spawner) {
     let flash_device;
     let user_led;
 
-    let mut sync_wifi = false;
-    let mut is_rtc_alarm = false;
-    let mut external_power = false;
-    let mut screen_refresh_type = Screen::None;
-    let mut image_dir = Shift::None;
-
     // Button handlers
     let mut up = Input::new(p.PIN_15, Pull::Down);
     let mut down = Input::new(p.PIN_11, Pull::Down);
@@ -93,36 +102,33 @@ async fn main(spawner: Spawner) {
     let mut c = Input::new(p.PIN_14, Pull::Down);
     let rtc_alarm = Input::new(p.PIN_8, Pull::Down);
 
-    if up.is_high() {
-        // Up
-        image_dir = Shift::Prev;
-        screen_refresh_type = Screen::Image;
+    // Holding A and C starts the setup access point instead of a sync
+    let setup = a.is_high() && c.is_high();
+
+    let reason = if up.is_high() {
         up.wait_for_low().await;
+        WakeReason::Up
     } else if down.is_high() {
-        // Down
-        image_dir = Shift::Next;
-        screen_refresh_type = Screen::Image;
         down.wait_for_low().await;
+        WakeReason::Down
     } else if a.is_high() {
-        // A
-        sync_wifi = true;
-        screen_refresh_type = Screen::TopBar;
         a.wait_for_low().await;
+        WakeReason::A
     } else if b.is_high() {
-        // B
-        screen_refresh_type = Screen::Full;
         b.wait_for_low().await;
+        WakeReason::B
     } else if c.is_high() {
-        // C
-        screen_refresh_type = Screen::TopBar;
         c.wait_for_low().await;
+        WakeReason::C
     } else if rtc_alarm.is_high() {
-        // RTC wake
-        is_rtc_alarm = true;
+        WakeReason::RtcAlarm
     } else {
         // We must be on external power
-        external_power = true;
-    }
+        WakeReason::ExternalPower
+    };
+
+    let mut wake = Wake::new(reason);
+    let external_power = wake.external_power();
 
     // User LED
     {
@@ -133,10 +139,16 @@ async fn main(spawner: Spawner) {
 
     // Load most recent flash data
     {
-        let flashdev = FlashDriver::new(p.FLASH, p.DMA_CH3);
+        let flashdev = FlashStore::new(FlashDriver::new(p.FLASH, p.DMA_CH3), flash::FLASH_OFFSET);
         flash_device = FLASH_DEVICE.init(Mutex::new(flashdev));
 
         flash::load_state(flash_device).await;
+        http::warn_untrusted();
+
+        // The first boot of an update, which rolls back unless it confirms itself
+        if ota::trial(flash_device).await {
+            spawner.must_spawn(ota::watch(Watchdog::new(p.WATCHDOG)));
+        }
     }
 
     // I2C RTC
@@ -146,33 +158,29 @@ async fn main(spawner: Spawner) {
         let i2c_bus = Mutex::new(i2c);
         let i2c_bus = I2C_BUS.init(i2c_bus);
 
-        let i2c_dev = I2cDevice::new(i2c_bus);
-        let rtc = RtcDriver::new(i2c_dev);
+        let rtc = BadgerRtc::new(I2cDevice::new(i2c_bus), I2cDevice::new(i2c_bus));
         rtc_device = RTC_DEVICE.init(Mutex::new(rtc));
 
-        check_trust_time(rtc_device).await;
-        get_time(rtc_device).await;
-
         let mut rtc = rtc_device.lock().await;
-
-        if is_rtc_alarm {
-            let now = rtc.get_datetime().await;
-
-            match now {
-                Ok(now) if now.minute() == 0 => {
-                    sync_wifi = true;
-                    screen_refresh_type = Screen::Full;
-                }
-                _ => {
-                    screen_refresh_type = Screen::TopBar;
-                }
-            }
-        }
-
-        // Pull image index from RTC ram byte, shift if we need, save it
-        image::set(rtc.read_ram_byte().await.unwrap_or(0) as usize);
-        image::shift(image_dir);
-        rtc.write_ram_byte(image::get() as u8).await.ok();
+        let calibration = *CALIBRATION.lock().await;
+        let saved = SAVED_WAKES.load(Ordering::Relaxed);
+
+        let woken = wake::wake_up(
+            &mut *rtc,
+            reason,
+            &calibration,
+            saved,
+            &timezone(),
+            &schedule(),
+        )
+        .await;
+        drop(rtc);
+
+        wake = woken.wake;
+        TRUST_TIME.store(woken.time.is_some(), Ordering::Relaxed);
+        *RTC_TIME.lock().await = woken.time;
+        image::set(woken.image);
+        WAKES.store(woken.wakes, Ordering::Relaxed);
     }
 
     // Long running tasks if we're on mains power
@@ -211,7 +219,9 @@ async fn main(spawner: Spawner) {
         let spi_bus = SPI_BUS.init(Mutex::new(spi));
 
         // If we're on mains, put something on the display
-        if external_power {
+        if setup {
+            DISPLAY_CHANGED.signal(Screen::Setup);
+        } else if external_power {
             DISPLAY_CHANGED.signal(Screen::Full);
         }
 
@@ -224,7 +234,7 @@ async fn main(spawner: Spawner) {
     }
 
     // Connect to wifi and sync
-    if sync_wifi || external_power {
+    if wake.sync_wifi || external_power {
         let pwr = Output::new(p.PIN_23, Level::Low);
         let cs = Output::new(p.PIN_25, Level::High);
         let mut pio = Pio::new(p.PIO0, Irqs);
@@ -248,7 +258,11 @@ async fn main(spawner: Spawner) {
             .set_power_management(cyw43::PowerManagementMode::PowerSave)
             .await;
 
-        let config = embassy_net::Config::dhcpv4(Default::default());
+        let config = if setup {
+            provision::config()
+        } else {
+            net::config()
+        };
 
         let (stack, netrunner) = embassy_net::new(
             net_device,
@@ -259,7 +273,28 @@ async fn main(spawner: Spawner) {
 
         spawner.must_spawn(net_task(netrunner));
 
-        if external_power {
+        if !setup {
+            net::listen_ipv6(&mut control, stack).await;
+            if net::uses_slaac() {
+                spawner.must_spawn(net::slaac(stack));
+            }
+        }
+
+        if setup {
+            provision::run(control, stack, rtc_device, flash_device).await;
+        } else if external_power {
+            spawner.spawn(control::run(stack, flash_device)).ok();
+            if let Some(host) = mqtt::host() {
+                spawner.spawn(mqtt::run(stack, host, flash_device)).ok();
+            }
+            if let Some(host) = mdns::hostname() {
+                // The radio drops multicast it hasn't been told about
+                control
+                    .add_multicast_address(badger_core::mdns::GROUP_MAC)
+                    .await
+                    .ok();
+                spawner.spawn(mdns::run(stack, host)).ok();
+            }
             spawner
                 .spawn(wifi::run(
                     control,
@@ -275,7 +310,14 @@ async fn main(spawner: Spawner) {
     }
 
     if !external_power {
-        DISPLAY_CHANGED.signal(screen_refresh_type);
+        DISPLAY_CHANGED.signal(wake.screen);
+
+        // Long enough to read, then the badge goes back to normal before sleeping
+        if matches!(wake.screen, Screen::Diagnostics) {
+            Timer::after_secs(30).await;
+            DISPLAY_CHANGED.signal(Screen::Full);
+        }
+
         Timer::after_secs(3).await;
         nighty_night(&mut power_latch, rtc_device).await;
     }
@@ -298,18 +340,20 @@ async fn nighty_night(power_latch: &mut Output<'static>, rtc_device: &'static Rt
 
     let mut rtc = rtc_device.lock().await;
 
-    rtc.disable_all_alarms().await.ok();
-    rtc.clear_alarm_flag().await.ok();
-
-    if let Ok(now) = rtc.get_datetime().await
+    // Don't arm the alarm during the very second it matches
+    if let Ok(now) = rtc.datetime().await
         && now.second() == 0
     {
         Timer::after_millis(1000 - now.millisecond() as u64).await
     }
 
-    rtc.set_alarm_seconds(0).await.ok();
-    rtc.control_alarm_seconds(Control::On).await.ok();
-    rtc.control_alarm_interrupt(Control::On).await.ok();
+    wake::sleep(
+        &mut *rtc,
+        TRUST_TIME.load(Ordering::Relaxed),
+        &timezone(),
+        &schedule(),
+    )
+    .await;
 
     Timer::after_secs(1).await;
     power_latch.set_low();
@@ -0,0 +1,96 @@
+//! Answers mDNS on external power, so the badge is `MDNS_HOSTNAME.local`
+//! and its JSON API turns up as an `_http._tcp` service, see
+//! `badger_core::mdns`
+
+use badger_core::control;
+use badger_core::mdns::{self, Responder};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Address, Stack};
+use embassy_time::{Duration, Timer, with_timeout};
+use log::info;
+
+use crate::state::POWER_MUTEX;
+
+const TXT: &[&str] = &["path=/api/state"];
+
+/// `MDNS_HOSTNAME` from .env, `badger` if it isn't set and `None` if it's
+/// empty or not a valid name
+pub fn hostname() -> Option<&'static str> {
+    let host = option_env!("MDNS_HOSTNAME").unwrap_or("badger");
+    if host.is_empty() {
+        return None;
+    }
+
+    let valid = mdns::hostname(host);
+    if valid.is_none() {
+        defmt::error!("MDNS_HOSTNAME isn't a valid name");
+    }
+    valid
+}
+
+#[embassy_executor::task]
+pub async fn run(stack: Stack<'static>, host: &'static str) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 1024];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 1024];
+
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(mdns::PORT).expect("Failed to bind mDNS");
+    stack
+        .join_multicast_group(Ipv4Address::from(mdns::GROUP))
+        .ok();
+
+    let group = IpEndpoint::new(IpAddress::Ipv4(Ipv4Address::from(mdns::GROUP)), mdns::PORT);
+    let responder = Responder::new(host, control::PORT, TXT);
+    let mut announced = None;
+
+    let mut query = [0; 512];
+    let mut reply = [0; 512];
+
+    loop {
+        stack.wait_config_up().await;
+        let Some(address) = stack
+            .config_v4()
+            .map(|config| config.address.address().octets())
+        else {
+            Timer::after_secs(1).await;
+            continue;
+        };
+
+        // Twice, a second apart, as RFC 6762 asks
+        if announced != Some(address) {
+            info!("mDNS: {}.local", host);
+
+            for _ in 0..2 {
+                if let Some(len) = responder.announce(address, &mut reply) {
+                    let _guard = POWER_MUTEX.lock().await;
+                    socket.send_to(&reply[..len], group).await.ok();
+                }
+                Timer::after_secs(1).await;
+            }
+            announced = Some(address);
+        }
+
+        // Wakes now and then to notice a new address
+        let Ok(Ok((len, meta))) =
+            with_timeout(Duration::from_secs(60), socket.recv_from(&mut query)).await
+        else {
+            continue;
+        };
+
+        let legacy = meta.endpoint.port != mdns::PORT;
+        if let Some(answer) = responder.answer(&query[..len], address, legacy, &mut reply) {
+            let to = if answer.unicast { meta.endpoint } else { group };
+
+            let _guard = POWER_MUTEX.lock().await;
+            socket.send_to(&reply[..answer.len], to).await.ok();
+        }
+    }
+}
@@ -0,0 +1,247 @@
+//! Publishes the badge's state to an MQTT broker on external power, and
+//! carries out the commands sent to it, see `badger_core::mqtt`. Home
+//! Assistant finds it through discovery, see `badger_core::homeassistant`.
+
+use core::convert::Infallible;
+use core::sync::atomic::Ordering;
+
+use badger_core::homeassistant::{self, ENTITIES};
+use badger_core::mqtt::{
+    self, Client, Message, MqttError, Options, Packet, Telemetry, Topic, topic,
+};
+use embassy_futures::select::{Either3, select3};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{IpAddress, Stack};
+use embassy_time::{Duration, Instant, Timer};
+use log::info;
+
+use crate::{
+    FlashDevice, control, image,
+    net::Resolver,
+    state::{LAST_SYNC, POWER_MUTEX, PUBLISH_STATE, STATUS, WAKES, WEATHER},
+};
+
+/// `MQTT_HOST` from .env, the client only runs when it's set
+pub fn host() -> Option<&'static str> {
+    option_env!("MQTT_HOST").filter(|host| !host.is_empty())
+}
+
+fn port() -> u16 {
+    option_env!("MQTT_PORT")
+        .and_then(|port| port.parse().ok())
+        .unwrap_or(mqtt::PORT)
+}
+
+fn prefix() -> &'static str {
+    option_env!("MQTT_PREFIX")
+        .filter(|prefix| !prefix.is_empty())
+        .unwrap_or("badger")
+}
+
+fn client_id() -> &'static str {
+    option_env!("MQTT_CLIENT_ID").unwrap_or("badger")
+}
+
+/// On unless `MQTT_DISCOVERY` is `false`
+fn discovery() -> bool {
+    option_env!("MQTT_DISCOVERY") != Some("false")
+}
+
+fn non_empty(value: Option<&'static str>) -> Option<&'static str> {
+    value.filter(|value| !value.is_empty())
+}
+
+#[embassy_executor::task]
+pub async fn run(
+    stack: Stack<'static>,
+    host: &'static str,
+    flash_device: &'static FlashDevice,
+) -> ! {
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+    let mut buf = [0; mqtt::PACKET_SIZE];
+    let mut failures = 0;
+
+    loop {
+        stack.wait_config_up().await;
+
+        let error = session(
+            stack,
+            host,
+            flash_device,
+            &mut rx_buffer,
+            &mut tx_buffer,
+            &mut buf,
+            &mut failures,
+        )
+        .await;
+
+        let delay = mqtt::reconnect_delay(failures);
+        info!("MQTT {:?}, reconnecting in {}s", error, delay);
+        failures = failures.saturating_add(1);
+
+        Timer::after_secs(delay as u64).await;
+    }
+}
+
+/// One connection to the broker, for as long as it lasts
+async fn session(
+    stack: Stack<'static>,
+    host: &str,
+    flash_device: &'static FlashDevice,
+    rx_buffer: &mut [u8],
+    tx_buffer: &mut [u8],
+    buf: &mut [u8],
+    failures: &mut u32,
+) -> MqttError {
+    let Ok(addr) = Resolver(stack).resolve(host).await else {
+        return MqttError::Io;
+    };
+
+    let mut socket = TcpSocket::new(stack, rx_buffer, tx_buffer);
+    // Pings keep it busy, so this only fires once the broker has gone
+    socket.set_timeout(Some(Duration::from_secs(
+        mqtt::KEEP_ALIVE_SECS as u64 * 3 / 2,
+    )));
+
+    if socket
+        .connect((IpAddress::from(addr), port()))
+        .await
+        .is_err()
+    {
+        return MqttError::Io;
+    }
+
+    let online = topic(prefix(), "online");
+    let options = Options {
+        client_id: client_id(),
+        username: non_empty(option_env!("MQTT_USERNAME")),
+        password: non_empty(option_env!("MQTT_PASSWORD")),
+        keep_alive_secs: mqtt::KEEP_ALIVE_SECS,
+        will: Some(Message {
+            topic: &online,
+            payload: b"offline",
+            retain: true,
+        }),
+    };
+
+    let mut client = match Client::connect(socket, buf, &options).await {
+        Ok(client) => client,
+        Err(e) => return e,
+    };
+
+    info!("connected to MQTT broker {}", host);
+    *failures = 0;
+
+    let Err(error) = serve(&mut client, buf, &online, flash_device).await;
+    error
+}
+
+async fn serve(
+    client: &mut Client<TcpSocket<'_>>,
+    buf: &mut [u8],
+    online: &str,
+    flash_device: &'static FlashDevice,
+) -> Result<Infallible, MqttError> {
+    let state = topic(prefix(), "state");
+
+    client
+        .publish(
+            buf,
+            &Message {
+                topic: online,
+                payload: b"online",
+                retain: true,
+            },
+        )
+        .await?;
+    for leaf in ["message", "image/set", "redraw"] {
+        client.subscribe(buf, &topic(prefix(), leaf)).await?;
+    }
+    if discovery() {
+        client.subscribe(buf, homeassistant::STATUS_TOPIC).await?;
+        announce(client, buf).await?;
+    }
+    publish_state(client, buf, &state).await?;
+
+    let ping_every = Duration::from_secs(mqtt::KEEP_ALIVE_SECS as u64 / 2);
+    let mut next_ping = Instant::now() + ping_every;
+
+    loop {
+        match select3(client.wait(), Timer::at(next_ping), PUBLISH_STATE.wait()).await {
+            Either3::First(first) => {
+                let Packet::Publish { topic, payload } = client.receive(first?, buf).await? else {
+                    continue;
+                };
+
+                if topic == homeassistant::STATUS_TOPIC {
+                    // Home Assistant restarted, and forgot about us
+                    if payload == b"online" {
+                        announce(client, buf).await?;
+                        publish_state(client, buf, &state).await?;
+                    }
+                } else if let Some(command) = mqtt::command(prefix(), topic, payload, image::get())
+                {
+                    control::apply(command, flash_device).await;
+                }
+            }
+            Either3::Second(_) => {
+                client.ping().await?;
+                next_ping = Instant::now() + ping_every;
+            }
+            Either3::Third(_) => publish_state(client, buf, &state).await?,
+        }
+    }
+}
+
+async fn publish_state(
+    client: &mut Client<TcpSocket<'_>>,
+    buf: &mut [u8],
+    state: &Topic,
+) -> Result<(), MqttError> {
+    let status = STATUS.lock().await.clone();
+    let telemetry = Telemetry {
+        weather: *WEATHER.lock().await,
+        // VBAT is read through GPIO29, which the radio has while it's on
+        battery_mv: None,
+        external_power: true,
+        image: image::get(),
+        last_sync: *LAST_SYNC.lock().await,
+        wakes: WAKES.load(Ordering::Relaxed),
+        status: &status,
+    };
+    let json = telemetry.to_json();
+
+    let _guard = POWER_MUTEX.lock().await;
+    client
+        .publish(
+            buf,
+            &Message {
+                topic: state,
+                payload: json.as_bytes(),
+                retain: true,
+            },
+        )
+        .await
+}
+
+/// Home Assistant discovery, one retained config per entity
+async fn announce(client: &mut Client<TcpSocket<'_>>, buf: &mut [u8]) -> Result<(), MqttError> {
+    let _guard = POWER_MUTEX.lock().await;
+
+    for entity in &ENTITIES {
+        let config = entity.config(prefix(), client_id());
+        client
+            .publish(
+                buf,
+                &Message {
+                    topic: &entity.config_topic(client_id()),
+                    payload: config.as_bytes(),
+                    retain: true,
+                },
+            )
+            .await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,268 @@
+//! How the badge gets its addresses, from the network settings on the
+//! setup page, see `badger_core::ipconfig`. DNS servers saved there are
+//! asked directly, so they also take the place of the ones DHCP hands out.
+
+use core::net::{IpAddr, Ipv6Addr};
+
+use badger_core::dns::{self, QueryType};
+use badger_core::ipconfig::{Ipv4Mode, Ipv6Mode};
+use badger_core::slaac::{self, Advertisement};
+use cyw43::Control;
+use embassy_net::dns::DnsQueryType;
+use embassy_net::raw::{self, IpProtocol, IpVersion, RawSocket};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{
+    Config, ConfigV4, ConfigV6, IpEndpoint, Ipv4Cidr, Ipv6Cidr, Stack, StaticConfigV4,
+    StaticConfigV6,
+};
+use embassy_rp::clocks::RoscRng;
+use embassy_time::{Duration, Timer, with_timeout};
+use embedded_nal_async::{AddrType, Dns};
+use heapless::Vec;
+use log::info;
+
+use crate::flash::settings;
+use crate::state::POWER_MUTEX;
+
+const DNS_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long a router gets to answer a solicitation
+const ADVERTISEMENT_TIMEOUT: Duration = Duration::from_secs(3);
+const SOLICIT_RETRY_MIN: Duration = Duration::from_secs(4);
+const SOLICIT_RETRY_MAX: Duration = Duration::from_secs(5 * 60);
+/// Routers advertise for much longer than this, but the prefix can change
+const SOLICIT_REFRESH: Duration = Duration::from_secs(30 * 60);
+
+pub fn config() -> Config {
+    let network = &settings().network;
+
+    let ipv4 = match network.ipv4 {
+        Ipv4Mode::Dhcp => ConfigV4::Dhcp(Default::default()),
+        Ipv4Mode::Static {
+            address,
+            prefix_len,
+            gateway,
+        } => ConfigV4::Static(StaticConfigV4 {
+            address: Ipv4Cidr::new(address, prefix_len),
+            gateway,
+            dns_servers: network.dns_v4().collect(),
+        }),
+        Ipv4Mode::Off => ConfigV4::None,
+    };
+
+    let ipv6 = match network.ipv6 {
+        // Set once a router answers, see `slaac`
+        Ipv6Mode::Off | Ipv6Mode::Slaac => ConfigV6::None,
+        Ipv6Mode::Static {
+            address,
+            prefix_len,
+            gateway,
+        } => ConfigV6::Static(StaticConfigV6 {
+            address: Ipv6Cidr::new(address, prefix_len),
+            gateway,
+            dns_servers: network.dns_v6().collect(),
+        }),
+    };
+
+    Config { ipv4, ipv6 }
+}
+
+/// Neighbour discovery is all multicast, which the radio drops unless
+/// it's been told about it
+pub async fn listen_ipv6(control: &mut Control<'_>, stack: Stack<'_>) {
+    let address = match settings().network.ipv6 {
+        Ipv6Mode::Off => return,
+        // Only the last 24 bits count, and those come from the MAC
+        Ipv6Mode::Slaac => slaac::address(Ipv6Addr::UNSPECIFIED, mac(stack)),
+        Ipv6Mode::Static { address, .. } => address,
+    };
+
+    for group in [slaac::ALL_NODES, slaac::solicited_node(address)] {
+        control
+            .add_multicast_address(slaac::multicast_mac(group))
+            .await
+            .ok();
+    }
+}
+
+pub fn uses_slaac() -> bool {
+    settings().network.ipv6 == Ipv6Mode::Slaac
+}
+
+fn mac(stack: Stack<'_>) -> [u8; 6] {
+    stack.hardware_address().ethernet_or_panic().0
+}
+
+/// The addresses a router advertises, with the DNS servers from the setup
+/// page ahead of its own
+fn slaac_config(advertisement: Advertisement, mac: [u8; 6]) -> Option<StaticConfigV6> {
+    let address = slaac::address(advertisement.prefix?, mac);
+
+    let mut dns_servers: Vec<Ipv6Addr, 3> = settings().network.dns_v6().collect();
+    if dns_servers.is_empty() {
+        dns_servers.extend(advertisement.dns);
+    }
+
+    Some(StaticConfigV6 {
+        address: Ipv6Cidr::new(address, 64),
+        // A lifetime of zero means it isn't a default router
+        gateway: (advertisement.router_lifetime_secs > 0).then_some(advertisement.router),
+        dns_servers,
+    })
+}
+
+async fn advertisement(socket: &RawSocket<'_>, packet: &mut [u8]) -> Advertisement {
+    loop {
+        if let Ok(len) = socket.recv(packet).await
+            && let Some(advertisement) = slaac::advertisement(&packet[..len])
+        {
+            return advertisement;
+        }
+    }
+}
+
+/// Asks the routers for a prefix, and asks again now and then in case it
+/// changes
+#[embassy_executor::task]
+pub async fn slaac(stack: Stack<'static>) -> ! {
+    let mut rx_meta = [raw::PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; 512];
+    let mut tx_meta = [raw::PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; slaac::SOLICITATION_SIZE];
+
+    let socket = RawSocket::new(
+        stack,
+        IpVersion::Ipv6,
+        IpProtocol::Icmpv6,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+
+    let mac = mac(stack);
+    let mut packet = [0; 512];
+    let mut retry = SOLICIT_RETRY_MIN;
+
+    loop {
+        stack.wait_link_up().await;
+
+        {
+            let _guard = POWER_MUTEX.lock().await;
+            socket.send(&slaac::solicitation()).await;
+        }
+
+        let config = with_timeout(ADVERTISEMENT_TIMEOUT, advertisement(&socket, &mut packet))
+            .await
+            .ok()
+            .and_then(|advertisement| slaac_config(advertisement, mac));
+
+        let wait = match config {
+            Some(config) => {
+                if stack.config_v6().as_ref() != Some(&config) {
+                    info!("SLAAC address {}", config.address.address());
+                    stack.set_config_v6(ConfigV6::Static(config));
+                }
+                retry = SOLICIT_RETRY_MIN;
+                SOLICIT_REFRESH
+            }
+            None => {
+                info!("no router advertised an IPv6 prefix");
+                retry = (retry * 2).min(SOLICIT_RETRY_MAX);
+                retry
+            }
+        };
+
+        Timer::after(wait).await;
+    }
+}
+
+/// DNS through the servers on the setup page, or the network's own
+#[derive(Clone, Copy)]
+pub struct Resolver<'a>(pub Stack<'a>);
+
+impl Resolver<'_> {
+    /// Looks for AAAA records on a network without IPv4
+    pub async fn resolve(&self, host: &str) -> Result<IpAddr, ()> {
+        let qtype = match self.0.config_v4() {
+            Some(_) => QueryType::A,
+            None => QueryType::Aaaa,
+        };
+
+        self.lookup(host, qtype).await
+    }
+
+    async fn lookup(&self, host: &str, qtype: QueryType) -> Result<IpAddr, ()> {
+        if let Ok(address) = host.parse() {
+            return Ok(address);
+        }
+
+        let servers = &settings().network.dns;
+        if servers.is_empty() {
+            let qtype = match qtype {
+                QueryType::A => DnsQueryType::A,
+                QueryType::Aaaa => DnsQueryType::Aaaa,
+            };
+            let addrs = self.0.dns_query(host, qtype).await.map_err(|_| ())?;
+
+            return addrs.first().map(|addr| (*addr).into()).ok_or(());
+        }
+
+        for server in servers {
+            if let Ok(address) = self.query(*server, host, qtype).await {
+                return Ok(address);
+            }
+        }
+
+        Err(())
+    }
+
+    async fn query(&self, server: IpAddr, host: &str, qtype: QueryType) -> Result<IpAddr, ()> {
+        let mut rx_meta = [PacketMetadata::EMPTY; 1];
+        let mut rx_buffer = [0; 512];
+        let mut tx_meta = [PacketMetadata::EMPTY; 1];
+        let mut tx_buffer = [0; 300];
+
+        let mut socket = UdpSocket::new(
+            self.0,
+            &mut rx_meta,
+            &mut rx_buffer,
+            &mut tx_meta,
+            &mut tx_buffer,
+        );
+        socket.bind(0).map_err(|_| ())?;
+
+        let id = RoscRng.next_u64() as u16;
+        let mut packet = [0; 512];
+        let len = dns::query(id, host, qtype, &mut packet).ok_or(())?;
+
+        let server = IpEndpoint::new(server.into(), dns::PORT);
+        socket
+            .send_to(&packet[..len], server)
+            .await
+            .map_err(|_| ())?;
+
+        match with_timeout(DNS_TIMEOUT, socket.recv_from(&mut packet)).await {
+            Ok(Ok((len, meta))) if meta.endpoint == server => {
+                dns::address(&packet[..len], id, qtype).ok_or(())
+            }
+            _ => Err(()),
+        }
+    }
+}
+
+impl Dns for Resolver<'_> {
+    type Error = ();
+
+    async fn get_host_by_name(&self, host: &str, addr_type: AddrType) -> Result<IpAddr, ()> {
+        match addr_type {
+            AddrType::IPv4 => self.lookup(host, QueryType::A).await,
+            AddrType::IPv6 => self.lookup(host, QueryType::Aaaa).await,
+            AddrType::Either => self.resolve(host).await,
+        }
+    }
+
+    async fn get_host_by_address(&self, _addr: IpAddr, _result: &mut [u8]) -> Result<usize, ()> {
+        Err(())
+    }
+}
@@ -0,0 +1,235 @@
+//! Over-the-air updates on external power, see `badger_core::ota`. After
+//! each sync `OTA_URL` is checked, and a newer update goes into the DFU
+//! partition for the bootloader in `bootloader/` to swap in on reset. The
+//! new firmware has `TRIAL_BUDGET` to confirm itself with a sync, or the
+//! watchdog resets it and the bootloader swaps the old one back.
+
+use badger_core::hal::Dfu;
+use badger_core::http::HttpError;
+use badger_core::ota::{self, BLOCK_SIZE, Header, OtaError, Version};
+use defmt::error;
+use embassy_boot_rp::{
+    AlignedBuffer, FirmwareUpdater, FirmwareUpdaterConfig, FirmwareUpdaterError, State,
+};
+use embassy_embedded_hal::flash::partition::Partition;
+use embassy_futures::select::{Either, select};
+use embassy_net::Stack;
+use embassy_rp::flash::WRITE_SIZE;
+use embassy_rp::watchdog::Watchdog;
+use embassy_sync::blocking_mutex::raw::{NoopRawMutex, ThreadModeRawMutex};
+use embassy_sync::mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+use embedded_io_async::Read;
+use log::info;
+use portable_atomic::{AtomicBool, Ordering};
+
+use crate::FlashDevice;
+use crate::flash::{self, FlashDriver};
+use crate::http::{ReadBody, request};
+use crate::state::{POWER_MUTEX, TRIED_UPDATE};
+
+/// Long enough for a sync or two, should the first one fail
+const TRIAL_BUDGET: Duration = Duration::from_secs(5 * 60);
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// This boot is an update that hasn't confirmed itself yet
+static TRIAL: AtomicBool = AtomicBool::new(false);
+static CONFIRMED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+unsafe extern "C" {
+    static __bootloader_dfu_start: u32;
+    static __bootloader_dfu_end: u32;
+}
+
+/// `OTA_URL` from .env, updates are only checked for when it's set
+pub fn url() -> Option<&'static str> {
+    option_env!("OTA_URL").filter(|url| !url.is_empty())
+}
+
+fn running() -> Version {
+    Version::parse(env!("CARGO_PKG_VERSION")).unwrap_or_default()
+}
+
+type FlashPartition<'a, 'b> = Partition<'a, NoopRawMutex, &'b mut FlashDriver>;
+
+fn updater<'a, 'b>(
+    flash: &'a Mutex<NoopRawMutex, &'b mut FlashDriver>,
+    aligned: &'a mut [u8],
+) -> FirmwareUpdater<'a, FlashPartition<'a, 'b>, FlashPartition<'a, 'b>> {
+    FirmwareUpdater::new(
+        FirmwareUpdaterConfig::from_linkerfile(flash, flash),
+        aligned,
+    )
+}
+
+/// Borrows the flash a block at a time, so state can still be saved
+struct DfuPartition(&'static FlashDevice);
+
+impl Dfu for DfuPartition {
+    type Error = FirmwareUpdaterError;
+
+    /// The bootloader needs a spare sector to swap through
+    fn capacity(&self) -> u32 {
+        let start = &raw const __bootloader_dfu_start as u32;
+        let end = &raw const __bootloader_dfu_end as u32;
+        end - start - BLOCK_SIZE as u32
+    }
+
+    async fn write_block(&mut self, offset: u32, block: &[u8]) -> Result<(), Self::Error> {
+        let mut storage = self.0.lock().await;
+        let flash = Mutex::new(storage.flash());
+        let mut aligned = AlignedBuffer([0; WRITE_SIZE]);
+
+        updater(&flash, &mut aligned.0)
+            .write_firmware(offset as usize, block)
+            .await
+    }
+}
+
+struct Download {
+    dfu: DfuPartition,
+    block: [u8; BLOCK_SIZE],
+    tried: Option<Version>,
+    result: Result<Option<Header>, OtaError>,
+}
+
+impl ReadBody for Download {
+    async fn read_body(
+        &mut self,
+        body: &mut impl Read<Error = reqwless::Error>,
+        _chunk: &mut [u8],
+    ) -> Result<(), HttpError> {
+        let tried = self.tried;
+        self.result = ota::download(body, &mut self.dfu, &mut self.block, |header| {
+            ota::wanted(header.version, running(), tried)
+        })
+        .await;
+
+        Ok(())
+    }
+}
+
+/// Checks `url` for an update, and resets into it if there's a newer one
+pub async fn update(stack: Stack<'static>, url: &str, flash_device: &'static FlashDevice) {
+    let Some(public_key) = option_env!("OTA_PUBLIC_KEY").and_then(ota::public_key) else {
+        error!("OTA_URL needs OTA_PUBLIC_KEY");
+        return;
+    };
+
+    let mut buf = [0; 1024];
+    let mut download = Download {
+        dfu: DfuPartition(flash_device),
+        block: [0; BLOCK_SIZE],
+        tried: *TRIED_UPDATE.lock().await,
+        result: Ok(None),
+    };
+
+    let response = {
+        let _guard = POWER_MUTEX.lock().await;
+        with_timeout(
+            DOWNLOAD_TIMEOUT,
+            request(&stack, url, &mut buf, &mut download),
+        )
+        .await
+    };
+
+    match response {
+        Ok(Ok(response)) if (200..300).contains(&response.status) => {}
+        Ok(Ok(_)) | Ok(Err(_)) => return,
+        Err(_) => {
+            error!("Update download timed out");
+            return;
+        }
+    }
+
+    let header = match download.result {
+        Ok(Some(header)) => header,
+        Ok(None) => return,
+        Err(e) => {
+            error!("Update download failed: {:?}", e);
+            return;
+        }
+    };
+
+    // Whatever happens next, it isn't downloaded again
+    *TRIED_UPDATE.lock().await = Some(header.version);
+    flash::save_state(flash_device).await;
+
+    // Covers the trailer `download` matched against the header, and with it
+    // the version `wanted` went by
+    let verified = {
+        let mut storage = flash_device.lock().await;
+        let flash = Mutex::new(storage.flash());
+        let mut aligned = AlignedBuffer([0; WRITE_SIZE]);
+
+        updater(&flash, &mut aligned.0)
+            .verify_and_mark_updated(&public_key, &header.signature, header.len)
+            .await
+    };
+
+    match verified {
+        Ok(()) => {
+            info!("installing update {}", header.version);
+            cortex_m::peripheral::SCB::sys_reset();
+        }
+        Err(e) => error!("Update {} refused: {:?}", header.version, e),
+    }
+}
+
+/// Whether this is the first boot of an update, which has to confirm itself
+pub async fn trial(flash_device: &'static FlashDevice) -> bool {
+    let mut storage = flash_device.lock().await;
+    let flash = Mutex::new(storage.flash());
+    let mut aligned = AlignedBuffer([0; WRITE_SIZE]);
+
+    let trial = matches!(
+        updater(&flash, &mut aligned.0).get_state().await,
+        Ok(State::Swap)
+    );
+    TRIAL.store(trial, Ordering::Relaxed);
+    trial
+}
+
+/// After a good sync, so the bootloader keeps this firmware
+pub async fn confirm(flash_device: &'static FlashDevice) {
+    if !TRIAL.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let confirmed = {
+        let mut storage = flash_device.lock().await;
+        let flash = Mutex::new(storage.flash());
+        let mut aligned = AlignedBuffer([0; WRITE_SIZE]);
+
+        updater(&flash, &mut aligned.0).mark_booted().await
+    };
+
+    match confirmed {
+        Ok(()) => {
+            info!("update {} confirmed", running());
+            TRIAL.store(false, Ordering::Relaxed);
+            CONFIRMED.signal(());
+        }
+        Err(e) => error!("Failed to confirm update: {:?}", e),
+    }
+}
+
+/// Resets a trial boot that hangs, or doesn't confirm itself in time
+#[embassy_executor::task]
+pub async fn watch(mut watchdog: Watchdog) {
+    watchdog.start(Duration::from_secs(8));
+    let deadline = Instant::now() + TRIAL_BUDGET;
+
+    while Instant::now() < deadline {
+        watchdog.feed();
+
+        if let Either::Second(_) = select(Timer::after_secs(2), CONFIRMED.wait()).await {
+            watchdog.stop();
+            return;
+        }
+    }
+
+    // Left unfed, the watchdog resets into the bootloader
+    info!("update {} didn't confirm itself, rolling back", running());
+}
@@ -0,0 +1,160 @@
+//! Setup mode, for holding A and C at power up. The badge becomes an open
+//! access point, hands out addresses, points every name at itself and
+//! serves `portal` until the form is saved.
+
+use badger_core::dhcp::{self, Leases};
+use badger_core::dns;
+use badger_core::hal::Rtc;
+use badger_core::portal::{self, AP_ADDRESS, AP_SSID};
+use badger_core::settings::Settings;
+use badger_core::wake;
+use cyw43::Control;
+use embassy_futures::select::{Either3, select3};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{Config, IpAddress, IpEndpoint, Ipv4Address, Ipv4Cidr, Stack, StaticConfigV4};
+use embassy_time::{Duration, Timer};
+use heapless::Vec;
+use log::info;
+
+use crate::{FlashDevice, RtcDevice, flash};
+
+const CHANNEL: u8 = 6;
+const HTTP_PORT: u16 = 80;
+/// Rarely more than a phone or two during setup
+const MAX_CLIENTS: usize = 4;
+
+/// The badge is the router, so there's no gateway or DNS server to ask
+pub fn config() -> Config {
+    Config::ipv4_static(StaticConfigV4 {
+        address: Ipv4Cidr::new(Ipv4Address::from(AP_ADDRESS), 24),
+        gateway: None,
+        dns_servers: Vec::new(),
+    })
+}
+
+pub async fn run(
+    mut control: Control<'static>,
+    stack: Stack<'static>,
+    rtc_device: &'static RtcDevice,
+    flash_device: &'static FlashDevice,
+) -> ! {
+    control.start_ap_open(AP_SSID, CHANNEL).await;
+    info!("setup access point {} up", AP_SSID);
+
+    let settings = match select3(http_server(stack), dhcp_server(stack), dns_server(stack)).await {
+        Either3::First(settings) => settings,
+        Either3::Second(never) | Either3::Third(never) => never,
+    };
+
+    if !flash::save_settings(flash_device, &settings).await {
+        defmt::error!("Failed to save settings to flash");
+    }
+
+    // Let the saved page reach the phone before the radio goes
+    Timer::after_secs(1).await;
+
+    // On battery the power latch drops with the reset, so the alarm is
+    // what brings the badge back up
+    rtc_device
+        .lock()
+        .await
+        .set_alarm(wake::sleep_alarm())
+        .await
+        .ok();
+
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
+async fn http_server(stack: Stack<'static>) -> Settings {
+    let current = flash::settings();
+
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+    // Phones send a lot of headers
+    let mut buf = [0; 2048];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(Duration::from_secs(10)));
+
+        if socket.accept(HTTP_PORT).await.is_err() {
+            continue;
+        }
+
+        let result = portal::handle(&mut socket, &mut buf, current).await;
+
+        socket.close();
+        socket.flush().await.ok();
+
+        match result {
+            Ok(Some(settings)) => return settings,
+            Ok(None) => {}
+            Err(e) => info!("setup request failed: {:?}", e),
+        }
+    }
+}
+
+async fn dhcp_server(stack: Stack<'static>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; 1024];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; 1024];
+
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket
+        .bind(dhcp::SERVER_PORT)
+        .expect("Failed to bind DHCP server");
+
+    // Clients don't have an address yet, so replies are broadcast
+    let broadcast = IpEndpoint::new(IpAddress::Ipv4(Ipv4Address::BROADCAST), dhcp::CLIENT_PORT);
+
+    let mut leases = Leases::<MAX_CLIENTS>::new(AP_ADDRESS);
+    let mut request = [0; 576];
+    let mut reply = [0; dhcp::PACKET_SIZE];
+
+    loop {
+        let Ok((len, _)) = socket.recv_from(&mut request).await else {
+            continue;
+        };
+
+        if let Some(len) = leases.reply(&request[..len], &mut reply) {
+            socket.send_to(&reply[..len], broadcast).await.ok();
+        }
+    }
+}
+
+async fn dns_server(stack: Stack<'static>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 1024];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 1024];
+
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(dns::PORT).expect("Failed to bind DNS server");
+
+    let mut query = [0; 512];
+    let mut answer = [0; 512];
+
+    loop {
+        let Ok((len, meta)) = socket.recv_from(&mut query).await else {
+            continue;
+        };
+
+        if let Some(len) = dns::captive_answer(&query[..len], AP_ADDRESS, &mut answer) {
+            socket.send_to(&answer[..len], meta).await.ok();
+        }
+    }
+}
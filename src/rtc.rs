@@ -0,0 +1,84 @@
+use badger_core::hal::{Alarm, Rtc};
+use embedded_hal_async::i2c::I2c;
+use pcf85063a::{Control, PCF85063};
+use time::PrimitiveDateTime;
+
+use crate::SharedI2c;
+
+const ADDRESS: u8 = 0x51;
+const OFFSET_REGISTER: u8 = 0x02;
+
+/// The PCF85063 on the shared I2C bus, plus a second handle on the bus
+/// for registers the driver doesn't cover
+pub struct BadgerRtc(PCF85063<SharedI2c>, SharedI2c);
+
+impl BadgerRtc {
+    pub fn new(i2c: SharedI2c, raw: SharedI2c) -> Self {
+        Self(PCF85063::new(i2c), raw)
+    }
+}
+
+impl Rtc for BadgerRtc {
+    type Error = ();
+
+    async fn datetime(&mut self) -> Result<PrimitiveDateTime, ()> {
+        self.0.get_datetime().await.map_err(|_| ())
+    }
+
+    async fn set_datetime(&mut self, now: &PrimitiveDateTime) -> Result<(), ()> {
+        self.0.set_datetime(now).await.map_err(|_| ())
+    }
+
+    async fn oscillator_stopped(&mut self) -> Result<bool, ()> {
+        // OS flag in the seconds register
+        self.0
+            .is_register_bit_flag_high(0x04, 0x80)
+            .await
+            .map_err(|_| ())
+    }
+
+    async fn ram_byte(&mut self) -> Result<u8, ()> {
+        self.0.read_ram_byte().await.map_err(|_| ())
+    }
+
+    async fn set_ram_byte(&mut self, value: u8) -> Result<(), ()> {
+        self.0.write_ram_byte(value).await.map_err(|_| ())
+    }
+
+    async fn set_offset(&mut self, offset: u8) -> Result<(), ()> {
+        self.1
+            .write(ADDRESS, &[OFFSET_REGISTER, offset])
+            .await
+            .map_err(|_| ())
+    }
+
+    async fn set_alarm(&mut self, alarm: Alarm) -> Result<(), ()> {
+        let rtc = &mut self.0;
+
+        rtc.disable_all_alarms().await.map_err(|_| ())?;
+        rtc.clear_alarm_flag().await.map_err(|_| ())?;
+
+        if let Some(second) = alarm.second {
+            rtc.set_alarm_seconds(second).await.map_err(|_| ())?;
+            rtc.control_alarm_seconds(Control::On)
+                .await
+                .map_err(|_| ())?;
+        }
+
+        if let Some(minute) = alarm.minute {
+            rtc.set_alarm_minutes(minute).await.map_err(|_| ())?;
+            rtc.control_alarm_minutes(Control::On)
+                .await
+                .map_err(|_| ())?;
+        }
+
+        if let Some(hour) = alarm.hour {
+            rtc.set_alarm_hours(hour).await.map_err(|_| ())?;
+            rtc.control_alarm_hours(Control::On).await.map_err(|_| ())?;
+        }
+
+        rtc.control_alarm_interrupt(Control::On)
+            .await
+            .map_err(|_| ())
+    }
+}
@@ -1,23 +1,53 @@
-use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, mutex::Mutex, signal::Signal};
-use portable_atomic::AtomicUsize;
-use serde::{Deserialize, Serialize};
+use badger_core::control::STATUS_SIZE;
+use embassy_sync::{
+    blocking_mutex::raw::ThreadModeRawMutex, mutex::Mutex, once_lock::OnceLock, signal::Signal,
+};
+use heapless::String;
+use portable_atomic::{AtomicBool, AtomicU32, AtomicUsize};
 use time::PrimitiveDateTime;
 
 use crate::MutexObj;
 
+pub use badger_core::bssids::BssidSet;
+pub use badger_core::diagnostics::Diagnostics;
+pub use badger_core::drift::Calibration;
+pub use badger_core::layout::Screen;
+pub use badger_core::ota::Version;
+pub use badger_core::settings::Settings;
+pub use badger_core::weather::CurrentWeather;
+pub use badger_core::widgets::Shown;
+
 pub static POWER_MUTEX: MutexObj<()> = Mutex::new(());
+/// UTC, see `time::to_local` for display
 pub static RTC_TIME: MutexObj<Option<PrimitiveDateTime>> = Mutex::new(None);
+pub static CALIBRATION: MutexObj<Calibration> = Mutex::new(Calibration {
+    anchor: None,
+    ppm: 0.0,
+    samples: 0,
+});
+
+/// `networks::ssid_hash` of the last network joined, tried first next time
+pub static LAST_NETWORK: MutexObj<Option<u32>> = Mutex::new(None);
+
+/// Every access point seen, kept in its own flash sector
+pub static BSSIDS: MutexObj<BssidSet> = Mutex::new(BssidSet::new());
+
+/// What the last syncs ran into, kept in its own flash sector
+pub static DIAGNOSTICS: MutexObj<Diagnostics> = Mutex::new(Diagnostics::new());
+
+/// Saved from the setup page, read once at boot, see `flash::settings`
+pub static SETTINGS: OnceLock<Settings> = OnceLock::new();
 
-#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
-pub enum Screen {
-    None,
-    TopBar,
-    Image,
-    Full,
-    Shutdown,
-}
 pub static DISPLAY_CHANGED: Signal<ThreadModeRawMutex, Screen> = Signal::new();
 pub static CURRENT_IMAGE: AtomicUsize = AtomicUsize::new(0);
+/// Changed over the control API or MQTT since the state was last saved
+pub static IMAGE_UNSAVED: AtomicBool = AtomicBool::new(false);
+/// Until C is pressed again, updates redraw the diagnostics rather than the rest
+pub static SHOWING_DIAGNOSTICS: AtomicBool = AtomicBool::new(false);
+/// Wakes saved to flash, the rest wait in the RTC, see `wake::RamByte`
+pub static SAVED_WAKES: AtomicU32 = AtomicU32::new(0);
+/// Every wake, this one included
+pub static WAKES: AtomicU32 = AtomicU32::new(0);
 
 pub enum Button {
     A,
@@ -28,11 +58,17 @@ pub enum Button {
 }
 pub static BUTTON_PRESSED: Signal<ThreadModeRawMutex, &'static Button> = Signal::new();
 
-#[derive(Serialize, Deserialize, Copy, Clone)]
-pub struct CurrentWeather {
-    pub temperature: f32,
-    pub weathercode: u8,
-    // pub is_day: u8,
-}
 pub static WEATHER: MutexObj<Option<CurrentWeather>> = Mutex::new(None);
+/// Set over the control API, empty for none
+pub static STATUS: MutexObj<String<STATUS_SIZE>> = Mutex::new(String::new());
+/// What the `.widgets` show, kept in RAM so a battery wake fetches them all
+pub static WIDGETS: MutexObj<Shown> = Mutex::new(Shown::new());
 pub static UPDATE_WEATHER: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// The last update installed, kept so one that rolled back isn't tried again
+pub static TRIED_UPDATE: MutexObj<Option<Version>> = Mutex::new(None);
+
+/// UTC, the last sync that brought back the time
+pub static LAST_SYNC: MutexObj<Option<PrimitiveDateTime>> = Mutex::new(None);
+/// Something `mqtt` publishes has changed
+pub static PUBLISH_STATE: Signal<ThreadModeRawMutex, ()> = Signal::new();
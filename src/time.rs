@@ -1,51 +1,130 @@
+use badger_core::clock::{self, ClockFormat, HourFormat};
+use badger_core::hal::Rtc;
+use badger_core::schedule::{QuietHours, Schedule};
+use badger_core::tz::TimeZone;
+use badger_core::wake;
 use core::sync::atomic::Ordering;
-use embassy_time::Timer;
+use defmt::{error, info};
+use embassy_time::{Instant, Timer};
 use portable_atomic::AtomicBool;
-use time::PrimitiveDateTime;
+use time::{Duration, PrimitiveDateTime};
 
 use crate::{
     RtcDevice,
-    state::{DISPLAY_CHANGED, POWER_MUTEX, RTC_TIME, Screen},
+    flash::settings,
+    state::{CALIBRATION, DISPLAY_CHANGED, POWER_MUTEX, RTC_TIME, Screen},
 };
 
 pub static TRUST_TIME: AtomicBool = AtomicBool::new(false);
 
+/// The POSIX rule from the setup page, or `TIMEZONE` from .env. The RTC
+/// itself keeps UTC.
+pub fn timezone() -> TimeZone {
+    let saved = Some(settings().timezone.as_str()).filter(|tz| !tz.is_empty());
+
+    let Some(tz) = saved.or(option_env!("TIMEZONE")) else {
+        return TimeZone::utc();
+    };
+
+    TimeZone::parse(tz).unwrap_or_else(|e| {
+        error!("Bad TIMEZONE {}: {:?}, using UTC", tz, e);
+        TimeZone::utc()
+    })
+}
+
+pub fn to_local(utc: PrimitiveDateTime) -> PrimitiveDateTime {
+    timezone().to_local(utc)
+}
+
+/// `CLOCK_HOURS`, `CLOCK_DATE` and `CLOCK_LANGUAGE` from .env
+pub fn clock_format() -> ClockFormat {
+    let default = ClockFormat::DEFAULT;
+
+    ClockFormat {
+        hours: option_env!("CLOCK_HOURS")
+            .and_then(HourFormat::parse)
+            .unwrap_or(default.hours),
+        date: option_env!("CLOCK_DATE").is_some_and(|v| v == "true"),
+        names: option_env!("CLOCK_LANGUAGE")
+            .and_then(clock::names)
+            .unwrap_or(default.names),
+    }
+}
+
+/// `REFRESH_MINUTES`, `SYNC_MINUTES` and `QUIET_HOURS` from .env
+pub fn schedule() -> Schedule {
+    let default = Schedule::DEFAULT;
+
+    Schedule {
+        refresh_minutes: option_env!("REFRESH_MINUTES")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default.refresh_minutes),
+        sync_minutes: option_env!("SYNC_MINUTES")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default.sync_minutes),
+        quiet: option_env!("QUIET_HOURS").and_then(QuietHours::parse),
+    }
+}
+
+/// What the RTC says right now, if we believe it
+pub async fn trusted_time(rtc_device: &'static RtcDevice) -> Option<PrimitiveDateTime> {
+    if !TRUST_TIME.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    let _guard = POWER_MUTEX.lock().await;
+    rtc_device.lock().await.datetime().await.ok()
+}
+
 pub async fn get_time(rtc_device: &'static RtcDevice) {
     if !TRUST_TIME.load(Ordering::Relaxed) {
         return;
     }
 
-    let _guard = POWER_MUTEX.lock().await;
-    let result = rtc_device.lock().await.get_datetime().await.ok();
+    let result = trusted_time(rtc_device).await;
     let mut data = RTC_TIME.lock().await;
     *data = result;
 }
 
-pub async fn set_time(rtc_device: &'static RtcDevice, now: PrimitiveDateTime) {
-    rtc_device
-        .lock()
-        .await
-        .set_datetime(&now)
-        .await
-        .expect("Failed to update RTC time");
+/// Sets the RTC from a sync if it has drifted, learning how fast it runs
+/// along the way, see `badger_core::wake::correct_time`. `taken_micros` is
+/// when `now` was right.
+pub async fn correct_time(
+    rtc_device: &'static RtcDevice,
+    now: PrimitiveDateTime,
+    offset_micros: Option<i64>,
+    taken_micros: u64,
+) {
+    let mut calibration = CALIBRATION.lock().await;
 
-    TRUST_TIME.store(true, Ordering::Relaxed);
+    let set = wake::correct_time(
+        &mut *rtc_device.lock().await,
+        &mut calibration,
+        now,
+        offset_micros,
+        |now| async move {
+            // Catch up, then land on a whole second since that's all the RTC keeps
+            let now =
+                now + Duration::microseconds((Instant::now().as_micros() - taken_micros) as i64);
+            let wait = (1_000_000 - now.microsecond()) % 1_000_000;
 
-    let mut data = RTC_TIME.lock().await;
-    *data = Some(now);
-}
+            Timer::after_micros(wait as u64).await;
+            now + Duration::microseconds(wait as i64)
+        },
+    )
+    .await;
+
+    info!(
+        "RTC drift: {}ppm from {} samples, offset register {:#x}",
+        calibration.ppm,
+        calibration.samples,
+        calibration.offset_register()
+    );
 
-pub async fn check_trust_time(rtc_device: &'static RtcDevice) {
-    // Check if the oscillator stopped, if not, we can
-    // use the existing time right away
-    let osc_did_stop = rtc_device
-        .lock()
-        .await
-        .is_register_bit_flag_high(0x04, 0x80)
-        .await
-        .unwrap_or(false);
-
-    TRUST_TIME.store(!osc_did_stop, Ordering::Relaxed);
+    if let Some(now) = set {
+        TRUST_TIME.store(true, Ordering::Relaxed);
+        *RTC_TIME.lock().await = Some(now);
+    }
 }
 
 #[embassy_executor::task]
@@ -1,71 +1,369 @@
-use cyw43::{Control, JoinOptions};
-use embassy_futures::{join::join, select::select};
-use embassy_net::Stack;
-use embassy_time::{Duration, Timer, with_timeout};
+use core::net::{IpAddr, SocketAddr};
+
+use badger_core::diagnostics::{Failure, Join, JoinStatus};
+use badger_core::hal::{Fetcher, HttpTransport, Network, Probe, Udp};
+use badger_core::http::{self, BodySink, HttpError, Response, RetryPolicy};
+use badger_core::networks::{Candidates, MAX_PROFILES, Profile, profiles, ssid_hash};
+use cyw43::{Control, JoinOptions, ScanOptions};
+use embassy_futures::select::{Either, select};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpEndpoint, Stack};
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+use heapless::{String, Vec};
 use log::info;
 
 use crate::{
     FlashDevice, RtcDevice, UserLed,
-    http::{fetch_time, fetch_weather},
+    flash::{self, settings},
+    http::{apply_sync, http_request, sync_config, widgets},
     led,
-    state::{DISPLAY_CHANGED, POWER_MUTEX, Screen, UPDATE_WEATHER},
+    net::Resolver,
+    ota,
+    state::{
+        BSSIDS, DIAGNOSTICS, DISPLAY_CHANGED, LAST_NETWORK, POWER_MUTEX, PUBLISH_STATE, Screen,
+        UPDATE_WEATHER, WIDGETS,
+    },
+    time::trusted_time,
 };
 
 pub static FW: &[u8] = include_bytes!("../cyw43-firmware/43439A0.bin");
 pub static CLM: &[u8] = include_bytes!("../cyw43-firmware/43439A0_clm.bin");
 
-static WIFI_SSID: &str = env!("WIFI_SSID");
+/// Either the password for `WIFI_SSID`, or `ssid<TAB>password` lines
 static WIFI_PASSWORD: &[u8] = include_bytes!("../.wifi");
 
-async fn connect(control: &mut Control<'_>, stack: &Stack<'_>) -> Result<(), ()> {
+/// Scanning and every join attempt have to fit in here
+const JOIN_BUDGET: Duration = Duration::from_secs(20);
+const SCAN_TIMEOUT: Duration = Duration::from_secs(5);
+/// Enough for a join to a network in range to finish
+const MIN_JOIN: Duration = Duration::from_secs(8);
+/// From joining to DHCP or the static config being up
+const ADDRESS_TIMEOUT: Duration = Duration::from_secs(8);
+
+/// Joining, addressing and every request have to fit in here
+const SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+/// Each try of a request, DNS and TLS included
+const REQUEST_TIMEOUT: Duration = Duration::from_millis(RetryPolicy::DEFAULT.attempt_ms as u64);
+/// The captive portal probe gets one short try, it's only a hint
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+const SYNC_INTERVAL: Duration = Duration::from_secs(3600);
+/// On external power, access points are counted between syncs too
+const SCAN_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Every scan also counts the access points it finds
+async fn scan(control: &mut Control<'_>, candidates: &mut Candidates<'_>) {
+    let mut scanner = control.scan(ScanOptions::default()).await;
+
+    while let Some(bss) = scanner.next().await {
+        candidates.seen(&bss.ssid[..bss.ssid_len as usize], bss.rssi);
+        BSSIDS.lock().await.insert(&bss.bssid);
+    }
+}
+
+async fn count_bssids(control: &mut Control<'_>) {
     let _guard = POWER_MUTEX.lock().await;
+    let mut candidates = Candidates::new(Vec::new());
+
+    if with_timeout(SCAN_TIMEOUT, scan(control, &mut candidates))
+        .await
+        .is_err()
+    {
+        info!("scan timed out");
+    }
+}
 
-    let mut connected_to_wifi = false;
+/// Flash wears out, so scans only save once they've found something new,
+/// and at most once a sync
+async fn save_bssids(saved: &mut u32, flash_device: &'static FlashDevice) {
+    let count = BSSIDS.lock().await.count();
 
-    match with_timeout(
-        Duration::from_secs(20),
-        control.join(WIFI_SSID, JoinOptions::new(WIFI_PASSWORD)),
-    )
-    .await
+    if count != *saved {
+        info!("{} unique bssids", count);
+        flash::save_bssids(flash_device).await;
+        *saved = count;
+    }
+}
+
+/// The network saved on the setup page goes first, in place of any line
+/// in `.wifi` for the same SSID
+fn wifi_profiles() -> Vec<Profile<'static>, MAX_PROFILES> {
+    let mut profiles = profiles(option_env!("WIFI_SSID"), WIFI_PASSWORD);
+    let saved = settings();
+
+    if !saved.ssid.is_empty() {
+        profiles.retain(|p| p.ssid != saved.ssid);
+        profiles.truncate(MAX_PROFILES - 1);
+        profiles
+            .insert(
+                0,
+                Profile {
+                    ssid: &saved.ssid,
+                    password: saved.password.as_bytes(),
+                },
+            )
+            .ok();
+    }
+
+    profiles
+}
+
+/// What the join ran into goes to the diagnostics screen, `at` is when
+/// the sync started
+async fn connect(control: &mut Control<'_>, stack: &Stack<'_>, at: Option<i64>) -> Result<(), ()> {
+    let _guard = POWER_MUTEX.lock().await;
+    let deadline = Instant::now() + JOIN_BUDGET;
+
+    let mut candidates = Candidates::new(wifi_profiles());
+
+    if with_timeout(SCAN_TIMEOUT, scan(control, &mut candidates))
+        .await
+        .is_err()
     {
-        Ok(Ok(_)) => {
-            connected_to_wifi = true;
-            info!("join successful");
+        info!("scan timed out");
+    }
+
+    let order = candidates.order(*LAST_NETWORK.lock().await);
+    let mut join = Join {
+        ssid: String::new(),
+        status: JoinStatus::NotTried,
+        rssi: None,
+        at,
+    };
+
+    for (i, profile) in order.iter().enumerate() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::from_ticks(0) {
+            info!("out of time to join");
+            break;
+        }
+
+        // Share what's left so one network that never answers can't use it all
+        let timeout = (remaining / (order.len() - i) as u32)
+            .max(MIN_JOIN)
+            .min(remaining);
+
+        join.ssid = String::try_from(profile.ssid).unwrap_or_default();
+        join.rssi = candidates.rssi(profile.ssid);
+
+        let options = match profile.password.is_empty() {
+            true => JoinOptions::new_open(),
+            false => JoinOptions::new(profile.password),
+        };
+
+        match with_timeout(timeout, control.join(profile.ssid, options)).await {
+            Ok(Ok(_)) => {
+                join.status = JoinStatus::Joined;
+                info!("joined {}", profile.ssid);
+                *LAST_NETWORK.lock().await = Some(ssid_hash(profile.ssid));
+                break;
+            }
+            Ok(Err(err)) => {
+                join.status = JoinStatus::Failed(err.status);
+                info!("join {} failed with status={}", profile.ssid, err.status);
+            }
+            Err(_) => {
+                join.status = JoinStatus::TimedOut;
+                info!("join {} timed out", profile.ssid);
+            }
         }
-        Ok(Err(err)) => {
-            info!("join failed with status={}", err.status);
+    }
+
+    let mut address_ms = None;
+    if join.status == JoinStatus::Joined {
+        let joined = Instant::now();
+
+        match with_timeout(ADDRESS_TIMEOUT, stack.wait_config_up()).await {
+            Ok(_) => address_ms = Some(joined.elapsed().as_millis() as u32),
+            Err(_) => {
+                info!("no address after joining");
+                join.status = JoinStatus::NoAddress;
+            }
+        }
+    }
+
+    let connected = join.status == JoinStatus::Joined;
+    {
+        let mut diagnostics = DIAGNOSTICS.lock().await;
+        diagnostics.join = Some(join);
+        diagnostics.address_ms = address_ms;
+        // Until this network has been probed
+        diagnostics.connectivity = None;
+    }
+
+    match connected {
+        true => Ok(()),
+        false => Err(()),
+    }
+}
+
+struct Wifi<'a> {
+    control: &'a mut Control<'static>,
+    stack: Stack<'static>,
+    /// On external power the link stays up between syncs, for the control API
+    keep_link: bool,
+    /// Unix seconds, for the diagnostics
+    started: Option<i64>,
+    /// When `SYNC_TIMEOUT` cuts the sync off, no retry starts that can't finish by then
+    deadline: Instant,
+}
+
+impl Network for Wifi<'_> {
+    type Error = ();
+
+    async fn connect(&mut self) -> Result<(), ()> {
+        if self.keep_link && self.stack.is_config_up() {
+            return Ok(());
         }
-        Err(_) => {
-            info!("join timed out");
+
+        connect(self.control, &self.stack, self.started).await
+    }
+
+    async fn disconnect(&mut self) {
+        if !self.keep_link {
+            self.control.leave().await;
         }
     }
+}
+
+impl HttpTransport for Wifi<'_> {
+    async fn request(
+        &self,
+        url: &str,
+        buf: &mut [u8],
+        sink: &mut impl BodySink,
+    ) -> Result<Response, HttpError> {
+        let _guard = POWER_MUTEX.lock().await;
+
+        with_timeout(REQUEST_TIMEOUT, http_request(&self.stack, url, buf, sink))
+            .await
+            .unwrap_or(Err(HttpError::Timeout))
+    }
+}
 
-    if !connected_to_wifi {
-        return Err(());
+impl Probe for Wifi<'_> {
+    async fn probe(
+        &self,
+        url: &str,
+        buf: &mut [u8],
+        sink: &mut impl BodySink,
+    ) -> Result<Response, HttpError> {
+        let _guard = POWER_MUTEX.lock().await;
+
+        with_timeout(PROBE_TIMEOUT, http_request(&self.stack, url, buf, sink))
+            .await
+            .unwrap_or(Err(HttpError::Timeout))
     }
+}
 
-    stack.wait_config_up().await;
+impl Fetcher for Wifi<'_> {
+    type Error = HttpError;
 
-    Ok(())
+    /// Backoff waits without `POWER_MUTEX`, so the other request can go
+    async fn get(
+        &self,
+        url: &str,
+        buf: &mut [u8],
+        sink: &mut impl BodySink,
+    ) -> Result<(), HttpError> {
+        http::get(
+            self,
+            url,
+            buf,
+            sink,
+            &RetryPolicy {
+                deadline_ms: self.deadline.as_millis(),
+                ..RetryPolicy::DEFAULT
+            },
+            || Instant::now().as_millis(),
+            |ms| Timer::after_millis(ms as u64),
+        )
+        .await
+    }
 }
 
+impl Udp for Wifi<'_> {
+    type Error = Failure;
+
+    async fn resolve(&self, host: &str) -> Result<IpAddr, Failure> {
+        Resolver(self.stack)
+            .resolve(host)
+            .await
+            .map_err(|_| Failure::Dns)
+    }
+
+    async fn exchange(
+        &self,
+        to: SocketAddr,
+        request: &[u8],
+        response: &mut [u8],
+    ) -> Result<usize, Failure> {
+        let _guard = POWER_MUTEX.lock().await;
+
+        let mut rx_meta = [PacketMetadata::EMPTY; 1];
+        let mut rx_buffer = [0; 128];
+        let mut tx_meta = [PacketMetadata::EMPTY; 1];
+        let mut tx_buffer = [0; 128];
+
+        let mut socket = UdpSocket::new(
+            self.stack,
+            &mut rx_meta,
+            &mut rx_buffer,
+            &mut tx_meta,
+            &mut tx_buffer,
+        );
+        socket.bind(0).map_err(|_| Failure::Connection)?;
+
+        let endpoint = IpEndpoint::new(to.ip().into(), to.port());
+        socket
+            .send_to(request, endpoint)
+            .await
+            .map_err(|_| Failure::Connection)?;
+
+        match with_timeout(Duration::from_secs(3), socket.recv_from(response)).await {
+            Ok(Ok((len, _))) => Ok(len),
+            Ok(Err(_)) => Err(Failure::Connection),
+            Err(_) => Err(Failure::Timeout),
+        }
+    }
+}
+
+/// Returns whether a widget changed
 async fn sync(
     rx_buffer: &mut [u8],
     control: &mut Control<'static>,
     stack: Stack<'static>,
     rtc_device: &'static RtcDevice,
     flash_driver: &'static FlashDevice,
-) {
-    if connect(control, &stack).await.is_ok() {
-        let (time_buf, weather_buf) = rx_buffer.split_at_mut(4096);
+    keep_link: bool,
+) -> bool {
+    let now = trusted_time(rtc_device).await;
+    let due = WIDGETS.lock().await.due(widgets(), now);
+    let started = now.map(|now| now.assume_utc().unix_timestamp());
+    let mut wifi = Wifi {
+        control,
+        stack,
+        keep_link,
+        started,
+        deadline: Instant::now() + SYNC_TIMEOUT,
+    };
 
-        join(
-            fetch_time(&stack, time_buf, rtc_device),
-            fetch_weather(&stack, weather_buf, flash_driver),
-        )
-        .await;
+    match badger_core::sync::sync(&mut wifi, rx_buffer, &sync_config(&due), now, || {
+        Instant::now().as_micros()
+    })
+    .await
+    {
+        Ok(synced) => {
+            DIAGNOSTICS.lock().await.record_sync(&synced, started);
+
+            // Reaching the network is what an update has to prove
+            if synced.time.is_ok() {
+                ota::confirm(flash_driver).await;
+            }
 
-        control.leave().await;
+            apply_sync(synced, rtc_device, flash_driver).await
+        }
+        Err(_) => false,
     }
 }
 
@@ -77,28 +375,57 @@ pub async fn run(
     rtc_device: &'static RtcDevice,
     flash_driver: &'static FlashDevice,
 ) -> ! {
-    let mut rx_buffer = [0; 8192];
+    // Headers and a chunk of body for each of the two requests, the bodies
+    // stream through rather than being held
+    let mut rx_buffer = [0; 2048];
+    let mut saved = BSSIDS.lock().await.count();
 
     loop {
-        select(
+        let synced = select(
             led::loop_breathe(user_led),
             with_timeout(
-                Duration::from_secs(30),
+                SYNC_TIMEOUT,
                 sync(
                     &mut rx_buffer,
                     &mut control,
                     stack,
                     rtc_device,
                     flash_driver,
+                    true,
                 ),
             ),
         )
         .await;
 
-        DISPLAY_CHANGED.signal(Screen::TopBar);
+        save_bssids(&mut saved, flash_driver).await;
+        flash::save_diagnostics(flash_driver).await;
+
+        if let Some(url) = ota::url() {
+            ota::update(stack, url, flash_driver).await;
+        }
+
+        let widgets_changed = matches!(synced, Either::Second(Ok(true)));
+        DISPLAY_CHANGED.signal(match widgets_changed {
+            true => Screen::Full,
+            false => Screen::TopBar,
+        });
+        PUBLISH_STATE.signal(());
         led::blink(user_led, 2).await;
 
-        select(Timer::after_secs(3600), UPDATE_WEATHER.wait()).await;
+        let next_sync = Instant::now() + SYNC_INTERVAL;
+
+        while Instant::now() < next_sync {
+            let next_scan = (Instant::now() + SCAN_INTERVAL).min(next_sync);
+
+            match select(Timer::at(next_scan), UPDATE_WEATHER.wait()).await {
+                Either::First(_) if next_scan < next_sync => {
+                    count_bssids(&mut control).await;
+                    DISPLAY_CHANGED.signal(Screen::TopBar);
+                }
+                Either::First(_) => {}
+                Either::Second(_) => break,
+            }
+        }
     }
 }
 
@@ -109,22 +436,29 @@ pub async fn run_once(
     rtc_device: &'static RtcDevice,
     flash_device: &'static FlashDevice,
 ) {
-    let mut rx_buffer = [0; 8192];
+    // Headers and a chunk of body for each of the two requests, the bodies
+    // stream through rather than being held
+    let mut rx_buffer = [0; 2048];
+    let mut saved = BSSIDS.lock().await.count();
 
     select(
         led::loop_breathe(user_led),
         with_timeout(
-            Duration::from_secs(30),
+            SYNC_TIMEOUT,
             sync(
                 &mut rx_buffer,
                 &mut control,
                 stack,
                 rtc_device,
                 flash_device,
+                false,
             ),
         ),
     )
     .await;
 
+    save_bssids(&mut saved, flash_device).await;
+    flash::save_diagnostics(flash_device).await;
+
     led::blink(user_led, 2).await;
 }